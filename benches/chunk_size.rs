@@ -0,0 +1,69 @@
+//! Compares `DownloadConfig::chunk_size` values on a large download from a local server.
+//!
+//! Run with `cargo bench --bench chunk_size`. Every size downloads the same file a few times,
+//! reporting every chunk and writing it straight to the file, and prints the throughput, the
+//! progress events emitted and, on Linux, the write system calls the process made per file.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use common::{io_counter, pattern, scratch_dir, MockServer};
+use parallel_downloads::{
+    download_file_with_config, DownloadConfig, DownloadEvent, ProgressThrottle, WriteConfig,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Size of the downloaded file.
+const FILE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Downloads of the file per chunk size.
+const ROUNDS: u32 = 5;
+
+fn main() {
+    let server = MockServer::serving(pattern(FILE_SIZE));
+    let url = server.url("/file.bin");
+    let path = scratch_dir("bench_chunk_size").join("file.bin");
+    let client = reqwest::blocking::Client::new();
+
+    println!(
+        "{:>10} {:>10} {:>12} {:>16}",
+        "chunk", "MiB/s", "events", "write syscalls"
+    );
+    for chunk_size in [4 << 10, 16 << 10, 64 << 10, 256 << 10, 1 << 20] {
+        let config = DownloadConfig {
+            chunk_size: Some(chunk_size),
+            progress_throttle: ProgressThrottle::NONE,
+            write: WriteConfig {
+                buffer_size: 0,
+                ..WriteConfig::default()
+            },
+            ..DownloadConfig::default()
+        };
+        let events = Arc::new(AtomicU64::new(0));
+        let writes_before = io_counter("syscw");
+        let started = Instant::now();
+        for _ in 0..ROUNDS {
+            let events = Arc::clone(&events);
+            download_file_with_config(&client, &url, &path, &config, move |event| {
+                if let DownloadEvent::Progress(_) = event {
+                    events.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+            .unwrap();
+        }
+        let elapsed = started.elapsed().as_secs_f64();
+        let writes = match (writes_before, io_counter("syscw")) {
+            (Some(before), Some(after)) => ((after - before) / u64::from(ROUNDS)).to_string(),
+            _ => "n/a".to_string(),
+        };
+        println!(
+            "{:>9}K {:>10.0} {:>12} {:>16}",
+            chunk_size >> 10,
+            (FILE_SIZE as f64 * f64::from(ROUNDS)) / elapsed / (1 << 20) as f64,
+            events.load(Ordering::Relaxed) / u64::from(ROUNDS),
+            writes
+        );
+    }
+}
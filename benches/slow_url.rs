@@ -0,0 +1,89 @@
+//! Shows that one slow download doesn't hold up the rest of a batch.
+//!
+//! Run with `cargo bench --bench slow_url`. A batch of quick files and one slow file is
+//! downloaded with the slow file at different positions. The time the batch took and the time
+//! its last quick file finished are printed next to the times they would take if every group
+//! of `concurrency` URLs had to finish before the next group started.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use common::{scratch_dir, MockServer, Response};
+use parallel_downloads::{download_batch_requests, BatchConfig, DownloadEvent, DownloadRequest};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of files answered quickly.
+const QUICK_FILES: usize = 24;
+
+/// How long the server takes to answer a quick file.
+const QUICK: Duration = Duration::from_millis(100);
+
+/// How long the server takes to answer the slow file.
+const SLOW: Duration = Duration::from_secs(2);
+
+/// Workers of the batch.
+const CONCURRENCY: usize = 4;
+
+fn main() {
+    let server = MockServer::start(|request| {
+        thread::sleep(if request.path == "/slow" { SLOW } else { QUICK });
+        Response::ok(request.path.clone())
+    });
+    let directory = scratch_dir("bench_slow_url");
+
+    println!(
+        "{:>14} {:>12} {:>12} {:>18} {:>18}",
+        "slow file at", "batch", "last quick", "chunked batch", "chunked quick"
+    );
+    for position in [0, QUICK_FILES / 2, QUICK_FILES] {
+        let mut paths: Vec<String> = (0..QUICK_FILES).map(|i| format!("quick-{}", i)).collect();
+        paths.insert(position, "slow".to_string());
+        let requests = paths
+            .iter()
+            .map(|name| {
+                DownloadRequest::new(server.url(&format!("/{}", name)), directory.join(name))
+            })
+            .collect();
+        let config = BatchConfig {
+            concurrency: CONCURRENCY,
+            ..BatchConfig::default()
+        };
+
+        let started = Instant::now();
+        let last_quick = Arc::new(Mutex::new(Duration::ZERO));
+        let seen = Arc::clone(&last_quick);
+        let results = download_batch_requests(requests, config, move |event| {
+            if let DownloadEvent::Completed { path, .. } = event {
+                if !path.ends_with("slow") {
+                    *seen.lock().unwrap() = started.elapsed();
+                }
+            }
+        })
+        .unwrap();
+        let batch = started.elapsed();
+        assert!(results.iter().all(|result| result.is_success()));
+
+        // Each group waits for its slowest file before the next one starts, so the quick files
+        // are done once the last group holding one of them is.
+        let mut chunked = Duration::ZERO;
+        let mut chunked_last_quick = Duration::ZERO;
+        for group in paths.chunks(CONCURRENCY) {
+            let slow = group.iter().any(|name| name == "slow");
+            chunked += if slow { SLOW } else { QUICK };
+            if group.iter().any(|name| name != "slow") {
+                chunked_last_quick = chunked;
+            }
+        }
+        let last_quick = *last_quick.lock().unwrap();
+        println!(
+            "{:>14} {:>11.2}s {:>11.2}s {:>17.2}s {:>17.2}s",
+            position,
+            batch.as_secs_f64(),
+            last_quick.as_secs_f64(),
+            chunked.as_secs_f64(),
+            chunked_last_quick.as_secs_f64()
+        );
+    }
+}
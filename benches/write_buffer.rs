@@ -0,0 +1,57 @@
+//! Compares `WriteConfig::buffer_size` values on a large download from a local server.
+//!
+//! Run with `cargo bench --bench write_buffer`. The body is read in small chunks, so every
+//! chunk would cost a write of its own without the buffer. Every size downloads the same file
+//! a few times and prints the throughput and, on Linux, the write system calls the process
+//! made per file.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use common::{io_counter, pattern, scratch_dir, MockServer};
+use parallel_downloads::{download_file_with_config, DownloadConfig, WriteConfig};
+use std::time::Instant;
+
+/// Size of the downloaded file.
+const FILE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Bytes read from the body at a time.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Downloads of the file per buffer size.
+const ROUNDS: u32 = 5;
+
+fn main() {
+    let server = MockServer::serving(pattern(FILE_SIZE));
+    let url = server.url("/file.bin");
+    let path = scratch_dir("bench_write_buffer").join("file.bin");
+    let client = reqwest::blocking::Client::new();
+
+    println!("{:>10} {:>10} {:>16}", "buffer", "MiB/s", "write syscalls");
+    for buffer_size in [0, 64 << 10, 256 << 10, 1 << 20] {
+        let config = DownloadConfig {
+            chunk_size: Some(CHUNK_SIZE),
+            write: WriteConfig {
+                buffer_size,
+                ..WriteConfig::default()
+            },
+            ..DownloadConfig::default()
+        };
+        let writes_before = io_counter("syscw");
+        let started = Instant::now();
+        for _ in 0..ROUNDS {
+            download_file_with_config(&client, &url, &path, &config, |_| ()).unwrap();
+        }
+        let elapsed = started.elapsed().as_secs_f64();
+        let writes = match (writes_before, io_counter("syscw")) {
+            (Some(before), Some(after)) => ((after - before) / u64::from(ROUNDS)).to_string(),
+            _ => "n/a".to_string(),
+        };
+        println!(
+            "{:>9}K {:>10.0} {:>16}",
+            buffer_size >> 10,
+            (FILE_SIZE as f64 * f64::from(ROUNDS)) / elapsed / (1 << 20) as f64,
+            writes
+        );
+    }
+}
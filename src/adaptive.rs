@@ -0,0 +1,175 @@
+use crate::control::Control;
+use crate::event::DownloadEvent;
+use crate::metrics::{BatchMetrics, Metrics};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::time::Duration;
+
+/// Settings for letting a batch find its own concurrency, used by
+/// [`crate::BatchConfig::adaptive_concurrency`].
+///
+/// The batch starts with `min` downloads at a time. After every `interval` it adds one more
+/// while throughput keeps up and no download had to be retried or failed, and halves the
+/// number as soon as some did, the way TCP finds the speed of a link. When adding a download
+/// made the batch slower, it takes the download back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptiveConcurrency {
+    /// The fewest downloads run at the same time, and the number the batch starts with.
+    pub min: usize,
+    /// The most downloads run at the same time.
+    pub max: usize,
+    /// How often throughput and errors are sampled to adjust the concurrency.
+    pub interval: Duration,
+}
+
+impl Default for AdaptiveConcurrency {
+    fn default() -> Self {
+        Self {
+            min: 4,
+            max: 64,
+            interval: Duration::from_secs(2),
+        }
+    }
+}
+
+/// The number of workers of a batch allowed to download at the same time, which may change
+/// while the batch runs.
+#[derive(Debug)]
+pub(crate) struct ConcurrencyLimit {
+    /// Workers currently holding a [`Permit`], and how many may.
+    state: Mutex<Slots>,
+    /// Signalled whenever a permit is returned or the limit grows.
+    freed: Condvar,
+}
+
+/// The counts behind a [`ConcurrencyLimit`].
+#[derive(Debug)]
+struct Slots {
+    /// Permits handed out.
+    active: usize,
+    /// Permits that may be handed out.
+    limit: usize,
+}
+
+impl ConcurrencyLimit {
+    /// Creates a limit allowing `limit` workers at a time.
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            state: Mutex::new(Slots { active: 0, limit }),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Waits until the worker may download, and holds the slot until the permit is dropped.
+    ///
+    /// A cancelled batch hands out permits right away, so its queue drains quickly.
+    pub(crate) fn acquire<'a>(&'a self, control: &Control) -> Permit<'a> {
+        let mut slots = self.state.lock().unwrap();
+        while slots.active >= slots.limit && !control.is_cancelled() {
+            // Cancellation doesn't signal the limit, so waiters look at it now and then.
+            slots = self
+                .freed
+                .wait_timeout(slots, Duration::from_millis(100))
+                .unwrap()
+                .0;
+        }
+        slots.active += 1;
+        Permit { limit: self }
+    }
+
+    /// Returns how many workers may download at the same time.
+    fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit
+    }
+
+    /// Changes how many workers may download at the same time. Workers beyond a lowered limit
+    /// finish their current download first.
+    fn set_limit(&self, limit: usize) {
+        self.state.lock().unwrap().limit = limit;
+        self.freed.notify_all();
+    }
+}
+
+/// A worker's slot in a [`ConcurrencyLimit`], returned when dropped.
+#[derive(Debug)]
+pub(crate) struct Permit<'a> {
+    limit: &'a ConcurrencyLimit,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.limit.state.lock().unwrap().active -= 1;
+        self.limit.freed.notify_one();
+    }
+}
+
+/// Starts a thread adjusting `limit` according to `settings` from the samples of `metrics`,
+/// reporting every change through `callback`.
+///
+/// The thread only holds on to `limit` weakly and stops once the workers have dropped it.
+pub(crate) fn spawn_controller(
+    limit: &Arc<ConcurrencyLimit>,
+    settings: AdaptiveConcurrency,
+    metrics: Arc<Metrics>,
+    callback: Arc<impl Fn(&DownloadEvent) + 'static + Send + Sync>,
+) {
+    let limit: Weak<ConcurrencyLimit> = Arc::downgrade(limit);
+    std::thread::spawn(move || {
+        let mut previous = metrics.snapshot();
+        // The throughput before the last increase, to take the increase back if it hurt.
+        let mut before_increase = None;
+        loop {
+            std::thread::sleep(settings.interval);
+            let Some(limit) = limit.upgrade() else {
+                break;
+            };
+            let sample = metrics.snapshot();
+            let (bytes_per_sec, errors) = rate_since(&previous, &sample);
+            let current = limit.limit();
+
+            let next = if errors > 0 {
+                // Back off hard at the first sign of trouble.
+                before_increase = None;
+                current / 2
+            } else if before_increase.is_some_and(|before| bytes_per_sec < before * 0.9) {
+                // The last download added made things worse.
+                before_increase = None;
+                current - 1
+            } else if sample.active_workers >= current {
+                // Only grow while every slot is busy, or the extra slot goes unused.
+                before_increase = Some(bytes_per_sec);
+                current + 1
+            } else {
+                current
+            }
+            .clamp(settings.min, settings.max);
+
+            if next != current {
+                limit.set_limit(next);
+                callback(&DownloadEvent::ConcurrencyChanged {
+                    from: current,
+                    to: next,
+                    bytes_per_sec,
+                    errors,
+                });
+            }
+            previous = sample;
+        }
+    });
+}
+
+/// Returns the bytes per second and the retries and failures between two samples.
+fn rate_since(previous: &BatchMetrics, sample: &BatchMetrics) -> (f64, u64) {
+    let seconds = sample
+        .elapsed
+        .saturating_sub(previous.elapsed)
+        .as_secs_f64();
+    let bytes = sample.bytes_downloaded - previous.bytes_downloaded;
+    let bytes_per_sec = if seconds > 0.0 {
+        bytes as f64 / seconds
+    } else {
+        0.0
+    };
+    let errors =
+        (sample.retries + sample.requests_failed) - (previous.retries + previous.requests_failed);
+    (bytes_per_sec, errors)
+}
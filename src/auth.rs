@@ -0,0 +1,58 @@
+use crate::headers::InvalidHeader;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use http::header::{HeaderValue, AUTHORIZATION};
+use std::fmt;
+
+/// Credentials sent with every request of a download.
+///
+/// The `Authorization` header is added to the initial request and to every retry. It is kept
+/// on redirects to the same origin and stripped on redirects to another host, scheme, or port.
+/// Credentials are redacted from the [`Debug`] output.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Auth {
+    /// HTTP basic authentication.
+    Basic {
+        /// The user name.
+        user: String,
+        /// The password.
+        password: String,
+    },
+    /// A bearer token, sent as `Authorization: Bearer <token>`.
+    Bearer(String),
+}
+
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Auth::Basic { user, .. } => f
+                .debug_struct("Basic")
+                .field("user", user)
+                .field("password", &"<redacted>")
+                .finish(),
+            Auth::Bearer(_) => f.debug_tuple("Bearer").field(&"<redacted>").finish(),
+        }
+    }
+}
+
+impl Auth {
+    /// Returns the `Authorization` header carrying these credentials, marked as sensitive.
+    pub(crate) fn header_value(&self) -> Result<HeaderValue, InvalidHeader> {
+        let value = match self {
+            Auth::Basic { user, password } => {
+                format!(
+                    "Basic {}",
+                    STANDARD.encode(format!("{}:{}", user, password))
+                )
+            }
+            Auth::Bearer(token) => format!("Bearer {}", token),
+        };
+        // The reason leaves the credentials out, since errors end up in logs.
+        let mut value = HeaderValue::from_str(&value).map_err(|_| InvalidHeader {
+            name: AUTHORIZATION.to_string(),
+            reason: "the credentials contain characters a header can't carry".to_string(),
+        })?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+}
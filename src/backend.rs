@@ -0,0 +1,125 @@
+use crate::error::DownloadError;
+use http::header::HeaderMap;
+use http::{Method, StatusCode, Version};
+#[cfg(feature = "reqwest")]
+use reqwest::tls::TlsInfo;
+use std::fmt;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use url::Url;
+
+/// A single HTTP request, as handed to an [`HttpBackend`].
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    /// The method to send, `GET` or `HEAD`.
+    pub method: Method,
+    /// The URL to request.
+    pub url: String,
+    /// Every header to send, including `Authorization` when the download has credentials.
+    pub headers: HeaderMap,
+}
+
+/// The answer to an [`HttpRequest`], with its body still to be read.
+pub struct HttpResponse {
+    /// The status the server answered with.
+    pub status: StatusCode,
+    /// The headers of the response.
+    pub headers: HeaderMap,
+    /// The URL that answered the request.
+    pub url: Url,
+    /// The HTTP version the response was served with.
+    pub version: Version,
+    /// The address of the server that answered, whose family tells whether IPv4 or IPv6 was
+    /// used. `None` when nothing was connected to or the backend can't tell.
+    pub remote_addr: Option<SocketAddr>,
+    /// The length of `body`, if known. Backends that decompress bodies report `None` for
+    /// compressed ones, since their `Content-Length` counts the compressed bytes.
+    pub content_length: Option<u64>,
+    /// The DER certificate the server presented, checked against
+    /// [`crate::DownloadConfig::pinned_keys`]. `None` over plain HTTP, or when the backend
+    /// can't tell.
+    pub peer_certificate: Option<Vec<u8>>,
+    /// The body, read in chunks as the download goes.
+    ///
+    /// Read errors are treated like those of [`HttpBackend::send`] wrapped in a
+    /// [`DownloadError::Body`], so their kind decides whether the download is retried.
+    pub body: Box<dyn Read + Send>,
+}
+
+impl fmt::Debug for HttpResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpResponse")
+            .field("status", &self.status)
+            .field("headers", &self.headers)
+            .field("url", &self.url)
+            .field("version", &self.version)
+            .field("remote_addr", &self.remote_addr)
+            .field("content_length", &self.content_length)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Sends the requests of blocking downloads.
+///
+/// Every request of [`crate::download_file_with_config`] and the other blocking downloads goes
+/// through a backend, so it can be replaced, for instance by one serving scripted responses in
+/// tests. Retries, redirects, resumption, and every check of the response are handled by the
+/// downloader on top of it. Implemented for the blocking reqwest client, which is what the
+/// crate builds by default, and with the `ureq` feature for `UreqBackend`.
+pub trait HttpBackend: Send + Sync {
+    /// Sends `request` and returns once the headers of the response have arrived.
+    ///
+    /// Redirects must not be followed, since the downloader applies
+    /// [`crate::DownloadConfig::redirects`] itself. Error statuses are returned as responses.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request to send.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` with the response, whatever its status.
+    /// * `Err` if no response arrived. [`DownloadError::Body`] is retried when the kind of its
+    ///   I/O error is transient, such as [`std::io::ErrorKind::ConnectionReset`] or
+    ///   [`std::io::ErrorKind::TimedOut`].
+    fn send(&self, request: HttpRequest) -> Result<HttpResponse, DownloadError>;
+}
+
+#[cfg(feature = "reqwest")]
+impl HttpBackend for reqwest::blocking::Client {
+    fn send(&self, request: HttpRequest) -> Result<HttpResponse, DownloadError> {
+        let response = self
+            .request(request.method, &request.url)
+            .headers(request.headers)
+            .send()
+            .map_err(DownloadError::request(&request.url))?;
+        let peer_certificate = response
+            .extensions()
+            .get::<TlsInfo>()
+            .and_then(TlsInfo::peer_certificate)
+            .map(<[u8]>::to_vec);
+        Ok(HttpResponse {
+            status: response.status(),
+            headers: response.headers().clone(),
+            url: response.url().clone(),
+            version: response.version(),
+            remote_addr: response.remote_addr(),
+            content_length: response.content_length(),
+            peer_certificate,
+            body: Box::new(response),
+        })
+    }
+}
+
+impl<B: HttpBackend + ?Sized> HttpBackend for Arc<B> {
+    fn send(&self, request: HttpRequest) -> Result<HttpResponse, DownloadError> {
+        (**self).send(request)
+    }
+}
+
+impl<B: HttpBackend + ?Sized> HttpBackend for Box<B> {
+    fn send(&self, request: HttpRequest) -> Result<HttpResponse, DownloadError> {
+        (**self).send(request)
+    }
+}
@@ -0,0 +1,94 @@
+use crate::error::DownloadError;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// How long a producer waits for room before checking for cancellation again.
+const POLL: Duration = Duration::from_millis(100);
+
+/// Bounds how many requests of a [`crate::Downloader`] may wait for a worker, so producers
+/// enqueueing faster than files download are held up instead of filling memory.
+///
+/// A request takes a place when it is enqueued and gives it back once a worker starts it.
+#[derive(Debug)]
+pub(crate) struct Backlog {
+    /// Most requests that may wait at the same time.
+    capacity: usize,
+    /// Requests waiting for a worker, and whether the downloader was closed.
+    state: Mutex<Places>,
+    /// Signalled whenever a place is given back or the downloader is closed.
+    freed: Condvar,
+}
+
+/// The counts behind a [`Backlog`].
+#[derive(Debug)]
+struct Places {
+    /// Places taken by requests that haven't started.
+    taken: usize,
+    /// Set once no more requests are accepted.
+    closed: bool,
+}
+
+impl Backlog {
+    /// Creates a backlog holding at most `capacity` requests, and at least one.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Mutex::new(Places {
+                taken: 0,
+                closed: false,
+            }),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Takes a place for a new request, waiting for one to be given back while the backlog
+    /// is full if `wait` is set.
+    ///
+    /// # Arguments
+    ///
+    /// * `wait` - Whether to block while the backlog is full.
+    /// * `cancelled` - Tells whether the downloader was cancelled, checked while waiting.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` once the request has a place, which must be given back with
+    ///   [`Backlog::release`].
+    /// * `Err` with [`DownloadError::QueueFull`] if the backlog is full and `wait` isn't set.
+    /// * `Err` with [`DownloadError::QueueClosed`] once the downloader is closed or cancelled,
+    ///   including while waiting.
+    pub(crate) fn reserve(
+        &self,
+        wait: bool,
+        cancelled: impl Fn() -> bool,
+    ) -> Result<(), DownloadError> {
+        let mut places = self.state.lock().unwrap();
+        loop {
+            if places.closed || cancelled() {
+                return Err(DownloadError::QueueClosed);
+            }
+            if places.taken < self.capacity {
+                places.taken += 1;
+                return Ok(());
+            }
+            if !wait {
+                return Err(DownloadError::QueueFull);
+            }
+            // Cancelling through a canceller doesn't signal the backlog, so producers look at
+            // it now and then.
+            places = self.freed.wait_timeout(places, POLL).unwrap().0;
+        }
+    }
+
+    /// Gives back the place of a request that started downloading or was never queued.
+    pub(crate) fn release(&self) {
+        let mut places = self.state.lock().unwrap();
+        places.taken = places.taken.saturating_sub(1);
+        self.freed.notify_one();
+    }
+
+    /// Refuses every further request, waking producers waiting for a place.
+    pub(crate) fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.freed.notify_all();
+    }
+}
@@ -0,0 +1,692 @@
+use crate::adaptive::{spawn_controller, AdaptiveConcurrency, ConcurrencyLimit};
+use crate::batch_progress::{BatchTracker, FileTally};
+use crate::client::{blocking_client, Client};
+use crate::config::{BatchConfig, DownloadConfig};
+use crate::context::Context;
+use crate::cookies::CookieJar;
+use crate::dedup::{fill, find_repeats, DuplicateLink};
+use crate::disk_space::check_disk_space;
+use crate::download::{download_with_context, probe_size};
+use crate::dry_run::plan;
+use crate::error::DownloadError;
+use crate::event::DownloadEvent;
+#[cfg(feature = "extract")]
+use crate::extract::extract_completed;
+use crate::handle::BatchHandle;
+use crate::headers::check_headers;
+use crate::host_limit::{HostLimiter, HostSlot};
+use crate::preflight::probe_all;
+use crate::queue::WorkQueue;
+use crate::rate_limit::RateLimiter;
+use crate::request::{resolve_destinations, DownloadRequest};
+use crate::result::{DownloadOutcome, DownloadResult};
+use crate::spans::{warn, BatchSpan};
+use crate::state::BatchState;
+use crate::summary::finish_batch;
+use crate::timing::DownloadTimings;
+use crate::url_check::{normalize_request, normalize_requests};
+use std::cmp::min;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Downloads a batch of files concurrently.
+///
+/// A fixed pool of worker threads pulls URLs from a shared queue until it is drained, so a
+/// slow download only occupies its own worker while the others keep starting new files.
+///
+/// # Arguments
+///
+/// * `urls` - A vector of string slices containing the URLs to download.
+/// * `callback` - A function or closure that receives the [`DownloadEvent`]s of every file in the batch.
+///
+/// # Returns
+///
+/// * `Ok` with one [`DownloadResult`] per URL, in the order the URLs were given. A failed
+///   download is reported in its result and does not fail the batch.
+/// * `Err` if the batch could not be started, or with a [`crate::BatchFailed`] if a download
+///   failed and [`BatchConfig::continue_on_error`] is turned off.
+pub fn download_batch(
+    urls: Vec<&str>,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+) -> Result<Vec<DownloadResult>, DownloadError> {
+    download_batch_with_config(urls, BatchConfig::default(), callback)
+}
+
+/// Downloads a batch of files concurrently using explicit settings.
+///
+/// Works like [`download_batch`], but the number of workers and the per-file settings
+/// (such as retries) are taken from `config`.
+///
+/// # Arguments
+///
+/// * `urls` - A vector of string slices containing the URLs to download.
+/// * `config` - The batch settings, including the per-file [`crate::DownloadConfig`].
+/// * `callback` - A function or closure that receives the [`DownloadEvent`]s of every file in the batch.
+///
+/// # Returns
+///
+/// * `Ok` with one [`DownloadResult`] per URL, in the order the URLs were given. A failed
+///   download is reported in its result and does not fail the batch.
+/// * `Err` if the batch could not be started, or with a [`crate::BatchFailed`] if a download
+///   failed and [`BatchConfig::continue_on_error`] is turned off.
+pub fn download_batch_with_config(
+    urls: Vec<&str>,
+    config: BatchConfig,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+) -> Result<Vec<DownloadResult>, DownloadError> {
+    // Save every URL under a unique, index-based file name in the working directory.
+    let requests = urls
+        .into_iter()
+        .enumerate()
+        .map(|(index, url)| DownloadRequest::new(url, format!("./test-{}.svg", index + 1)))
+        .collect();
+
+    download_batch_requests(requests, config, callback)
+}
+
+/// Downloads a batch of files concurrently into a directory, naming each file automatically.
+///
+/// File names come from the last path segment of each URL (see
+/// [`DownloadRequest::in_directory`]) and are overridden by the server's
+/// `Content-Disposition` header when it suggests one. The path each file actually landed at is
+/// reported in [`DownloadEvent::Completed`].
+///
+/// # Arguments
+///
+/// * `urls` - A vector of string slices containing the URLs to download.
+/// * `output_dir` - The directory all files are saved in.
+/// * `config` - The batch settings, including the per-file [`crate::DownloadConfig`].
+/// * `callback` - A function or closure that receives the [`DownloadEvent`]s of every file in the batch.
+///
+/// # Returns
+///
+/// * `Ok` with one [`DownloadResult`] per URL, in the order the URLs were given.
+/// * `Err` with a [`crate::DuplicateDestination`] if two URLs map to the same file name,
+///   unless [`crate::OverwritePolicy::RenameWithSuffix`] numbers them instead.
+/// * `Err` if the batch could not be started for any other reason.
+pub fn download_batch_to_dir(
+    urls: Vec<&str>,
+    output_dir: impl AsRef<Path>,
+    mut config: BatchConfig,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+) -> Result<Vec<DownloadResult>, DownloadError> {
+    let output_dir = output_dir.as_ref();
+
+    // Name every file after its URL, falling back to a 1-based index.
+    let file_names = config.download.file_names;
+    let requests = urls
+        .into_iter()
+        .enumerate()
+        .map(|(index, url)| {
+            DownloadRequest::in_directory_with(url, output_dir, index + 1, &file_names)
+        })
+        .collect();
+
+    // Let servers override the derived names.
+    config.download.name_from_content_disposition = true;
+
+    download_batch_requests(requests, config, callback)
+}
+
+/// Downloads a batch of requests concurrently, saving each URL to its own destination.
+///
+/// This blocks until the batch is done; use [`start_batch`] to run it in the background with
+/// the ability to cancel it.
+///
+/// # Arguments
+///
+/// * `requests` - The files to download and where to save them.
+/// * `config` - The batch settings, including the per-file [`crate::DownloadConfig`].
+/// * `callback` - A function or closure that receives the [`DownloadEvent`]s of every file in the batch.
+///
+/// # Returns
+///
+/// * `Ok` with one [`DownloadResult`] per request, in the order the requests were given. A
+///   failed download is reported in its result and, unless
+///   [`BatchConfig::continue_on_error`] is turned off, does not fail the batch.
+/// * `Err` with a [`crate::BatchFailed`] listing every result if a download failed in strict
+///   mode. The other downloads still run to completion.
+/// * `Err` with a [`crate::DuplicateDestination`] if two requests share a destination, an
+///   [`crate::InvalidHeader`] if a configured header is malformed, or an
+///   [`crate::InvalidConfig`] if a setting can't be used; nothing is downloaded in any case.
+pub fn download_batch_requests(
+    requests: Vec<DownloadRequest>,
+    config: BatchConfig,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+) -> Result<Vec<DownloadResult>, DownloadError> {
+    // Every download runs to completion; strict mode only decides how failures are reported.
+    let continue_on_error = config.continue_on_error;
+    let results = start_batch(requests, config, callback)?.join();
+    finish_batch(results, continue_on_error)
+}
+
+/// Starts downloading a batch of requests in the background and returns immediately.
+///
+/// The returned [`BatchHandle`] can cancel the batch and [`join`](BatchHandle::join) it to
+/// collect the [`crate::DownloadResult`] of every request.
+///
+/// # Arguments
+///
+/// * `requests` - The files to download and where to save them.
+/// * `config` - The batch settings, including the per-file [`crate::DownloadConfig`].
+/// * `callback` - A function or closure that receives the [`DownloadEvent`]s of every file in the batch.
+///
+/// # Returns
+///
+/// * `Ok(BatchHandle)` once the workers have been spawned.
+/// * `Err` with a [`crate::DuplicateDestination`] if two requests share a destination, an
+///   [`crate::InvalidHeader`] if a configured header is malformed, an [`crate::InvalidConfig`]
+///   if a setting can't be used, or an [`crate::InvalidUrl`] if a URL is invalid and
+///   [`BatchConfig::strict_urls`] is set; nothing is downloaded in any case.
+pub fn start_batch(
+    mut requests: Vec<DownloadRequest>,
+    mut config: BatchConfig,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+) -> Result<BatchHandle, DownloadError> {
+    // Parse every URL before anything starts, failing the batch on an invalid one in strict mode.
+    normalize_requests(&mut requests, config.strict_urls)?;
+
+    // Refuse to start if two requests would clobber each other's files.
+    resolve_destinations(&mut requests, config.download.overwrite)?;
+
+    // Reject malformed headers and settings now rather than failing every download that uses
+    // them.
+    check_headers(&requests, &config.download)?;
+    config.download.check()?;
+
+    // Define the maximum number of threads to use, which is the smaller of the concurrency limit or the total number of URLs.
+    let thread_count = min(config.worker_count(), requests.len());
+
+    // Build a single client shared by every download so connections to the same host are pooled.
+    let client = Arc::new(blocking_client(&mut config)?);
+
+    // Learn each file's size and whether its server accepts ranges before downloading it.
+    if config.preflight {
+        let found = probe_all(&client, &requests, &config, thread_count);
+        for (request, found) in requests.iter_mut().zip(found) {
+            match found {
+                Ok(remote) => remote.apply(request),
+                Err(e) => warn!("Preflight of {} failed: {}", request.url, e),
+            }
+        }
+    }
+
+    // Refuse to start a batch that can't fit on its destination filesystems.
+    if config.preflight_disk_space {
+        let sizes = expected_sizes(
+            &client,
+            &requests,
+            &config.download,
+            config.cookies.as_ref(),
+            thread_count,
+        );
+        check_disk_space(
+            requests
+                .iter()
+                .zip(sizes)
+                .filter_map(|(request, size)| Some((request.destination.as_path(), size?))),
+        )?;
+    }
+
+    // Pick up where an earlier run with the same state file stopped, keeping partial files
+    // so the next run can do the same. A dry run leaves the state file alone.
+    let state = match &config.state_file {
+        Some(path) if !config.dry_run => Some(BatchState::open(path, &requests)?),
+        _ => None,
+    };
+    if state.is_some() {
+        config.download.resume = true;
+    }
+
+    // Fetch every distinct file once, holding back the requests that repeat it.
+    let repeats = match &config.dedup {
+        Some(dedup) if !config.dry_run => find_repeats(&requests, dedup),
+        _ => Vec::new(),
+    };
+    let mut queue = WorkQueue::new(requests);
+    queue.hold_repeats(repeats);
+
+    spawn_workers(client, queue, state, thread_count, config, callback, None)
+}
+
+/// Downloads a stream of requests concurrently, taking each one only when a worker is free.
+///
+/// Works like [`download_batch_requests`], but the requests are never collected up front, so
+/// a lazily read list of any size (such as a [`crate::UrlList`]) only keeps the downloads in
+/// flight in memory. Because the batch isn't known in advance,
+/// [`BatchConfig::preflight_disk_space`] is ignored and destinations are checked for
+/// duplicates as the requests arrive.
+///
+/// If the stream yields an error, or a request reuses an earlier destination, no further
+/// requests are taken, the downloads in flight are cancelled, and the batch fails with that
+/// error.
+///
+/// # Arguments
+///
+/// * `requests` - The files to download and where to save them, read as workers become free.
+/// * `config` - The batch settings, including the per-file [`crate::DownloadConfig`].
+/// * `callback` - A function or closure that receives the [`DownloadEvent`]s of every file in the batch.
+///
+/// # Returns
+///
+/// * `Ok` with one [`DownloadResult`] per request, in the order the stream yielded them.
+/// * `Err` with the error that stopped the stream, such as a [`crate::MalformedUrl`] from a
+///   strict [`crate::UrlList`] or a [`crate::DuplicateDestination`].
+/// * `Err` with a [`crate::BatchFailed`] if a download failed and
+///   [`BatchConfig::continue_on_error`] is turned off.
+pub fn download_batch_stream(
+    requests: impl Iterator<Item = Result<DownloadRequest, DownloadError>> + Send + 'static,
+    config: BatchConfig,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+) -> Result<Vec<DownloadResult>, DownloadError> {
+    let continue_on_error = config.continue_on_error;
+    let handle = start_batch_stream(requests, config, callback)?;
+    finish_batch(handle.try_join()?, continue_on_error)
+}
+
+/// Starts downloading a stream of requests in the background and returns immediately.
+///
+/// Works like [`download_batch_stream`], but the returned [`BatchHandle`] can cancel the batch.
+/// Once cancelled, no more requests are read from the stream. Use
+/// [`BatchHandle::try_join`] to collect the results and learn whether the stream failed.
+///
+/// # Arguments
+///
+/// * `requests` - The files to download and where to save them, read as workers become free.
+/// * `config` - The batch settings, including the per-file [`crate::DownloadConfig`].
+/// * `callback` - A function or closure that receives the [`DownloadEvent`]s of every file in the batch.
+///
+/// # Returns
+///
+/// * `Ok(BatchHandle)` once the workers have been spawned.
+/// * `Err` with an [`crate::InvalidHeader`] if a configured header is malformed, an
+///   [`crate::InvalidConfig`] if a setting can't be used, or if the HTTP client could not be
+///   built.
+pub fn start_batch_stream(
+    requests: impl Iterator<Item = Result<DownloadRequest, DownloadError>> + Send + 'static,
+    mut config: BatchConfig,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+) -> Result<BatchHandle, DownloadError> {
+    // Reject malformed batch headers and settings now; a streamed request's own headers fail
+    // only its download.
+    check_headers(&[], &config.download)?;
+    config.download.check()?;
+
+    let client = Arc::new(blocking_client(&mut config)?);
+    let thread_count = config.worker_count();
+    // Normalize each URL as its request is read, stopping the stream at an invalid one in
+    // strict mode.
+    let strict_urls = config.strict_urls;
+    let requests = requests.enumerate().map(move |(index, request)| {
+        let mut request = request?;
+        normalize_request(index, &mut request, strict_urls)?;
+        Ok(request)
+    });
+    spawn_workers(
+        client,
+        WorkQueue::streaming(requests, config.download.overwrite),
+        None,
+        thread_count,
+        config,
+        callback,
+        None,
+    )
+}
+
+/// Starts `thread_count` workers that download the requests of `queue` until it is drained.
+///
+/// `state` records the progress of a batch with a state file. Every result is sent to `sink` as soon as its download ends when given, and otherwise
+/// collected by the returned handle.
+pub(crate) fn spawn_workers(
+    client: Arc<Client>,
+    queue: WorkQueue,
+    state: Option<BatchState>,
+    thread_count: usize,
+    config: BatchConfig,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+    sink: Option<mpsc::Sender<DownloadResult>>,
+) -> Result<BatchHandle, DownloadError> {
+    // A streamed batch counts its files as they are taken from the stream.
+    let total_files = queue.total();
+    let streaming = total_files.is_none();
+
+    // Every download of the batch is traced as a child of the batch's span.
+    let span = BatchSpan::new(total_files);
+
+    // Cancellation state, the global rate limit, and the aggregate progress counters, observed
+    // by every worker between chunks.
+    let context = Arc::new(Context {
+        limiter: RateLimiter::new(config.max_bytes_per_sec),
+        hosts: HostLimiter::new(config.max_connections_per_host),
+        batch_progress: config
+            .on_batch_progress
+            .map(|callback| BatchTracker::new(total_files.unwrap_or(0), callback)),
+        proxy: config.proxy,
+        cookies: config.cookies.clone(),
+        state,
+        refresh_url: config.refresh_url.clone(),
+        on_complete: config.on_complete.clone(),
+        ..Context::default()
+    });
+
+    // Events are also reported per request when the caller asked for it.
+    let on_file_event = config.on_file_event;
+
+    // Whether the first failure stops the batch, and whether downloads only run dry.
+    let fail_fast = config.fail_fast;
+    let dry_run = config.dry_run;
+
+    // Every finished download is also recorded in the caller's Prometheus metrics and JSON log.
+    #[cfg(feature = "prometheus")]
+    let prometheus = config.prometheus;
+    let json_log = config.json_log;
+
+    // How repeats of a request get its file, when the batch is deduplicated.
+    let link = config.dedup.map_or(DuplicateLink::Copy, |dedup| dedup.link);
+
+    // How many workers download at once, when the batch finds that out for itself.
+    let adaptive = config.adaptive_concurrency;
+
+    // Share the per-file settings between workers.
+    let config = Arc::new(config.download);
+
+    // Share the event callback between workers so every file reports through it.
+    let callback = Arc::new(callback);
+
+    // With adaptive concurrency every worker waits for a slot under the current limit before
+    // taking a job, and a controller moves the limit as the batch runs.
+    let limit = adaptive.map(|adaptive| {
+        let min = adaptive.min.max(1);
+        let adaptive = AdaptiveConcurrency {
+            min,
+            max: adaptive.max.max(min),
+            ..adaptive
+        };
+        let limit = Arc::new(ConcurrencyLimit::new(min));
+        spawn_controller(
+            &limit,
+            adaptive,
+            Arc::clone(&context.metrics),
+            Arc::clone(&callback),
+        );
+        limit
+    });
+
+    // The queue is shared between workers; the lock is only held while taking the next job.
+    let queue = Arc::new(Mutex::new(queue));
+
+    // The error that stopped a streamed batch, if any.
+    let failure = Arc::new(Mutex::new(None));
+
+    // Preallocate space for thread handles to avoid dynamic resizing later.
+    let mut workers = Vec::with_capacity(thread_count);
+
+    for _ in 0..thread_count {
+        // Clone the shared queue, client, and context handles for use inside the worker.
+        let queue = Arc::clone(&queue);
+        let client = Arc::clone(&client);
+        let callback = Arc::clone(&callback);
+        let config = Arc::clone(&config);
+        let context = Arc::clone(&context);
+        let on_file_event = on_file_event.clone();
+        let failure = Arc::clone(&failure);
+        let sink = sink.clone();
+        let batch_span = span.clone();
+        let limit = limit.clone();
+        #[cfg(feature = "prometheus")]
+        let prometheus = prometheus.clone();
+        let json_log = json_log.clone();
+
+        // Spawn a long-lived worker that keeps downloading until the queue is empty.
+        let worker = std::thread::spawn(move || {
+            let mut results = Vec::new();
+
+            loop {
+                // Wait for a slot under the batch's concurrency limit, if it has one, and
+                // hold it until the job is done.
+                let _permit = limit.as_ref().map(|limit| limit.acquire(&context.control));
+
+                // Take the next job and a slot on its host, releasing the lock before the
+                // download starts.
+                let job = queue
+                    .lock()
+                    .unwrap()
+                    .next(context.hosts.as_ref(), &context.control);
+                let (index, request, slot, queued_at) = match job {
+                    Some(Ok(job)) => job,
+                    Some(Err(e)) => {
+                        // A broken stream stops the whole batch.
+                        *failure.lock().unwrap() = Some(e);
+                        context.control.cancel();
+                        break;
+                    }
+                    None => break,
+                };
+                if streaming {
+                    if let Some(tracker) = &context.batch_progress {
+                        tracker.file_queued();
+                    }
+                }
+                // The worker counts as active until every request of this job has ended.
+                let _busy = context.metrics.worker_busy();
+
+                // Forward the events of the request at an index, with the index as the id of
+                // their progress and the time it was queued in their timings, to the batch
+                // callback and, tagged with the index, to the per-file callback. The timings
+                // of the final event are kept for the request's result.
+                let timings = Mutex::new(HashMap::new());
+                let notify = |index: usize, queued: Duration, event: &DownloadEvent| {
+                    let event = &*event.for_request(index, queued);
+                    if let Some(ended) = event.timings() {
+                        timings.lock().unwrap().insert(index, ended);
+                    }
+                    if let Some(state) = &context.state {
+                        state.record(index, event);
+                    }
+                    callback(event);
+                    if let Some(on_file_event) = &on_file_event {
+                        on_file_event(index, event);
+                    }
+                };
+
+                // A redirect to another host moves the download to that host's slots before
+                // the next request is sent.
+                let slot = Mutex::new(slot);
+                let started = Instant::now();
+                let queued = started.saturating_duration_since(queued_at);
+                let span = batch_span.download(index, &request.url, &request.destination);
+                context.metrics.request_started();
+                let report = |event: &DownloadEvent| {
+                    if let DownloadEvent::Redirected { to, .. }
+                    | DownloadEvent::MirrorFailover { to, .. }
+                    | DownloadEvent::UrlRefreshed { to, .. } = event
+                    {
+                        HostSlot::follow(&mut slot.lock().unwrap(), to, &context.control);
+                    }
+                    span.record(event);
+                    notify(index, queued, event);
+                };
+
+                // Download the file to the destination given by the request, unless an earlier
+                // run with the same state file already did. Once the batch is cancelled this
+                // returns immediately, so the rest of the queue drains quickly.
+                let outcome = match completed_earlier(&context, index) {
+                    Some(path) => {
+                        report(&DownloadEvent::Skipped { path: path.clone() });
+                        DownloadOutcome::Skipped { path }
+                    }
+                    None if dry_run => span.in_scope(|| {
+                        plan(
+                            &client,
+                            &request.url,
+                            &request.destination,
+                            &request.effective_config(&config),
+                            context.cookies.as_ref(),
+                        )
+                    }),
+                    None => DownloadOutcome::from_result(span.in_scope(|| {
+                        download_with_context(
+                            &client,
+                            &request.url,
+                            &request.mirrors,
+                            &request.destination,
+                            &request.effective_config(&config),
+                            &context,
+                            &report,
+                        )
+                    })),
+                };
+                drop(slot);
+
+                // Requests for the same file get it from this download. If it failed, each
+                // of them is downloaded on its own instead.
+                let repeats = queue.lock().unwrap().take_repeats(index);
+                let mut finished = Vec::with_capacity(1 + repeats.len());
+                if matches!(outcome, DownloadOutcome::Failed { .. }) {
+                    let mut queue = queue.lock().unwrap();
+                    for (index, request, queued_at) in repeats {
+                        queue.requeue(index, request, queued_at);
+                    }
+                } else {
+                    for (repeat, request, queued_at) in repeats {
+                        let started = Instant::now();
+                        let queued = started.saturating_duration_since(queued_at);
+                        let span = batch_span.download(repeat, &request.url, &request.destination);
+                        context.metrics.request_started();
+                        let report = |event: &DownloadEvent| {
+                            span.record(event);
+                            notify(repeat, queued, event);
+                        };
+                        let outcome = match completed_earlier(&context, repeat) {
+                            Some(path) => {
+                                report(&DownloadEvent::Skipped { path: path.clone() });
+                                DownloadOutcome::Skipped { path }
+                            }
+                            None => span.in_scope(|| {
+                                fill(
+                                    &outcome,
+                                    &request,
+                                    &request.effective_config(&config),
+                                    link,
+                                    &report,
+                                )
+                            }),
+                        };
+                        // A copy counts as a download that finished instantly.
+                        if let (Some(tracker), DownloadOutcome::Completed { bytes, .. }) =
+                            (&context.batch_progress, &outcome)
+                        {
+                            let mut tally = FileTally::new(Some(tracker));
+                            tally.start(Some(*bytes), *bytes);
+                            tally.commit();
+                        }
+                        finished.push((repeat, request, outcome, started, queued));
+                    }
+                }
+                finished.insert(0, (index, request, outcome, started, queued));
+                let mut timings = timings.into_inner().unwrap();
+
+                for (index, request, outcome, started, queued) in finished {
+                    // Archives are unpacked on this worker, as part of the download's duration.
+                    #[cfg(feature = "extract")]
+                    let outcome = extract_completed(outcome, &request);
+                    let outcome = outcome.or_abort(&context.control, fail_fast);
+
+                    // Count the finished file towards the batch totals.
+                    if let Some(tracker) = &context.batch_progress {
+                        tracker.file_ended(&outcome);
+                    }
+                    context.metrics.request_ended(&outcome);
+
+                    let result = DownloadResult {
+                        id: index as u64,
+                        url: request.url,
+                        destination: request.destination,
+                        outcome,
+                        duration: started.elapsed(),
+                        // Files kept or cancelled send no final timings of their own.
+                        timings: timings.remove(&index).unwrap_or(DownloadTimings {
+                            queued,
+                            total: started.elapsed(),
+                            ..DownloadTimings::default()
+                        }),
+                    };
+                    #[cfg(feature = "prometheus")]
+                    if let Some(prometheus) = &prometheus {
+                        prometheus.observe(&result);
+                    }
+                    if let Some(json_log) = &json_log {
+                        json_log.record(&result);
+                    }
+                    match &sink {
+                        // Nobody may be listening anymore, in which case the result is dropped.
+                        Some(sink) => {
+                            let _ = sink.send(result);
+                        }
+                        None => results.push((index, result)),
+                    }
+                }
+            }
+
+            // Save the last updates once this worker is done.
+            if let Some(state) = &context.state {
+                state.flush();
+            }
+
+            results
+        });
+
+        // Store the thread handle so it can be joined later.
+        workers.push(worker);
+    }
+
+    Ok(BatchHandle::new(
+        Arc::clone(&context.control),
+        workers,
+        failure,
+        queue,
+        Arc::clone(&context.metrics),
+    ))
+}
+
+/// Returns where the request at `index` was saved if an earlier run with the batch's state
+/// file completed it.
+fn completed_earlier(context: &Context, index: usize) -> Option<PathBuf> {
+    context.state.as_ref()?.completed(index)
+}
+
+/// Returns the size of every request's file, asking the servers of those without an
+/// [`DownloadRequest::expected_size`] on up to `thread_count` threads.
+fn expected_sizes(
+    client: &Client,
+    requests: &[DownloadRequest],
+    config: &DownloadConfig,
+    cookies: Option<&CookieJar>,
+    thread_count: usize,
+) -> Vec<Option<u64>> {
+    let sizes = Mutex::new(vec![None; requests.len()]);
+    let next = AtomicUsize::new(0);
+
+    std::thread::scope(|scope| {
+        for _ in 0..thread_count {
+            scope.spawn(|| loop {
+                // Claim the next request; the thread is done once every request is claimed.
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(request) = requests.get(index) else {
+                    break;
+                };
+                let size = request.expected_size.or_else(|| {
+                    let config = request.effective_config(config);
+                    probe_size(client, &request.url, &config, cookies)
+                });
+                sizes.lock().unwrap()[index] = size;
+            });
+        }
+    });
+
+    sizes.into_inner().unwrap()
+}
@@ -0,0 +1,347 @@
+use crate::batch::download_batch_requests;
+use crate::batch_progress::BatchTracker;
+use crate::client::async_client;
+use crate::config::BatchConfig;
+use crate::context::Context;
+use crate::disk_space::check_disk_space;
+use crate::download_async::{download_with_context_async, probe_size};
+use crate::error::DownloadError;
+use crate::event::DownloadEvent;
+#[cfg(feature = "extract")]
+use crate::extract::extract_completed_async;
+use crate::headers::check_headers;
+use crate::host_limit::{host_key, HostLimiter};
+use crate::rate_limit::RateLimiter;
+use crate::request::{resolve_destinations, DownloadRequest};
+use crate::result::{DownloadOutcome, DownloadResult};
+use crate::spans::BatchSpan;
+use crate::state::BatchState;
+use crate::summary::finish_batch;
+use crate::timing::DownloadTimings;
+use crate::url_check::normalize_requests;
+use futures_util::future::join_all;
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::path::Path;
+use std::time::Instant;
+use tokio::sync::Semaphore;
+
+/// Downloads a batch of files concurrently without blocking.
+///
+/// Concurrency is bounded by a [`Semaphore`] rather than OS threads, so all downloads run as
+/// futures on the caller's tokio runtime.
+///
+/// # Arguments
+///
+/// * `urls` - A vector of string slices containing the URLs to download.
+/// * `callback` - A function or closure that receives the [`DownloadEvent`]s of every file in the batch.
+///
+/// # Returns
+///
+/// * `Ok` with one [`DownloadResult`] per URL, in the order the URLs were given. A failed
+///   download is reported in its result and does not fail the batch.
+/// * `Err` if the batch could not be started, or with a [`crate::BatchFailed`] if a download
+///   failed and [`BatchConfig::continue_on_error`] is turned off.
+pub async fn download_batch_async(
+    urls: Vec<&str>,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+) -> Result<Vec<DownloadResult>, DownloadError> {
+    download_batch_async_with_config(urls, BatchConfig::default(), callback).await
+}
+
+/// Downloads a batch of files concurrently without blocking, using explicit settings.
+///
+/// # Arguments
+///
+/// * `urls` - A vector of string slices containing the URLs to download.
+/// * `config` - The batch settings; `concurrency` sets the number of semaphore permits.
+/// * `callback` - A function or closure that receives the [`DownloadEvent`]s of every file in the batch.
+///
+/// # Returns
+///
+/// * `Ok` with one [`DownloadResult`] per URL, in the order the URLs were given. A failed
+///   download is reported in its result and does not fail the batch.
+/// * `Err` if the batch could not be started, or with a [`crate::BatchFailed`] if a download
+///   failed and [`BatchConfig::continue_on_error`] is turned off.
+pub async fn download_batch_async_with_config(
+    urls: Vec<&str>,
+    config: BatchConfig,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+) -> Result<Vec<DownloadResult>, DownloadError> {
+    // Save every URL under a unique, index-based file name in the working directory.
+    let requests = urls
+        .into_iter()
+        .enumerate()
+        .map(|(index, url)| DownloadRequest::new(url, format!("./test-{}.svg", index + 1)))
+        .collect();
+
+    download_batch_requests_async(requests, config, callback).await
+}
+
+/// Downloads a batch of files concurrently into a directory without blocking, naming each file automatically.
+///
+/// This is the async counterpart of [`crate::download_batch_to_dir`].
+///
+/// # Arguments
+///
+/// * `urls` - A vector of string slices containing the URLs to download.
+/// * `output_dir` - The directory all files are saved in.
+/// * `config` - The batch settings; `concurrency` sets the number of semaphore permits.
+/// * `callback` - A function or closure that receives the [`DownloadEvent`]s of every file in the batch.
+///
+/// # Returns
+///
+/// * `Ok` with one [`DownloadResult`] per URL, in the order the URLs were given.
+/// * `Err` with a [`crate::DuplicateDestination`] if two URLs map to the same file name,
+///   unless [`crate::OverwritePolicy::RenameWithSuffix`] numbers them instead.
+/// * `Err` if the batch could not be started for any other reason.
+pub async fn download_batch_to_dir_async(
+    urls: Vec<&str>,
+    output_dir: impl AsRef<Path>,
+    mut config: BatchConfig,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+) -> Result<Vec<DownloadResult>, DownloadError> {
+    let output_dir = output_dir.as_ref();
+
+    // Name every file after its URL, falling back to a 1-based index.
+    let file_names = config.download.file_names;
+    let requests = urls
+        .into_iter()
+        .enumerate()
+        .map(|(index, url)| {
+            DownloadRequest::in_directory_with(url, output_dir, index + 1, &file_names)
+        })
+        .collect();
+
+    // Let servers override the derived names.
+    config.download.name_from_content_disposition = true;
+
+    download_batch_requests_async(requests, config, callback).await
+}
+
+/// Downloads a batch of requests concurrently without blocking, saving each URL to its own destination.
+///
+/// # Arguments
+///
+/// * `requests` - The files to download and where to save them.
+/// * `config` - The batch settings; `concurrency` sets the number of semaphore permits.
+/// * `callback` - A function or closure that receives the [`DownloadEvent`]s of every file in the batch.
+///
+/// # Returns
+///
+/// * `Ok` with one [`DownloadResult`] per request, in the order the requests were given. A
+///   failed download is reported in its result and, unless
+///   [`BatchConfig::continue_on_error`] is turned off, does not fail the batch.
+/// * `Err` with a [`crate::BatchFailed`] listing every result if a download failed in strict
+///   mode. The other downloads still run to completion.
+/// * `Err` with a [`crate::DuplicateDestination`] if two requests share a destination.
+/// * `Err` with a [`crate::InvalidHeader`] if a configured header is malformed.
+/// * `Err` with an [`crate::InvalidConfig`] if a setting can't be used.
+pub async fn download_batch_requests_async(
+    mut requests: Vec<DownloadRequest>,
+    mut config: BatchConfig,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+) -> Result<Vec<DownloadResult>, DownloadError> {
+    // The checks of a dry run block, so the blocking API does them on tokio's blocking threads.
+    if config.dry_run {
+        return tokio::task::spawn_blocking(move || {
+            download_batch_requests(requests, config, callback)
+        })
+        .await
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic.into_panic()));
+    }
+
+    // Parse every URL before anything starts, failing the batch on an invalid one in strict mode.
+    normalize_requests(&mut requests, config.strict_urls)?;
+
+    // Refuse to start if two requests would clobber each other's files.
+    resolve_destinations(&mut requests, config.download.overwrite)?;
+
+    // Reject malformed headers and settings now rather than failing every download that uses
+    // them.
+    check_headers(&requests, &config.download)?;
+    config.download.check()?;
+
+    // Build a single client shared by every download so connections to the same host are pooled.
+    let client = async_client(&mut config)?;
+
+    // Permits limit how many downloads can be streaming at the same time.
+    let semaphore = Semaphore::new(config.concurrency.max(1));
+
+    // Refuse to start a batch that can't fit on its destination filesystems, asking the
+    // servers for the sizes the requests don't carry.
+    if config.preflight_disk_space {
+        let sizes = join_all(requests.iter().map(|request| async {
+            if request.expected_size.is_some() {
+                return request.expected_size;
+            }
+            let _permit = semaphore
+                .acquire()
+                .await
+                .expect("download semaphore is never closed");
+            let cookies = config.cookies.as_ref();
+            let config = request.effective_config(&config.download);
+            probe_size(&client, &request.url, &config, cookies).await
+        }))
+        .await;
+        check_disk_space(
+            requests
+                .iter()
+                .zip(sizes)
+                .filter_map(|(request, size)| Some((request.destination.as_path(), size?))),
+        )?;
+    }
+
+    // Pick up where an earlier run with the same state file stopped, keeping partial files
+    // so the next run can do the same.
+    let state = match &config.state_file {
+        Some(path) => Some(BatchState::open(path, &requests)?),
+        None => None,
+    };
+    if state.is_some() {
+        config.download.resume = true;
+    }
+
+    // The global rate limit and aggregate progress counters shared by every download in the batch.
+    let context = Context {
+        limiter: RateLimiter::new(config.max_bytes_per_sec),
+        hosts: HostLimiter::new(config.max_connections_per_host),
+        batch_progress: config
+            .on_batch_progress
+            .clone()
+            .map(|callback| BatchTracker::new(requests.len(), callback)),
+        proxy: config.proxy.clone(),
+        cookies: config.cookies.clone(),
+        refresh_url: config.refresh_url.clone(),
+        on_complete: config.on_complete.clone(),
+        state,
+        ..Context::default()
+    };
+
+    // Create the futures most urgent first. The semaphore hands out permits in the order they
+    // were asked for, so higher priorities start first and ties keep the batch order.
+    let mut requests: Vec<_> = requests.into_iter().enumerate().collect();
+    requests.sort_by_key(|(_, request)| Reverse(request.priority));
+
+    // Every request of the batch is queued from the start, and traced as a child of the
+    // batch's span.
+    let queued_at = Instant::now();
+    let span = BatchSpan::new(Some(requests.len()));
+
+    // Build one future per request, each waiting for a permit before it starts downloading.
+    let downloads = requests.into_iter().map(|(index, request)| {
+        let client = &client;
+        let semaphore = &semaphore;
+        let on_file_event = &config.on_file_event;
+        let fail_fast = config.fail_fast;
+        #[cfg(feature = "prometheus")]
+        let prometheus = &config.prometheus;
+        let json_log = &config.json_log;
+        let config = &config.download;
+        let context = &context;
+        let callback = &callback;
+        let span = span.download(index, &request.url, &request.destination);
+        async move {
+            // Wait for a slot on the request's host first, so downloads held back by a busy
+            // host don't take permits away from other hosts.
+            let _slot = match (&context.hosts, host_key(&request.url)) {
+                (Some(hosts), Some(host)) => Some(hosts.acquire_async(&host).await),
+                _ => None,
+            };
+
+            // Hold the permit for the whole download so it is released only when the file is done.
+            // The semaphore is never closed, so acquiring a permit cannot fail.
+            let _permit = semaphore
+                .acquire()
+                .await
+                .expect("download semaphore is never closed");
+
+            // Forward this file's events, with the request's index as the id of their progress
+            // and the time it was queued in their timings, to the batch callback and, tagged with
+            // the index, to the per-file callback. The timings of the final event are kept for
+            // the result.
+            let started = Instant::now();
+            let queued = started.saturating_duration_since(queued_at);
+            let timings = Cell::new(None);
+            let report = |event: &DownloadEvent| {
+                let event = &*event.for_request(index, queued);
+                span.record(event);
+                if let Some(ended) = event.timings() {
+                    timings.set(Some(ended));
+                }
+                if let Some(state) = &context.state {
+                    state.record(index, event);
+                }
+                callback(event);
+                if let Some(on_file_event) = on_file_event {
+                    on_file_event(index, event);
+                }
+            };
+
+            // Download the file to the destination given by the request, unless an earlier
+            // run with the same state file already did.
+            let completed = context
+                .state
+                .as_ref()
+                .and_then(|state| state.completed(index));
+            let outcome = match completed {
+                Some(path) => {
+                    report(&DownloadEvent::Skipped { path: path.clone() });
+                    DownloadOutcome::Skipped { path }
+                }
+                None => DownloadOutcome::from_result(
+                    span.instrument(download_with_context_async(
+                        client,
+                        &request.url,
+                        &request.mirrors,
+                        &request.destination,
+                        &request.effective_config(config),
+                        context,
+                        &report,
+                    ))
+                    .await,
+                ),
+            };
+            // Archives are unpacked as part of the download's duration.
+            #[cfg(feature = "extract")]
+            let outcome = extract_completed_async(outcome, &request).await;
+            let outcome = outcome.or_abort(&context.control, fail_fast);
+
+            // Count the finished file towards the batch totals.
+            if let Some(tracker) = &context.batch_progress {
+                tracker.file_ended(&outcome);
+            }
+
+            let result = DownloadResult {
+                id: index as u64,
+                url: request.url,
+                destination: request.destination,
+                outcome,
+                duration: started.elapsed(),
+                // Files kept or cancelled send no final timings of their own.
+                timings: timings.get().unwrap_or(DownloadTimings {
+                    queued,
+                    total: started.elapsed(),
+                    ..DownloadTimings::default()
+                }),
+            };
+            #[cfg(feature = "prometheus")]
+            if let Some(prometheus) = prometheus {
+                prometheus.observe(&result);
+            }
+            if let Some(json_log) = json_log {
+                json_log.record(&result);
+            }
+            (index, result)
+        }
+    });
+
+    // Drive all downloads to completion, then put the results back in submission order.
+    let mut results = join_all(downloads).await;
+    if let Some(state) = &context.state {
+        state.flush();
+    }
+    results.sort_by_key(|(index, _)| *index);
+    let results = results.into_iter().map(|(_, result)| result).collect();
+    finish_batch(results, config.continue_on_error)
+}
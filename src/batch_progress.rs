@@ -0,0 +1,192 @@
+use crate::result::DownloadOutcome;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Aggregate progress of a whole batch, passed to [`crate::BatchConfig::on_batch_progress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BatchProgress {
+    /// Number of requests in the batch. For a batch streamed with
+    /// [`crate::download_batch_stream`], the number of requests taken from the stream so far.
+    pub total_files: usize,
+    /// Number of files downloaded successfully so far.
+    pub files_completed: usize,
+    /// Number of files that failed so far.
+    pub files_failed: usize,
+    /// Number of files skipped so far because an existing file was kept, either by the
+    /// overwrite policy or because the server reported it as not modified.
+    pub files_skipped: usize,
+    /// Bytes downloaded across all workers so far.
+    pub bytes_downloaded: u64,
+    /// Sum of the sizes reported by the servers of every download started so far.
+    pub total_bytes: u64,
+    /// `true` when at least one started download did not report its size, so `total_bytes`
+    /// underestimates the real total.
+    pub has_unknown_sizes: bool,
+}
+
+/// Callback receiving the aggregate progress of a batch.
+pub type BatchProgressCallback = Arc<dyn Fn(&BatchProgress) + Send + Sync>;
+
+/// Lock-free counters behind [`BatchProgress`], updated from every worker's chunk loop.
+pub(crate) struct BatchTracker {
+    total_files: AtomicUsize,
+    files_completed: AtomicUsize,
+    files_failed: AtomicUsize,
+    files_skipped: AtomicUsize,
+    bytes_downloaded: AtomicU64,
+    total_bytes: AtomicU64,
+    unknown_sizes: AtomicUsize,
+    callback: BatchProgressCallback,
+}
+
+impl fmt::Debug for BatchTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BatchTracker")
+            .field("progress", &self.snapshot())
+            .finish_non_exhaustive()
+    }
+}
+
+impl BatchTracker {
+    /// Creates a tracker for a batch of `total_files` requests.
+    pub(crate) fn new(total_files: usize, callback: BatchProgressCallback) -> Self {
+        Self {
+            total_files: AtomicUsize::new(total_files),
+            files_completed: AtomicUsize::new(0),
+            files_failed: AtomicUsize::new(0),
+            files_skipped: AtomicUsize::new(0),
+            bytes_downloaded: AtomicU64::new(0),
+            total_bytes: AtomicU64::new(0),
+            unknown_sizes: AtomicUsize::new(0),
+            callback,
+        }
+    }
+
+    /// Reads the current counters into a [`BatchProgress`].
+    pub(crate) fn snapshot(&self) -> BatchProgress {
+        BatchProgress {
+            total_files: self.total_files.load(Ordering::Relaxed),
+            files_completed: self.files_completed.load(Ordering::Relaxed),
+            files_failed: self.files_failed.load(Ordering::Relaxed),
+            files_skipped: self.files_skipped.load(Ordering::Relaxed),
+            bytes_downloaded: self.bytes_downloaded.load(Ordering::Relaxed),
+            total_bytes: self.total_bytes.load(Ordering::Relaxed),
+            has_unknown_sizes: self.unknown_sizes.load(Ordering::Relaxed) > 0,
+        }
+    }
+
+    /// Counts a request taken from a streamed batch.
+    pub(crate) fn file_queued(&self) {
+        self.total_files.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Invokes the batch callback with the current counters.
+    pub(crate) fn report(&self) {
+        (self.callback)(&self.snapshot());
+    }
+
+    /// Records that a file finished, successfully or not, and reports the new totals.
+    pub(crate) fn file_finished(&self, success: bool) {
+        let counter = if success {
+            &self.files_completed
+        } else {
+            &self.files_failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.report();
+    }
+
+    /// Records that a file was skipped because an existing file was kept.
+    pub(crate) fn file_skipped(&self) {
+        self.files_skipped.fetch_add(1, Ordering::Relaxed);
+        self.report();
+    }
+
+    /// Counts a finished file according to how it ended. Cancelled and aborted files are not
+    /// counted.
+    pub(crate) fn file_ended(&self, outcome: &DownloadOutcome) {
+        match outcome {
+            DownloadOutcome::Completed { .. } | DownloadOutcome::DryRun { .. } => {
+                self.file_finished(true)
+            }
+            DownloadOutcome::Failed { .. } => self.file_finished(false),
+            DownloadOutcome::Skipped { .. } | DownloadOutcome::NotModified { .. } => {
+                self.file_skipped()
+            }
+            DownloadOutcome::Cancelled | DownloadOutcome::Aborted => {}
+        }
+    }
+}
+
+/// One download attempt's contribution to a [`BatchTracker`].
+///
+/// Everything counted by an attempt is subtracted again when it is dropped without
+/// [`FileTally::commit`], so a retried download does not count its bytes twice.
+#[derive(Debug)]
+pub(crate) struct FileTally<'a> {
+    tracker: Option<&'a BatchTracker>,
+    bytes: u64,
+    total: Option<Option<u64>>,
+    committed: bool,
+}
+
+impl<'a> FileTally<'a> {
+    /// Creates an empty tally, which does nothing when there is no tracker.
+    pub(crate) fn new(tracker: Option<&'a BatchTracker>) -> Self {
+        Self {
+            tracker,
+            bytes: 0,
+            total: None,
+            committed: false,
+        }
+    }
+
+    /// Records the size of the file and the bytes that were already on disk.
+    pub(crate) fn start(&mut self, total: Option<u64>, already_downloaded: u64) {
+        let Some(tracker) = self.tracker else {
+            return;
+        };
+        match total {
+            Some(total) => tracker.total_bytes.fetch_add(total, Ordering::Relaxed),
+            None => tracker.unknown_sizes.fetch_add(1, Ordering::Relaxed) as u64,
+        };
+        self.total = Some(total);
+        self.add(already_downloaded);
+    }
+
+    /// Records newly downloaded bytes and reports the new totals.
+    pub(crate) fn add(&mut self, bytes: u64) {
+        let Some(tracker) = self.tracker else {
+            return;
+        };
+        tracker.bytes_downloaded.fetch_add(bytes, Ordering::Relaxed);
+        self.bytes += bytes;
+        tracker.report();
+    }
+
+    /// Keeps this attempt's contribution once the file has been fully downloaded.
+    pub(crate) fn commit(mut self) {
+        self.committed = true;
+    }
+}
+
+impl Drop for FileTally<'_> {
+    fn drop(&mut self) {
+        let Some(tracker) = self.tracker.filter(|_| !self.committed) else {
+            return;
+        };
+        tracker
+            .bytes_downloaded
+            .fetch_sub(self.bytes, Ordering::Relaxed);
+        match self.total {
+            Some(Some(total)) => {
+                tracker.total_bytes.fetch_sub(total, Ordering::Relaxed);
+            }
+            Some(None) => {
+                tracker.unknown_sizes.fetch_sub(1, Ordering::Relaxed);
+            }
+            None => {}
+        }
+    }
+}
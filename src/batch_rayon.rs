@@ -0,0 +1,193 @@
+use crate::batch_progress::BatchTracker;
+use crate::client::blocking_client;
+use crate::config::BatchConfig;
+use crate::context::Context;
+use crate::download::download_with_context;
+use crate::dry_run::plan;
+use crate::error::DownloadError;
+use crate::event::DownloadEvent;
+#[cfg(feature = "extract")]
+use crate::extract::extract_completed;
+use crate::headers::check_headers;
+use crate::host_limit::{host_key, HostLimiter, HostSlot};
+use crate::rate_limit::RateLimiter;
+use crate::request::{resolve_destinations, DownloadRequest};
+use crate::result::{DownloadOutcome, DownloadResult};
+use crate::spans::BatchSpan;
+use crate::summary::finish_batch;
+use crate::timing::DownloadTimings;
+use crate::url_check::normalize_requests;
+use rayon::prelude::*;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Downloads a batch of requests on a rayon thread pool instead of the crate's own workers.
+///
+/// Every request becomes a task of the pool the call runs in: the global pool, or the pool of
+/// an enclosing [`rayon::ThreadPool::install`]. At most as many files download at the same time
+/// as that pool has threads, so [`BatchConfig::concurrency`] and
+/// [`BatchConfig::adaptive_concurrency`] don't apply. Downloads block their thread on the
+/// network until they end, and so do requests waiting for a slot under
+/// [`BatchConfig::max_connections_per_host`], which bounds how many threads a single host
+/// ties up. Other parallel work on the same pool waits for free threads meanwhile. Install a
+/// pool of its own for the batch if that matters.
+///
+/// Retries, rate limits, callbacks and metrics work as in [`crate::start_batch`], but request
+/// priorities don't, since rayon decides the order tasks run in.
+/// [`BatchConfig::preflight`], [`BatchConfig::preflight_disk_space`],
+/// [`BatchConfig::state_file`] and [`BatchConfig::dedup`] are ignored, while
+/// [`BatchConfig::dry_run`] is honoured. Only available with the
+/// `rayon` feature.
+///
+/// # Arguments
+///
+/// * `requests` - The files to download and where to save them.
+/// * `config` - The batch settings, including the per-file [`crate::DownloadConfig`].
+/// * `callback` - A function or closure that receives the [`DownloadEvent`]s of every file in the batch.
+///
+/// # Returns
+///
+/// * `Ok` with one [`DownloadResult`] per request, in the order the requests were given.
+/// * `Err` with a [`crate::DuplicateDestination`], an [`crate::InvalidHeader`] or an
+///   [`crate::InvalidConfig`] before anything is downloaded, or with a [`crate::BatchFailed`] if a download failed and
+///   [`BatchConfig::continue_on_error`] is turned off.
+pub fn download_batch_rayon(
+    mut requests: Vec<DownloadRequest>,
+    mut config: BatchConfig,
+    callback: impl Fn(&DownloadEvent) + Send + Sync,
+) -> Result<Vec<DownloadResult>, DownloadError> {
+    // Parse every URL before anything starts, failing the batch on an invalid one in strict mode.
+    normalize_requests(&mut requests, config.strict_urls)?;
+
+    // Refuse to start if two requests would clobber each other's files.
+    resolve_destinations(&mut requests, config.download.overwrite)?;
+
+    // Reject malformed headers and settings now rather than failing every download that uses
+    // them.
+    check_headers(&requests, &config.download)?;
+    config.download.check()?;
+
+    // Build a single client shared by every download so connections to the same host are pooled.
+    let client = blocking_client(&mut config)?;
+
+    // The global rate limit, per-host limits and aggregate progress counters shared by every
+    // download in the batch.
+    let context = Context {
+        limiter: RateLimiter::new(config.max_bytes_per_sec),
+        hosts: HostLimiter::new(config.max_connections_per_host),
+        batch_progress: config
+            .on_batch_progress
+            .clone()
+            .map(|callback| BatchTracker::new(requests.len(), callback)),
+        proxy: config.proxy.clone(),
+        cookies: config.cookies.clone(),
+        refresh_url: config.refresh_url.clone(),
+        on_complete: config.on_complete.clone(),
+        ..Context::default()
+    };
+
+    // Every request of the batch is queued from the start, and traced as a child of the
+    // batch's span.
+    let queued_at = Instant::now();
+    let span = BatchSpan::new(Some(requests.len()));
+
+    let results = requests
+        .into_par_iter()
+        .enumerate()
+        .map(|(index, request)| {
+            // Wait for a slot on the request's host, blocking this thread like the download
+            // itself does. A redirect to another host moves the download to that host's slots.
+            let slot = match (&context.hosts, host_key(&request.url)) {
+                (Some(hosts), Some(host)) => hosts.acquire(&host, &context.control),
+                _ => None,
+            };
+            let slot = Mutex::new(slot);
+            let _busy = context.metrics.worker_busy();
+
+            // Forward this file's events, with the request's index as the id of their progress
+            // and the time it was queued in their timings, to the batch callback and, tagged
+            // with the index, to the per-file callback. The timings of the final event are kept
+            // for the result.
+            let started = Instant::now();
+            let queued = started.saturating_duration_since(queued_at);
+            let timings = Mutex::new(None);
+            let span = span.download(index, &request.url, &request.destination);
+            context.metrics.request_started();
+            let report = |event: &DownloadEvent| {
+                if let DownloadEvent::Redirected { to, .. }
+                | DownloadEvent::MirrorFailover { to, .. }
+                | DownloadEvent::UrlRefreshed { to, .. } = event
+                {
+                    HostSlot::follow(&mut slot.lock().unwrap(), to, &context.control);
+                }
+                span.record(event);
+                let event = &*event.for_request(index, queued);
+                if let Some(ended) = event.timings() {
+                    *timings.lock().unwrap() = Some(ended);
+                }
+                callback(event);
+                if let Some(on_file_event) = &config.on_file_event {
+                    on_file_event(index, event);
+                }
+            };
+
+            // Download the file to the destination given by the request, or only check it in a
+            // dry run.
+            let download = &request.effective_config(&config.download);
+            let outcome = span.in_scope(|| {
+                if config.dry_run {
+                    return plan(
+                        &client,
+                        &request.url,
+                        &request.destination,
+                        download,
+                        context.cookies.as_ref(),
+                    );
+                }
+                DownloadOutcome::from_result(download_with_context(
+                    &client,
+                    &request.url,
+                    &request.mirrors,
+                    &request.destination,
+                    download,
+                    &context,
+                    &report,
+                ))
+            });
+            drop(slot);
+            // Archives are unpacked on this thread, as part of the download's duration.
+            #[cfg(feature = "extract")]
+            let outcome = extract_completed(outcome, &request);
+            let outcome = outcome.or_abort(&context.control, config.fail_fast);
+
+            // Count the finished file towards the batch totals.
+            if let Some(tracker) = &context.batch_progress {
+                tracker.file_ended(&outcome);
+            }
+            context.metrics.request_ended(&outcome);
+
+            let result = DownloadResult {
+                id: index as u64,
+                url: request.url,
+                destination: request.destination,
+                outcome,
+                duration: started.elapsed(),
+                // Files kept or cancelled send no final timings of their own.
+                timings: timings.into_inner().unwrap().unwrap_or(DownloadTimings {
+                    queued,
+                    total: started.elapsed(),
+                    ..DownloadTimings::default()
+                }),
+            };
+            #[cfg(feature = "prometheus")]
+            if let Some(prometheus) = &config.prometheus {
+                prometheus.observe(&result);
+            }
+            if let Some(json_log) = &config.json_log {
+                json_log.record(&result);
+            }
+            result
+        })
+        .collect();
+    finish_batch(results, config.continue_on_error)
+}
@@ -0,0 +1,152 @@
+use crate::error::DownloadError;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+
+/// An expected digest of a downloaded file, as a hex string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Checksum {
+    /// A SHA-256 digest.
+    Sha256(String),
+    /// An MD5 digest.
+    Md5(String),
+}
+
+impl Checksum {
+    /// Returns the expected digest as given, in hex.
+    pub fn expected(&self) -> &str {
+        match self {
+            Checksum::Sha256(hex) | Checksum::Md5(hex) => hex,
+        }
+    }
+
+    /// Returns `true` if `actual` matches the expected digest, ignoring case.
+    pub fn matches(&self, actual: &str) -> bool {
+        self.expected().trim().eq_ignore_ascii_case(actual)
+    }
+}
+
+/// Returned when a downloaded file does not match its expected [`Checksum`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    /// The digest the file was expected to have.
+    pub expected: String,
+    /// The digest of the bytes that were actually downloaded.
+    pub actual: String,
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "checksum mismatch: expected {}, got {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl Error for ChecksumMismatch {}
+
+/// Compares the file at `path` against `checksum`.
+///
+/// A SHA-256 digest already computed while streaming can be passed as `streamed_sha256` to
+/// skip re-reading the file. On a mismatch the file is deleted and a [`ChecksumMismatch`] is
+/// returned.
+pub(crate) fn verify_file(
+    path: &Path,
+    checksum: &Checksum,
+    streamed_sha256: Option<&str>,
+) -> Result<(), DownloadError> {
+    let actual = match (checksum, streamed_sha256) {
+        (Checksum::Sha256(_), Some(digest)) => digest.to_string(),
+        (Checksum::Sha256(_), None) => {
+            hash_file::<Sha256>(path).map_err(DownloadError::io(path))?
+        }
+        (Checksum::Md5(_), _) => hash_file::<Md5>(path).map_err(DownloadError::io(path))?,
+    };
+
+    if !checksum.matches(&actual) {
+        // Never leave a corrupted file behind at the destination.
+        std::fs::remove_file(path).map_err(DownloadError::io(path))?;
+        return Err(ChecksumMismatch {
+            expected: checksum.expected().to_string(),
+            actual,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Hashes a body for its [`Checksum`] as it streams past, when there is no file to hash
+/// once it is complete.
+pub(crate) enum BodyHasher {
+    /// Computes a SHA-256 digest.
+    Sha256(Sha256),
+    /// Computes an MD5 digest.
+    Md5(Md5),
+}
+
+impl BodyHasher {
+    /// Starts hashing a body for `checksum`.
+    pub(crate) fn new(checksum: &Checksum) -> Self {
+        match checksum {
+            Checksum::Sha256(_) => BodyHasher::Sha256(Sha256::new()),
+            Checksum::Md5(_) => BodyHasher::Md5(Md5::new()),
+        }
+    }
+
+    /// Hashes the next chunk of the body.
+    pub(crate) fn update(&mut self, chunk: &[u8]) {
+        match self {
+            BodyHasher::Sha256(hasher) => hasher.update(chunk),
+            BodyHasher::Md5(hasher) => hasher.update(chunk),
+        }
+    }
+
+    /// Compares the hashed body against `checksum`.
+    pub(crate) fn verify(self, checksum: &Checksum) -> Result<(), ChecksumMismatch> {
+        let actual = match self {
+            BodyHasher::Sha256(hasher) => to_hex(&hasher.finalize()),
+            BodyHasher::Md5(hasher) => to_hex(&hasher.finalize()),
+        };
+        if !checksum.matches(&actual) {
+            return Err(ChecksumMismatch {
+                expected: checksum.expected().to_string(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Hashes the complete file at `path` with SHA-256, for a download whose body didn't stream
+/// past in file order, returning the lowercase hex digest.
+pub(crate) fn file_sha256(path: &Path) -> Result<String, DownloadError> {
+    hash_file::<Sha256>(path).map_err(DownloadError::io(path))
+}
+
+/// Reads the file at `path` through the digest `D`, returning the lowercase hex digest.
+fn hash_file<D: Digest>(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = D::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Formats bytes as a lowercase hex string.
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
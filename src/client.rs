@@ -0,0 +1,233 @@
+use crate::config::BatchConfig;
+#[cfg(feature = "reqwest")]
+use crate::dns::IpFamily;
+use crate::error::DownloadError;
+use crate::headers::InvalidHeader;
+#[cfg(any(feature = "rustls", feature = "rustls-native-roots"))]
+use crate::pin_verifier;
+#[cfg(feature = "reqwest")]
+use crate::pool::PoolConfig;
+#[cfg(feature = "reqwest")]
+use crate::protocol::{HttpConfig, HttpVersion};
+#[cfg(feature = "reqwest")]
+use crate::proxy::ProxyConfig;
+use crate::spans::debug;
+#[cfg(feature = "reqwest")]
+use crate::spans::warn;
+#[cfg(feature = "reqwest")]
+use crate::timeout::TimeoutConfig;
+use http::header::HeaderValue;
+#[cfg(feature = "reqwest")]
+use reqwest::redirect::Policy;
+#[cfg(feature = "reqwest")]
+use std::net::SocketAddr;
+
+/// The blocking client batches, preflight checks and the convenience functions send their
+/// requests with: reqwest's, or ureq's in a build with only the `ureq` feature.
+#[cfg(feature = "reqwest")]
+pub(crate) type Client = reqwest::blocking::Client;
+/// The ureq client, in a build without reqwest.
+#[cfg(not(feature = "reqwest"))]
+pub(crate) type Client = crate::ureq_backend::UreqBackend;
+
+/// Applies the settings of `config` that the blocking and the async client share to
+/// `builder`: the `User-Agent`, redirects, proxies, HTTP versions, DNS overrides, local
+/// address, TLS settings and connection pool.
+///
+/// Both builders have the same methods without a trait in common, hence a macro. It evaluates
+/// to the builder, or returns a malformed `User-Agent` or proxy from the enclosing function.
+#[cfg(feature = "reqwest")]
+macro_rules! configure {
+    ($builder:expr, $config:expr) => {{
+        let config: &BatchConfig = $config;
+        // Redirects are followed by the downloader so every hop can be checked and reported.
+        let mut builder = $builder
+            .user_agent(user_agent(config)?)
+            .redirect(Policy::none());
+        if let Some(proxies) = config.proxy.proxies().map_err(DownloadError::Client)? {
+            // An empty list still has to disable the proxies picked up from the environment.
+            builder = builder.no_proxy();
+            for proxy in proxies {
+                builder = builder.proxy(proxy);
+            }
+        }
+        let http = &config.http;
+        builder = match http.version {
+            HttpVersion::Negotiate => builder,
+            HttpVersion::Http1Only => builder.http1_only(),
+            HttpVersion::Http2PriorKnowledge => builder.http2_prior_knowledge(),
+        }
+        .http2_initial_stream_window_size(http.http2_stream_window)
+        .http2_initial_connection_window_size(http.http2_connection_window)
+        .http2_adaptive_window(http.http2_adaptive_window);
+        // The port of a connection comes from its URL, whatever the override says.
+        for (host, addrs) in &config.dns.overrides {
+            let addrs: Vec<_> = addrs.iter().map(|&ip| SocketAddr::new(ip, 0)).collect();
+            builder = builder.resolve_to_addrs(host, &addrs);
+        }
+        if let Some(resolver) = config.dns.client_resolver() {
+            builder = builder.dns_resolver(resolver);
+        }
+        builder = builder.local_address(config.local_address);
+        let tls = &config.tls;
+        // rustls wins when both TLS backends are compiled in, since reqwest would pick
+        // native-tls.
+        #[cfg(any(feature = "rustls", feature = "rustls-native-roots"))]
+        {
+            builder = builder.use_rustls_tls();
+        }
+        for certificate in &tls.root_certificates {
+            builder = builder.add_root_certificate(certificate.clone());
+        }
+        builder = builder.danger_accept_invalid_certs(tls.danger_accept_invalid_certs);
+        if let Some(version) = tls.min_version {
+            builder = builder.min_tls_version(version);
+        }
+        if let Some(version) = tls.max_version {
+            builder = builder.max_tls_version(version);
+        }
+        // The settings above are replaced by a rustls configuration checking the pins, which
+        // applies them itself.
+        #[cfg(any(feature = "rustls", feature = "rustls-native-roots"))]
+        if pin_verifier::checks_pins(config) {
+            builder = builder.use_preconfigured_tls(pin_verifier::client_config(config)?);
+        }
+        let pool = &config.pool;
+        debug!("Building the HTTP client with {:?}", pool);
+        builder = builder
+            .pool_max_idle_per_host(pool.max_idle_per_host)
+            .pool_idle_timeout(pool.idle_timeout)
+            .tcp_keepalive(pool.tcp_keepalive)
+            .tcp_nodelay(pool.tcp_nodelay);
+        // Pinned hosts the handshake doesn't check are checked against the certificate the
+        // client keeps with each response.
+        builder.tls_info(!config.download.pinned_keys.is_empty())
+    }};
+}
+
+/// Builds a blocking client with the batch's proxy settings, HTTP versions, DNS overrides, TLS
+/// settings, connection pool, connect and read timeouts, and `User-Agent`.
+///
+/// Returns [`BatchConfig::client`] instead when the caller passed one in. A client built
+/// here that checks the pins of [`crate::DownloadConfig::pinned_keys`] during the handshake
+/// records so in `config`, sparing the downloads their probes.
+#[cfg(feature = "reqwest")]
+pub(crate) fn blocking_client(config: &mut BatchConfig) -> Result<Client, DownloadError> {
+    if let Some(client) = &config.client {
+        warn_ignored(config);
+        return Ok(client.clone());
+    }
+    let timeouts = &config.download.timeouts;
+    // The blocking client applies its timeout to every individual read, which makes it the
+    // read timeout.
+    let client = configure!(Client::builder(), config)
+        .connect_timeout(timeouts.connect)
+        .timeout(timeouts.read)
+        .build()
+        .map_err(DownloadError::Client)?;
+    #[cfg(any(feature = "rustls", feature = "rustls-native-roots"))]
+    record_pin_checks(config);
+    Ok(client)
+}
+
+/// Builds a ureq client with the batch's settings, as [`crate::UreqBackend::new`] does.
+///
+/// # Returns
+///
+/// * `Err` with an [`crate::InvalidConfig`] naming a setting ureq can't apply, rather than
+///   sending the requests without it.
+#[cfg(not(feature = "reqwest"))]
+pub(crate) fn blocking_client(config: &mut BatchConfig) -> Result<Client, DownloadError> {
+    user_agent(config)?;
+    debug!("Building the HTTP client with {:?}", config.pool);
+    Ok(Client::strict(config)?)
+}
+
+/// Builds an async client with the batch's proxy settings, HTTP versions, DNS overrides, TLS
+/// settings, connection pool, connect and read timeouts, and `User-Agent`.
+///
+/// Returns [`BatchConfig::async_client`] instead when the caller passed one in, and records
+/// pins checked during the handshake as [`blocking_client`] does.
+#[cfg(feature = "async")]
+pub(crate) fn async_client(config: &mut BatchConfig) -> Result<reqwest::Client, DownloadError> {
+    if let Some(client) = &config.async_client {
+        warn_ignored(config);
+        return Ok(client.clone());
+    }
+    let timeouts = &config.download.timeouts;
+    let mut builder = configure!(reqwest::Client::builder(), config);
+    if let Some(connect) = timeouts.connect {
+        builder = builder.connect_timeout(connect);
+    }
+    if let Some(read) = timeouts.read {
+        builder = builder.read_timeout(read);
+    }
+    let client = builder.build().map_err(DownloadError::Client)?;
+    #[cfg(any(feature = "rustls", feature = "rustls-native-roots"))]
+    record_pin_checks(config);
+    Ok(client)
+}
+
+/// Records in `config` that the client just built from it checks its pins during the
+/// handshake, if it does.
+#[cfg(any(feature = "rustls", feature = "rustls-native-roots"))]
+fn record_pin_checks(config: &mut BatchConfig) {
+    if pin_verifier::checks_pins(config) {
+        config.download.pinned_keys.check_in_handshake();
+    }
+}
+
+/// Warns about the client settings of `config` that were changed from their defaults even
+/// though a client passed in by the caller is used instead of one built from them.
+#[cfg(feature = "reqwest")]
+fn warn_ignored(config: &BatchConfig) {
+    let defaults = TimeoutConfig::default();
+    let timeouts = &config.download.timeouts;
+    let tls = &config.tls;
+    let ignored: Vec<_> = [
+        ("proxy", config.proxy != ProxyConfig::default()),
+        ("http", config.http != HttpConfig::default()),
+        (
+            "dns",
+            !config.dns.overrides.is_empty()
+                || config.dns.resolver.is_some()
+                || config.dns.family != IpFamily::Any,
+        ),
+        (
+            "tls",
+            !tls.root_certificates.is_empty()
+                || tls.danger_accept_invalid_certs
+                || tls.min_version.is_some()
+                || tls.max_version.is_some(),
+        ),
+        (
+            "user_agent",
+            config.user_agent != BatchConfig::default().user_agent,
+        ),
+        ("pool", config.pool != PoolConfig::default()),
+        ("local_address", config.local_address.is_some()),
+        (
+            "download.timeouts.connect",
+            timeouts.connect != defaults.connect,
+        ),
+        ("download.timeouts.read", timeouts.read != defaults.read),
+    ]
+    .into_iter()
+    .filter_map(|(name, changed)| changed.then_some(name))
+    .collect();
+    if !ignored.is_empty() {
+        warn!(
+            "The batch uses the HTTP client it was given, so these settings are ignored: {}",
+            ignored.join(", ")
+        );
+    }
+}
+
+/// Parses [`BatchConfig::user_agent`], so a malformed value fails before anything is sent
+/// rather than when the client is built.
+fn user_agent(config: &BatchConfig) -> Result<HeaderValue, InvalidHeader> {
+    HeaderValue::from_str(&config.user_agent).map_err(|e| InvalidHeader {
+        name: "User-Agent".to_string(),
+        reason: e.to_string(),
+    })
+}
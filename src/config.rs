@@ -0,0 +1,573 @@
+use crate::adaptive::AdaptiveConcurrency;
+use crate::auth::Auth;
+use crate::batch_progress::BatchProgressCallback;
+use crate::checksum::Checksum;
+use crate::content_type::ContentTypeFilter;
+use crate::cookies::CookieJar;
+#[cfg(feature = "gzip")]
+use crate::decompress::{ChecksumTarget, Decompress};
+use crate::dedup::DedupConfig;
+use crate::dns::DnsConfig;
+use crate::encoding::ContentEncoding;
+use crate::event::FileEventCallback;
+#[cfg(feature = "prometheus")]
+use crate::exporter::PrometheusMetrics;
+use crate::hook::CompleteHook;
+use crate::interceptor::Interceptors;
+use crate::json_log::JsonLog;
+use crate::naming::FileNameConfig;
+use crate::overwrite::OverwritePolicy;
+use crate::pinning::KeyPins;
+use crate::pool::PoolConfig;
+use crate::presigned::UrlRefresher;
+use crate::progress::ProgressThrottle;
+use crate::protocol::HttpConfig;
+use crate::proxy::ProxyConfig;
+use crate::redirect::RedirectPolicy;
+use crate::retry::RetryConfig;
+use crate::segment::SegmentConfig;
+use crate::timeout::TimeoutConfig;
+use crate::tls::TlsConfig;
+use crate::write_buffer::WriteConfig;
+use std::error::Error;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Bytes read from a response body at a time unless [`DownloadConfig::chunk_size`] says
+/// otherwise.
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The largest [`DownloadConfig::chunk_size`] used; larger sizes are capped to it.
+const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// How far back the smoothed speed looks unless [`DownloadConfig::speed_window`] says
+/// otherwise.
+const DEFAULT_SPEED_WINDOW: Duration = Duration::from_secs(5);
+
+/// Settings applied to every individual file download.
+#[derive(Debug, Clone, Default)]
+pub struct DownloadConfig {
+    /// How transient failures are retried.
+    pub retry: RetryConfig,
+    /// Continue a partially downloaded file with an HTTP `Range` request.
+    ///
+    /// The partial data lives next to the destination as `<destination>.part` (see
+    /// [`DownloadConfig::partial_suffix`]), which is kept after a failed attempt while this is
+    /// enabled and continued by the next attempt or run.
+    ///
+    /// Only used when the server advertises `Accept-Ranges: bytes`. If the server answers the
+    /// ranged request with the full body instead, the file is truncated and downloaded again.
+    pub resume: bool,
+    /// Replace the destination's file name with the one suggested by a
+    /// `Content-Disposition: attachment; filename=...` response header, when present.
+    ///
+    /// The file is still saved in the destination's directory. The final path is reported in
+    /// [`crate::DownloadEvent::Completed`].
+    pub name_from_content_disposition: bool,
+    /// How names suggested through `Content-Disposition` are made safe to use as file names.
+    /// [`crate::DownloadRequest::in_directory_with`] takes the same settings for names derived
+    /// from URLs.
+    pub file_names: FileNameConfig,
+    /// Leave the partially written `<destination>.part` file on disk when a download is
+    /// cancelled instead of deleting it. Useful together with [`DownloadConfig::resume`].
+    pub keep_partial_on_cancel: bool,
+    /// Suffix appended to the destination's file name while the body is written, so other
+    /// tools can tell a file in progress from a finished one. The file is renamed to the
+    /// destination only once it is complete and verified. Defaults to `None`, which uses
+    /// `.part`; an empty suffix does too.
+    pub partial_suffix: Option<String>,
+    /// Fail when the destination's directory doesn't exist, instead of creating it and any
+    /// missing parents before the file is written.
+    pub require_existing_parent: bool,
+    /// Caps the throughput of this download alone, in bytes per second.
+    ///
+    /// Enforced independently of, and in combination with, [`BatchConfig::max_bytes_per_sec`].
+    /// `None` or `Some(0)` means unlimited.
+    pub max_bytes_per_sec: Option<u64>,
+    /// The digest the downloaded file must match.
+    ///
+    /// The file is hashed once it has been fully written. On a mismatch it is deleted and the
+    /// download fails with a [`crate::ChecksumMismatch`]. Mismatches are not retried.
+    pub checksum: Option<Checksum>,
+    /// Hash the body with SHA-256 while it is being written and report the digest in
+    /// [`crate::DownloadEvent::Completed`] and the batch results, without a second pass over
+    /// the file.
+    ///
+    /// Resumed and segmented downloads, whose bytes don't all stream past in order, hash the
+    /// file once it is complete instead.
+    pub compute_sha256: bool,
+    /// What to do when the destination already exists.
+    ///
+    /// Checked before any request is sent. When [`DownloadConfig::name_from_content_disposition`]
+    /// is enabled the final name is only known once the response arrives, so the check happens
+    /// then instead.
+    pub overwrite: OverwritePolicy,
+    /// Send `If-None-Match` / `If-Modified-Since` for destinations downloaded before, and keep
+    /// the existing file when the server answers `304 Not Modified`.
+    ///
+    /// The `ETag` and `Last-Modified` of every successful download are stored next to the file
+    /// as `<destination>.validators`. Files without stored validators are fetched normally.
+    pub conditional_requests: bool,
+    /// Extra `(name, value)` headers sent with every request, such as `Authorization`.
+    ///
+    /// Batches reject invalid names or values with a [`crate::InvalidHeader`] before any
+    /// download starts.
+    pub headers: Vec<(String, String)>,
+    /// Credentials sent with every request. They replace an `Authorization` entry in
+    /// [`DownloadConfig::headers`].
+    pub auth: Option<Auth>,
+    /// Rewrite every HTTP request right before it is sent, in order, for headers that can't
+    /// be fixed in advance such as a signature over the path and the time. They run again for
+    /// every retry, mirror and redirect hop; see [`crate::RequestInterceptor`].
+    pub interceptors: Interceptors,
+    /// Connect, read, and overall time limits.
+    ///
+    /// The connect and read limits are applied to the client when a batch or
+    /// [`crate::download_file`] builds it; clients passed in by the caller keep their own.
+    /// The overall limit is always enforced between chunks.
+    pub timeouts: TimeoutConfig,
+    /// How redirects are followed and how many hops are allowed.
+    pub redirects: RedirectPolicy,
+    /// The public keys pinned hosts must present, checked before anything is downloaded from
+    /// them, during the handshake or on the responses as [`KeyPins`] describes. A host that
+    /// presents another key fails the download with a [`crate::PinMismatch`], which is neither
+    /// retried nor failed over to a mirror.
+    pub pinned_keys: KeyPins,
+    /// Skip checking the finished file against the size the server advertised.
+    ///
+    /// By default a file whose size differs from its `Content-Length` (or, when resuming, the
+    /// total in `Content-Range`) fails with a [`crate::SizeMismatch`]. Enable this for servers
+    /// known to send wrong lengths.
+    pub ignore_content_length: bool,
+    /// The largest file, in bytes, a download may produce. `None` means unlimited.
+    ///
+    /// A server that advertises a larger file is refused before anything is written, and a
+    /// body that grows past the limit, because its length was missing or wrong, is stopped as
+    /// soon as it does. Either way the download fails with a [`crate::SizeLimitExceeded`],
+    /// which is neither retried nor failed over to a mirror, and the partial file is removed.
+    /// A decompressed download is held to the limit for both its body and the file it decodes
+    /// to, so a small gzip bomb is stopped as it expands.
+    pub max_size: Option<u64>,
+    /// Whether the server may compress the body it sends. By default every request asks for
+    /// the file as it is stored, so its length can be checked. With [`ContentEncoding::Any`],
+    /// a body that arrives compressed is downloaded without a known total, and its size isn't
+    /// checked.
+    pub content_encoding: ContentEncoding,
+    /// The content types the server may answer with. A response of another type fails with
+    /// a [`crate::UnexpectedContentType`] before any of its body is written. Accepts every
+    /// type by default.
+    pub content_types: ContentTypeFilter,
+    /// Splits large files across several connections when the server supports ranges.
+    pub segments: SegmentConfig,
+    /// Sizes the temporary file to its final length before the body is written, so the
+    /// filesystem can allocate it contiguously.
+    ///
+    /// Only used when the length is known and the download is not resumed. A transfer that
+    /// ends short truncates the file back to the bytes received. Segmented downloads always
+    /// preallocate, since every segment writes at its own offset.
+    pub preallocate: bool,
+    /// Checks that the destination's filesystem can hold the rest of the file before the body
+    /// is streamed, and again every 64 MiB while it is written.
+    ///
+    /// Only used when the length is known. A download that would run out of room fails early
+    /// with a [`crate::InsufficientDiskSpace`] instead of leaving a truncated file behind.
+    pub check_disk_space: bool,
+    /// Limits how often [`crate::DownloadEvent::Progress`] is sent. By default at most one
+    /// event is sent every 100 ms; use [`ProgressThrottle::NONE`] to report every chunk.
+    pub progress_throttle: ProgressThrottle,
+    /// How far back [`crate::DownloadCallbackProgress::smoothed_bytes_per_sec`], and the ETA
+    /// derived from it, look. Longer windows give steadier figures that follow changes of
+    /// speed more slowly. `None` or a zero duration use 5 seconds.
+    pub speed_window: Option<Duration>,
+    /// How the body is buffered on its way to disk, and whether the file is synced before it
+    /// is moved into place.
+    pub write: WriteConfig,
+    /// Bytes read from the response body at a time by the blocking API.
+    ///
+    /// Smaller chunks report progress more often, keep closer to rate limits and notice a
+    /// cancellation sooner; larger ones take fewer system calls and callbacks per file. The
+    /// buffer is allocated once per download and reused for every chunk. Sizes are capped at
+    /// 16 MiB, and `None` uses 64 KiB. `Some(0)` fails the download with an
+    /// [`InvalidConfig`]. The async API reads the chunks as the connection delivers them.
+    pub chunk_size: Option<usize>,
+    /// Decompresses the body on its way into the temporary file, so a `.gz` file can be saved
+    /// under the name of its content. `None` saves the body as it arrives.
+    ///
+    /// Progress and the length check count the compressed bytes received, while
+    /// [`DownloadConfig::max_size`], the free space check, the size reported by
+    /// [`crate::DownloadEvent::Completed`] and [`DownloadConfig::compute_sha256`] cover the
+    /// decompressed file. A body that isn't a valid stream of the format fails the download
+    /// with a [`crate::DecompressError`], which is not retried but fails over to a mirror.
+    /// Such a download is neither resumed nor split into segments, since its partial file
+    /// holds decompressed bytes. [`crate::download_to_writer`] hands the body over as it was
+    /// sent.
+    #[cfg(feature = "gzip")]
+    pub decompress: Option<Decompress>,
+    /// Whether [`DownloadConfig::checksum`] is compared against the compressed download or
+    /// the decompressed file, when [`DownloadConfig::decompress`] is set. Defaults to the
+    /// compressed download.
+    #[cfg(feature = "gzip")]
+    pub checksum_target: ChecksumTarget,
+}
+
+/// Settings for downloading a batch of files.
+#[derive(Clone)]
+pub struct BatchConfig {
+    /// Maximum number of downloads running at the same time.
+    pub concurrency: usize,
+    /// Lets the batch adjust how many downloads run at the same time, between the bounds
+    /// given, from the throughput and errors it observes, instead of always running
+    /// [`BatchConfig::concurrency`] downloads.
+    ///
+    /// Every change is reported to the batch callback as a
+    /// [`crate::DownloadEvent::ConcurrencyChanged`]. Only the blocking API adapts; the async
+    /// API keeps to `concurrency`. Defaults to `None`.
+    pub adaptive_concurrency: Option<AdaptiveConcurrency>,
+    /// Settings applied to each download in the batch.
+    pub download: DownloadConfig,
+    /// Caps the combined throughput of all downloads in the batch, in bytes per second.
+    ///
+    /// The limit applies to the aggregate of every concurrent download, not to each one
+    /// individually. `None` or `Some(0)` means unlimited.
+    pub max_bytes_per_sec: Option<u64>,
+    /// Maximum number of downloads in flight against any single host, however high
+    /// [`BatchConfig::concurrency`] is. `None` or `Some(0)` means unlimited. Defaults to 6.
+    ///
+    /// Requests for a busy host wait while requests for other hosts go ahead. With the blocking
+    /// API a download that is redirected or fails over to a mirror on another host moves to
+    /// that host's limit. A segmented download counts once, however many connections it opens.
+    pub max_connections_per_host: Option<usize>,
+    /// Receives the aggregate [`crate::BatchProgress`] of the batch after every chunk
+    /// downloaded by any worker and whenever a file finishes.
+    pub on_batch_progress: Option<BatchProgressCallback>,
+    /// Receives every [`crate::DownloadEvent`] of the batch along with the index of the request
+    /// it belongs to, so events can be told apart by file. Called after the batch callback.
+    pub on_file_event: Option<FileEventCallback>,
+    /// The proxies the batch's HTTP client connects through.
+    pub proxy: ProxyConfig,
+    /// The HTTP versions the batch's client speaks, and how it tunes HTTP/2.
+    pub http: HttpConfig,
+    /// Host names the batch's client connects to at fixed addresses, the resolver it looks
+    /// up the others with, and which IP versions it connects over.
+    pub dns: DnsConfig,
+    /// The local address outgoing connections are bound to, such as the address of the
+    /// interface a routing policy expects them from. Only remote addresses of its IP version
+    /// can be reached. Defaults to `None`, which lets the system choose.
+    pub local_address: Option<IpAddr>,
+    /// The certificates the batch's client trusts and the TLS versions it speaks.
+    pub tls: TlsConfig,
+    /// How many idle connections the batch's client keeps per host and for how long, and its
+    /// TCP options.
+    pub pool: PoolConfig,
+    /// The `User-Agent` the batch's HTTP client sends. A `User-Agent` in
+    /// [`DownloadConfig::headers`] or a request's own headers replaces it. Batches reject a
+    /// value that isn't a valid header with a [`crate::InvalidHeader`] before any download
+    /// starts. Defaults to `parallel-downloads/<version>`.
+    pub user_agent: String,
+    /// Stores the cookies servers set and sends them back with later requests, sharing them
+    /// across every download of the batch. Keep a clone of the jar to seed it before the batch
+    /// starts or read it once the batch has finished. Defaults to `None`, which neither stores
+    /// nor sends cookies apart from a `Cookie` header that is configured explicitly, which
+    /// also takes the place of the jar's. The downloader applies the jar itself, so it works
+    /// with any client.
+    pub cookies: Option<CookieJar>,
+    /// The blocking client every request of the batch is sent with, instead of one the batch
+    /// builds. Clones share the caller's connection pool.
+    ///
+    /// The client keeps its own settings, so [`BatchConfig::proxy`], [`BatchConfig::http`],
+    /// [`BatchConfig::dns`], [`BatchConfig::local_address`], [`BatchConfig::tls`],
+    /// [`BatchConfig::pool`], [`BatchConfig::user_agent`] and the connect and read limits of
+    /// [`DownloadConfig::timeouts`] are ignored, with a warning for each one that was changed.
+    /// The client should not follow redirects, see [`reqwest::redirect::Policy::none`], or
+    /// [`DownloadConfig::redirects`] can't check them, and it needs
+    /// [`reqwest::blocking::ClientBuilder::tls_info`] for [`DownloadConfig::pinned_keys`].
+    /// Every other setting, such as the overall time limit, headers and retries, still
+    /// applies. Defaults to `None`. Only with the `reqwest` feature.
+    #[cfg(feature = "reqwest")]
+    pub client: Option<reqwest::blocking::Client>,
+    /// The client every request of an async batch is sent with, instead of one the batch
+    /// builds. It keeps its own settings just like [`BatchConfig::client`] does. Defaults to
+    /// `None`.
+    #[cfg(feature = "async")]
+    pub async_client: Option<reqwest::Client>,
+    /// Whether a batch with failed downloads still returns `Ok` with every result.
+    ///
+    /// Failures are always captured per request and never stop the other downloads. When this
+    /// is `false` (strict mode), a batch with any failure returns
+    /// [`crate::DownloadError::BatchFailed`] once every download has finished. Defaults to `true`.
+    pub continue_on_error: bool,
+    /// Stops the whole batch as soon as one download fails, once its retries are used up.
+    ///
+    /// Queued downloads are never started and running ones stop at their next chunk, as when
+    /// the batch is cancelled, but they end as [`crate::DownloadOutcome::Aborted`], so the
+    /// failure that stopped the batch stands out from them. Combine it with
+    /// [`BatchConfig::continue_on_error`] turned off for the batch to return an error.
+    /// Defaults to `false`.
+    pub fail_fast: bool,
+    /// Checks every request as far as possible without downloading it, and creates or
+    /// changes nothing on disk.
+    ///
+    /// Each request is checked the way its download would go: its destination against its
+    /// overwrite policy and for a directory that could be written to, then, unless the file
+    /// would be skipped, its server with the `HEAD` request, or `GET` of the first byte, that
+    /// [`crate::preflight`] sends, with the same headers, credentials, proxy and redirects.
+    /// Destinations are resolved for duplicates as usual. Every request ends as
+    /// [`crate::DownloadOutcome::DryRun`], telling what would happen, or as
+    /// [`crate::DownloadOutcome::Failed`] with the error its download would likely run into.
+    /// Names picked by `Content-Disposition` aren't known before downloading, so the dry run
+    /// checks the requested destination.
+    ///
+    /// A dry run keeps no [`BatchConfig::state_file`] and downloads no [`BatchConfig::dedup`]
+    /// copies. Defaults to `false`.
+    pub dry_run: bool,
+    /// Whether a request with an invalid URL fails the whole batch before any download starts.
+    ///
+    /// Every URL and mirror is parsed before the workers start, and normalized: the scheme and
+    /// host are lowercased, default ports and fragments dropped. A URL that doesn't parse or
+    /// isn't `http`, `https`, `file`, `data`, or with the `ftp` feature `ftp`, fails the
+    /// batch with a [`crate::InvalidUrl`] naming its request when this is `true`. Otherwise
+    /// the request fails on its own with that error, without contacting anyone, while the
+    /// rest of the batch runs. A streamed batch stops at the first invalid request it pulls
+    /// instead. Defaults to `false`.
+    pub strict_urls: bool,
+    /// Mints a fresh URL for a download whose pre-signed URL expired.
+    ///
+    /// When a download fails with [`crate::DownloadError::UrlExpired`], the refresher is asked
+    /// for a new URL and the same attempt is made again right away with it, reported as
+    /// [`crate::DownloadEvent::UrlRefreshed`]. A refresher returning `None`, or a fresh URL
+    /// that expired too, fails the download as usual. Defaults to `None`.
+    pub refresh_url: Option<UrlRefresher>,
+    /// Runs on every file as soon as it is downloaded, on the worker that downloaded it and
+    /// after the file was moved into place, so post-processing can start before the batch
+    /// ends. Errors and panics of the hook are kept as the file's
+    /// [`crate::DownloadResult::hook_error`] and fail neither the file nor the batch. Defaults
+    /// to `None`.
+    pub on_complete: Option<CompleteHook>,
+    /// Checks that every destination filesystem can hold the batch before any download starts.
+    ///
+    /// Sizes come from [`crate::DownloadRequest::expected_size`], falling back to a `HEAD`
+    /// request for each file that has none; files whose size stays unknown are not counted.
+    /// Destinations on different mount points are checked separately. A batch that doesn't
+    /// fit fails with a [`crate::InsufficientDiskSpace`] and downloads nothing. Defaults to
+    /// `false`.
+    pub preflight_disk_space: bool,
+    /// Asks the server of every request about its file before any download starts, as
+    /// [`crate::preflight`] does.
+    ///
+    /// The answers fill in the [`crate::DownloadRequest::expected_size`] of requests without
+    /// one, which [`BatchConfig::preflight_disk_space`] then checks without asking again, and
+    /// turn off segmented downloads of files whose server doesn't accept ranges. Requests that
+    /// couldn't be asked about are downloaded as usual.
+    ///
+    /// Only batches whose requests are known up front, such as [`crate::start_batch`], are
+    /// preflighted; streamed batches, [`crate::Downloader`] and the async API ignore it.
+    /// Defaults to `false`.
+    pub preflight: bool,
+    /// Records the progress of the batch in this JSON file, so an interrupted run can pick up
+    /// where it stopped.
+    ///
+    /// The file lists every request with its status, the bytes written so far, and the
+    /// validators the server sent. A later batch with the same file skips the requests it
+    /// completed, as long as their files are still on disk, and resumes partial files with
+    /// `Range` requests; downloads of such a batch always keep their partial files, as with
+    /// [`DownloadConfig::resume`]. The file is rewritten at most once a second and once more
+    /// when the batch ends, replacing it in a single rename. Headers carrying credentials,
+    /// such as `Authorization` or an API key, are left out of it. See also
+    /// [`crate::resume_batch`].
+    ///
+    /// Only batches whose requests are known up front, such as [`crate::start_batch`] or an
+    /// async batch, keep a state file; streamed batches and [`crate::Downloader`] ignore it.
+    /// Defaults to `None`.
+    pub state_file: Option<PathBuf>,
+    /// Downloads every distinct URL of the batch only once.
+    ///
+    /// Later requests for a URL that an earlier request already fetches, with the same headers,
+    /// credentials and checksum, wait for that download and then get a copy or hard link of
+    /// its file. Each of them still reports its own events, as a download that completed
+    /// instantly, and gets its own result. If the download fails, the others are downloaded
+    /// on their own instead.
+    ///
+    /// Only batches whose requests are known up front, such as [`crate::start_batch`], are
+    /// deduplicated. Defaults to `None`, which downloads every request.
+    pub dedup: Option<DedupConfig>,
+    /// Most requests a [`crate::Downloader`] holds that haven't started downloading.
+    ///
+    /// While that many are waiting, [`crate::Downloader::enqueue`] blocks until a worker takes
+    /// one, so a producer reading a huge list of URLs is held back instead of queueing all of
+    /// them in memory, and [`crate::Downloader::try_enqueue`] returns
+    /// [`crate::DownloadError::QueueFull`]. Other batches ignore it. Defaults to `None`, which
+    /// doesn't bound the queue.
+    pub queue_capacity: Option<usize>,
+    /// Writes a JSON line describing every download of the batch to this log as soon as the
+    /// download ends. Defaults to `None`.
+    pub json_log: Option<JsonLog>,
+    /// Records every download of the batch in these Prometheus metrics as it ends. Only
+    /// available with the `prometheus` feature. Defaults to `None`.
+    #[cfg(feature = "prometheus")]
+    pub prometheus: Option<PrometheusMetrics>,
+}
+
+impl fmt::Debug for BatchConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("BatchConfig");
+        debug
+            .field("concurrency", &self.concurrency)
+            .field("adaptive_concurrency", &self.adaptive_concurrency)
+            .field("download", &self.download)
+            .field("max_bytes_per_sec", &self.max_bytes_per_sec)
+            .field("max_connections_per_host", &self.max_connections_per_host)
+            .field("on_batch_progress", &self.on_batch_progress.is_some())
+            .field("on_file_event", &self.on_file_event.is_some())
+            .field("proxy", &self.proxy)
+            .field("http", &self.http)
+            .field("dns", &self.dns)
+            .field("local_address", &self.local_address)
+            .field("tls", &self.tls)
+            .field("pool", &self.pool)
+            .field("user_agent", &self.user_agent)
+            .field("cookies", &self.cookies)
+            .field("continue_on_error", &self.continue_on_error)
+            .field("fail_fast", &self.fail_fast)
+            .field("dry_run", &self.dry_run)
+            .field("strict_urls", &self.strict_urls)
+            .field("refresh_url", &self.refresh_url)
+            .field("on_complete", &self.on_complete)
+            .field("preflight_disk_space", &self.preflight_disk_space)
+            .field("preflight", &self.preflight)
+            .field("state_file", &self.state_file)
+            .field("dedup", &self.dedup)
+            .field("queue_capacity", &self.queue_capacity)
+            .field("json_log", &self.json_log);
+        #[cfg(feature = "reqwest")]
+        debug.field("client", &self.client);
+        #[cfg(feature = "async")]
+        debug.field("async_client", &self.async_client);
+        #[cfg(feature = "prometheus")]
+        debug.field("prometheus", &self.prometheus);
+        debug.finish()
+    }
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 50,
+            adaptive_concurrency: None,
+            download: DownloadConfig::default(),
+            max_bytes_per_sec: None,
+            max_connections_per_host: Some(6),
+            on_batch_progress: None,
+            on_file_event: None,
+            proxy: ProxyConfig::default(),
+            http: HttpConfig::default(),
+            dns: DnsConfig::default(),
+            local_address: None,
+            tls: TlsConfig::default(),
+            pool: PoolConfig::default(),
+            user_agent: concat!("parallel-downloads/", env!("CARGO_PKG_VERSION")).to_string(),
+            cookies: None,
+            #[cfg(feature = "reqwest")]
+            client: None,
+            #[cfg(feature = "async")]
+            async_client: None,
+            continue_on_error: true,
+            fail_fast: false,
+            dry_run: false,
+            strict_urls: false,
+            refresh_url: None,
+            on_complete: None,
+            preflight_disk_space: false,
+            preflight: false,
+            state_file: None,
+            dedup: None,
+            queue_capacity: None,
+            json_log: None,
+            #[cfg(feature = "prometheus")]
+            prometheus: None,
+        }
+    }
+}
+
+impl DownloadConfig {
+    /// Returns the checksum the finished temporary file must match: [`DownloadConfig::checksum`],
+    /// unless it applies to the compressed bytes of a decompressed download, which are checked
+    /// as they stream past instead.
+    pub(crate) fn file_checksum(&self) -> Option<&Checksum> {
+        #[cfg(feature = "gzip")]
+        if self.decompress.is_some() && self.checksum_target == ChecksumTarget::Compressed {
+            return None;
+        }
+        self.checksum.as_ref()
+    }
+
+    /// Returns `true` if the body is decompressed on its way into the temporary file, which
+    /// then never holds the bytes the server sent.
+    pub(crate) fn decompresses(&self) -> bool {
+        #[cfg(feature = "gzip")]
+        return self.decompress.is_some();
+        #[cfg(not(feature = "gzip"))]
+        false
+    }
+
+    /// Returns the size of the buffer the body is read into, following
+    /// [`DownloadConfig::chunk_size`].
+    pub(crate) fn chunk_size(&self) -> usize {
+        match self.chunk_size {
+            None => DEFAULT_CHUNK_SIZE,
+            // An empty buffer would read as the end of the body; `check` refuses it earlier.
+            Some(size) => size.clamp(1, MAX_CHUNK_SIZE),
+        }
+    }
+
+    /// Fails with an [`InvalidConfig`] if a setting has a value no download can run with, so
+    /// the mistake is reported before anything is sent.
+    pub(crate) fn check(&self) -> Result<(), InvalidConfig> {
+        if self.chunk_size == Some(0) {
+            return Err(InvalidConfig {
+                setting: "chunk_size",
+                reason: "chunks must hold at least one byte".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns how far back the smoothed speed looks, following
+    /// [`DownloadConfig::speed_window`].
+    pub(crate) fn speed_window(&self) -> Duration {
+        match self.speed_window {
+            None | Some(Duration::ZERO) => DEFAULT_SPEED_WINDOW,
+            Some(window) => window,
+        }
+    }
+}
+
+/// Returned when a [`DownloadConfig`] setting has a value no download can run with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidConfig {
+    /// The name of the setting, such as `chunk_size`.
+    pub setting: &'static str,
+    /// Why its value was refused.
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid `{}` setting: {}", self.setting, self.reason)
+    }
+}
+
+impl Error for InvalidConfig {}
+
+impl BatchConfig {
+    /// Returns how many workers the blocking API starts for the batch: the most downloads
+    /// that may run at the same time.
+    pub(crate) fn worker_count(&self) -> usize {
+        match &self.adaptive_concurrency {
+            Some(adaptive) => adaptive.max.max(adaptive.min),
+            None => self.concurrency,
+        }
+        .max(1)
+    }
+}
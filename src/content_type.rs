@@ -0,0 +1,91 @@
+use crate::config::DownloadConfig;
+use http::header::{HeaderMap, CONTENT_TYPE};
+use std::error::Error;
+use std::fmt;
+
+/// The content types a download accepts, used by [`DownloadConfig::content_types`].
+///
+/// Checked against the `Content-Type` of the response before any of its body is written, so
+/// a login page or captive portal answering in place of the file fails the download instead
+/// of being saved under the file's name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContentTypeFilter {
+    /// The accepted types. An entry ending in `/`, such as `image/`, accepts every subtype;
+    /// any other entry, such as `application/zip`, must match exactly. Matching ignores case
+    /// and parameters such as `charset`. An empty list accepts every response.
+    pub allowed: Vec<String>,
+    /// Accept responses that carry no `Content-Type` at all. Defaults to `false`.
+    pub allow_missing: bool,
+}
+
+impl ContentTypeFilter {
+    /// Returns `true` if a response with the given `Content-Type`, if any, may be saved.
+    fn accepts(&self, content_type: Option<&str>) -> bool {
+        if self.allowed.is_empty() {
+            return true;
+        }
+        let Some(content_type) = content_type else {
+            return self.allow_missing;
+        };
+        // Parameters such as `; charset=utf-8` don't change what the body is.
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        self.allowed.iter().any(|allowed| {
+            let allowed = allowed.trim().to_ascii_lowercase();
+            if allowed.ends_with('/') {
+                essence.starts_with(&allowed)
+            } else {
+                essence == allowed
+            }
+        })
+    }
+}
+
+/// Returned when a server answers with a `Content-Type` that
+/// [`DownloadConfig::content_types`] doesn't accept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnexpectedContentType {
+    /// The URL that was downloaded.
+    pub url: String,
+    /// The accepted types, as configured.
+    pub expected: Vec<String>,
+    /// The `Content-Type` the server sent, or `None` if it sent none.
+    pub got: Option<String>,
+}
+
+impl fmt::Display for UnexpectedContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.got {
+            Some(got) => write!(f, "{} returned content type {}", self.url, got)?,
+            None => write!(f, "{} returned no content type", self.url)?,
+        }
+        write!(f, ", expected one of {}", self.expected.join(", "))
+    }
+}
+
+impl Error for UnexpectedContentType {}
+
+/// Checks the `Content-Type` of the response to `url` against
+/// [`DownloadConfig::content_types`].
+pub(crate) fn check_content_type(
+    url: &str,
+    headers: &HeaderMap,
+    config: &DownloadConfig,
+) -> Result<(), UnexpectedContentType> {
+    // A header that isn't valid text is kept for the error, but matches no accepted type.
+    let got = headers
+        .get(CONTENT_TYPE)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+    if config.content_types.accepts(got.as_deref()) {
+        return Ok(());
+    }
+    Err(UnexpectedContentType {
+        url: url.to_string(),
+        expected: config.content_types.allowed.clone(),
+        got,
+    })
+}
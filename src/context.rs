@@ -0,0 +1,40 @@
+use crate::batch_progress::BatchTracker;
+use crate::control::Control;
+use crate::cookies::CookieJar;
+use crate::hook::CompleteHook;
+use crate::host_limit::HostLimiter;
+use crate::metrics::Metrics;
+use crate::presigned::UrlRefresher;
+use crate::proxy::ProxyConfig;
+use crate::rate_limit::RateLimiter;
+use crate::state::BatchState;
+use crate::throttle::HostThrottle;
+use std::sync::Arc;
+
+/// State shared by every download of a batch, in addition to its configuration.
+#[derive(Debug, Default)]
+pub(crate) struct Context {
+    /// Cancellation and pause state.
+    pub(crate) control: Arc<Control>,
+    /// Limits the combined throughput of the batch, if configured.
+    pub(crate) limiter: Option<RateLimiter>,
+    /// Limits the downloads in flight against each host, if configured.
+    pub(crate) hosts: Option<HostLimiter>,
+    /// Aggregate progress counters, if the batch has a progress callback.
+    pub(crate) batch_progress: Option<BatchTracker>,
+    /// The proxies the batch's client was built with, used to explain connection failures.
+    pub(crate) proxy: ProxyConfig,
+    /// The cookies sent with the batch's requests and stored from its responses, if it has a
+    /// jar.
+    pub(crate) cookies: Option<CookieJar>,
+    /// Records the progress of the batch in its state file, if it has one.
+    pub(crate) state: Option<BatchState>,
+    /// Hosts that asked the batch to back off with `Retry-After`.
+    pub(crate) throttle: HostThrottle,
+    /// Counters of the batch, polled through its handle.
+    pub(crate) metrics: Arc<Metrics>,
+    /// Mints fresh URLs for downloads whose pre-signed URL expired, if configured.
+    pub(crate) refresh_url: Option<UrlRefresher>,
+    /// Runs on every file as soon as it is downloaded, if configured.
+    pub(crate) on_complete: Option<CompleteHook>,
+}
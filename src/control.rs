@@ -0,0 +1,120 @@
+use crate::error::DownloadError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Shared state used to steer the downloads of a running batch.
+#[derive(Debug, Default)]
+pub(crate) struct Control {
+    /// Set once the batch has been cancelled.
+    cancelled: AtomicBool,
+    /// Set while the batch is paused.
+    paused: AtomicBool,
+    /// Set when the batch was cancelled because one of its downloads failed.
+    aborted: AtomicBool,
+    /// Guards changes to `paused` and `cancelled` so parked workers never miss a wake-up, and
+    /// keeps track of how long the downloads have been paused.
+    lock: Mutex<PauseClock>,
+    /// Signalled when the batch is resumed or cancelled.
+    wake: Condvar,
+}
+
+/// How long the downloads of a [`Control`] have spent paused.
+#[derive(Debug, Default)]
+struct PauseClock {
+    /// When the current pause began, while paused.
+    since: Option<Instant>,
+    /// The length of every pause that has ended.
+    total: Duration,
+}
+
+impl Control {
+    /// Requests that every download using this control stops.
+    pub(crate) fn cancel(&self) {
+        let _guard = self.lock.lock().unwrap();
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.wake.notify_all();
+    }
+
+    /// Cancels every download using this control because one of them failed, unless the
+    /// downloads were already cancelled for another reason.
+    pub(crate) fn abort(&self) {
+        let _guard = self.lock.lock().unwrap();
+        if !self.is_cancelled() {
+            self.aborted.store(true, Ordering::SeqCst);
+        }
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.wake.notify_all();
+    }
+
+    /// Returns `true` once [`Control::abort`] has cancelled the downloads.
+    pub(crate) fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::SeqCst)
+    }
+
+    /// Parks every download using this control at its next chunk boundary.
+    pub(crate) fn pause(&self) {
+        let mut clock = self.lock.lock().unwrap();
+        clock.since.get_or_insert_with(Instant::now);
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Lets paused downloads continue from where they stopped.
+    pub(crate) fn resume(&self) {
+        let mut clock = self.lock.lock().unwrap();
+        if let Some(since) = clock.since.take() {
+            clock.total += since.elapsed();
+        }
+        self.paused.store(false, Ordering::SeqCst);
+        self.wake.notify_all();
+    }
+
+    /// Returns `true` while the downloads are paused.
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Returns how long the downloads have been paused in all, counting the current pause.
+    pub(crate) fn paused_time(&self) -> Duration {
+        let clock = self.lock.lock().unwrap();
+        clock.total + clock.since.map_or(Duration::ZERO, |since| since.elapsed())
+    }
+
+    /// Returns `true` once [`Control::cancel`] has been called.
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Waits for `delay`, or until the downloads are cancelled, returning
+    /// [`DownloadError::Cancelled`] if they were.
+    pub(crate) fn sleep(&self, delay: Duration) -> Result<(), DownloadError> {
+        let guard = self.lock.lock().unwrap();
+        let _guard = self
+            .wake
+            .wait_timeout_while(guard, delay, |_| !self.is_cancelled())
+            .unwrap();
+        if self.is_cancelled() {
+            Err(DownloadError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Blocks while the downloads are paused, then returns [`DownloadError::Cancelled`] if the
+    /// download should stop now.
+    pub(crate) fn checkpoint(&self) -> Result<(), DownloadError> {
+        // Only take the lock when paused so the common path stays a pair of atomic loads.
+        if self.is_paused() {
+            let mut guard = self.lock.lock().unwrap();
+            while self.is_paused() && !self.is_cancelled() {
+                guard = self.wake.wait(guard).unwrap();
+            }
+        }
+
+        if self.is_cancelled() {
+            Err(DownloadError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
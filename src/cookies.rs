@@ -0,0 +1,247 @@
+use crate::error::DownloadError;
+use crate::temp_file::temp_path;
+use cookie_store::{CookieStore, RawCookie};
+use http::header::{HeaderMap, HeaderValue, SET_COOKIE};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use url::Url;
+
+/// Characters that can't appear in a cookie name.
+const SEPARATORS: &[u8] = b"()<>@,;:\\\"/[]?={}";
+
+/// A cookie held by a [`CookieJar`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    /// The name of the cookie.
+    pub name: String,
+    /// The value sent back to the server.
+    pub value: String,
+    /// The host the cookie is sent to, along with its subdomains unless the server set it
+    /// without a `Domain` attribute.
+    pub domain: String,
+    /// The path the cookie is sent for, along with every path below it.
+    pub path: String,
+}
+
+/// The cookies of a batch, shared by all of its downloads through [`crate::BatchConfig::cookies`].
+///
+/// Cookies set by any response, including redirects, are stored in the jar and sent with every
+/// later request they match, whichever worker makes it. Clones share the same cookies, so a
+/// jar kept by the caller can be seeded before the batch starts and read once it has finished.
+/// [`CookieJar::save`] and [`CookieJar::load`] carry the jar over to a later run.
+#[derive(Clone, Default)]
+pub struct CookieJar {
+    /// The cookies, locked while a response stores its cookies or a request reads them.
+    store: Arc<Mutex<CookieStore>>,
+}
+
+impl CookieJar {
+    /// Creates an empty jar.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a jar written by [`CookieJar::save`].
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file the jar was saved to.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` with the cookies of the file that haven't expired yet, or an empty jar if the file
+    ///   doesn't exist yet.
+    /// * `Err` with an I/O error if the file can't be read or is malformed.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, DownloadError> {
+        let path = path.as_ref();
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::new()),
+            Err(e) => return Err(DownloadError::io(path)(e)),
+        };
+        let store = cookie_store::serde::json::load(BufReader::new(file)).map_err(|e| {
+            DownloadError::io(path)(io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+        })?;
+        Ok(Self {
+            store: Arc::new(Mutex::new(store)),
+        })
+    }
+
+    /// Writes the cookies of the jar to `path` as JSON, replacing the file.
+    ///
+    /// Session cookies, which a browser would drop when it closes, are saved as well, so a
+    /// later run can continue a login. The jar is written to a temporary file that is then
+    /// renamed over `path`, so a crash never leaves a half-written jar behind.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to write.
+    ///
+    /// # Returns
+    ///
+    /// * `Err` with an I/O error if the file can't be written.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), DownloadError> {
+        let path = path.as_ref();
+        let mut contents = Vec::new();
+        // Expired cookies are written too, and dropped again when the jar is loaded.
+        cookie_store::serde::json::save_incl_expired_and_nonpersistent(
+            &self.store.lock().unwrap(),
+            &mut contents,
+        )
+        .map_err(|e| DownloadError::io(path)(io::Error::other(e.to_string())))?;
+
+        let temp = temp_path(path);
+        let written = File::create(&temp)
+            .and_then(|mut file| {
+                file.write_all(&contents)?;
+                file.sync_all()
+            })
+            .map_err(DownloadError::io(&temp));
+        if written.is_err() {
+            // Cleanup is best effort; the write error is the one worth reporting.
+            let _ = std::fs::remove_file(&temp);
+        }
+        written?;
+        std::fs::rename(&temp, path).map_err(DownloadError::io(path))
+    }
+
+    /// Adds a cookie to the jar, replacing any cookie with the same name, domain and path.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the cookie.
+    /// * `value` - Its value.
+    /// * `domain` - The host to send it to. It is sent to the subdomains of the host as well.
+    /// * `path` - The path to send it for, such as `/`.
+    ///
+    /// # Returns
+    ///
+    /// * `Err` with an [`InvalidCookie`] if the name, value, domain or path is malformed.
+    pub fn add(
+        &self,
+        name: &str,
+        value: &str,
+        domain: &str,
+        path: &str,
+    ) -> Result<(), DownloadError> {
+        let invalid = |reason: String| InvalidCookie {
+            name: name.to_string(),
+            reason,
+        };
+        // RFC 6265: the name is a token, and the value leaves out what would end it early.
+        if name.is_empty()
+            || !name
+                .bytes()
+                .all(|b| b.is_ascii_graphic() && !SEPARATORS.contains(&b))
+        {
+            return Err(invalid("the name must be a non-empty token".to_string()).into());
+        }
+        if !value
+            .bytes()
+            .all(|b| b.is_ascii_graphic() && !matches!(b, b'"' | b',' | b';' | b'\\'))
+        {
+            return Err(
+                invalid("the value contains a character a cookie can't carry".to_string()).into(),
+            );
+        }
+        let domain = domain.trim_start_matches('.');
+        // The cookie is stored as if `domain` had set it, which is what scopes it to that host.
+        let url = Url::parse(&format!("https://{}", domain))
+            .and_then(|url| url.join(path))
+            .map_err(|e| invalid(format!("invalid domain or path: {}", e)))?;
+        let cookie = RawCookie::build((name, value))
+            .domain(domain)
+            .path(path)
+            .build();
+        self.store
+            .lock()
+            .unwrap()
+            .insert_raw(&cookie, &url)
+            .map_err(|e| invalid(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Returns every cookie in the jar that hasn't expired, in no particular order.
+    pub fn cookies(&self) -> Vec<Cookie> {
+        self.store
+            .lock()
+            .unwrap()
+            .iter_unexpired()
+            .map(|cookie| Cookie {
+                name: cookie.name().to_string(),
+                value: cookie.value().to_string(),
+                domain: String::from(&cookie.domain),
+                path: String::from(&cookie.path),
+            })
+            .collect()
+    }
+
+    /// Removes every cookie from the jar.
+    pub fn clear(&self) {
+        self.store.lock().unwrap().clear();
+    }
+
+    /// Stores the cookies a response from `url` set with `Set-Cookie`. Malformed cookies are
+    /// ignored, as a browser would.
+    pub(crate) fn store_response(&self, url: &Url, headers: &HeaderMap) {
+        let cookies = headers
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| RawCookie::parse(value.to_string()).ok())
+            .collect::<Vec<_>>();
+        self.store
+            .lock()
+            .unwrap()
+            .store_response_cookies(cookies.into_iter(), url);
+    }
+
+    /// Returns the `Cookie` header of a request to `url`, or `None` if no cookie matches it.
+    pub(crate) fn header_for(&self, url: &Url) -> Option<HeaderValue> {
+        let value = self
+            .store
+            .lock()
+            .unwrap()
+            .get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        if value.is_empty() {
+            return None;
+        }
+        HeaderValue::from_str(&value).ok()
+    }
+}
+
+impl fmt::Debug for CookieJar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Cookie values are often credentials, so only their number is shown.
+        f.debug_struct("CookieJar")
+            .field(
+                "cookies",
+                &self.store.lock().unwrap().iter_unexpired().count(),
+            )
+            .finish()
+    }
+}
+
+/// A cookie that can't be added to a [`CookieJar`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidCookie {
+    /// The name of the cookie.
+    pub name: String,
+    /// What is wrong with it.
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidCookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid cookie {}: {}", self.name, self.reason)
+    }
+}
+
+impl Error for InvalidCookie {}
@@ -0,0 +1,160 @@
+use crate::backend::HttpResponse;
+use crate::error::DownloadError;
+use crate::url_check::InvalidUrl;
+use base64::alphabet::STANDARD;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use http::header::{HeaderMap, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+use http::{Method, StatusCode, Version};
+use percent_encoding::percent_decode_str;
+use std::io::Cursor;
+use url::Url;
+
+/// Decodes base64 payloads, with or without their trailing `=` padding.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// The decoded payload of a `data:` URL, served to a download as a server would send it.
+///
+/// Downloads of such URLs never reach the HTTP client. The payload is announced with its
+/// media type as `Content-Type` and its decoded length as `Content-Length`, so it is written
+/// through the temporary file, the checks of the finished file and the overwrite policy like
+/// any other download, and a payload over [`crate::DownloadConfig::max_size`] is refused
+/// before anything is written.
+#[derive(Debug)]
+pub(crate) struct DataUrl {
+    /// The URL, as parsed.
+    url: Url,
+    /// The media type before the payload, such as `image/png` or `text/plain;charset=utf-8`.
+    media_type: String,
+    /// The decoded payload.
+    data: Vec<u8>,
+}
+
+/// Returns `true` if `url` carries its payload inline rather than naming a resource.
+pub(crate) fn is_data_url(url: &str) -> bool {
+    url.trim()
+        .get(..5)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("data:"))
+}
+
+/// Parses and decodes the `data:[<media type>][;base64],<payload>` URL `url`.
+///
+/// # Arguments
+///
+/// * `url` - The URL. Its payload is percent-decoded, then base64-decoded when the media type
+///   is followed by `;base64`. Whitespace in a base64 payload is ignored.
+///
+/// # Returns
+///
+/// * `Ok` with the decoded payload. A missing media type defaults to
+///   `text/plain;charset=US-ASCII`.
+/// * `Err` with an [`InvalidUrl`] naming what is wrong with the URL, such as a missing comma
+///   or a payload that isn't valid base64.
+pub(crate) fn parse(url: &str) -> Result<DataUrl, DownloadError> {
+    let invalid = |reason: String| {
+        DownloadError::from(InvalidUrl {
+            index: None,
+            url: url.to_string(),
+            reason,
+        })
+    };
+    let parsed = Url::parse(url.trim()).map_err(|e| invalid(e.to_string()))?;
+    // Read the payload from the URL as given, since parsing may escape some of its bytes.
+    let rest = &url.trim()[5..];
+    let (header, payload) = rest
+        .split_once(',')
+        .ok_or_else(|| invalid("the payload isn't preceded by a comma".to_string()))?;
+    // Fragments are never part of the payload.
+    let payload = payload
+        .split_once('#')
+        .map_or(payload, |(payload, _)| payload);
+
+    let (media_type, base64) = match header.rsplit_once(';') {
+        Some((media_type, encoding)) if encoding.trim().eq_ignore_ascii_case("base64") => {
+            (media_type, true)
+        }
+        _ => (header, false),
+    };
+    let media_type = match media_type.trim() {
+        "" => "text/plain;charset=US-ASCII".to_string(),
+        media_type if media_type.starts_with(';') => format!("text/plain{}", media_type),
+        media_type => percent_decode_str(media_type)
+            .decode_utf8_lossy()
+            .into_owned(),
+    };
+
+    let decoded: Vec<u8> = percent_decode_str(payload).collect();
+    let data = if base64 {
+        let text: Vec<u8> = decoded
+            .into_iter()
+            .filter(|byte| !byte.is_ascii_whitespace())
+            .collect();
+        BASE64
+            .decode(text)
+            .map_err(|e| invalid(format!("the base64 payload is malformed: {}", e)))?
+    } else {
+        decoded
+    };
+
+    Ok(DataUrl {
+        url: parsed,
+        media_type,
+        data,
+    })
+}
+
+impl DataUrl {
+    /// The headers a server would send with the payload. A media type that isn't a valid
+    /// header value is left out.
+    fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Ok(media_type) = HeaderValue::try_from(self.media_type.as_str()) {
+            headers.insert(CONTENT_TYPE, media_type);
+        }
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(self.data.len()));
+        headers
+    }
+
+    /// Answers a blocking `method` request with the payload.
+    pub(crate) fn into_response(self, method: &Method) -> HttpResponse {
+        let headers = self.headers();
+        let data = if method == Method::HEAD {
+            Vec::new()
+        } else {
+            self.data
+        };
+        HttpResponse {
+            status: StatusCode::OK,
+            headers,
+            url: self.url,
+            version: Version::HTTP_11,
+            remote_addr: None,
+            content_length: Some(data.len() as u64),
+            peer_certificate: None,
+            body: Box::new(Cursor::new(data)),
+        }
+    }
+
+    /// Answers an async `method` request with the payload.
+    #[cfg(feature = "async")]
+    pub(crate) fn into_async_response(self, method: &Method) -> reqwest::Response {
+        use reqwest::ResponseBuilderExt;
+
+        let headers = self.headers();
+        let data = if method == Method::HEAD {
+            Vec::new()
+        } else {
+            self.data
+        };
+        let mut response = http::Response::builder()
+            .status(StatusCode::OK)
+            .url(self.url)
+            .body(reqwest::Body::from(data))
+            .expect("the status and URL are valid");
+        response.headers_mut().extend(headers);
+        response.into()
+    }
+}
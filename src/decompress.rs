@@ -0,0 +1,163 @@
+use crate::checksum::{BodyHasher, Checksum};
+use crate::config::DownloadConfig;
+use crate::error::DownloadError;
+use crate::length::check_received;
+use flate2::write::MultiGzDecoder;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// How a downloaded file is decompressed before it is moved to its destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decompress {
+    /// The body is a gzip stream, such as a `.gz` file, and the destination gets its content.
+    Gzip,
+}
+
+/// Which bytes [`crate::DownloadConfig::checksum`] is compared against when the file is
+/// decompressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChecksumTarget {
+    /// The compressed bytes the server sent, as published next to most `.gz` files.
+    #[default]
+    Compressed,
+    /// The decompressed bytes written to the destination.
+    Decompressed,
+}
+
+/// Returned when a downloaded file can't be decompressed because it is truncated, corrupt or
+/// not compressed at all.
+///
+/// Nothing is left at the destination, and the partial file is removed so the next attempt
+/// downloads it again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecompressError {
+    /// The destination the decompressed file was meant for.
+    pub path: PathBuf,
+    /// Why the file couldn't be decompressed.
+    pub reason: String,
+}
+
+impl fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to decompress the download of {}: {}",
+            self.path.display(),
+            self.reason
+        )
+    }
+}
+
+impl Error for DecompressError {}
+
+/// How many compressed bytes are decoded at a time, so that a chunk of a gzip bomb decodes to
+/// a few MiB at most before it is checked against the size limit and written.
+pub(crate) const DECODE_STEP: usize = 4 * 1024;
+
+/// Decompresses the body of a download on its way into the temporary file.
+///
+/// The body is fed to the decoder a chunk at a time, in steps of [`DECODE_STEP`] bytes, and
+/// every step's output is checked against [`DownloadConfig::max_size`] before the sink writes
+/// it. The compressed bytes are hashed along the way when the checksum applies to them, and
+/// checked once the stream ends.
+pub(crate) struct Decoder {
+    /// The decoder, writing its output into a buffer the sink takes it from after each step.
+    decoder: MultiGzDecoder<Vec<u8>>,
+    /// The checksum of the compressed bytes and their hash so far, if it applies to them.
+    checksum: Option<(Checksum, BodyHasher)>,
+    /// How many bytes were decoded so far.
+    decoded: u64,
+    /// The largest file the download may produce.
+    max_size: Option<u64>,
+    /// The URL the body comes from, named by size errors.
+    url: String,
+    /// The destination the decompressed file is meant for, named by decoding errors.
+    path: PathBuf,
+}
+
+impl Decoder {
+    /// Starts decompressing the body of `url` for `path`, if `config` asks for it.
+    pub(crate) fn new(url: &str, path: &Path, config: &DownloadConfig) -> Option<Self> {
+        let Decompress::Gzip = config.decompress?;
+        let checksum = config
+            .checksum
+            .clone()
+            .filter(|_| config.checksum_target == ChecksumTarget::Compressed)
+            .map(|checksum| {
+                let hasher = BodyHasher::new(&checksum);
+                (checksum, hasher)
+            });
+        Some(Self {
+            decoder: MultiGzDecoder::new(Vec::new()),
+            checksum,
+            decoded: 0,
+            max_size: config.max_size,
+            url: url.to_string(),
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Decodes the next `input` bytes of the body, at most [`DECODE_STEP`] of them, and
+    /// returns what they decoded to.
+    ///
+    /// # Returns
+    ///
+    /// * `Err` with a [`DecompressError`] if the body isn't a valid gzip stream, or a
+    ///   [`crate::SizeLimitExceeded`] if the decoded bytes grew past the size limit.
+    pub(crate) fn decode(&mut self, input: &[u8]) -> Result<&[u8], DownloadError> {
+        self.decoder.get_mut().clear();
+        if let Some((_, hasher)) = &mut self.checksum {
+            hasher.update(input);
+        }
+        self.decoder
+            .write_all(input)
+            .map_err(|error| self.corrupt(&error, false))?;
+        self.decoded_output()
+    }
+
+    /// Ends the stream once the whole body was decoded, and returns the last bytes it decoded
+    /// to.
+    ///
+    /// # Returns
+    ///
+    /// * `Err` with a [`DecompressError`] if the stream is truncated, a
+    ///   [`crate::ChecksumMismatch`] if the compressed bytes don't match the checksum, or a
+    ///   [`crate::SizeLimitExceeded`] if the decoded bytes grew past the size limit.
+    pub(crate) fn finish(&mut self) -> Result<&[u8], DownloadError> {
+        self.decoder.get_mut().clear();
+        self.decoder
+            .try_finish()
+            .map_err(|error| self.corrupt(&error, true))?;
+        if let Some((checksum, hasher)) = self.checksum.take() {
+            hasher.verify(&checksum)?;
+        }
+        self.decoded_output()
+    }
+
+    /// Counts the output of the last step against the size limit and returns it.
+    fn decoded_output(&mut self) -> Result<&[u8], DownloadError> {
+        let output = self.decoder.get_ref();
+        self.decoded += output.len() as u64;
+        check_received(&self.url, self.decoded, self.max_size)?;
+        Ok(output)
+    }
+
+    /// The error for a body that isn't a valid gzip stream, as `error` tells, `at_end` once
+    /// the body ended.
+    fn corrupt(&self, error: &io::Error, at_end: bool) -> DownloadError {
+        let reason = if self.decoder.header().is_none() {
+            "it is not a gzip stream".to_string()
+        } else if at_end {
+            // flate2 tells neither apart from a stream missing its trailer.
+            "the gzip stream is truncated or doesn't match its CRC-32".to_string()
+        } else {
+            format!("the gzip stream is corrupt: {}", error)
+        };
+        DownloadError::Decompress(DecompressError {
+            path: self.path.clone(),
+            reason,
+        })
+    }
+}
@@ -0,0 +1,224 @@
+use crate::config::DownloadConfig;
+use crate::directories::create_parent_dirs;
+use crate::error::DownloadError;
+use crate::event::DownloadEvent;
+use crate::overwrite::Claim;
+use crate::progress::DownloadCallbackProgress;
+use crate::request::DownloadRequest;
+use crate::result::DownloadOutcome;
+use crate::skip::Skipped;
+use crate::spans::warn;
+use crate::temp_file::partial_path;
+use crate::timing::DownloadTimings;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use url::Url;
+
+/// How the other destinations of a URL requested more than once get their file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateLink {
+    /// Copy the downloaded file, so every destination can be changed on its own.
+    #[default]
+    Copy,
+    /// Hard link the downloaded file, which takes no extra space. Falls back to copying when
+    /// the destinations are on different filesystems.
+    HardLink,
+}
+
+/// Settings for fetching every distinct URL of a batch only once, used by
+/// [`crate::BatchConfig::dedup`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DedupConfig {
+    /// How the other destinations of a URL get the downloaded file.
+    pub link: DuplicateLink,
+    /// Compares URLs after dropping their fragment and default port and lowercasing their
+    /// scheme and host, instead of character by character.
+    pub normalize_urls: bool,
+}
+
+/// Finds the requests of a batch that fetch the same file as an earlier request.
+///
+/// Two requests fetch the same file when their URLs match and they send the same headers and
+/// credentials, expect the same checksum and decompress the file alike. Returns the position of
+/// every repeated request together with the position of the first one.
+pub(crate) fn find_repeats(
+    requests: &[DownloadRequest],
+    config: &DedupConfig,
+) -> Vec<(usize, usize)> {
+    let mut firsts: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut repeats = Vec::new();
+    for (index, request) in requests.iter().enumerate() {
+        let key = if config.normalize_urls {
+            normalize_url(&request.url)
+        } else {
+            request.url.clone()
+        };
+        let firsts = firsts.entry(key).or_default();
+        match firsts
+            .iter()
+            .find(|&&first| same_file(&requests[first], request))
+        {
+            Some(&first) => repeats.push((index, first)),
+            None => firsts.push(index),
+        }
+    }
+    repeats
+}
+
+/// Gives `request` the file an identical request ended with, as `source`.
+///
+/// The file is copied or linked to the request's destination, applying its overwrite policy,
+/// and reported as a download that completed instantly: `Started`, a single `Progress` and
+/// `Completed`. When the identical request was cancelled so is this one. A failed request is
+/// never passed; its repeats are queued for their own download instead.
+pub(crate) fn fill(
+    source: &DownloadOutcome,
+    request: &DownloadRequest,
+    config: &DownloadConfig,
+    link: DuplicateLink,
+    report: &impl Fn(&DownloadEvent),
+) -> DownloadOutcome {
+    let (from, sha256, final_url, http_version, remote_addr) = match source {
+        DownloadOutcome::Completed {
+            path,
+            sha256,
+            final_url,
+            http_version,
+            remote_addr,
+            ..
+        } => (
+            path,
+            sha256.clone(),
+            final_url.clone(),
+            *http_version,
+            *remote_addr,
+        ),
+        DownloadOutcome::Skipped { path } | DownloadOutcome::NotModified { path } => {
+            (path, None, request.url.clone(), None, None)
+        }
+        // Dry runs hold back no repeats, since filling them would write files.
+        DownloadOutcome::Cancelled
+        | DownloadOutcome::Aborted
+        | DownloadOutcome::DryRun { .. }
+        | DownloadOutcome::Failed { .. } => {
+            report(&DownloadEvent::Cancelled {
+                path: request.destination.clone(),
+            });
+            return DownloadOutcome::Cancelled;
+        }
+    };
+
+    match place(from, &request.destination, config, link) {
+        Ok(Some((path, bytes))) => {
+            report(&DownloadEvent::Started {
+                url: request.url.clone(),
+                total_bytes: Some(bytes),
+            });
+            report(&DownloadEvent::Progress(DownloadCallbackProgress {
+                url: Arc::from(request.url.as_str()),
+                ..DownloadCallbackProgress::new(bytes, Some(bytes))
+            }));
+            report(&DownloadEvent::Completed {
+                path: path.clone(),
+                bytes,
+                sha256: sha256.clone(),
+                timings: DownloadTimings::default(),
+            });
+            DownloadOutcome::Completed {
+                path,
+                bytes,
+                sha256,
+                final_url,
+                http_version,
+                remote_addr,
+                extracted: Vec::new(),
+                hook_error: None,
+            }
+        }
+        Ok(None) => {
+            let skipped = Skipped::existing(request.destination.clone());
+            report(&skipped.event());
+            skipped.outcome()
+        }
+        Err(error) => {
+            report(&DownloadEvent::Failed {
+                error: error.to_string(),
+                timings: DownloadTimings::default(),
+            });
+            DownloadOutcome::Failed { error }
+        }
+    }
+}
+
+/// Copies or links `from` to `destination` through a temporary file, returning where it ended
+/// up and its size, or `None` if the overwrite policy kept an existing file.
+fn place(
+    from: &Path,
+    destination: &Path,
+    config: &DownloadConfig,
+    link: DuplicateLink,
+) -> Result<Option<(PathBuf, u64)>, DownloadError> {
+    create_parent_dirs(destination, config)?;
+    let Some(claim) = Claim::new(destination, config.overwrite)? else {
+        return Ok(None);
+    };
+    let path = claim.path().to_path_buf();
+    let temp = partial_path(&path, config);
+
+    let linked = link == DuplicateLink::HardLink && {
+        // A leftover temporary file would make the link fail.
+        let _ = std::fs::remove_file(&temp);
+        match std::fs::hard_link(from, &temp) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!(
+                    "Failed to hard link {} to {}, copying instead: {}",
+                    from.display(),
+                    path.display(),
+                    e
+                );
+                false
+            }
+        }
+    };
+    let placed = if linked {
+        std::fs::metadata(&temp).map(|metadata| metadata.len())
+    } else {
+        std::fs::copy(from, &temp)
+    }
+    .map_err(DownloadError::io(&temp))
+    .and_then(|bytes| {
+        std::fs::rename(&temp, &path)
+            .map(|()| bytes)
+            .map_err(DownloadError::io(&path))
+    });
+    if placed.is_err() {
+        // Cleanup is best effort; the copy error is the one worth reporting.
+        let _ = std::fs::remove_file(&temp);
+    }
+    let bytes = placed?;
+    Ok(Some((path, bytes)))
+}
+
+/// Returns `true` if two requests for the same URL would download the same file.
+fn same_file(first: &DownloadRequest, other: &DownloadRequest) -> bool {
+    #[cfg(feature = "gzip")]
+    if first.decompress != other.decompress || first.checksum_target != other.checksum_target {
+        return false;
+    }
+    first.headers == other.headers && first.auth == other.auth && first.checksum == other.checksum
+}
+
+/// Drops the fragment and default port of `url` and lowercases its scheme and host, leaving
+/// anything that isn't a valid URL as it is.
+fn normalize_url(url: &str) -> String {
+    match Url::parse(url) {
+        // Parsing already lowercases the scheme and host and drops default ports.
+        Ok(mut url) => {
+            url.set_fragment(None);
+            url.into()
+        }
+        Err(_) => url.to_string(),
+    }
+}
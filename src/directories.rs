@@ -0,0 +1,32 @@
+use crate::config::DownloadConfig;
+use crate::error::DownloadError;
+use std::io;
+use std::path::Path;
+
+/// Creates the missing parent directories of the destination `path`, unless
+/// [`DownloadConfig::require_existing_parent`] is set.
+///
+/// Workers creating the same tree at the same time don't get in each other's way, since a
+/// directory that another worker created first counts as created. A directory that still can't
+/// be created fails with an I/O error naming it and the destination.
+pub(crate) fn create_parent_dirs(
+    path: &Path,
+    config: &DownloadConfig,
+) -> Result<(), DownloadError> {
+    if config.require_existing_parent {
+        return Ok(());
+    }
+    match path.parent() {
+        // A bare file name lives in the working directory, which always exists.
+        Some(parent) if !parent.as_os_str().is_empty() => {
+            std::fs::create_dir_all(parent).map_err(|e| {
+                // Name the file too, since the directory alone rarely says which download failed.
+                DownloadError::io(parent)(io::Error::new(
+                    e.kind(),
+                    format!("cannot create the directory of {}: {}", path.display(), e),
+                ))
+            })
+        }
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,160 @@
+use crate::error::DownloadError;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How many bytes a download writes between two checks of the free space left.
+const CHECK_INTERVAL: u64 = 64 * 1024 * 1024;
+
+/// Returned when a filesystem doesn't have room for the files about to be written to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsufficientDiskSpace {
+    /// A directory on the filesystem that ran out of room.
+    pub path: PathBuf,
+    /// Bytes the downloads on this filesystem still need.
+    pub needed: u64,
+    /// Bytes available on the filesystem.
+    pub available: u64,
+}
+
+impl fmt::Display for InsufficientDiskSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "not enough disk space at {}: {} bytes needed but only {} available",
+            self.path.display(),
+            self.needed,
+            self.available
+        )
+    }
+}
+
+impl Error for InsufficientDiskSpace {}
+
+/// Checks that every filesystem has room for the files about to be downloaded to it.
+///
+/// `needs` pairs each destination with its expected size. Destinations on the same
+/// filesystem are summed, so a batch spread across several mount points is checked against
+/// each of them separately.
+pub(crate) fn check_disk_space<'a>(
+    needs: impl IntoIterator<Item = (&'a Path, u64)>,
+) -> Result<(), DownloadError> {
+    let mut filesystems: HashMap<FilesystemId, (PathBuf, u64)> = HashMap::new();
+    for (destination, size) in needs {
+        let directory = existing_directory(destination);
+        let id = filesystem_id(directory).map_err(DownloadError::io(directory))?;
+        filesystems
+            .entry(id)
+            .or_insert_with(|| (directory.to_path_buf(), 0))
+            .1 += size;
+    }
+
+    for (directory, needed) in filesystems.into_values() {
+        ensure_room(&directory, needed)?;
+    }
+    Ok(())
+}
+
+/// Fails with [`InsufficientDiskSpace`] unless the filesystem holding `path` has `needed`
+/// bytes free.
+pub(crate) fn ensure_room(path: &Path, needed: u64) -> Result<(), DownloadError> {
+    let directory = existing_directory(path);
+    let available = fs2::available_space(directory).map_err(DownloadError::io(directory))?;
+    if needed > available {
+        return Err(InsufficientDiskSpace {
+            path: directory.to_path_buf(),
+            needed,
+            available,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Stops a single download early once its filesystem can no longer hold the rest of the file.
+///
+/// Checking up front and then every [`CHECK_INTERVAL`] bytes catches a disk that other
+/// writers are filling up, before the download runs into the end of it.
+pub(crate) struct DiskGuard<'a> {
+    /// The file being written.
+    path: &'a Path,
+    /// Bytes still to be written.
+    remaining: u64,
+    /// Bytes written since the last check.
+    unchecked: u64,
+    /// Whether the file may grow past the bytes expected, as a decompressed file does.
+    open_ended: bool,
+}
+
+impl<'a> DiskGuard<'a> {
+    /// Checks that `path` has room for `remaining` more bytes and starts watching it.
+    pub(crate) fn new(path: &'a Path, remaining: u64) -> Result<Self, DownloadError> {
+        ensure_room(path, remaining)?;
+        Ok(Self {
+            path,
+            remaining,
+            unchecked: 0,
+            open_ended: false,
+        })
+    }
+
+    /// Keeps checking that the next [`CHECK_INTERVAL`] bytes fit once the expected bytes are
+    /// written, for a file whose final size isn't known up front.
+    pub(crate) fn open_ended(mut self) -> Self {
+        self.open_ended = true;
+        self
+    }
+
+    /// Counts `bytes` written to the file, checking the free space again now and then.
+    pub(crate) fn record(&mut self, bytes: u64) -> Result<(), DownloadError> {
+        self.remaining = self.remaining.saturating_sub(bytes);
+        self.unchecked += bytes;
+        if self.unchecked >= CHECK_INTERVAL {
+            self.unchecked = 0;
+            let needed = if self.open_ended {
+                self.remaining.max(CHECK_INTERVAL)
+            } else {
+                self.remaining
+            };
+            ensure_room(self.path, needed)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the deepest existing directory that `path` is (or will be) created in.
+fn existing_directory(path: &Path) -> &Path {
+    path.ancestors()
+        .skip(1)
+        .find(|ancestor| ancestor.is_dir())
+        // A relative path whose directories don't exist yet starts in the working directory.
+        .unwrap_or(Path::new("."))
+}
+
+/// Identifies the filesystem a directory lives on.
+#[cfg(unix)]
+type FilesystemId = u64;
+
+/// Identifies the filesystem a directory lives on.
+#[cfg(not(unix))]
+type FilesystemId = PathBuf;
+
+/// Returns the device the directory is stored on.
+#[cfg(unix)]
+fn filesystem_id(directory: &Path) -> io::Result<FilesystemId> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(std::fs::metadata(directory)?.dev())
+}
+
+/// Returns the root of the directory's absolute path, such as its drive.
+#[cfg(not(unix))]
+fn filesystem_id(directory: &Path) -> io::Result<FilesystemId> {
+    let absolute = directory.canonicalize()?;
+    Ok(absolute
+        .ancestors()
+        .last()
+        .unwrap_or(&absolute)
+        .to_path_buf())
+}
@@ -0,0 +1,303 @@
+#[cfg(feature = "reqwest")]
+use crate::spans::warn;
+#[cfg(feature = "reqwest")]
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::collections::HashMap;
+use std::fmt;
+#[cfg(feature = "reqwest")]
+use std::future::Future;
+#[cfg(any(feature = "reqwest", feature = "ureq"))]
+use std::io;
+use std::net::IpAddr;
+#[cfg(any(feature = "reqwest", feature = "ureq"))]
+use std::net::{SocketAddr, ToSocketAddrs};
+#[cfg(feature = "reqwest")]
+use std::pin::Pin;
+#[cfg(feature = "reqwest")]
+use std::sync::mpsc::{self, Sender};
+#[cfg(feature = "reqwest")]
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+#[cfg(feature = "reqwest")]
+use std::task::{Context, Poll, Waker};
+#[cfg(feature = "reqwest")]
+use std::thread;
+
+/// How the batch's client finds the addresses of hosts.
+///
+/// Only the address a connection goes to changes: requests still carry the host name of their
+/// URL in the `Host` header and, for `https://`, in the TLS handshake, and certificates are
+/// checked against it. Clients passed in by the caller keep their own resolution.
+#[derive(Clone, Default)]
+pub struct DnsConfig {
+    /// Addresses to connect to instead of looking up the host name, by host name. Names are
+    /// matched case-insensitively. The port still comes from the URL, or the default port of
+    /// its scheme.
+    pub overrides: HashMap<String, Vec<IpAddr>>,
+    /// Looks up the host names that aren't overridden, in place of the system resolver.
+    /// Only with the `reqwest` feature.
+    #[cfg(feature = "reqwest")]
+    pub resolver: Option<Arc<dyn Resolve>>,
+    /// Which addresses of a looked-up host are connected to, and in which order. Addresses
+    /// in [`DnsConfig::overrides`] are used as they are. Defaults to [`IpFamily::Any`].
+    pub family: IpFamily,
+}
+
+/// Which IP versions the batch's client connects over.
+///
+/// When a host has addresses of both families, the client connects to the first address and
+/// races one of the other family against it if it doesn't answer within a fraction of a
+/// second, so the preferred family is tried first without hanging on a broken one. A
+/// [`crate::BatchConfig::local_address`] restricts connections to its own family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IpFamily {
+    /// Every address, in the order the resolver returned them.
+    #[default]
+    Any,
+    /// Only IPv4 addresses. Hosts without one fail to connect.
+    Ipv4Only,
+    /// Only IPv6 addresses. Hosts without one fail to connect.
+    Ipv6Only,
+    /// IPv4 addresses first, then IPv6 ones.
+    PreferIpv4,
+    /// IPv6 addresses first, then IPv4 ones.
+    PreferIpv6,
+}
+
+#[cfg(any(feature = "reqwest", feature = "ureq"))]
+impl IpFamily {
+    /// Drops and reorders `addrs` as the family asks.
+    fn order(self, mut addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        match self {
+            IpFamily::Any => {}
+            IpFamily::Ipv4Only => addrs.retain(SocketAddr::is_ipv4),
+            IpFamily::Ipv6Only => addrs.retain(SocketAddr::is_ipv6),
+            // The sort is stable, so the resolver's order holds within each family.
+            IpFamily::PreferIpv4 => addrs.sort_by_key(SocketAddr::is_ipv6),
+            IpFamily::PreferIpv6 => addrs.sort_by_key(SocketAddr::is_ipv4),
+        }
+        addrs
+    }
+
+    /// Describes the failure of a lookup of `host` that found no address of the family.
+    fn missing(self, host: &str) -> String {
+        let wanted = match self {
+            IpFamily::Ipv6Only => "IPv6",
+            _ => "IPv4",
+        };
+        format!("{} has no {} address", host, wanted)
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl DnsConfig {
+    /// Returns the resolver the batch's client looks host names up with, or `None` to keep
+    /// the client's own.
+    pub(crate) fn client_resolver(&self) -> Option<Arc<SharedResolver>> {
+        if self.resolver.is_none() && self.family == IpFamily::Any {
+            return None;
+        }
+        Some(Arc::new(SharedResolver {
+            resolver: self.resolver.clone(),
+            family: self.family,
+        }))
+    }
+}
+
+#[cfg(feature = "ureq")]
+impl DnsConfig {
+    /// Returns the resolver a [`crate::UreqBackend`] looks host names up with, or `None` to
+    /// keep ureq's own.
+    pub(crate) fn ureq_resolver(&self) -> Option<UreqResolver> {
+        if self.overrides.is_empty() && self.family == IpFamily::Any {
+            return None;
+        }
+        let overrides = self
+            .overrides
+            .iter()
+            .map(|(host, addrs)| (host.to_ascii_lowercase(), addrs.clone()))
+            .collect();
+        Some(UreqResolver {
+            overrides,
+            family: self.family,
+        })
+    }
+}
+
+impl fmt::Debug for DnsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("DnsConfig");
+        debug.field("overrides", &self.overrides);
+        #[cfg(feature = "reqwest")]
+        debug.field("resolver", &self.resolver.is_some());
+        debug.field("family", &self.family).finish()
+    }
+}
+
+/// Hands the lookups of a client to the resolver of a [`DnsConfig`], or the system resolver,
+/// and keeps the addresses of its [`IpFamily`].
+#[cfg(feature = "reqwest")]
+pub(crate) struct SharedResolver {
+    /// The resolver shared with the [`DnsConfig`], if it has one.
+    resolver: Option<Arc<dyn Resolve>>,
+    /// Which addresses are kept, and in which order.
+    family: IpFamily,
+}
+
+#[cfg(feature = "reqwest")]
+impl Resolve for SharedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        let lookup = match &self.resolver {
+            Some(resolver) => resolver.resolve(name),
+            None => Box::pin(SystemLookup::start(host.clone())),
+        };
+        let family = self.family;
+        if family == IpFamily::Any {
+            return lookup;
+        }
+        Box::pin(async move {
+            let addrs = family.order(lookup.await?.collect());
+            if addrs.is_empty() {
+                return Err(family.missing(&host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// How many threads look host names up with the system resolver, shared by every client of
+/// the process. Further lookups queue until one of them is free.
+#[cfg(feature = "reqwest")]
+const LOOKUP_THREADS: usize = 4;
+
+/// A host waiting for a lookup thread, with the state its addresses go to.
+#[cfg(feature = "reqwest")]
+type LookupJob = (String, Arc<Mutex<LookupState>>);
+
+/// Looks a host name up with the system resolver on one of [`LOOKUP_THREADS`] threads, since
+/// the lookup blocks.
+#[cfg(feature = "reqwest")]
+struct SystemLookup {
+    /// Shared with the thread doing the lookup.
+    state: Arc<Mutex<LookupState>>,
+}
+
+/// What a [`SystemLookup`] shares with its thread.
+#[cfg(feature = "reqwest")]
+#[derive(Default)]
+struct LookupState {
+    /// The addresses, once the lookup is done.
+    addrs: Option<io::Result<Vec<SocketAddr>>>,
+    /// The task waiting for them.
+    waker: Option<Waker>,
+}
+
+#[cfg(feature = "reqwest")]
+impl LookupState {
+    /// Stores the outcome of the lookup and wakes the task waiting for it.
+    fn finish(state: &Mutex<LookupState>, addrs: io::Result<Vec<SocketAddr>>) {
+        let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.addrs = Some(addrs);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl SystemLookup {
+    /// Queues the lookup of `host` for the next free lookup thread.
+    fn start(host: String) -> Self {
+        let state = Arc::new(Mutex::new(LookupState::default()));
+        if lookup_queue().send((host, Arc::clone(&state))).is_err() {
+            let error = io::Error::other("no thread could be started to look the host up");
+            LookupState::finish(&state, Err(error));
+        }
+        Self { state }
+    }
+}
+
+/// Returns the queue of the lookup threads, starting them on the first lookup.
+///
+/// The threads live as long as the process. If none of them could be started, the queue has
+/// no receiver left and every lookup fails.
+#[cfg(feature = "reqwest")]
+fn lookup_queue() -> &'static Sender<LookupJob> {
+    static QUEUE: OnceLock<Sender<LookupJob>> = OnceLock::new();
+    QUEUE.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<LookupJob>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for index in 0..LOOKUP_THREADS {
+            let receiver = Arc::clone(&receiver);
+            let spawned = thread::Builder::new()
+                .name(format!("dns-lookup-{}", index))
+                .spawn(move || loop {
+                    let job = receiver
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .recv();
+                    let Ok((host, state)) = job else { return };
+                    // The port is replaced by the one of the URL.
+                    let addrs = (host.as_str(), 0).to_socket_addrs().map(Iterator::collect);
+                    LookupState::finish(&state, addrs);
+                });
+            if let Err(e) = spawned {
+                warn!("Failed to start a thread looking host names up: {}", e);
+            }
+        }
+        sender
+    })
+}
+
+#[cfg(feature = "reqwest")]
+impl Future for SystemLookup {
+    type Output = Result<Addrs, Box<dyn std::error::Error + Send + Sync>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        match state.addrs.take() {
+            Some(addrs) => Poll::Ready(
+                addrs
+                    .map(|addrs: Vec<_>| Box::new(addrs.into_iter()) as Addrs)
+                    .map_err(Into::into),
+            ),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Applies the overrides and [`IpFamily`] of a [`DnsConfig`] to the lookups of a ureq agent,
+/// which looks up the system resolver for the hosts that aren't overridden.
+#[cfg(feature = "ureq")]
+pub(crate) struct UreqResolver {
+    /// The overridden addresses, by lowercased host name.
+    overrides: HashMap<String, Vec<IpAddr>>,
+    /// Which addresses of a looked-up host are kept, and in which order.
+    family: IpFamily,
+}
+
+#[cfg(feature = "ureq")]
+impl ureq::Resolver for UreqResolver {
+    fn resolve(&self, netloc: &str) -> io::Result<Vec<SocketAddr>> {
+        // ureq asks for `host:port`, with IPv6 literals in brackets.
+        let (host, port) = netloc
+            .rsplit_once(':')
+            .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, netloc.to_string()))?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if let Some(addrs) = self.overrides.get(&host.to_ascii_lowercase()) {
+            return Ok(addrs.iter().map(|&ip| SocketAddr::new(ip, port)).collect());
+        }
+        let addrs = self.family.order(netloc.to_socket_addrs()?.collect());
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                self.family.missing(host),
+            ));
+        }
+        Ok(addrs)
+    }
+}
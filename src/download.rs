@@ -0,0 +1,1632 @@
+use crate::auth::Auth;
+use crate::backend::{HttpBackend, HttpRequest, HttpResponse};
+use crate::checksum::{file_sha256, verify_file};
+use crate::client::blocking_client;
+use crate::config::{BatchConfig, DownloadConfig};
+use crate::content_type::check_content_type;
+use crate::context::Context;
+use crate::cookies::CookieJar;
+use crate::data_url::{self, is_data_url};
+#[cfg(feature = "gzip")]
+use crate::decompress::Decoder;
+use crate::directories::create_parent_dirs;
+use crate::disk_space::DiskGuard;
+use crate::encoding::{is_encoded, request_identity};
+use crate::error::DownloadError;
+use crate::event::{DownloadEvent, EventFlow};
+#[cfg(feature = "ftp")]
+use crate::ftp;
+use crate::headers::header_map;
+use crate::hook::panic_message;
+use crate::interceptor::for_attempt;
+use crate::length::{check_advertised, verify_length, verify_written, SizeMismatch};
+use crate::local_file::{self, is_file_url, LocalFile};
+use crate::naming::content_disposition_destination;
+use crate::overwrite::Claim;
+use crate::pinning::{report, PinMismatch};
+use crate::preflight::RemoteFile;
+use crate::presigned::{refresh, status_error};
+use crate::protocol::ProtocolVersion;
+use crate::proxy::explain_proxy_error;
+use crate::redirect::Redirects;
+use crate::resume::{
+    accepts_ranges, content_length, content_range_total, existing_length, range_from,
+};
+use crate::retry::{RetriesExhausted, RetryPolicy};
+use crate::segment::{range_header, RangeIgnored, SegmentPanicked};
+use crate::skip::{SkipReason, Skipped};
+use crate::spans::{warn, DownloadSpan};
+use crate::temp_file::{keep_after_error, partial_path};
+use crate::timeout::explain_timeout;
+use crate::timing::Stopwatch;
+use crate::transfer::{copy_body, Finished, SharedTransfer, Transfer};
+use crate::url_check::{check_url, http_version};
+use crate::validators::{remember, Validators};
+use crate::write_buffer::FileSink;
+use http::header::{HeaderMap, AUTHORIZATION, COOKIE, IF_RANGE, RANGE};
+use http::{Method, StatusCode, Version};
+use std::any::Any;
+use std::cell::RefCell;
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom};
+use std::net::SocketAddr;
+use std::ops::{ControlFlow, Range};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Downloads a file from the given URL and saves it to the specified path.
+///
+/// This is a convenience wrapper around [`download_file_with_client`] that builds a new
+/// client for a single ad-hoc download. Prefer the client variant when downloading many
+/// files so connections can be reused.
+///
+/// A `file://` URL is copied from the local disk instead; a missing or unreadable source
+/// fails with a [`DownloadError::Io`] naming it. The payload of a `data:` URL is decoded and
+/// saved, and a malformed one fails with a [`crate::InvalidUrl`] telling what is wrong.
+///
+/// # Arguments
+///
+/// * `url` - A reference to a string or string-like value specifying the download URL.
+/// * `path` - A reference to a `Path` or `PathBuf` specifying where the file will be saved.
+/// * `callback` - A function or closure that receives every [`DownloadEvent`] for this download,
+///   and may return [`std::ops::ControlFlow::Break`] to cancel it, as described by [`EventFlow`].
+///
+/// # Returns
+///
+/// * `Ok(())` if the download succeeds.
+/// * `Err` if any error occurs.
+pub fn download_file<R: EventFlow>(
+    url: impl AsRef<str>,
+    path: impl AsRef<Path>,
+    callback: impl Fn(&DownloadEvent) -> R + 'static + Send + Sync,
+) -> Result<(), DownloadError> {
+    // Initialize a blocking HTTP client with the default proxy, timeout and `User-Agent`
+    // settings.
+    let client = blocking_client(&mut BatchConfig::default())?;
+
+    download_file_with_client(&client, url, path, callback)
+}
+
+/// Downloads a file from the given URL using an existing client and saves it to the specified path.
+///
+/// Sharing one client between downloads lets requests to the same host reuse pooled
+/// connections instead of opening a new connection (and TLS session) for every file.
+/// The default [`DownloadConfig`] is used; see [`download_file_with_config`] to customize it.
+///
+/// # Arguments
+///
+/// * `client` - The blocking HTTP client used to issue the request, or any other
+///   [`crate::HttpBackend`].
+/// * `url` - A reference to a string or string-like value specifying the download URL.
+/// * `path` - A reference to a `Path` or `PathBuf` specifying where the file will be saved.
+/// * `callback` - A function or closure that receives every [`DownloadEvent`] for this download,
+///   and may return [`std::ops::ControlFlow::Break`] to cancel it, as described by [`EventFlow`].
+///
+/// # Returns
+///
+/// * `Ok(())` if the download succeeds.
+/// * `Err` if any error occurs.
+pub fn download_file_with_client<R: EventFlow>(
+    client: &impl HttpBackend,
+    url: impl AsRef<str>,
+    path: impl AsRef<Path>,
+    callback: impl Fn(&DownloadEvent) -> R + 'static + Send + Sync,
+) -> Result<(), DownloadError> {
+    download_file_with_config(client, url, path, &DownloadConfig::default(), callback)
+}
+
+/// Downloads a file from the given URL using an existing client and explicit settings.
+///
+/// The callback receives [`DownloadEvent::Started`] once the server responds,
+/// [`DownloadEvent::Progress`] as chunks are written (as often as
+/// [`DownloadConfig::progress_throttle`] allows), a [`DownloadEvent::Retrying`] before each
+/// retry, and finally either [`DownloadEvent::Completed`] or [`DownloadEvent::Failed`].
+///
+/// Transient failures (connection errors, timeouts, and 5xx responses) are retried according
+/// to [`DownloadConfig::retry`]. Every attempt starts over, unless [`DownloadConfig::resume`]
+/// is enabled and the server supports range requests.
+///
+/// The body is written to `<path>.part` in the same directory and only renamed to `path` once
+/// it has been fully written and verified, so `path` never holds a truncated file. If the
+/// download fails, the temporary file is removed and any existing file at `path` is left as it
+/// was.
+///
+/// # Arguments
+///
+/// * `client` - The blocking HTTP client used to issue the request, or any other
+///   [`crate::HttpBackend`].
+/// * `url` - A reference to a string or string-like value specifying the download URL.
+/// * `path` - A reference to a `Path` or `PathBuf` specifying where the file will be saved.
+/// * `config` - The settings applied to this download.
+/// * `callback` - A function or closure that receives every [`DownloadEvent`] for this download,
+///   and may return [`std::ops::ControlFlow::Break`] to cancel it, as described by [`EventFlow`].
+///
+/// # Returns
+///
+/// * `Ok(())` if the download succeeds.
+/// * `Err` if any error occurs. When retries were attempted, the error is a [`RetriesExhausted`]
+///   carrying the attempt count and the final underlying error.
+pub fn download_file_with_config<R: EventFlow>(
+    client: &impl HttpBackend,
+    url: impl AsRef<str>,
+    path: impl AsRef<Path>,
+    config: &DownloadConfig,
+    callback: impl Fn(&DownloadEvent) -> R + 'static + Send + Sync,
+) -> Result<(), DownloadError> {
+    download_file_with_mirrors(client, url, &[], path, config, callback)
+}
+
+/// Downloads a file from the given URL, falling back to other URLs serving the same file.
+///
+/// When the download fails with a non-success status, keeps failing after its retries, or
+/// doesn't match its checksum, the next mirror is tried, announced by a
+/// [`DownloadEvent::MirrorFailover`]. Each mirror gets its own [`DownloadConfig::retry`]
+/// attempts, and progress starts over with its [`DownloadEvent::Started`]. With
+/// [`DownloadConfig::resume`], a mirror continues the part the previous URL had written.
+///
+/// # Arguments
+///
+/// * `client` - The blocking HTTP client used to issue the request, or any other
+///   [`crate::HttpBackend`].
+/// * `url` - The URL tried first.
+/// * `mirrors` - The URLs tried next, in order.
+/// * `path` - A reference to a `Path` or `PathBuf` specifying where the file will be saved.
+/// * `config` - The settings applied to this download.
+/// * `callback` - A function or closure that receives every [`DownloadEvent`] for this download,
+///   and may return [`std::ops::ControlFlow::Break`] to cancel it, as described by [`EventFlow`].
+///
+/// # Returns
+///
+/// * `Ok(())` as soon as one of the URLs succeeds.
+/// * `Err` with the error of the last URL tried if none of them did, or with the first error
+///   that another URL couldn't fix, such as a failure to write the file.
+pub fn download_file_with_mirrors<R: EventFlow>(
+    client: &impl HttpBackend,
+    url: impl AsRef<str>,
+    mirrors: &[String],
+    path: impl AsRef<Path>,
+    config: &DownloadConfig,
+    callback: impl Fn(&DownloadEvent) -> R + 'static + Send + Sync,
+) -> Result<(), DownloadError> {
+    let (url, path) = (url.as_ref(), path.as_ref());
+    let span = DownloadSpan::new(url, path);
+    // A callback breaking out cancels the download, which stops before its next chunk.
+    let context = Context::default();
+    let callback = |event: &DownloadEvent| {
+        span.record(event);
+        if callback(event).flow().is_break() {
+            context.control.cancel();
+        }
+    };
+    span.in_scope(|| download_with_context(client, url, mirrors, path, config, &context, &callback))
+        .map(|_| ())
+}
+
+/// Downloads a file while honouring the shared batch state in `context`, such as cancellation
+/// and the global rate limit.
+///
+/// Emits the terminal [`DownloadEvent`] and returns how the download ended. A cancelled
+/// download reports [`DownloadEvent::Cancelled`] and returns [`DownloadError::Cancelled`].
+/// `mirrors` are tried in order after `url` fails.
+pub(crate) fn download_with_context(
+    client: &dyn HttpBackend,
+    url: &str,
+    mirrors: &[String],
+    path: &Path,
+    config: &DownloadConfig,
+    context: &Context,
+    callback: &(impl Fn(&DownloadEvent) + Sync),
+) -> Result<Finished, DownloadError> {
+    // Time the phases of the download from the events it sends.
+    let stopwatch = Stopwatch::start();
+    let callback = &|event: &DownloadEvent| {
+        stopwatch.observe(event);
+        callback(event);
+    };
+
+    // Run the transfer and translate its outcome into a terminal event.
+    match transfer_from_mirrors(client, url, mirrors, path, config, context, callback) {
+        Ok(Finished::Downloaded(mut transferred)) => {
+            callback(&DownloadEvent::Completed {
+                path: transferred.path.clone(),
+                bytes: transferred.bytes,
+                sha256: transferred.sha256.clone(),
+                timings: stopwatch.timings(),
+            });
+            // Hand the file to the batch's hook while it is still on this worker.
+            if let Some(hook) = &context.on_complete {
+                transferred.hook_error = hook.run(&hook.completed_file(url, &transferred));
+            }
+            Ok(Finished::Downloaded(transferred))
+        }
+        Ok(Finished::Kept(skipped)) => {
+            callback(&skipped.event());
+            Ok(Finished::Kept(skipped))
+        }
+        Err(error) if error.is_cancelled() => {
+            callback(&DownloadEvent::Cancelled {
+                path: path.to_path_buf(),
+            });
+            Err(error)
+        }
+        Err(error) => {
+            callback(&DownloadEvent::Failed {
+                error: error.to_string(),
+                timings: stopwatch.timings(),
+            });
+            Err(error)
+        }
+    }
+}
+
+/// Runs [`transfer_with_retries`] on `url` and then on each of `mirrors`, until one succeeds
+/// or fails in a way another URL wouldn't fix.
+fn transfer_from_mirrors(
+    client: &dyn HttpBackend,
+    url: &str,
+    mirrors: &[String],
+    path: &Path,
+    config: &DownloadConfig,
+    context: &Context,
+    callback: &(impl Fn(&DownloadEvent) + Sync),
+) -> Result<Finished, DownloadError> {
+    let mut url = url;
+    let mut mirrors = mirrors.iter();
+    loop {
+        match transfer_with_retries(client, url, path, config, context, callback) {
+            Err(error) if error.fails_over() => {
+                let Some(next) = mirrors.next() else {
+                    return Err(error);
+                };
+                warn!("Download of {} failed, trying {}: {}", url, next, error);
+                callback(&DownloadEvent::MirrorFailover {
+                    from: url.to_string(),
+                    to: next.clone(),
+                    error: error.to_string(),
+                });
+                url = next;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Runs [`transfer`] until it succeeds, fails permanently, or runs out of attempts.
+fn transfer_with_retries(
+    client: &dyn HttpBackend,
+    url: &str,
+    path: &Path,
+    config: &DownloadConfig,
+    context: &Context,
+    callback: &(impl Fn(&DownloadEvent) + Sync),
+) -> Result<Finished, DownloadError> {
+    // Check the download and claim its destination once, before the first request.
+    let claim = match prepare(url, path, config)? {
+        ControlFlow::Continue(claim) => claim,
+        ControlFlow::Break(skipped) => return Ok(Finished::Kept(skipped)),
+    };
+    let path = claim.as_ref().map_or(path, Claim::path);
+
+    // A pre-signed URL that expired is replaced by a fresh one and tried again right away.
+    let mut current = url.to_string();
+    let finished = with_retries(url, config, context, callback, |attempt| {
+        let config = &*for_attempt(config, attempt);
+        match transfer(client, &current, path, config, context, callback) {
+            Err(DownloadError::UrlExpired(expired)) => {
+                current = refresh(expired, context, callback)?;
+                transfer(client, &current, path, config, context, callback)
+            }
+            result => result,
+        }
+    })?;
+    Ok(finished)
+}
+
+/// Runs `attempt` until it succeeds, fails permanently, or runs out of attempts, waiting out
+/// the retry policy's delay and any `Retry-After` of the host of `url` in between. `attempt`
+/// is passed the number of the attempt, starting at 1.
+pub(crate) fn with_retries<T>(
+    url: &str,
+    config: &DownloadConfig,
+    context: &Context,
+    callback: &impl Fn(&DownloadEvent),
+    mut attempt: impl FnMut(u32) -> Result<T, DownloadError>,
+) -> Result<T, DownloadError> {
+    let mut attempts = 1;
+
+    loop {
+        // Don't start another attempt once the download has been cancelled.
+        context.control.checkpoint()?;
+
+        // Keep away from a host that asked the batch to back off.
+        if let Some(delay) = throttle_delay(url, context, callback) {
+            context.control.sleep(delay)?;
+        }
+
+        match attempt(attempts) {
+            Ok(value) => return Ok(value),
+            Err(error) => {
+                let delay = next_attempt(url, &mut attempts, error, config, context, callback)?;
+                context.control.sleep(delay)?;
+            }
+        }
+    }
+}
+
+/// Checks `url` and `config` and applies the overwrite policy to `path` before the first
+/// attempt of a download, creating the directories the file goes in.
+///
+/// # Returns
+///
+/// * `Continue` with the claim on the destination, or `None` when the server names the file
+///   and the policy is applied to its response instead.
+/// * `Break` with the existing file the policy keeps.
+/// * `Err` if the URL or the settings can't be used, or the destination can't be claimed.
+pub(crate) fn prepare(
+    url: &str,
+    path: &Path,
+    config: &DownloadConfig,
+) -> Result<ControlFlow<Skipped, Option<Claim>>, DownloadError> {
+    // Refuse a malformed URL or an unsupported scheme, and settings no download can run
+    // with, before touching the disk.
+    check_url(url)?;
+    config.check()?;
+
+    // Make room for the destination before anything is written to its directory.
+    create_parent_dirs(path, config)?;
+
+    // Apply the overwrite policy once, before the first request. When the server names the
+    // file, the policy is applied to each response instead.
+    if config.name_from_content_disposition {
+        return Ok(ControlFlow::Continue(None));
+    }
+    Ok(match Claim::new(path, config.overwrite)? {
+        Some(claim) => ControlFlow::Continue(Some(claim)),
+        None => ControlFlow::Break(Skipped::existing(path.to_path_buf())),
+    })
+}
+
+/// Returns how long to wait before the next attempt at `url` if its host asked the batch to
+/// back off, reporting the wait as [`DownloadEvent::Throttled`].
+pub(crate) fn throttle_delay(
+    url: &str,
+    context: &Context,
+    callback: &impl Fn(&DownloadEvent),
+) -> Option<Duration> {
+    let delay = context.throttle.remaining(url)?;
+    callback(&DownloadEvent::Throttled {
+        url: url.to_string(),
+        delay,
+    });
+    Some(delay)
+}
+
+/// Decides what follows attempt number `attempts` at `url` failing with `error`.
+///
+/// # Returns
+///
+/// * `Ok` with how long to wait before the next attempt, reported as
+///   [`DownloadEvent::Retrying`] once `attempts` counts it.
+/// * `Err` with the error to give up with: a cancellation, a failure the retry policy doesn't
+///   retry, or a [`RetriesExhausted`] once retries have been used.
+pub(crate) fn next_attempt(
+    url: &str,
+    attempts: &mut u32,
+    error: DownloadError,
+    config: &DownloadConfig,
+    context: &Context,
+    callback: &impl Fn(&DownloadEvent),
+) -> Result<Duration, DownloadError> {
+    // Cancellation is never retried and is not reported as an exhausted retry.
+    if error.is_cancelled() {
+        return Err(error);
+    }
+    // The retry policy decides which failures are retried, and after how long.
+    let Some(backoff) = config.retry.next_delay(*attempts, &error) else {
+        // Once retries have been used, report how many attempts were made.
+        return Err(if *attempts > 1 {
+            RetriesExhausted {
+                attempts: *attempts,
+                last_error: Box::new(error),
+            }
+            .into()
+        } else {
+            error
+        });
+    };
+    let delay = retry_delay(url, &error, backoff, config, context, callback);
+    warn!(
+        "Attempt {} for {} failed, retrying in {:?}: {}",
+        attempts, url, delay, error
+    );
+    *attempts += 1;
+    context.metrics.retried();
+    callback(&DownloadEvent::Retrying {
+        attempt: *attempts,
+        delay,
+        error: error.to_string(),
+    });
+    Ok(delay)
+}
+
+/// Returns how long to wait after an attempt at `url` failed with `error` before the next
+/// one, given the `backoff` the retry policy picked.
+///
+/// A server that asked for a delay with `Retry-After` gets at least that long, up to
+/// [`crate::RetryConfig::max_retry_after`], and so does every other download from its host.
+pub(crate) fn retry_delay(
+    url: &str,
+    error: &DownloadError,
+    backoff: Duration,
+    config: &DownloadConfig,
+    context: &Context,
+    callback: &impl Fn(&DownloadEvent),
+) -> Duration {
+    let Some(requested) = error.retry_after() else {
+        return backoff;
+    };
+    let requested = requested.min(config.retry.max_retry_after);
+    context.throttle.pause(url, requested);
+    callback(&DownloadEvent::Throttled {
+        url: url.to_string(),
+        delay: requested,
+    });
+    backoff.max(requested)
+}
+
+/// Asks the server for the size of `url` with a `HEAD` request.
+///
+/// Returns `None` if the request fails or the server doesn't report a length.
+pub(crate) fn probe_size(
+    client: &dyn HttpBackend,
+    url: &str,
+    config: &DownloadConfig,
+    cookies: Option<&CookieJar>,
+) -> Option<u64> {
+    let headers = header_map(&config.headers).ok()?;
+    let response =
+        send_following(client, Method::HEAD, url, headers, config, cookies, &|_| {}).ok()?;
+    content_length(&response.headers).filter(|_| response.status.is_success())
+}
+
+/// Asks the server about `url` without downloading it, for [`crate::preflight`].
+///
+/// Sends a `HEAD` request, or a `GET` for the first byte if the server refuses `HEAD`.
+/// Redirects are followed silently.
+pub(crate) fn probe(
+    client: &dyn HttpBackend,
+    url: &str,
+    config: &DownloadConfig,
+    cookies: Option<&CookieJar>,
+) -> Result<RemoteFile, DownloadError> {
+    let mut headers = header_map(&config.headers)?;
+    let head = send_following(
+        client,
+        Method::HEAD,
+        url,
+        headers.clone(),
+        config,
+        cookies,
+        &|_| {},
+    )?;
+    if !rejects_head(head.status) {
+        return Ok(RemoteFile::from_response(
+            url,
+            head.status,
+            &head.url,
+            &head.headers,
+        ));
+    }
+
+    // The body is never read, so a server ignoring the range costs no more than its headers.
+    headers.insert(RANGE, range_header(&(0..1)));
+    let get = send_following(client, Method::GET, url, headers, config, cookies, &|_| {})?;
+    Ok(RemoteFile::from_response(
+        url,
+        get.status,
+        &get.url,
+        &get.headers,
+    ))
+}
+
+/// Returns `true` if a server answering `HEAD` with `status` may still answer a `GET`.
+///
+/// Besides servers that don't implement `HEAD`, pre-signed URLs are often only signed for
+/// `GET` and refuse anything else as forbidden.
+fn rejects_head(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED | StatusCode::FORBIDDEN
+    )
+}
+
+/// Downloads `url` into a temporary file next to `path` and moves it into place on success.
+///
+/// Returns the path the file was actually saved to and its size on disk. On failure the
+/// temporary file is removed unless it is kept for a later resume.
+fn transfer(
+    client: &dyn HttpBackend,
+    url: &str,
+    path: &Path,
+    config: &DownloadConfig,
+    context: &Context,
+    callback: &(impl Fn(&DownloadEvent) + Sync),
+) -> Result<Finished, DownloadError> {
+    let temp = partial_path(path, config);
+    let result = stream_to_temp(client, url, path, &temp, config, context, callback)
+        .map_err(|error| explain_timeout(error, &config.timeouts))
+        .map_err(|error| explain_proxy_error(error, url, &context.proxy));
+
+    if let Err(error) = &result {
+        if !keep_after_error(error, config) {
+            // Cleanup is best effort; the download error is the one worth reporting.
+            let _ = std::fs::remove_file(&temp);
+        }
+    }
+
+    result
+}
+
+/// Streams the body of `url` into `temp`, then renames it to its final destination.
+///
+/// `path` is the requested destination, which the server may rename through
+/// `Content-Disposition`. Every decision about the response is made by helpers shared with
+/// the async API, leaving only the requests and the body's reads to this function.
+fn stream_to_temp(
+    client: &dyn HttpBackend,
+    url: &str,
+    path: &Path,
+    temp: &Path,
+    config: &DownloadConfig,
+    context: &Context,
+    callback: &(impl Fn(&DownloadEvent) + Sync),
+) -> Result<Finished, DownloadError> {
+    // Remember when the request started so progress can report speed and ETA.
+    let started = Instant::now();
+
+    // When resuming, continue an earlier partial file if the server supports ranges.
+    let offset = resume_offset(temp, config);
+    // Custom headers and credentials go out with every request, including the range probe.
+    let headers = header_map(&config.headers)?;
+    let ranged = offset == 0
+        || accepts_ranges(
+            // The probe's redirects are followed silently; the GET below reports them.
+            &send_following(
+                client,
+                Method::HEAD,
+                url,
+                headers.clone(),
+                config,
+                context.cookies.as_ref(),
+                &|_| {},
+            )?
+            .headers,
+        );
+    let plan = Plan::new(url, path, offset, ranged, config, callback);
+
+    // Split a large file across several connections when the server accepts ranges. The
+    // probe's headers then stand in for those of the GET response below.
+    let segments = if plan.may_segment(config) {
+        // Hold the probe's redirects back until it is clear the probe replaces the GET.
+        let hops = RefCell::new(Vec::new());
+        let probe = send_following(
+            client,
+            Method::HEAD,
+            url,
+            headers.clone(),
+            config,
+            context.cookies.as_ref(),
+            &|event| hops.borrow_mut().push(event.clone()),
+        )?;
+        segment_plan(probe.status, &probe.headers, config).map(|ranges| {
+            hops.into_inner().iter().for_each(callback);
+            (probe, ranges)
+        })
+    } else {
+        None
+    };
+
+    // Perform an HTTP GET request to the given URL, treating non-success statuses as errors.
+    let (response, segments) = match segments {
+        Some((probe, ranges)) => (probe, Some(ranges)),
+        None => {
+            let headers = plan.get_headers(&headers, path, context);
+            let cookies = context.cookies.as_ref();
+            let response =
+                send_following(client, Method::GET, url, headers, config, cookies, callback)?;
+            (response, None)
+        }
+    };
+    let head = ResponseHead {
+        status: response.status,
+        headers: &response.headers,
+        url: &response.url,
+        version: response.version,
+        remote_addr: response.remote_addr,
+    };
+    let mut accepted = match accept(url, path, temp, &plan, head, config, context)? {
+        Verdict::Save(accepted) => *accepted,
+        Verdict::Kept(skipped) => return Ok(Finished::Kept(skipped)),
+        Verdict::Failed => return Err(status_error(url, response)),
+    };
+    let claim = accepted.claim.take();
+    let path = claim.as_ref().map_or(path, Claim::path);
+
+    let (mut transfer, written) = match segments {
+        // Download every range on its own connection straight into place in the temporary
+        // file, which is allocated at its full size up front.
+        Some(ranges) => {
+            let total = allocate_segments(url, temp, &ranges, config)?;
+            let transfer = Transfer::start(url, Some(total), 0, started, config, context, callback)
+                .without_hashing();
+            let fetch = |range: &Range<u64>| {
+                let mut headers = headers.clone();
+                headers.insert(RANGE, range_header(range));
+                let cookies = context.cookies.as_ref();
+                send_following(client, Method::GET, url, headers, config, cookies, &|_| {})
+            };
+            // A preallocated file has gaps wherever a segment stopped, so it can never be
+            // resumed and is removed even when partial files are otherwise kept.
+            let transfer = download_segments(&fetch, url, temp, &ranges, context, transfer)
+                .inspect_err(|_| {
+                    let _ = std::fs::remove_file(temp);
+                })?;
+            (transfer, Written::Segments)
+        }
+        None => {
+            let length = response.content_length;
+            let (file, disk_guard, streaming) =
+                open_temp(url, temp, &plan, &accepted, length, config, callback)?;
+
+            // Collect small chunks into larger writes.
+            let sink = FileSink::new(file, temp, config.write.buffer_size, disk_guard);
+            #[cfg(feature = "gzip")]
+            let sink = sink.decoding(Decoder::new(url, path, config));
+            let mut sink = sink;
+
+            // Announce that the body is about to be streamed, counting any resumed prefix as downloaded.
+            let mut transfer = Transfer::start(
+                url,
+                streaming.expected_bytes,
+                streaming.prefix,
+                started,
+                config,
+                context,
+                callback,
+            );
+
+            // Stream the body into the file.
+            let mut body = response.body.take(streaming.limit);
+            let copied = copy_body(url, &mut body, &mut sink, &mut transfer, context)?;
+
+            // Write out whatever is still buffered before the file is checked.
+            let (file, bytes) = sink.into_file()?;
+            (
+                transfer,
+                Written::Streamed {
+                    file,
+                    bytes: streaming.prefix + bytes,
+                    received: streaming.prefix + copied,
+                    streaming,
+                },
+            )
+        }
+    };
+
+    // Check the complete file and move it into place.
+    let sha256 = transfer.take_sha256();
+    let fresh_validators = accepted.fresh_validators.take();
+    let decompressed = written.decompressed(config);
+    let sha256 = finish_temp(
+        temp,
+        path,
+        written,
+        sha256,
+        fresh_validators.as_ref(),
+        config,
+    )?;
+
+    Ok(accepted.finished(transfer, path, sha256, decompressed))
+}
+
+/// What the answer to the `GET` of a download holds, as [`classify`] tells from its status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Reply {
+    /// The file hasn't changed since it was last downloaded.
+    NotModified,
+    /// An error status, described by the body of the response.
+    Failed,
+    /// Nothing: the partial file already holds the whole file, which the server answered
+    /// with 416 and the file's size.
+    Complete,
+    /// The rest of the partial file.
+    Resumed,
+    /// The whole file from its first byte, even when a range was asked for.
+    Whole,
+}
+
+/// Tells what the answer with `status` and `headers` to the `GET` of `url` holds, given the
+/// `offset` it was asked to resume from and whether it was a `conditional` request.
+///
+/// A 416 that doesn't fit the partial file in `temp` removes it, so the next attempt starts
+/// over. A body that is a page standing in for the file is refused before anything is
+/// written.
+pub(crate) fn classify(
+    url: &str,
+    temp: &Path,
+    offset: u64,
+    status: StatusCode,
+    headers: &HeaderMap,
+    conditional: bool,
+    config: &DownloadConfig,
+) -> Result<Reply, DownloadError> {
+    if conditional && status == StatusCode::NOT_MODIFIED {
+        return Ok(Reply::NotModified);
+    }
+    if offset > 0
+        && status == StatusCode::RANGE_NOT_SATISFIABLE
+        && content_range_total(headers) == Some(offset)
+    {
+        return Ok(Reply::Complete);
+    }
+    if status == StatusCode::RANGE_NOT_SATISFIABLE {
+        let _ = std::fs::remove_file(temp);
+    }
+    if is_error(status) {
+        return Ok(Reply::Failed);
+    }
+    check_content_type(url, headers, config)?;
+    Ok(if offset > 0 && status == StatusCode::PARTIAL_CONTENT {
+        Reply::Resumed
+    } else {
+        Reply::Whole
+    })
+}
+
+/// Returns `true` if `status` is a client or server error.
+pub(crate) fn is_error(status: StatusCode) -> bool {
+    status.is_client_error() || status.is_server_error()
+}
+
+/// Returns the headers of the `GET` of a download: `headers` plus, when resuming from
+/// `offset`, the range to send and the version of the file at `path` that a batch state
+/// recorded, under which the server sends the whole file again if it changed since, and the
+/// `validators` of a conditional request.
+pub(crate) fn get_headers(
+    headers: &HeaderMap,
+    offset: u64,
+    path: &Path,
+    validators: Option<&Validators>,
+    context: &Context,
+) -> HeaderMap {
+    let mut headers = headers.clone();
+    if offset > 0 {
+        headers.insert(RANGE, range_from(offset));
+        let if_range = context
+            .state
+            .as_ref()
+            .and_then(|state| state.validators(path))
+            .and_then(|validators| validators.if_range());
+        if let Some(if_range) = if_range {
+            headers.insert(IF_RANGE, if_range);
+        }
+    }
+    if let Some(validators) = validators {
+        headers.extend(validators.request_headers());
+    }
+    headers
+}
+
+/// Records which version of the file at `path` the response `headers` describe in the batch
+/// state, if there is one, so a later run resumes it safely.
+pub(crate) fn remember_version(context: &Context, path: &Path, headers: &HeaderMap) {
+    if let Some(state) = &context.state {
+        if let Some(validators) = Validators::from_headers(headers) {
+            state.remember_validators(path, validators);
+        }
+    }
+}
+
+/// Applies the overwrite policy to the name the server picked in `headers` for the file
+/// requested at `path`, keeping it in the requested directory, when
+/// [`DownloadConfig::name_from_content_disposition`] lets it pick one. A partial file being
+/// resumed from `offset` keeps the name it was started under.
+///
+/// # Returns
+///
+/// * `Continue` with the claim on the destination, or `None` when the name was claimed
+///   before the first attempt.
+/// * `Break` with the existing file the policy keeps.
+pub(crate) fn claim_served_name(
+    path: &Path,
+    offset: u64,
+    headers: &HeaderMap,
+    config: &DownloadConfig,
+) -> Result<ControlFlow<Skipped, Option<Claim>>, DownloadError> {
+    if !config.name_from_content_disposition {
+        return Ok(ControlFlow::Continue(None));
+    }
+    let destination = if offset == 0 {
+        content_disposition_destination(path, headers, &config.file_names)
+    } else {
+        path.to_path_buf()
+    };
+    Ok(match Claim::new(&destination, config.overwrite)? {
+        Some(claim) => ControlFlow::Continue(Some(claim)),
+        None => ControlFlow::Break(Skipped::existing(destination)),
+    })
+}
+
+/// Returns the size of the whole file a `reply` with `headers` and the body length
+/// `content_length` belongs to, if the server reported it.
+///
+/// The length of a compressed body says nothing about the file, so its total stays unknown.
+/// A resumed body only holds what comes after `offset`.
+pub(crate) fn expected_bytes(
+    reply: Reply,
+    offset: u64,
+    headers: &HeaderMap,
+    content_length: Option<u64>,
+) -> Option<u64> {
+    match reply {
+        Reply::Complete => Some(offset),
+        _ if is_encoded(headers) => None,
+        Reply::Resumed => {
+            content_range_total(headers).or_else(|| content_length.map(|length| length + offset))
+        }
+        _ => content_length,
+    }
+}
+
+/// Returns `true` if a download starting at `offset` may be split into segments, which a
+/// resumed download or a conditional request with `validators` never is.
+pub(crate) fn may_segment(
+    offset: u64,
+    validators: Option<&Validators>,
+    config: &DownloadConfig,
+) -> bool {
+    config.segments.is_enabled() && offset == 0 && validators.is_none() && !config.decompresses()
+}
+
+/// Splits the file a `HEAD` answered with `status` and `headers` into the ranges of its
+/// segments, or returns `None` if the server doesn't take ranges, the file is compressed or
+/// too small.
+pub(crate) fn segment_plan(
+    status: StatusCode,
+    headers: &HeaderMap,
+    config: &DownloadConfig,
+) -> Option<Vec<Range<u64>>> {
+    Some(headers)
+        .filter(|headers| status.is_success() && accepts_ranges(headers) && !is_encoded(headers))
+        .and_then(content_length)
+        .and_then(|total| config.segments.plan(total))
+}
+
+/// Allocates `temp` at the full size of a file split into `ranges`, once it fits the size
+/// limit and the disk, and returns that size.
+pub(crate) fn allocate_segments(
+    url: &str,
+    temp: &Path,
+    ranges: &[Range<u64>],
+    config: &DownloadConfig,
+) -> Result<u64, DownloadError> {
+    let total = ranges.last().map_or(0, |range| range.end);
+    check_advertised(url, Some(total), 0, config)?;
+    if config.check_disk_space {
+        DiskGuard::new(temp, total)?;
+    }
+    std::fs::File::create(temp)
+        .and_then(|file| file.set_len(total))
+        .map_err(DownloadError::io(temp))?;
+    Ok(total)
+}
+
+/// Checks the answer with `status` to the range request of a segment of `url`, once it isn't
+/// an error: anything but a partial response would write the wrong bytes at its offset.
+pub(crate) fn check_segment(url: &str, status: StatusCode) -> Result<(), RangeIgnored> {
+    if status == StatusCode::PARTIAL_CONTENT {
+        Ok(())
+    } else {
+        Err(RangeIgnored {
+            url: url.to_string(),
+        })
+    }
+}
+
+/// Collects how many bytes each segment of `temp` split into `ranges` wrote, failing with the
+/// first real failure rather than a segment that merely stopped because of it, or when a
+/// segment came out short and left a gap in the middle of the file.
+pub(crate) fn check_segments(
+    temp: &Path,
+    ranges: &[Range<u64>],
+    results: Vec<Result<u64, DownloadError>>,
+) -> Result<(), DownloadError> {
+    let mut written = 0;
+    for result in results {
+        written += result?;
+    }
+    let total = ranges.last().map_or(0, |range| range.end);
+    if written != total {
+        return Err(SizeMismatch {
+            path: temp.to_path_buf(),
+            expected: total,
+            actual: written,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Checks the complete file in `temp` and moves it to `path`, returning its SHA-256.
+///
+/// `sha256` is the digest computed while streaming, if any. Segments arrive out of order and
+/// a resumed prefix never streams past, so those files are hashed here instead when
+/// [`DownloadConfig::compute_sha256`] asks for it, as are decompressed files, so the digest is
+/// that of the file at `path`. The file is rejected if it doesn't match the expected checksum,
+/// and the `fresh_validators` of its response are remembered for a conditional request.
+pub(crate) fn finish_file(
+    temp: &Path,
+    path: &Path,
+    sha256: Option<String>,
+    fresh_validators: Option<&Validators>,
+    config: &DownloadConfig,
+) -> Result<Option<String>, DownloadError> {
+    let sha256 = match sha256 {
+        None if config.compute_sha256 => Some(file_sha256(temp)?),
+        sha256 => sha256,
+    };
+    // Reject the finished file if it doesn't match the expected digest, reusing the SHA-256
+    // when there is one.
+    if let Some(checksum) = config.file_checksum() {
+        verify_file(temp, checksum, sha256.as_deref())?;
+    }
+
+    // Move the complete file into place in a single step.
+    std::fs::rename(temp, path).map_err(DownloadError::io(path))?;
+    if config.conditional_requests {
+        remember(path, fresh_validators);
+    }
+    Ok(sha256)
+}
+
+/// Returns the byte a download into `temp` resumes from: the length of its partial file when
+/// [`DownloadConfig::resume`] is set, and `0` otherwise or when the body is decompressed.
+pub(crate) fn resume_offset(temp: &Path, config: &DownloadConfig) -> u64 {
+    if config.resume && !config.decompresses() {
+        existing_length(temp)
+    } else {
+        0
+    }
+}
+
+/// What a download settles before sending its `GET`: where it resumes from and whether it
+/// asks the server to skip an unchanged file.
+pub(crate) struct Plan {
+    /// The byte the partial file is continued from, or `0` when the file starts over.
+    pub(crate) offset: u64,
+    /// The validators of a conditional request, if one is made.
+    pub(crate) validators: Option<Validators>,
+}
+
+impl Plan {
+    /// Plans the download of `url` to `path`, resuming from `offset` only if the server
+    /// `accepts_ranges`, and reporting through `callback` when the partial file is discarded.
+    pub(crate) fn new(
+        url: &str,
+        path: &Path,
+        offset: u64,
+        accepts_ranges: bool,
+        config: &DownloadConfig,
+        callback: &impl Fn(&DownloadEvent),
+    ) -> Self {
+        let offset = if offset > 0 && !accepts_ranges {
+            callback(&DownloadEvent::ResumeRestarted {
+                url: url.to_string(),
+                discarded_bytes: offset,
+            });
+            0
+        } else {
+            offset
+        };
+        // Ask the server to skip a file that hasn't changed since it was last downloaded.
+        let validators = if config.conditional_requests && offset == 0 {
+            Validators::load(path)
+        } else {
+            None
+        };
+        Self { offset, validators }
+    }
+
+    /// Returns `true` if the download may be split into segments, as [`may_segment`] tells.
+    pub(crate) fn may_segment(&self, config: &DownloadConfig) -> bool {
+        may_segment(self.offset, self.validators.as_ref(), config)
+    }
+
+    /// Returns the headers of the `GET` of the download to `path`, as [`get_headers`] builds
+    /// them from `headers`.
+    pub(crate) fn get_headers(
+        &self,
+        headers: &HeaderMap,
+        path: &Path,
+        context: &Context,
+    ) -> HeaderMap {
+        get_headers(
+            headers,
+            self.offset,
+            path,
+            self.validators.as_ref(),
+            context,
+        )
+    }
+}
+
+/// The parts of a response that [`accept`] decides on, borrowed from whichever type the
+/// client answered with.
+pub(crate) struct ResponseHead<'a> {
+    /// The status of the response.
+    pub(crate) status: StatusCode,
+    /// The headers of the response.
+    pub(crate) headers: &'a HeaderMap,
+    /// The URL that answered, once redirects were followed.
+    pub(crate) url: &'a Url,
+    /// The HTTP version the response was served with.
+    pub(crate) version: Version,
+    /// The address of the server that answered, if the client reports it.
+    pub(crate) remote_addr: Option<SocketAddr>,
+}
+
+/// What [`accept`] makes of the answer to the `GET` of a download.
+pub(crate) enum Verdict {
+    /// Save the body of the response.
+    Save(Box<Accepted>),
+    /// Keep the existing file without saving anything.
+    Kept(Skipped),
+    /// The response is an error status, which its body describes.
+    Failed,
+}
+
+/// A response [`accept`] lets a download save, and what the download keeps of it.
+pub(crate) struct Accepted {
+    /// What the body holds.
+    pub(crate) reply: Reply,
+    /// The URL that answered, once redirects were followed.
+    pub(crate) final_url: String,
+    /// The HTTP version the response was served with, or `None` for a URL that isn't HTTP.
+    pub(crate) http_version: Option<ProtocolVersion>,
+    /// The address of the server that answered, if the client reports it.
+    pub(crate) remote_addr: Option<SocketAddr>,
+    /// The headers of the response.
+    pub(crate) headers: HeaderMap,
+    /// The validators of the response, remembered for a conditional request once the file
+    /// is in place.
+    pub(crate) fresh_validators: Option<Validators>,
+    /// The claim on the name the server picked, if it picked one.
+    pub(crate) claim: Option<Claim>,
+}
+
+impl Accepted {
+    /// Ends the download saved to `path` with the SHA-256 `sha256`, reporting its completion
+    /// through `transfer`.
+    ///
+    /// `decompressed` is the size of a file whose body was decompressed, which is reported
+    /// instead of the bytes received.
+    pub(crate) fn finished<F: Fn(&DownloadEvent)>(
+        self,
+        transfer: Transfer<'_, F>,
+        path: &Path,
+        sha256: Option<String>,
+        decompressed: Option<u64>,
+    ) -> Finished {
+        let mut transferred = transfer.finish(
+            path.to_path_buf(),
+            sha256,
+            self.final_url,
+            self.http_version,
+            self.remote_addr,
+            self.headers,
+        );
+        if let Some(bytes) = decompressed {
+            transferred.bytes = bytes;
+        }
+        Finished::Downloaded(transferred)
+    }
+}
+
+/// Decides what the download of `url` to `path`, planned as `plan`, does with the response
+/// `head` to its `GET`, as [`classify`] tells and the overwrite policy allows.
+///
+/// A response to save records the version of the file in the batch state, if there is one,
+/// and claims the name the server picked.
+pub(crate) fn accept(
+    url: &str,
+    path: &Path,
+    temp: &Path,
+    plan: &Plan,
+    head: ResponseHead<'_>,
+    config: &DownloadConfig,
+    context: &Context,
+) -> Result<Verdict, DownloadError> {
+    let conditional = plan.validators.is_some();
+    let reply = classify(
+        url,
+        temp,
+        plan.offset,
+        head.status,
+        head.headers,
+        conditional,
+        config,
+    )?;
+    match reply {
+        Reply::NotModified => {
+            return Ok(Verdict::Kept(Skipped {
+                path: path.to_path_buf(),
+                reason: SkipReason::NotModified,
+            }))
+        }
+        Reply::Failed => return Ok(Verdict::Failed),
+        _ => {}
+    }
+    remember_version(context, path, head.headers);
+
+    // Remember the new validators so the next run can make a conditional request.
+    let fresh_validators = if config.conditional_requests {
+        Validators::from_headers(head.headers)
+    } else {
+        None
+    };
+
+    // Let the server pick the file name and apply the overwrite policy to it.
+    let claim = match claim_served_name(path, plan.offset, head.headers, config)? {
+        ControlFlow::Continue(claim) => claim,
+        ControlFlow::Break(skipped) => return Ok(Verdict::Kept(skipped)),
+    };
+    Ok(Verdict::Save(Box::new(Accepted {
+        reply,
+        final_url: head.url.to_string(),
+        http_version: http_version(head.url, head.version),
+        remote_addr: head.remote_addr,
+        headers: head.headers.clone(),
+        fresh_validators,
+        claim,
+    })))
+}
+
+/// How [`open_temp`] set up the temporary file a body is streamed into.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Streaming {
+    /// The bytes of the partial file the body continues, which count as downloaded.
+    pub(crate) prefix: u64,
+    /// The size of the whole file, if the server reported it.
+    pub(crate) expected_bytes: Option<u64>,
+    /// Whether the file was allocated at `expected_bytes` up front.
+    pub(crate) preallocated: bool,
+    /// How many bytes of the body to read. The body answering a complete partial file is
+    /// only an error page, so none of it is read.
+    pub(crate) limit: u64,
+}
+
+/// Opens `temp` for the body of the response `accepted` let the download of `url` save, of
+/// `content_length` bytes if the response said: appended to when resuming as `plan` settled,
+/// and created afresh otherwise.
+///
+/// A file too large for [`DownloadConfig::max_size`] is refused before `temp` is touched,
+/// and one that doesn't fit on the disk before anything is written.
+///
+/// # Returns
+///
+/// * The file, the guard watching the disk while it is written, and how it was set up.
+pub(crate) fn open_temp<'a>(
+    url: &str,
+    temp: &'a Path,
+    plan: &Plan,
+    accepted: &Accepted,
+    content_length: Option<u64>,
+    config: &DownloadConfig,
+    callback: &impl Fn(&DownloadEvent),
+) -> Result<(std::fs::File, Option<DiskGuard<'a>>, Streaming), DownloadError> {
+    let reply = accepted.reply;
+    if reply == Reply::Whole && plan.offset > 0 {
+        callback(&DownloadEvent::ResumeRestarted {
+            url: url.to_string(),
+            discarded_bytes: plan.offset,
+        });
+    }
+    let prefix = if reply == Reply::Whole {
+        0
+    } else {
+        plan.offset
+    };
+
+    // Refuse a file that is too large before touching the temporary file.
+    let expected_bytes = expected_bytes(reply, plan.offset, &accepted.headers, content_length);
+    check_advertised(url, expected_bytes, prefix, config)?;
+
+    // Append to the partial file when resuming, otherwise start a fresh temporary file.
+    let file = if prefix > 0 {
+        OpenOptions::new()
+            .append(true)
+            .open(temp)
+            .map_err(DownloadError::io(temp))?
+    } else {
+        std::fs::File::create(temp).map_err(DownloadError::io(temp))?
+    };
+
+    // Make sure the rest of the file fits on the disk before streaming it. A decompressed file
+    // takes at least the compressed bytes, and is watched for however large it grows.
+    let disk_guard = if !config.check_disk_space {
+        None
+    } else if config.decompresses() {
+        Some(DiskGuard::new(temp, expected_bytes.unwrap_or(0))?.open_ended())
+    } else {
+        match expected_bytes {
+            Some(total) => Some(DiskGuard::new(
+                temp,
+                total.saturating_sub(existing_length(temp)),
+            )?),
+            None => None,
+        }
+    };
+
+    // Reserve the whole file up front so the filesystem can allocate it contiguously.
+    let preallocated =
+        config.preallocate && prefix == 0 && expected_bytes.is_some() && !config.decompresses();
+    if let Some(total) = expected_bytes.filter(|_| preallocated) {
+        file.set_len(total).map_err(DownloadError::io(temp))?;
+    }
+
+    let limit = if reply == Reply::Complete {
+        0
+    } else {
+        u64::MAX
+    };
+    Ok((
+        file,
+        disk_guard,
+        Streaming {
+            prefix,
+            expected_bytes,
+            preallocated,
+            limit,
+        },
+    ))
+}
+
+/// How the body of a download reached its temporary file, which tells [`finish_temp`] how
+/// to check it.
+pub(crate) enum Written {
+    /// Every segment was written into place in a file allocated at its full size.
+    Segments,
+    /// The body was streamed into `file`, which [`open_temp`] set up as `streaming`
+    /// describes and which now holds `bytes`, counting a resumed prefix. `received` counts the
+    /// bytes of the body, which differ from those of the file when it was decompressed.
+    Streamed {
+        file: std::fs::File,
+        bytes: u64,
+        received: u64,
+        streaming: Streaming,
+    },
+}
+
+impl Written {
+    /// Returns the size of the file if its body was decompressed, so the file's size differs
+    /// from the bytes received.
+    pub(crate) fn decompressed(&self, config: &DownloadConfig) -> Option<u64> {
+        match self {
+            Written::Streamed { bytes, .. } if config.decompresses() => Some(*bytes),
+            _ => None,
+        }
+    }
+}
+
+/// Makes sure the body `written` to `temp` reached the disk whole, then checks the file and
+/// moves it to `path` with [`finish_file`], returning its SHA-256.
+///
+/// A preallocated file that ended short shrinks back to the bytes actually received. Every
+/// byte meant for the file must have reached it, and the body must have the size the server
+/// advertised, which for a resumed file also proves it was stitched back together
+/// completely.
+pub(crate) fn finish_temp(
+    temp: &Path,
+    path: &Path,
+    written: Written,
+    sha256: Option<String>,
+    fresh_validators: Option<&Validators>,
+    config: &DownloadConfig,
+) -> Result<Option<String>, DownloadError> {
+    match written {
+        Written::Segments => {
+            if config.write.sync {
+                // Windows only syncs files opened for writing.
+                OpenOptions::new()
+                    .write(true)
+                    .open(temp)
+                    .and_then(|file| file.sync_all())
+                    .map_err(DownloadError::io(temp))?;
+            }
+        }
+        Written::Streamed {
+            mut file,
+            bytes,
+            received,
+            streaming,
+        } => {
+            if streaming.preallocated {
+                file.stream_position()
+                    .and_then(|written| file.set_len(written))
+                    .map_err(DownloadError::io(temp))?;
+            }
+            if config.write.sync {
+                file.sync_all().map_err(DownloadError::io(temp))?;
+            }
+            drop(file);
+            verify_written(temp, bytes)?;
+            if !config.ignore_content_length {
+                verify_length(temp, received, streaming.expected_bytes)?;
+            }
+        }
+    }
+    finish_file(temp, path, sha256, fresh_validators, config)
+}
+
+/// Downloads every range of the file at `url` on its own thread, writing each straight into
+/// its place in `temp`.
+///
+/// `fetch` sends the ranged request for one segment. All segments report into the same
+/// `transfer`, so progress covers the whole file. The first failing segment stops the others.
+fn download_segments<'a, F: Fn(&DownloadEvent) + Sync>(
+    fetch: &(impl Fn(&Range<u64>) -> Result<HttpResponse, DownloadError> + Sync),
+    url: &str,
+    temp: &Path,
+    ranges: &[Range<u64>],
+    context: &Context,
+    transfer: Transfer<'a, F>,
+) -> Result<Transfer<'a, F>, DownloadError> {
+    let transfer = Mutex::new(transfer);
+    let stop = AtomicBool::new(false);
+
+    let results: Vec<_> = std::thread::scope(|scope| {
+        let workers: Vec<_> = ranges
+            .iter()
+            .map(|range| {
+                let (transfer, stop) = (&transfer, &stop);
+                scope.spawn(move || {
+                    // A panic fails the download like any other segment error, and stops the
+                    // other segments just as early.
+                    let result = catch_unwind(AssertUnwindSafe(|| {
+                        download_segment(fetch, url, temp, range, context, transfer, stop)
+                    }))
+                    .unwrap_or_else(|panic| Err(segment_panicked(url, panic)));
+                    if result.is_err() {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    result
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| Err(segment_panicked(url, panic)))
+            })
+            .collect()
+    });
+
+    check_segments(temp, ranges, results)?;
+    Ok(transfer.into_inner().unwrap())
+}
+
+/// The error of a segment of `url` whose thread panicked with `panic`.
+fn segment_panicked(url: &str, panic: Box<dyn Any + Send>) -> DownloadError {
+    SegmentPanicked {
+        url: url.to_string(),
+        message: panic_message(panic.as_ref()),
+    }
+    .into()
+}
+
+/// Downloads one segment of a file into its place in `temp`, returning how many bytes were
+/// written.
+///
+/// Stops early without an error once `stop` is set by a failing segment.
+fn download_segment<F: Fn(&DownloadEvent)>(
+    fetch: &impl Fn(&Range<u64>) -> Result<HttpResponse, DownloadError>,
+    url: &str,
+    temp: &Path,
+    range: &Range<u64>,
+    context: &Context,
+    transfer: &Mutex<Transfer<'_, F>>,
+    stop: &AtomicBool,
+) -> Result<u64, DownloadError> {
+    let response = fetch(range)?;
+    if is_error(response.status) {
+        return Err(status_error(url, response));
+    }
+    check_segment(url, response.status)?;
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(temp)
+        .map_err(DownloadError::io(temp))?;
+    file.seek(SeekFrom::Start(range.start))
+        .map_err(DownloadError::io(temp))?;
+    let mut pace = SharedTransfer { transfer, stop };
+    let write_buffer = pace.lock().write_buffer();
+    let mut sink = FileSink::new(file, temp, write_buffer, None);
+
+    // Never write past the end of this segment, even if the server sends more. Both limits
+    // are shared by every segment of the file.
+    let mut body = response.body.take(range.end - range.start);
+    let written = copy_body(url, &mut body, &mut sink, &mut pace, context)?;
+
+    sink.into_file()?;
+    Ok(written)
+}
+
+/// Builds a request to `url` carrying the given headers and credentials, as the interceptors
+/// of `config` rewrite it.
+pub(crate) fn build_request(
+    method: Method,
+    url: &str,
+    headers: &HeaderMap,
+    auth: Option<&Auth>,
+    config: &DownloadConfig,
+) -> Result<HttpRequest, DownloadError> {
+    let mut headers = headers.clone();
+    if let Some(auth) = auth {
+        headers.insert(AUTHORIZATION, auth.header_value()?);
+    }
+    Ok(config.interceptors.apply(HttpRequest {
+        method,
+        url: url.to_string(),
+        headers,
+    }))
+}
+
+/// Sends a `method` request to `url` and follows its redirects according to
+/// [`DownloadConfig::redirects`], reporting every hop through `callback`.
+///
+/// Every hop sends the matching cookies of `cookies` and stores the ones its response sets.
+/// The key of a pinned host is checked during the handshake when the client does so, and
+/// otherwise only as a best effort, with a [`pin_probe`] ahead of every hop and a check of its
+/// response, as [`crate::KeyPins`] describes.
+pub(crate) fn send_following(
+    client: &dyn HttpBackend,
+    method: Method,
+    url: &str,
+    mut headers: HeaderMap,
+    config: &DownloadConfig,
+    cookies: Option<&CookieJar>,
+    callback: &impl Fn(&DownloadEvent),
+) -> Result<HttpResponse, DownloadError> {
+    // A local file is read from the disk and a data URL carries its payload, leaving no
+    // redirects or certificates to check.
+    if is_data_url(url) {
+        return data_url::parse(url).map(|data| data.into_response(&method));
+    }
+    #[cfg(feature = "ftp")]
+    if ftp::is_ftp_url(url) {
+        return ftp::open(
+            &method,
+            url,
+            &headers,
+            config.auth.as_ref(),
+            &config.timeouts,
+        );
+    }
+    if is_file_url(url) {
+        return local_file::open(&method, url, &headers).map(LocalFile::into_response);
+    }
+
+    request_identity(&mut headers, config);
+    let mut redirects = Redirects::new(&config.redirects, headers, config.auth.as_ref());
+    let hop = |url: &str, redirects: &Redirects| {
+        if let Some(probe) = pin_probe(url, config, callback)? {
+            check_pins(&client.send(probe)?, config, callback)?;
+        }
+        let request = build_request(
+            method.clone(),
+            url,
+            redirects.headers(),
+            redirects.auth(),
+            config,
+        )?;
+        let response = client
+            .send(with_cookies(request, cookies))
+            .map_err(|e| reported(e, callback))?;
+        check_pins(&response, config, callback)?;
+        if let Some(cookies) = cookies {
+            cookies.store_response(&response.url, &response.headers);
+        }
+        Ok::<_, DownloadError>(response)
+    };
+    let mut response = hop(url, &redirects)?;
+
+    while let Some(target) = redirects.next(&response.url, response.status, &response.headers)? {
+        callback(&DownloadEvent::Redirected {
+            from: response.url.to_string(),
+            to: target.to_string(),
+        });
+        response = hop(target.as_str(), &redirects)?;
+    }
+
+    Ok(response)
+}
+
+/// Returns the request that checks the key of the host of `url` before anything secret is
+/// sent to it, or `None` if the host isn't pinned or its key is checked during the handshake.
+///
+/// Without the handshake check, the certificate only arrives with a response, so a pinned host
+/// is first sent a bare `HEAD` without credentials, cookies, configured headers or
+/// interceptors. The request itself follows once the key matched, over whichever connection
+/// the client picks, and is checked again.
+///
+/// # Returns
+///
+/// * `Err` with a [`PinMismatch`], reported through `callback`, if `url` is on a pinned host
+///   but not `https://`, since no key could ever be checked.
+pub(crate) fn pin_probe(
+    url: &str,
+    config: &DownloadConfig,
+    callback: &impl Fn(&DownloadEvent),
+) -> Result<Option<HttpRequest>, PinMismatch> {
+    let pins = &config.pinned_keys;
+    let Some(parsed) = Url::parse(url).ok().filter(|url| pins.is_pinned(url)) else {
+        return Ok(None);
+    };
+    if parsed.scheme() != "https" {
+        return pins.check(&parsed, None, callback).map(|()| None);
+    }
+    Ok((!pins.checked_in_handshake()).then(|| HttpRequest {
+        method: Method::HEAD,
+        url: url.to_string(),
+        headers: HeaderMap::new(),
+    }))
+}
+
+/// Reports the [`PinMismatch`] a client rejected a TLS handshake with through `callback`,
+/// returning `error` as it was.
+pub(crate) fn reported(error: DownloadError, callback: &impl Fn(&DownloadEvent)) -> DownloadError {
+    if let DownloadError::PinMismatch(mismatch) = &error {
+        report(mismatch, callback);
+    }
+    error
+}
+
+/// Adds the `Cookie` header `cookies` hold for the URL of `request`, unless the request was
+/// configured with one of its own.
+pub(crate) fn with_cookies(mut request: HttpRequest, cookies: Option<&CookieJar>) -> HttpRequest {
+    if request.headers.contains_key(COOKIE) {
+        return request;
+    }
+    let cookie = Url::parse(&request.url)
+        .ok()
+        .and_then(|url| cookies?.header_for(&url));
+    if let Some(cookie) = cookie {
+        request.headers.insert(COOKIE, cookie);
+    }
+    request
+}
+
+/// Checks the certificate that served `response` against [`DownloadConfig::pinned_keys`],
+/// unless the client already checked them during the handshake.
+fn check_pins(
+    response: &HttpResponse,
+    config: &DownloadConfig,
+    callback: &impl Fn(&DownloadEvent),
+) -> Result<(), PinMismatch> {
+    if config.pinned_keys.checked_in_handshake() {
+        return Ok(());
+    }
+    config.pinned_keys.check(
+        &response.url,
+        response.peer_certificate.as_deref(),
+        callback,
+    )
+}
@@ -0,0 +1,900 @@
+use crate::backend::HttpRequest;
+use crate::client::async_client;
+use crate::config::{BatchConfig, DownloadConfig};
+use crate::context::Context;
+use crate::cookies::CookieJar;
+use crate::data_url::{self, is_data_url};
+#[cfg(feature = "gzip")]
+use crate::decompress::{Decoder, DECODE_STEP};
+use crate::disk_space::DiskGuard;
+use crate::download::{
+    accept, allocate_segments, build_request, check_segment, check_segments, finish_temp, is_error,
+    next_attempt, open_temp, pin_probe, prepare, reported, resume_offset, segment_plan,
+    throttle_delay, with_cookies, Plan, ResponseHead, Verdict, Written,
+};
+use crate::encoding::request_identity;
+use crate::error::DownloadError;
+use crate::event::{DownloadEvent, EventFlow};
+#[cfg(feature = "ftp")]
+use crate::ftp;
+use crate::headers::header_map;
+use crate::interceptor::for_attempt;
+use crate::local_file::{self, is_file_url, LocalFile};
+use crate::overwrite::Claim;
+use crate::pinning::PinMismatch;
+use crate::presigned::{refresh, status_error_async};
+use crate::proxy::explain_proxy_error;
+use crate::redirect::Redirects;
+use crate::resume::{accepts_ranges, content_length};
+use crate::segment::range_header;
+use crate::spans::{warn, DownloadSpan};
+use crate::temp_file::{keep_after_error, partial_path};
+use crate::timeout::explain_timeout;
+use crate::timing::Stopwatch;
+use crate::transfer::{Finished, SharedTransfer, Transfer};
+use futures_util::future::join_all;
+use futures_util::StreamExt;
+use http::header::{HeaderMap, RANGE};
+use http::Method;
+use reqwest::tls::TlsInfo;
+use reqwest::{Client, Response};
+use std::cell::RefCell;
+use std::future::Future;
+use std::io::SeekFrom;
+use std::ops::{ControlFlow, Range};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+/// Downloads a file from the given URL and saves it to the specified path without blocking.
+///
+/// This is a convenience wrapper around [`download_file_async_with_client`] that builds a new
+/// client for a single ad-hoc download.
+///
+/// # Arguments
+///
+/// * `url` - A reference to a string or string-like value specifying the download URL.
+/// * `path` - A reference to a `Path` or `PathBuf` specifying where the file will be saved.
+/// * `callback` - A function or closure that receives every [`DownloadEvent`] for this download,
+///   and may return [`std::ops::ControlFlow::Break`] to cancel it, as described by [`EventFlow`].
+///
+/// # Returns
+///
+/// * `Ok(())` if the download succeeds.
+/// * `Err` if any error occurs.
+pub async fn download_file_async<R: EventFlow>(
+    url: impl AsRef<str>,
+    path: impl AsRef<Path>,
+    callback: impl Fn(&DownloadEvent) -> R + 'static + Send + Sync,
+) -> Result<(), DownloadError> {
+    // Initialize an async HTTP client with the default proxy, timeout and `User-Agent`
+    // settings.
+    let client = async_client(&mut BatchConfig::default())?;
+
+    download_file_async_with_client(&client, url, path, callback).await
+}
+
+/// Downloads a file from the given URL using an existing async client and saves it to the specified path.
+///
+/// The default [`DownloadConfig`] is used; see [`download_file_async_with_config`] to customize it.
+///
+/// # Arguments
+///
+/// * `client` - The async HTTP client used to issue the request.
+/// * `url` - A reference to a string or string-like value specifying the download URL.
+/// * `path` - A reference to a `Path` or `PathBuf` specifying where the file will be saved.
+/// * `callback` - A function or closure that receives every [`DownloadEvent`] for this download,
+///   and may return [`std::ops::ControlFlow::Break`] to cancel it, as described by [`EventFlow`].
+///
+/// # Returns
+///
+/// * `Ok(())` if the download succeeds.
+/// * `Err` if any error occurs.
+pub async fn download_file_async_with_client<R: EventFlow>(
+    client: &Client,
+    url: impl AsRef<str>,
+    path: impl AsRef<Path>,
+    callback: impl Fn(&DownloadEvent) -> R + 'static + Send + Sync,
+) -> Result<(), DownloadError> {
+    download_file_async_with_config(client, url, path, &DownloadConfig::default(), callback).await
+}
+
+/// Downloads a file from the given URL using an existing async client and explicit settings.
+///
+/// This is the async counterpart of [`crate::download_file_with_config`] and emits the same
+/// sequence of events, including retries of transient failures.
+///
+/// # Arguments
+///
+/// * `client` - The async HTTP client used to issue the request.
+/// * `url` - A reference to a string or string-like value specifying the download URL.
+/// * `path` - A reference to a `Path` or `PathBuf` specifying where the file will be saved.
+/// * `config` - The settings applied to this download.
+/// * `callback` - A function or closure that receives every [`DownloadEvent`] for this download,
+///   and may return [`std::ops::ControlFlow::Break`] to cancel it, as described by [`EventFlow`].
+///
+/// # Returns
+///
+/// * `Ok(())` if the download succeeds.
+/// * `Err` if any error occurs. When retries were attempted, the error is a [`crate::RetriesExhausted`].
+pub async fn download_file_async_with_config<R: EventFlow>(
+    client: &Client,
+    url: impl AsRef<str>,
+    path: impl AsRef<Path>,
+    config: &DownloadConfig,
+    callback: impl Fn(&DownloadEvent) -> R + 'static + Send + Sync,
+) -> Result<(), DownloadError> {
+    download_file_async_with_mirrors(client, url, &[], path, config, callback).await
+}
+
+/// Downloads a file from the given URL, falling back to other URLs serving the same file.
+///
+/// This is the async counterpart of [`crate::download_file_with_mirrors`] and tries the
+/// mirrors in the same way.
+///
+/// # Arguments
+///
+/// * `client` - The async HTTP client used to issue the request.
+/// * `url` - The URL tried first.
+/// * `mirrors` - The URLs tried next, in order.
+/// * `path` - A reference to a `Path` or `PathBuf` specifying where the file will be saved.
+/// * `config` - The settings applied to this download.
+/// * `callback` - A function or closure that receives every [`DownloadEvent`] for this download,
+///   and may return [`std::ops::ControlFlow::Break`] to cancel it, as described by [`EventFlow`].
+///
+/// # Returns
+///
+/// * `Ok(())` as soon as one of the URLs succeeds.
+/// * `Err` with the error of the last URL tried if none of them did, or with the first error
+///   that another URL couldn't fix.
+pub async fn download_file_async_with_mirrors<R: EventFlow>(
+    client: &Client,
+    url: impl AsRef<str>,
+    mirrors: &[String],
+    path: impl AsRef<Path>,
+    config: &DownloadConfig,
+    callback: impl Fn(&DownloadEvent) -> R + 'static + Send + Sync,
+) -> Result<(), DownloadError> {
+    let (url, path) = (url.as_ref(), path.as_ref());
+    let span = DownloadSpan::new(url, path);
+    // A callback breaking out cancels the download, which stops before its next chunk.
+    let context = Context::default();
+    let callback = |event: &DownloadEvent| {
+        span.record(event);
+        if callback(event).flow().is_break() {
+            context.control.cancel();
+        }
+    };
+    span.instrument(download_with_context_async(
+        client, url, mirrors, path, config, &context, &callback,
+    ))
+    .await
+    .map(|_| ())
+}
+
+/// Downloads a file while honouring the shared batch state in `context`, such as cancellation
+/// and the global rate limit.
+///
+/// Emits the terminal [`DownloadEvent`] and returns how the download ended. A cancelled
+/// download reports [`DownloadEvent::Cancelled`] and returns [`DownloadError::Cancelled`].
+/// `mirrors` are tried in order after `url` fails.
+pub(crate) async fn download_with_context_async(
+    client: &Client,
+    url: &str,
+    mirrors: &[String],
+    path: &Path,
+    config: &DownloadConfig,
+    context: &Context,
+    callback: &impl Fn(&DownloadEvent),
+) -> Result<Finished, DownloadError> {
+    // Time the phases of the download from the events it sends.
+    let stopwatch = Stopwatch::start();
+    let callback = &|event: &DownloadEvent| {
+        stopwatch.observe(event);
+        callback(event);
+    };
+
+    // Run the transfer and translate its outcome into a terminal event.
+    match transfer_from_mirrors(client, url, mirrors, path, config, context, callback).await {
+        Ok(Finished::Downloaded(mut transferred)) => {
+            callback(&DownloadEvent::Completed {
+                path: transferred.path.clone(),
+                bytes: transferred.bytes,
+                sha256: transferred.sha256.clone(),
+                timings: stopwatch.timings(),
+            });
+            // The hook may block, so it runs on tokio's blocking threads.
+            if let Some(hook) = context.on_complete.clone() {
+                let file = hook.completed_file(url, &transferred);
+                transferred.hook_error = tokio::task::spawn_blocking(move || hook.run(&file))
+                    .await
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic.into_panic()));
+            }
+            Ok(Finished::Downloaded(transferred))
+        }
+        Ok(Finished::Kept(skipped)) => {
+            callback(&skipped.event());
+            Ok(Finished::Kept(skipped))
+        }
+        Err(error) if error.is_cancelled() => {
+            callback(&DownloadEvent::Cancelled {
+                path: path.to_path_buf(),
+            });
+            Err(error)
+        }
+        Err(error) => {
+            callback(&DownloadEvent::Failed {
+                error: error.to_string(),
+                timings: stopwatch.timings(),
+            });
+            Err(error)
+        }
+    }
+}
+
+/// Runs [`transfer_with_retries`] on `url` and then on each of `mirrors`, until one succeeds
+/// or fails in a way another URL wouldn't fix.
+async fn transfer_from_mirrors(
+    client: &Client,
+    url: &str,
+    mirrors: &[String],
+    path: &Path,
+    config: &DownloadConfig,
+    context: &Context,
+    callback: &impl Fn(&DownloadEvent),
+) -> Result<Finished, DownloadError> {
+    let mut url = url;
+    let mut mirrors = mirrors.iter();
+    loop {
+        match transfer_with_retries(client, url, path, config, context, callback).await {
+            Err(error) if error.fails_over() => {
+                let Some(next) = mirrors.next() else {
+                    return Err(error);
+                };
+                warn!("Download of {} failed, trying {}: {}", url, next, error);
+                callback(&DownloadEvent::MirrorFailover {
+                    from: url.to_string(),
+                    to: next.clone(),
+                    error: error.to_string(),
+                });
+                url = next;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Runs [`transfer`] until it succeeds, fails permanently, or runs out of attempts.
+///
+/// The checks before the first attempt and the decision after every failed one are those of
+/// the blocking API; only the waits in between are async.
+async fn transfer_with_retries(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    config: &DownloadConfig,
+    context: &Context,
+    callback: &impl Fn(&DownloadEvent),
+) -> Result<Finished, DownloadError> {
+    // Check the download and claim its destination once, before the first request.
+    let claim = match prepare(url, path, config)? {
+        ControlFlow::Continue(claim) => claim,
+        ControlFlow::Break(skipped) => return Ok(Finished::Kept(skipped)),
+    };
+    let path = claim.as_ref().map_or(path, Claim::path);
+    let mut current = url.to_string();
+    let mut attempts = 1;
+
+    loop {
+        // Don't start another attempt once the download has been cancelled.
+        if context.control.is_cancelled() {
+            return Err(DownloadError::Cancelled);
+        }
+
+        // Keep away from a host that asked the batch to back off.
+        if let Some(delay) = throttle_delay(url, context, callback) {
+            tokio::time::sleep(delay).await;
+        }
+
+        // A pre-signed URL that expired is replaced by a fresh one and tried again right away.
+        let attempt_config = &*for_attempt(config, attempts);
+        let result = match transfer(client, &current, path, attempt_config, context, callback).await
+        {
+            Err(DownloadError::UrlExpired(expired)) => match refresh(expired, context, callback) {
+                Ok(fresh) => {
+                    current = fresh;
+                    transfer(client, &current, path, attempt_config, context, callback).await
+                }
+                Err(error) => Err(error),
+            },
+            result => result,
+        };
+        match result {
+            Ok(finished) => return Ok(finished),
+            Err(error) => {
+                let delay = next_attempt(url, &mut attempts, error, config, context, callback)?;
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+/// Asks the server for the size of `url` with a `HEAD` request.
+///
+/// Returns `None` if the request fails or the server doesn't report a length.
+pub(crate) async fn probe_size(
+    client: &Client,
+    url: &str,
+    config: &DownloadConfig,
+    cookies: Option<&CookieJar>,
+) -> Option<u64> {
+    let headers = header_map(&config.headers).ok()?;
+    let response = send_following(client, Method::HEAD, url, headers, config, cookies, &|_| {})
+        .await
+        .ok()?;
+    content_length(response.headers()).filter(|_| response.status().is_success())
+}
+
+/// Downloads `url` into a temporary file next to `path` and moves it into place on success.
+///
+/// Returns the path the file was actually saved to and its size on disk. On failure the
+/// temporary file is removed unless it is kept for a later resume.
+async fn transfer(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    config: &DownloadConfig,
+    context: &Context,
+    callback: &impl Fn(&DownloadEvent),
+) -> Result<Finished, DownloadError> {
+    let temp = partial_path(path, config);
+    let result = stream_to_temp(client, url, path, &temp, config, context, callback)
+        .await
+        .map_err(|error| explain_timeout(error, &config.timeouts))
+        .map_err(|error| explain_proxy_error(error, url, &context.proxy));
+
+    if let Err(error) = &result {
+        if !keep_after_error(error, config) {
+            // Cleanup is best effort; the download error is the one worth reporting.
+            let _ = tokio::fs::remove_file(&temp).await;
+        }
+    }
+
+    result
+}
+
+/// Streams the body of `url` into `temp`, then renames it to its final destination.
+///
+/// `path` is the requested destination, which the server may rename through
+/// `Content-Disposition`. Every decision about the response is made by the same helpers as
+/// in the blocking API, leaving only the requests and the body's reads to this function.
+async fn stream_to_temp(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    temp: &Path,
+    config: &DownloadConfig,
+    context: &Context,
+    callback: &impl Fn(&DownloadEvent),
+) -> Result<Finished, DownloadError> {
+    // Remember when the request started so progress can report speed and ETA.
+    let started = Instant::now();
+
+    // When resuming, continue an earlier partial file if the server supports ranges.
+    let offset = resume_offset(temp, config);
+    // Custom headers and credentials go out with every request, including the range probe.
+    let headers = header_map(&config.headers)?;
+    let ranged = offset == 0
+        || accepts_ranges(
+            // The probe's redirects are followed silently; the GET below reports them.
+            send_following(
+                client,
+                Method::HEAD,
+                url,
+                headers.clone(),
+                config,
+                context.cookies.as_ref(),
+                &|_| {},
+            )
+            .await?
+            .headers(),
+        );
+    let plan = Plan::new(url, path, offset, ranged, config, callback);
+
+    // Split a large file across several connections when the server accepts ranges. The
+    // probe's headers then stand in for those of the GET response below.
+    let segments = if plan.may_segment(config) {
+        // Hold the probe's redirects back until it is clear the probe replaces the GET.
+        let hops = RefCell::new(Vec::new());
+        let probe = send_following(
+            client,
+            Method::HEAD,
+            url,
+            headers.clone(),
+            config,
+            context.cookies.as_ref(),
+            &|event| hops.borrow_mut().push(event.clone()),
+        )
+        .await?;
+        segment_plan(probe.status(), probe.headers(), config).map(|ranges| {
+            hops.into_inner().iter().for_each(callback);
+            (probe, ranges)
+        })
+    } else {
+        None
+    };
+
+    // Perform an HTTP GET request to the given URL, treating non-success statuses as errors.
+    let (response, segments) = match segments {
+        Some((probe, ranges)) => (probe, Some(ranges)),
+        None => {
+            let headers = plan.get_headers(&headers, path, context);
+            let cookies = context.cookies.as_ref();
+            let response =
+                send_following(client, Method::GET, url, headers, config, cookies, callback)
+                    .await?;
+            (response, None)
+        }
+    };
+    let head = ResponseHead {
+        status: response.status(),
+        headers: response.headers(),
+        url: response.url(),
+        version: response.version(),
+        remote_addr: response.remote_addr(),
+    };
+    let mut accepted = match accept(url, path, temp, &plan, head, config, context)? {
+        Verdict::Save(accepted) => *accepted,
+        Verdict::Kept(skipped) => return Ok(Finished::Kept(skipped)),
+        Verdict::Failed => return Err(status_error_async(url, response).await),
+    };
+    let claim = accepted.claim.take();
+    let path = claim.as_ref().map_or(path, Claim::path);
+
+    let (mut transfer, written) = match segments {
+        // Download every range on its own connection straight into place in the temporary
+        // file, which is allocated at its full size up front.
+        Some(ranges) => {
+            let total = allocate_segments(url, temp, &ranges, config)?;
+            let transfer = Transfer::start(url, Some(total), 0, started, config, context, callback)
+                .without_hashing();
+            let quiet = |_: &DownloadEvent| {};
+            let fetch = |range: &Range<u64>| {
+                let mut headers = headers.clone();
+                headers.insert(RANGE, range_header(range));
+                let cookies = context.cookies.as_ref();
+                send_following(client, Method::GET, url, headers, config, cookies, &quiet)
+            };
+            // A preallocated file has gaps wherever a segment stopped, so it can never be
+            // resumed and is removed even when partial files are otherwise kept.
+            let transfer =
+                match download_segments(&fetch, url, temp, &ranges, context, transfer).await {
+                    Ok(transfer) => transfer,
+                    Err(error) => {
+                        let _ = tokio::fs::remove_file(temp).await;
+                        return Err(error);
+                    }
+                };
+            (transfer, Written::Segments)
+        }
+        None => {
+            // A local file's body is streamed without a length of its own, so its header
+            // counts.
+            let length = response
+                .content_length()
+                .or_else(|| content_length(response.headers()));
+            let (file, disk_guard, streaming) =
+                open_temp(url, temp, &plan, &accepted, length, config, callback)?;
+
+            // Collect small chunks into larger writes.
+            let file = tokio::fs::File::from_std(file);
+            let sink = AsyncFileSink::new(file, temp, config.write.buffer_size, disk_guard);
+            #[cfg(feature = "gzip")]
+            let sink = sink.decoding(Decoder::new(url, path, config));
+            let mut sink = sink;
+
+            // Announce that the body is about to be streamed, counting any resumed prefix as downloaded.
+            let transfer = Mutex::new(Transfer::start(
+                url,
+                streaming.expected_bytes,
+                streaming.prefix,
+                started,
+                config,
+                context,
+                callback,
+            ));
+
+            // Stream the body into the file.
+            let stop = AtomicBool::new(false);
+            let pace = SharedTransfer {
+                transfer: &transfer,
+                stop: &stop,
+            };
+            let copied =
+                copy_stream(url, response, streaming.limit, &mut sink, &pace, context).await;
+
+            // Make sure everything buffered, by the writer and by the async file handle, reaches
+            // the disk. tokio's writer doesn't flush when it is dropped, so this happens after a
+            // failure too, leaving a partial file that can be resumed.
+            let file = sink.into_file().await;
+            let received = streaming.prefix + copied?;
+            let (file, bytes) = file?;
+            let written = Written::Streamed {
+                file: file.into_std().await,
+                bytes: streaming.prefix + bytes,
+                received,
+                streaming,
+            };
+            (transfer.into_inner().unwrap(), written)
+        }
+    };
+
+    // Checking, hashing and unpacking the complete file block, so they run on tokio's blocking
+    // threads.
+    let sha256 = transfer.take_sha256();
+    let fresh_validators = accepted.fresh_validators.take();
+    let decompressed = written.decompressed(config);
+    let (temp, destination, config) = (temp.to_path_buf(), path.to_path_buf(), config.clone());
+    let sha256 = tokio::task::spawn_blocking(move || {
+        finish_temp(
+            &temp,
+            &destination,
+            written,
+            sha256,
+            fresh_validators.as_ref(),
+            &config,
+        )
+    })
+    .await
+    .unwrap_or_else(|panic| std::panic::resume_unwind(panic.into_panic()))?;
+
+    Ok(accepted.finished(transfer, path, sha256, decompressed))
+}
+
+/// Downloads every range of the file at `url` at the same time, writing each straight into
+/// its place in `temp`.
+///
+/// `fetch` sends the ranged request for one segment. All segments report into the same
+/// `transfer`, so progress covers the whole file. The first failing segment stops the others.
+async fn download_segments<'a, F, R>(
+    fetch: &impl Fn(&Range<u64>) -> R,
+    url: &str,
+    temp: &Path,
+    ranges: &[Range<u64>],
+    context: &Context,
+    transfer: Transfer<'a, F>,
+) -> Result<Transfer<'a, F>, DownloadError>
+where
+    F: Fn(&DownloadEvent),
+    R: Future<Output = Result<Response, DownloadError>>,
+{
+    let transfer = Mutex::new(transfer);
+    let stop = AtomicBool::new(false);
+    let pace = SharedTransfer {
+        transfer: &transfer,
+        stop: &stop,
+    };
+
+    let results = join_all(ranges.iter().map(|range| async {
+        let result = download_segment(fetch(range).await, url, temp, range, context, &pace).await;
+        if result.is_err() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        result
+    }))
+    .await;
+    check_segments(temp, ranges, results)?;
+
+    Ok(transfer.into_inner().unwrap())
+}
+
+/// Writes the answer to the range request of one segment into its place in `temp`, returning
+/// how many bytes were written.
+///
+/// Stops early without an error once another segment failed.
+async fn download_segment<F: Fn(&DownloadEvent)>(
+    response: Result<Response, DownloadError>,
+    url: &str,
+    temp: &Path,
+    range: &Range<u64>,
+    context: &Context,
+    pace: &SharedTransfer<'_, '_, F>,
+) -> Result<u64, DownloadError> {
+    let response = response?;
+    if pace.stop.load(Ordering::Relaxed) {
+        return Ok(0);
+    }
+    if is_error(response.status()) {
+        return Err(status_error_async(url, response).await);
+    }
+    check_segment(url, response.status())?;
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(temp)
+        .await
+        .map_err(DownloadError::io(temp))?;
+    file.seek(SeekFrom::Start(range.start))
+        .await
+        .map_err(DownloadError::io(temp))?;
+    let write_buffer = pace.lock().write_buffer();
+    let mut sink = AsyncFileSink::new(file, temp, write_buffer, None);
+
+    // Never write past the end of this segment, even if the server sends more.
+    let limit = range.end - range.start;
+    let written = copy_stream(url, response, limit, &mut sink, pace, context).await?;
+
+    sink.into_file().await?;
+    Ok(written)
+}
+
+/// Copies up to `limit` bytes of the body of `response` into `sink` one chunk at a time,
+/// returning how many bytes were copied.
+///
+/// This is the chunk loop of every async download, making the checks of the blocking
+/// [`crate::transfer::copy_body`]: between chunks it stops if the download was cancelled or
+/// another segment failed, and waits for the rate limits within the overall time limit;
+/// every chunk is checked against the size limit before it is written and reported once it
+/// has been.
+async fn copy_stream<F: Fn(&DownloadEvent)>(
+    url: &str,
+    response: Response,
+    limit: u64,
+    sink: &mut AsyncFileSink<'_>,
+    pace: &SharedTransfer<'_, '_, F>,
+    context: &Context,
+) -> Result<u64, DownloadError> {
+    let mut stream = response.bytes_stream();
+    let mut copied = 0;
+
+    while copied < limit {
+        // Stop between chunks if the download was cancelled.
+        if context.control.is_cancelled() {
+            return Err(DownloadError::Cancelled);
+        }
+        if pace.stop.load(Ordering::Relaxed) {
+            break;
+        }
+
+        // Wait for both the global and the per-download rate limits to allow another chunk.
+        // The transfer is never locked across a wait.
+        let delay = pace.lock().limit_delay();
+        tokio::time::sleep(delay).await;
+
+        // Receive the next chunk within the overall time limit; the stream ends once the body
+        // has been fully consumed.
+        let time_left = pace.lock().time_left()?;
+        let next = match time_left {
+            Some(left) => tokio::time::timeout(left, stream.next())
+                .await
+                .map_err(|_| pace.lock().deadline_exceeded())?,
+            None => stream.next().await,
+        };
+        let Some(chunk) = next else {
+            break;
+        };
+        let chunk = chunk.map_err(DownloadError::request(url))?;
+        let chunk = &chunk[..chunk.len().min((limit - copied) as usize)];
+
+        // Write the chunk once it fits the size limit, then report the updated progress.
+        pace.lock().admit(chunk.len() as u64)?;
+        sink.write_chunk(chunk).await?;
+        pace.lock().record(chunk);
+        copied += chunk.len() as u64;
+    }
+
+    Ok(copied)
+}
+
+/// The temporary file an async download writes into, the counterpart of
+/// [`crate::write_buffer::FileSink`].
+struct AsyncFileSink<'a> {
+    /// The file, behind its buffer.
+    file: tokio::io::BufWriter<tokio::fs::File>,
+    /// Where the file is, named by write errors.
+    path: &'a Path,
+    /// Watches the free space while the file grows, if the download checks it.
+    disk_guard: Option<DiskGuard<'a>>,
+    /// How many bytes were written to the file.
+    written: u64,
+    /// Decompresses the chunks before they are written, if the download asks for it.
+    #[cfg(feature = "gzip")]
+    decoder: Option<Decoder>,
+}
+
+impl<'a> AsyncFileSink<'a> {
+    /// Starts writing to `file`, which is open at `path`.
+    fn new(
+        file: tokio::fs::File,
+        path: &'a Path,
+        buffer_size: usize,
+        disk_guard: Option<DiskGuard<'a>>,
+    ) -> Self {
+        Self {
+            file: tokio::io::BufWriter::with_capacity(buffer_size, file),
+            path,
+            disk_guard,
+            written: 0,
+            #[cfg(feature = "gzip")]
+            decoder: None,
+        }
+    }
+
+    /// Decompresses the chunks with `decoder` before writing them.
+    #[cfg(feature = "gzip")]
+    fn decoding(mut self, decoder: Option<Decoder>) -> Self {
+        self.decoder = decoder;
+        self
+    }
+
+    /// Writes `chunk` to the file, or what it decompresses to.
+    async fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), DownloadError> {
+        #[cfg(feature = "gzip")]
+        if let Some(mut decoder) = self.decoder.take() {
+            for input in chunk.chunks(DECODE_STEP) {
+                let decoded = decoder.decode(input)?;
+                self.write_out(decoded).await?;
+            }
+            self.decoder = Some(decoder);
+            return Ok(());
+        }
+        self.write_out(chunk).await
+    }
+
+    /// Writes `bytes` to the file as they are.
+    async fn write_out(&mut self, bytes: &[u8]) -> Result<(), DownloadError> {
+        self.file
+            .write_all(bytes)
+            .await
+            .map_err(DownloadError::io(self.path))?;
+        self.written += bytes.len() as u64;
+        match &mut self.disk_guard {
+            Some(guard) => guard.record(bytes.len() as u64),
+            None => Ok(()),
+        }
+    }
+
+    /// Ends the decompressed stream if there is one, writes out whatever is still buffered,
+    /// by the writer and by the async file handle, and hands the file back with the number of
+    /// bytes written to it.
+    async fn into_file(mut self) -> Result<(tokio::fs::File, u64), DownloadError> {
+        #[cfg(feature = "gzip")]
+        if let Some(mut decoder) = self.decoder.take() {
+            let rest = decoder.finish()?;
+            self.write_out(rest).await?;
+        }
+        self.file
+            .flush()
+            .await
+            .map_err(DownloadError::io(self.path))?;
+        Ok((self.file.into_inner(), self.written))
+    }
+}
+
+/// Sends a `method` request to `url` and follows its redirects according to
+/// [`DownloadConfig::redirects`], reporting every hop through `callback`.
+///
+/// Every request is built by [`build_request`] of the blocking API, then handed to `client`.
+/// Every hop sends the matching cookies of `cookies` and stores the ones its response sets.
+/// The key of a pinned host is checked during the handshake when the client does so, and
+/// otherwise only as a best effort, with a [`pin_probe`] ahead of every hop and a check of its
+/// response, as [`crate::KeyPins`] describes.
+async fn send_following(
+    client: &Client,
+    method: Method,
+    url: &str,
+    mut headers: HeaderMap,
+    config: &DownloadConfig,
+    cookies: Option<&CookieJar>,
+    callback: &impl Fn(&DownloadEvent),
+) -> Result<Response, DownloadError> {
+    // A local file is read from the disk and a data URL carries its payload, leaving no
+    // redirects or certificates to check.
+    if is_data_url(url) {
+        return data_url::parse(url).map(|data| data.into_async_response(&method));
+    }
+    #[cfg(feature = "ftp")]
+    if ftp::is_ftp_url(url) {
+        return ftp::open_async(
+            &method,
+            url,
+            &headers,
+            config.auth.as_ref(),
+            &config.timeouts,
+        )
+        .await;
+    }
+    if is_file_url(url) {
+        return local_file::open(&method, url, &headers).map(LocalFile::into_async_response);
+    }
+
+    request_identity(&mut headers, config);
+    let mut redirects = Redirects::new(&config.redirects, headers, config.auth.as_ref());
+    let mut response =
+        send_hop(client, &method, url, &redirects, config, cookies, callback).await?;
+
+    while let Some(target) =
+        redirects.next(response.url(), response.status(), response.headers())?
+    {
+        callback(&DownloadEvent::Redirected {
+            from: response.url().to_string(),
+            to: target.to_string(),
+        });
+        response = send_hop(
+            client,
+            &method,
+            target.as_str(),
+            &redirects,
+            config,
+            cookies,
+            callback,
+        )
+        .await?;
+    }
+
+    Ok(response)
+}
+
+/// Sends one hop of [`send_following`], checking the key of a pinned host with a
+/// [`pin_probe`] before anything secret goes out to it, unless the client checks it during the
+/// handshake.
+async fn send_hop(
+    client: &Client,
+    method: &Method,
+    url: &str,
+    redirects: &Redirects<'_>,
+    config: &DownloadConfig,
+    cookies: Option<&CookieJar>,
+    callback: &impl Fn(&DownloadEvent),
+) -> Result<Response, DownloadError> {
+    if let Some(probe) = pin_probe(url, config, callback)? {
+        check_pins(&send(client, probe).await?, config, callback)?;
+    }
+    let request = build_request(
+        method.clone(),
+        url,
+        redirects.headers(),
+        redirects.auth(),
+        config,
+    )?;
+    let response = send(client, with_cookies(request, cookies))
+        .await
+        .map_err(|e| reported(e, callback))?;
+    check_pins(&response, config, callback)?;
+    if let Some(cookies) = cookies {
+        cookies.store_response(response.url(), response.headers());
+    }
+    Ok(response)
+}
+
+/// Sends `request` with `client`.
+async fn send(client: &Client, request: HttpRequest) -> Result<Response, DownloadError> {
+    client
+        .request(request.method, &request.url)
+        .headers(request.headers)
+        .send()
+        .await
+        .map_err(DownloadError::request(&request.url))
+}
+
+/// Checks the certificate that served `response` against [`DownloadConfig::pinned_keys`],
+/// unless the client already checked them during the handshake.
+fn check_pins(
+    response: &Response,
+    config: &DownloadConfig,
+    callback: &impl Fn(&DownloadEvent),
+) -> Result<(), PinMismatch> {
+    if config.pinned_keys.checked_in_handshake() {
+        return Ok(());
+    }
+    config.pinned_keys.check(
+        response.url(),
+        response
+            .extensions()
+            .get::<TlsInfo>()
+            .and_then(TlsInfo::peer_certificate),
+        callback,
+    )
+}
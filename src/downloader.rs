@@ -0,0 +1,286 @@
+use crate::backlog::Backlog;
+use crate::batch::spawn_workers;
+use crate::client::blocking_client;
+use crate::config::BatchConfig;
+use crate::error::DownloadError;
+use crate::event::DownloadEvent;
+use crate::handle::{BatchHandle, Canceller};
+use crate::headers::check_headers;
+use crate::metrics::BatchMetrics;
+use crate::priority::Priority;
+use crate::queue::WorkQueue;
+use crate::request::{DownloadRequest, UniqueDestinations};
+use crate::result::DownloadResult;
+use crate::summary::finish_batch;
+use crate::url_check::normalize_request;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// A long-lived batch that accepts new requests while earlier ones are still downloading.
+///
+/// Workers keep draining the queue as requests are [`enqueue`](Downloader::enqueue)d, so a
+/// crawler can feed in URLs as it discovers them without losing concurrency between batches.
+/// Events are reported through the callback as usual, and each [`DownloadResult`] is available
+/// from [`Downloader::next_result`] as soon as its download ends.
+///
+/// With [`crate::BatchConfig::queue_capacity`] set, [`Downloader::enqueue`] blocks while the
+/// queue is full, and [`Downloader::try_enqueue`] refuses the request instead.
+///
+/// Call [`Downloader::close`] once no more requests will arrive; the workers exit when the
+/// queue is empty. Dropping the downloader closes it too, letting queued downloads finish in
+/// the background. [`crate::BatchConfig::preflight_disk_space`] does not apply, since the
+/// batch is never known in full.
+///
+/// ```no_run
+/// use parallel_downloads::{BatchConfig, DownloadRequest, Downloader};
+///
+/// let downloader = Downloader::start(BatchConfig::default(), |_event| {}).unwrap();
+/// downloader
+///     .enqueue(DownloadRequest::new(
+///         "https://www.rust-lang.org/static/images/rust-logo-blk.svg",
+///         "./rust-logo.svg",
+///     ))
+///     .unwrap();
+/// downloader.close();
+///
+/// while let Some(result) = downloader.next_result() {
+///     println!("{}: {}", result.url, result.is_success());
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Downloader {
+    /// Accepts new requests until the downloader is closed.
+    inbox: Mutex<Inbox>,
+    /// Bounds the requests waiting for a worker.
+    backlog: Arc<Backlog>,
+    /// Results of finished downloads that haven't been taken yet.
+    results: Mutex<Receiver<DownloadResult>>,
+    /// The running workers.
+    handle: BatchHandle,
+    /// Whether a failed download fails [`Downloader::join`].
+    continue_on_error: bool,
+}
+
+/// The sending side of a [`Downloader`]'s queue.
+#[derive(Debug)]
+struct Inbox {
+    /// Hands requests to the workers, with the time they were enqueued; `None` once closed.
+    sender: Option<Sender<(DownloadRequest, Instant)>>,
+    /// Destinations of every request enqueued so far.
+    destinations: UniqueDestinations,
+    /// Number of requests enqueued so far.
+    enqueued: usize,
+    /// Whether a request with an invalid URL is refused, following
+    /// [`crate::BatchConfig::strict_urls`].
+    strict_urls: bool,
+}
+
+impl Downloader {
+    /// Starts the workers of an empty queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The batch settings, including the per-file [`crate::DownloadConfig`].
+    /// * `callback` - A function or closure that receives the [`DownloadEvent`]s of every file.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Downloader)` once the workers have been spawned.
+    /// * `Err` with an [`crate::InvalidHeader`] if a configured header is malformed, an
+    ///   [`crate::InvalidConfig`] if a setting can't be used, or if the HTTP client could not
+    ///   be built.
+    pub fn start(
+        mut config: BatchConfig,
+        callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+    ) -> Result<Self, DownloadError> {
+        check_headers(&[], &config.download)?;
+        config.download.check()?;
+
+        let client = Arc::new(blocking_client(&mut config)?);
+        let (sender, receiver) = mpsc::channel();
+        let backlog = Arc::new(Backlog::new(config.queue_capacity.unwrap_or(usize::MAX)));
+        let (result_sender, results) = mpsc::channel();
+        let continue_on_error = config.continue_on_error;
+        let overwrite = config.download.overwrite;
+        let strict_urls = config.strict_urls;
+        let thread_count = config.worker_count();
+        let handle = spawn_workers(
+            client,
+            WorkQueue::channel(receiver, Arc::clone(&backlog)),
+            None,
+            thread_count,
+            config,
+            callback,
+            Some(result_sender),
+        )?;
+
+        Ok(Self {
+            inbox: Mutex::new(Inbox {
+                sender: Some(sender),
+                destinations: UniqueDestinations::new(overwrite),
+                enqueued: 0,
+                strict_urls,
+            }),
+            backlog,
+            results: Mutex::new(results),
+            handle,
+            continue_on_error,
+        })
+    }
+
+    /// Adds a request to the queue, to be downloaded as soon as a worker is free.
+    ///
+    /// While [`crate::BatchConfig::queue_capacity`] requests are waiting, blocks until a worker
+    /// takes one or the downloader is cancelled.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The file to download and where to save it.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` with the request's position in the queue once it is queued, which
+    ///   [`Downloader::set_priority`] takes.
+    /// * `Err` with a [`crate::DuplicateDestination`] if an earlier request uses the same
+    ///   destination.
+    /// * `Err` with a [`crate::InvalidUrl`] if the request's URL is invalid and
+    ///   [`crate::BatchConfig::strict_urls`] is set.
+    /// * `Err` with [`DownloadError::QueueClosed`] if the downloader was closed or cancelled.
+    pub fn enqueue(&self, request: DownloadRequest) -> Result<usize, DownloadError> {
+        self.offer(request, true)
+    }
+
+    /// Adds a request to the queue like [`Downloader::enqueue`], but returns right away when
+    /// the queue is full.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The file to download and where to save it. A request that is refused is
+    ///   dropped, so keep a clone to offer it again.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` with the request's position in the queue once it is queued.
+    /// * `Err` with [`DownloadError::QueueFull`] if [`crate::BatchConfig::queue_capacity`]
+    ///   requests are already waiting.
+    /// * `Err` with a [`crate::DuplicateDestination`], [`crate::InvalidUrl`] or
+    ///   [`DownloadError::QueueClosed`], as with [`Downloader::enqueue`].
+    pub fn try_enqueue(&self, request: DownloadRequest) -> Result<usize, DownloadError> {
+        self.offer(request, false)
+    }
+
+    /// Queues `request` once it has a place in the backlog, waiting for one if `wait` is set.
+    fn offer(&self, request: DownloadRequest, wait: bool) -> Result<usize, DownloadError> {
+        // Wait for room before taking the inbox, so a full queue doesn't hold up close().
+        self.backlog.reserve(wait, || self.handle.is_cancelled())?;
+        let queued = self.send(request);
+        if queued.is_err() {
+            self.backlog.release();
+        }
+        queued
+    }
+
+    /// Hands `request` to the workers under the next position.
+    fn send(&self, mut request: DownloadRequest) -> Result<usize, DownloadError> {
+        let mut inbox = self.inbox.lock().unwrap();
+        if self.handle.is_cancelled() {
+            return Err(DownloadError::QueueClosed);
+        }
+        let index = inbox.enqueued;
+        let Inbox {
+            sender,
+            destinations,
+            strict_urls,
+            ..
+        } = &mut *inbox;
+        let Some(sender) = sender else {
+            return Err(DownloadError::QueueClosed);
+        };
+        normalize_request(index, &mut request, *strict_urls)?;
+        destinations.insert(index, &mut request)?;
+        sender
+            .send((request, Instant::now()))
+            .map_err(|_| DownloadError::QueueClosed)?;
+        inbox.enqueued += 1;
+        Ok(index)
+    }
+
+    /// Signals that no more requests will be enqueued, so the workers exit once the queue is
+    /// empty.
+    pub fn close(&self) {
+        self.inbox.lock().unwrap().sender = None;
+        self.backlog.close();
+    }
+
+    /// Cancels every queued and running download and closes the downloader, waking producers
+    /// blocked in [`Downloader::enqueue`].
+    ///
+    /// In-flight downloads stop at the next chunk boundary and are reported as
+    /// [`crate::DownloadOutcome::Cancelled`].
+    pub fn cancel(&self) {
+        self.close();
+        self.handle.cancel();
+    }
+
+    /// Returns a [`Canceller`] that cancels the downloader like [`Downloader::cancel`] from
+    /// another thread or a signal handler. [`Downloader::enqueue`] refuses new requests
+    /// afterwards.
+    pub fn canceller(&self) -> Canceller {
+        self.handle.canceller()
+    }
+
+    /// Returns the current counters of the downloader; see [`BatchHandle::metrics`].
+    pub fn metrics(&self) -> BatchMetrics {
+        self.handle.metrics()
+    }
+
+    /// Changes the priority of a queued request that hasn't started downloading yet; see
+    /// [`BatchHandle::set_priority`].
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The position returned by [`Downloader::enqueue`].
+    /// * `priority` - The new priority.
+    pub fn set_priority(&self, index: usize, priority: Priority) -> bool {
+        self.handle.set_priority(index, priority)
+    }
+
+    /// Pauses every running download; see [`BatchHandle::pause`].
+    pub fn pause(&self) {
+        self.handle.pause();
+    }
+
+    /// Resumes downloads paused with [`Downloader::pause`].
+    pub fn resume(&self) {
+        self.handle.resume();
+    }
+
+    /// Waits for the next download to end and returns its result.
+    ///
+    /// Results arrive in the order the downloads finish. Returns `None` once the downloader is
+    /// closed and every result has been taken.
+    pub fn next_result(&self) -> Option<DownloadResult> {
+        self.results.lock().unwrap().recv().ok()
+    }
+
+    /// Returns the result of a download that has already ended, without waiting.
+    pub fn try_next_result(&self) -> Option<DownloadResult> {
+        self.results.lock().unwrap().try_recv().ok()
+    }
+
+    /// Closes the downloader, waits for the queue to drain, and returns every result that
+    /// hasn't been taken with [`Downloader::next_result`] yet.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` with the remaining results, in the order the downloads finished.
+    /// * `Err` with a [`crate::BatchFailed`] if one of them failed and
+    ///   [`crate::BatchConfig::continue_on_error`] is turned off.
+    pub fn join(self) -> Result<Vec<DownloadResult>, DownloadError> {
+        self.close();
+        self.handle.join();
+        let results = self.results.into_inner().unwrap().try_iter().collect();
+        finish_batch(results, self.continue_on_error)
+    }
+}
@@ -0,0 +1,164 @@
+use crate::backend::HttpBackend;
+use crate::config::DownloadConfig;
+use crate::cookies::CookieJar;
+use crate::download::probe;
+use crate::error::DownloadError;
+use crate::length::SizeLimitExceeded;
+use crate::overwrite::{numbered, DestinationExists, OverwritePolicy};
+use crate::presigned::status_error_without_body;
+use crate::result::DownloadOutcome;
+use crate::url_check::check_url;
+use http::header::HeaderMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What a batch would do with the destination of a request, as found by a
+/// [`crate::BatchConfig::dry_run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlannedAction {
+    /// Nothing exists at the destination yet, so the file would be saved there.
+    Create,
+    /// The file at the destination would be replaced, following
+    /// [`OverwritePolicy::Overwrite`].
+    Overwrite,
+    /// The file at the destination would be kept without contacting the server, following
+    /// [`OverwritePolicy::SkipExisting`].
+    Skip,
+    /// The file would be saved next to the existing one under this name, following
+    /// [`OverwritePolicy::RenameWithSuffix`].
+    Rename(PathBuf),
+}
+
+impl PlannedAction {
+    /// Describes the action in a few words, such as `create` or `rename to a (1).txt`.
+    pub fn describe(&self) -> String {
+        match self {
+            PlannedAction::Create => "create".to_string(),
+            PlannedAction::Overwrite => "overwrite".to_string(),
+            PlannedAction::Skip => "skip".to_string(),
+            PlannedAction::Rename(path) => format!("rename to {}", path.display()),
+        }
+    }
+}
+
+/// Works out what downloading `url` to `path` would do, without writing anything.
+///
+/// The destination is checked against its overwrite policy and for a directory the file could
+/// be written to. Unless the file would be skipped, the server is then asked about it the way
+/// [`crate::preflight`] does, and a failed request, an error status or a file larger than
+/// [`DownloadConfig::max_size`] fails the dry run like it would fail the download.
+pub(crate) fn plan(
+    client: &dyn HttpBackend,
+    url: &str,
+    path: &Path,
+    config: &DownloadConfig,
+    cookies: Option<&CookieJar>,
+) -> DownloadOutcome {
+    let outcome = check_url(url)
+        .and_then(|_| destination_action(path, config))
+        .and_then(|action| {
+            if action == PlannedAction::Skip {
+                return Ok(DownloadOutcome::DryRun {
+                    path: path.to_path_buf(),
+                    action,
+                    remote: None,
+                });
+            }
+
+            let remote = probe(client, url, config, cookies)?;
+            if !remote.status.is_success() {
+                return Err(status_error_without_body(
+                    url,
+                    remote.status,
+                    &HeaderMap::new(),
+                ));
+            }
+            if let (Some(limit), Some(size)) = (config.max_size, remote.content_length) {
+                if size > limit {
+                    return Err(SizeLimitExceeded {
+                        url: url.to_string(),
+                        limit,
+                        advertised: Some(size),
+                        received: 0,
+                    }
+                    .into());
+                }
+            }
+            let path = match &action {
+                PlannedAction::Rename(renamed) => renamed.clone(),
+                _ => path.to_path_buf(),
+            };
+            Ok(DownloadOutcome::DryRun {
+                path,
+                action,
+                remote: Some(remote),
+            })
+        });
+    outcome.unwrap_or_else(|error| DownloadOutcome::Failed { error })
+}
+
+/// Applies the overwrite policy of `config` to `path` and checks that the file could be
+/// written, only looking at the filesystem.
+fn destination_action(
+    path: &Path,
+    config: &DownloadConfig,
+) -> Result<PlannedAction, DownloadError> {
+    let fail = |kind: io::ErrorKind, message: &str| {
+        Err(DownloadError::io(path)(io::Error::new(kind, message)))
+    };
+    if path.is_dir() {
+        return fail(
+            io::ErrorKind::IsADirectory,
+            "the destination is a directory",
+        );
+    }
+
+    // The closest directory that exists is where the missing ones would be created.
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if config.require_existing_parent && !parent.is_dir() {
+        return fail(
+            io::ErrorKind::NotFound,
+            "the destination's directory doesn't exist",
+        );
+    }
+    match parent.ancestors().find(|ancestor| ancestor.exists()) {
+        Some(ancestor) if !ancestor.is_dir() => {
+            return fail(
+                io::ErrorKind::NotADirectory,
+                "a parent of the destination is a file",
+            );
+        }
+        Some(ancestor) => {
+            let metadata = ancestor.metadata().map_err(DownloadError::io(ancestor))?;
+            if metadata.permissions().readonly() {
+                return fail(
+                    io::ErrorKind::PermissionDenied,
+                    "the destination's directory is read-only",
+                );
+            }
+        }
+        None => {}
+    }
+
+    if !path.exists() {
+        return Ok(PlannedAction::Create);
+    }
+    match config.overwrite {
+        OverwritePolicy::Overwrite => Ok(PlannedAction::Overwrite),
+        OverwritePolicy::SkipExisting => Ok(PlannedAction::Skip),
+        OverwritePolicy::Error => Err(DestinationExists {
+            path: path.to_path_buf(),
+        }
+        .into()),
+        OverwritePolicy::RenameWithSuffix => {
+            let renamed = (1..)
+                .map(|number| numbered(path, number))
+                .find(|candidate| !candidate.exists())
+                .expect("some numbered name is free");
+            Ok(PlannedAction::Rename(renamed))
+        }
+    }
+}
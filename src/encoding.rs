@@ -0,0 +1,37 @@
+use crate::config::DownloadConfig;
+use http::header::{HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING};
+
+/// Whether a download lets the server compress the body it sends.
+///
+/// A compressed body's `Content-Length` counts the compressed bytes, which match neither the
+/// bytes a decompressing client hands over nor the size of the file on the server, so it can't
+/// drive progress or the size check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContentEncoding {
+    /// Asks for the file as it is stored by sending `Accept-Encoding: identity`, unless
+    /// [`DownloadConfig::headers`] already carry an `Accept-Encoding`.
+    #[default]
+    Identity,
+    /// Sends no `Accept-Encoding` of its own, so the server may compress the body.
+    Any,
+}
+
+/// Asks for the file as it is stored, if `config` says so and the configured headers don't
+/// ask for something else.
+pub(crate) fn request_identity(headers: &mut HeaderMap, config: &DownloadConfig) {
+    if config.content_encoding == ContentEncoding::Identity
+        && !headers.contains_key(ACCEPT_ENCODING)
+    {
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("identity"));
+    }
+}
+
+/// Returns `true` if the server compressed the body it sends, so its length in bytes says
+/// nothing about the file.
+pub(crate) fn is_encoded(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(CONTENT_ENCODING)
+        .iter()
+        .flat_map(|value| value.to_str().unwrap_or("unknown").split(','))
+        .any(|coding| !coding.trim().eq_ignore_ascii_case("identity") && !coding.trim().is_empty())
+}
@@ -0,0 +1,589 @@
+use crate::checksum::ChecksumMismatch;
+use crate::config::InvalidConfig;
+use crate::content_type::UnexpectedContentType;
+use crate::cookies::InvalidCookie;
+#[cfg(feature = "gzip")]
+use crate::decompress::DecompressError;
+use crate::disk_space::InsufficientDiskSpace;
+#[cfg(feature = "extract")]
+use crate::extract::ExtractError;
+use crate::headers::InvalidHeader;
+use crate::length::{SizeLimitExceeded, SizeMismatch};
+use crate::manifest::InvalidManifest;
+use crate::overwrite::DestinationExists;
+use crate::pinning::PinMismatch;
+use crate::presigned::UrlExpired;
+#[cfg(feature = "reqwest")]
+use crate::proxy::ProxyUnreachable;
+use crate::redirect::{RedirectRefused, TooManyRedirects};
+use crate::request::DuplicateDestination;
+use crate::retry::RetriesExhausted;
+use crate::segment::{RangeIgnored, SegmentPanicked};
+use crate::state::InvalidState;
+use crate::summary::BatchFailed;
+use crate::throttle::retry_after;
+use crate::timeout::Timeout;
+use crate::tls::InvalidCertificate;
+use crate::url_check::{redact_url, InvalidUrl};
+use crate::url_list::MalformedUrl;
+use http::header::HeaderMap;
+use http::StatusCode;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Everything that can make a download or a batch fail.
+///
+/// Some variants only exist with the feature that can raise them, such as `Decompress` with
+/// `gzip`, so matches need a wildcard arm to build with any set of features.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DownloadError {
+    /// Sending the request or receiving the response failed.
+    #[cfg(feature = "reqwest")]
+    Request {
+        /// The URL that was requested, without its credentials.
+        url: String,
+        /// The underlying client error.
+        source: reqwest::Error,
+    },
+    /// The server answered with a client or server error status.
+    Status {
+        /// The URL that was requested, without its credentials.
+        url: String,
+        /// The status the server answered with.
+        status: StatusCode,
+        /// How long the server asked to wait before the next request, from the `Retry-After`
+        /// of a `429` or `503` response.
+        retry_after: Option<Duration>,
+    },
+    /// A pre-signed URL was refused because its signature expired.
+    UrlExpired(UrlExpired),
+    /// Reading the response body failed.
+    Body {
+        /// The URL that was requested, without its credentials.
+        url: String,
+        /// The underlying I/O error.
+        source: io::Error,
+    },
+    /// A local file operation failed.
+    Io {
+        /// The file that was being accessed.
+        path: PathBuf,
+        /// The underlying I/O error.
+        source: io::Error,
+    },
+    /// The writer given to [`crate::download_to_writer`] failed.
+    Writer(io::Error),
+    /// The downloaded file did not match its expected checksum and was deleted.
+    ChecksumMismatch(ChecksumMismatch),
+    /// The downloaded file did not have the size the server advertised.
+    SizeMismatch(SizeMismatch),
+    /// The server answered with a content type [`crate::DownloadConfig::content_types`]
+    /// doesn't accept.
+    UnexpectedContentType(UnexpectedContentType),
+    /// The file is larger than [`crate::DownloadConfig::max_size`] allows.
+    SizeLimitExceeded(SizeLimitExceeded),
+    /// A server advertised range support but ignored a segment's range request.
+    RangeIgnored(RangeIgnored),
+    /// The thread reading a segment of the file panicked.
+    SegmentPanicked(SegmentPanicked),
+    /// A filesystem does not have room for the files about to be written to it.
+    InsufficientDiskSpace(InsufficientDiskSpace),
+    /// A downloaded file could not be decompressed and was deleted.
+    #[cfg(feature = "gzip")]
+    Decompress(DecompressError),
+    /// A downloaded archive could not be unpacked.
+    #[cfg(feature = "extract")]
+    Extract(ExtractError),
+    /// The download was cancelled.
+    Cancelled,
+    /// A connect, read, or overall time limit was exceeded.
+    Timeout(Timeout),
+    /// The configured proxy could not be reached.
+    #[cfg(feature = "reqwest")]
+    Proxy(ProxyUnreachable),
+    /// The request was redirected more often than [`crate::RedirectPolicy::max_hops`] allows.
+    TooManyRedirects(TooManyRedirects),
+    /// The [`crate::RedirectPolicy`] does not allow following a redirect.
+    RedirectRefused(RedirectRefused),
+    /// Every attempt of a retried download failed.
+    RetriesExhausted(RetriesExhausted),
+    /// Two requests in a batch share a destination.
+    DuplicateDestination(DuplicateDestination),
+    /// A configured request header is malformed.
+    InvalidHeader(InvalidHeader),
+    /// A [`crate::DownloadConfig`] setting has a value no download can run with.
+    InvalidConfig(InvalidConfig),
+    /// The destination already exists and [`crate::OverwritePolicy::Error`] forbids replacing it.
+    DestinationExists(DestinationExists),
+    /// The HTTP client could not be built, for example because of an invalid proxy URL.
+    #[cfg(feature = "reqwest")]
+    Client(reqwest::Error),
+    /// A batch in strict mode finished with at least one failed download.
+    BatchFailed(BatchFailed),
+    /// A line of a strict [`crate::UrlList`] is not a valid URL.
+    MalformedUrl(MalformedUrl),
+    /// The URL of a download is malformed or uses an unsupported scheme.
+    InvalidUrl(InvalidUrl),
+    /// A manifest could not be parsed.
+    InvalidManifest(InvalidManifest),
+    /// A batch state file could not be parsed.
+    InvalidState(InvalidState),
+    /// A cookie added to a [`crate::CookieJar`] is malformed.
+    InvalidCookie(InvalidCookie),
+    /// A certificate added to a [`crate::TlsConfig`] is malformed.
+    InvalidCertificate(InvalidCertificate),
+    /// A pinned host presented a key that isn't one of its [`crate::KeyPins`].
+    PinMismatch(PinMismatch),
+    /// A request was enqueued on a [`crate::Downloader`] that was already closed or cancelled.
+    QueueClosed,
+    /// A request was offered to a [`crate::Downloader`] with
+    /// [`crate::Downloader::try_enqueue`] while its queue held
+    /// [`crate::BatchConfig::queue_capacity`] requests.
+    QueueFull,
+}
+
+impl DownloadError {
+    /// Returns a function wrapping a client error of a request to `url`.
+    ///
+    /// The credentials of `url` are redacted, and the client error drops its own copy of it.
+    #[cfg(feature = "reqwest")]
+    pub(crate) fn request(url: &str) -> impl FnOnce(reqwest::Error) -> Self + '_ {
+        move |source| {
+            // A handshake refused for a pinned key is the mismatch rather than a failed request.
+            #[cfg(any(feature = "rustls", feature = "rustls-native-roots"))]
+            if let Some(mismatch) = crate::pin_verifier::rejected_pin(url, &source) {
+                return DownloadError::PinMismatch(mismatch);
+            }
+            DownloadError::Request {
+                url: redact_url(url),
+                source: source.without_url(),
+            }
+        }
+    }
+
+    /// Returns a function wrapping an I/O error on the file at `path`.
+    pub(crate) fn io(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| DownloadError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    /// Describes an error status the server answered a request to `url` with, keeping any
+    /// delay it asked for. The credentials of `url` are redacted.
+    pub(crate) fn status(url: &str, status: StatusCode, headers: &HeaderMap) -> Self {
+        DownloadError::Status {
+            url: redact_url(url),
+            status,
+            retry_after: retry_after(status, headers),
+        }
+    }
+
+    /// Returns how long the server asked to wait before the next attempt, if it did.
+    pub(crate) fn retry_after(&self) -> Option<Duration> {
+        match self {
+            DownloadError::Status { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Wraps an error raised while reading the body of `url`.
+    ///
+    /// Body reads surface as I/O errors even when the client failed underneath, so the client
+    /// error is unwrapped again to keep it classifiable. The credentials of `url` are
+    /// redacted.
+    pub(crate) fn body(url: &str, error: io::Error) -> Self {
+        let url = redact_url(url);
+        #[cfg(feature = "reqwest")]
+        if error
+            .get_ref()
+            .is_some_and(|inner| inner.is::<reqwest::Error>())
+        {
+            return Self::unwrap_request(url, error);
+        }
+        DownloadError::Body { url, source: error }
+    }
+
+    /// Takes the client error out of `error`, a body read of the already redacted `url`.
+    #[cfg(feature = "reqwest")]
+    fn unwrap_request(url: String, error: io::Error) -> Self {
+        let kind = error.kind();
+        match error
+            .into_inner()
+            .map(|inner| inner.downcast::<reqwest::Error>())
+        {
+            Some(Ok(source)) => DownloadError::Request {
+                url,
+                source: source.without_url(),
+            },
+            // Not reachable after the check in `body`, but rebuilding the error keeps it intact.
+            Some(Err(inner)) => DownloadError::Body {
+                url,
+                source: io::Error::new(kind, inner),
+            },
+            None => DownloadError::Body {
+                url,
+                source: kind.into(),
+            },
+        }
+    }
+
+    /// Returns `true` if the error looks transient and the download is worth retrying.
+    ///
+    /// Connection failures, resets, timeouts, 5xx and `429 Too Many Requests` responses are
+    /// retriable; other client errors such as 404, checksum mismatches, and local file-system
+    /// errors are not.
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            // reqwest reports a body the connection dropped as a decode error, so the I/O error
+            // underneath decides, as it does for other backends.
+            #[cfg(feature = "reqwest")]
+            DownloadError::Request { source, .. } => {
+                source.is_timeout()
+                    || source.is_connect()
+                    || source.is_request()
+                    || source.is_body()
+                    || io_cause(source).is_some_and(|cause| is_transient(cause.kind()))
+            }
+            DownloadError::Status { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            DownloadError::Body { source, .. } => is_transient(source.kind()),
+            // A short file usually means the connection dropped before the body was complete.
+            DownloadError::SizeMismatch(error) => error.is_truncated(),
+            // Every phase of a timeout may succeed on a later attempt, and so may a proxy that
+            // was briefly unreachable.
+            DownloadError::Timeout(_) => true,
+            #[cfg(feature = "reqwest")]
+            DownloadError::Proxy(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if another mirror of the file may succeed where this error stopped the
+    /// download.
+    ///
+    /// Besides transient errors, which have already been retried by then, any non-success
+    /// status, a file that doesn't match its checksum or can't be decompressed, an answer of the
+    /// wrong content type, a mirror with an invalid URL and an expired pre-signed URL are worth
+    /// trying elsewhere.
+    pub(crate) fn fails_over(&self) -> bool {
+        match self {
+            DownloadError::RetriesExhausted(error) => error.last_error.fails_over(),
+            DownloadError::Status { .. }
+            | DownloadError::ChecksumMismatch(_)
+            | DownloadError::UnexpectedContentType(_)
+            | DownloadError::InvalidUrl(_)
+            | DownloadError::UrlExpired(_) => true,
+            #[cfg(feature = "gzip")]
+            DownloadError::Decompress(_) => true,
+            error => error.is_retriable(),
+        }
+    }
+
+    /// Returns `true` if the download was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, DownloadError::Cancelled)
+    }
+}
+
+/// Returns `true` if an I/O error of `kind` is a connection failure a later attempt may not
+/// run into.
+fn is_transient(kind: io::ErrorKind) -> bool {
+    matches!(
+        kind,
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+/// Returns the first I/O error among the causes of a client error.
+#[cfg(feature = "reqwest")]
+fn io_cause(error: &reqwest::Error) -> Option<&io::Error> {
+    let mut cause = error.source();
+    while let Some(error) = cause {
+        if let Some(io) = error.downcast_ref::<io::Error>() {
+            return Some(io);
+        }
+        cause = error.source();
+    }
+    None
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "reqwest")]
+            DownloadError::Request { url, source } => {
+                write!(f, "request to {} failed: {}", url, source)
+            }
+            DownloadError::Status { url, status, .. } => {
+                write!(f, "server returned {} for {}", status, url)
+            }
+            DownloadError::Body { url, source } => {
+                write!(f, "reading the response of {} failed: {}", url, source)
+            }
+            DownloadError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            DownloadError::Writer(source) => write!(f, "writing the body failed: {}", source),
+            DownloadError::ChecksumMismatch(error) => error.fmt(f),
+            DownloadError::SizeMismatch(error) => error.fmt(f),
+            DownloadError::SizeLimitExceeded(error) => error.fmt(f),
+            DownloadError::UnexpectedContentType(error) => error.fmt(f),
+            DownloadError::RangeIgnored(error) => error.fmt(f),
+            DownloadError::SegmentPanicked(error) => error.fmt(f),
+            DownloadError::InsufficientDiskSpace(error) => error.fmt(f),
+            #[cfg(feature = "gzip")]
+            DownloadError::Decompress(error) => error.fmt(f),
+            #[cfg(feature = "extract")]
+            DownloadError::Extract(error) => error.fmt(f),
+            DownloadError::Cancelled => f.write_str("download was cancelled"),
+            DownloadError::Timeout(error) => error.fmt(f),
+            #[cfg(feature = "reqwest")]
+            DownloadError::Proxy(error) => error.fmt(f),
+            DownloadError::TooManyRedirects(error) => error.fmt(f),
+            DownloadError::RedirectRefused(error) => error.fmt(f),
+            DownloadError::RetriesExhausted(error) => error.fmt(f),
+            DownloadError::DuplicateDestination(error) => error.fmt(f),
+            DownloadError::InvalidHeader(error) => error.fmt(f),
+            DownloadError::InvalidConfig(error) => error.fmt(f),
+            DownloadError::DestinationExists(error) => error.fmt(f),
+            #[cfg(feature = "reqwest")]
+            DownloadError::Client(source) => {
+                write!(f, "failed to build the HTTP client: {}", source)
+            }
+            DownloadError::BatchFailed(error) => error.fmt(f),
+            DownloadError::MalformedUrl(error) => error.fmt(f),
+            DownloadError::InvalidUrl(error) => error.fmt(f),
+            DownloadError::UrlExpired(error) => error.fmt(f),
+            DownloadError::InvalidManifest(error) => error.fmt(f),
+            DownloadError::InvalidState(error) => error.fmt(f),
+            DownloadError::InvalidCookie(error) => error.fmt(f),
+            DownloadError::InvalidCertificate(error) => error.fmt(f),
+            DownloadError::PinMismatch(error) => error.fmt(f),
+            DownloadError::QueueClosed => {
+                f.write_str("the downloader is closed and accepts no more requests")
+            }
+            DownloadError::QueueFull => f.write_str("the downloader's queue is full"),
+        }
+    }
+}
+
+impl Error for DownloadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            #[cfg(feature = "reqwest")]
+            DownloadError::Request { source, .. } | DownloadError::Client(source) => Some(source),
+            DownloadError::Body { source, .. }
+            | DownloadError::Io { source, .. }
+            | DownloadError::Writer(source) => Some(source),
+            // The wrapped errors are displayed as this error, so their causes come next.
+            DownloadError::ChecksumMismatch(error) => error.source(),
+            DownloadError::SizeMismatch(error) => error.source(),
+            DownloadError::SizeLimitExceeded(error) => error.source(),
+            DownloadError::UnexpectedContentType(error) => error.source(),
+            DownloadError::RangeIgnored(error) => error.source(),
+            DownloadError::SegmentPanicked(error) => error.source(),
+            DownloadError::InsufficientDiskSpace(error) => error.source(),
+            #[cfg(feature = "gzip")]
+            DownloadError::Decompress(error) => error.source(),
+            #[cfg(feature = "extract")]
+            DownloadError::Extract(error) => error.source(),
+            DownloadError::Timeout(error) => error.source(),
+            #[cfg(feature = "reqwest")]
+            DownloadError::Proxy(error) => error.source(),
+            DownloadError::TooManyRedirects(error) => error.source(),
+            DownloadError::RedirectRefused(error) => error.source(),
+            DownloadError::RetriesExhausted(error) => error.source(),
+            DownloadError::DuplicateDestination(error) => error.source(),
+            DownloadError::InvalidHeader(error) => error.source(),
+            DownloadError::InvalidConfig(error) => error.source(),
+            DownloadError::DestinationExists(error) => error.source(),
+            DownloadError::BatchFailed(error) => error.source(),
+            DownloadError::MalformedUrl(error) => error.source(),
+            DownloadError::InvalidUrl(error) => error.source(),
+            DownloadError::UrlExpired(error) => error.source(),
+            DownloadError::InvalidManifest(error) => error.source(),
+            DownloadError::InvalidState(error) => error.source(),
+            DownloadError::InvalidCookie(error) => error.source(),
+            DownloadError::InvalidCertificate(error) => error.source(),
+            DownloadError::PinMismatch(error) => error.source(),
+            DownloadError::Status { .. }
+            | DownloadError::Cancelled
+            | DownloadError::QueueClosed
+            | DownloadError::QueueFull => None,
+        }
+    }
+}
+
+impl From<ChecksumMismatch> for DownloadError {
+    fn from(error: ChecksumMismatch) -> Self {
+        DownloadError::ChecksumMismatch(error)
+    }
+}
+
+impl From<SizeMismatch> for DownloadError {
+    fn from(error: SizeMismatch) -> Self {
+        DownloadError::SizeMismatch(error)
+    }
+}
+
+impl From<SizeLimitExceeded> for DownloadError {
+    fn from(error: SizeLimitExceeded) -> Self {
+        DownloadError::SizeLimitExceeded(error)
+    }
+}
+
+impl From<UnexpectedContentType> for DownloadError {
+    fn from(error: UnexpectedContentType) -> Self {
+        DownloadError::UnexpectedContentType(error)
+    }
+}
+
+impl From<RangeIgnored> for DownloadError {
+    fn from(error: RangeIgnored) -> Self {
+        DownloadError::RangeIgnored(error)
+    }
+}
+
+impl From<SegmentPanicked> for DownloadError {
+    fn from(error: SegmentPanicked) -> Self {
+        DownloadError::SegmentPanicked(error)
+    }
+}
+
+impl From<InsufficientDiskSpace> for DownloadError {
+    fn from(error: InsufficientDiskSpace) -> Self {
+        DownloadError::InsufficientDiskSpace(error)
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl From<DecompressError> for DownloadError {
+    fn from(error: DecompressError) -> Self {
+        DownloadError::Decompress(error)
+    }
+}
+
+#[cfg(feature = "extract")]
+impl From<ExtractError> for DownloadError {
+    fn from(error: ExtractError) -> Self {
+        DownloadError::Extract(error)
+    }
+}
+
+impl From<Timeout> for DownloadError {
+    fn from(error: Timeout) -> Self {
+        DownloadError::Timeout(error)
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl From<ProxyUnreachable> for DownloadError {
+    fn from(error: ProxyUnreachable) -> Self {
+        DownloadError::Proxy(error)
+    }
+}
+
+impl From<TooManyRedirects> for DownloadError {
+    fn from(error: TooManyRedirects) -> Self {
+        DownloadError::TooManyRedirects(error)
+    }
+}
+
+impl From<RedirectRefused> for DownloadError {
+    fn from(error: RedirectRefused) -> Self {
+        DownloadError::RedirectRefused(error)
+    }
+}
+
+impl From<RetriesExhausted> for DownloadError {
+    fn from(error: RetriesExhausted) -> Self {
+        DownloadError::RetriesExhausted(error)
+    }
+}
+
+impl From<DuplicateDestination> for DownloadError {
+    fn from(error: DuplicateDestination) -> Self {
+        DownloadError::DuplicateDestination(error)
+    }
+}
+
+impl From<InvalidHeader> for DownloadError {
+    fn from(error: InvalidHeader) -> Self {
+        DownloadError::InvalidHeader(error)
+    }
+}
+
+impl From<InvalidConfig> for DownloadError {
+    fn from(error: InvalidConfig) -> Self {
+        DownloadError::InvalidConfig(error)
+    }
+}
+
+impl From<DestinationExists> for DownloadError {
+    fn from(error: DestinationExists) -> Self {
+        DownloadError::DestinationExists(error)
+    }
+}
+
+impl From<BatchFailed> for DownloadError {
+    fn from(error: BatchFailed) -> Self {
+        DownloadError::BatchFailed(error)
+    }
+}
+
+impl From<MalformedUrl> for DownloadError {
+    fn from(error: MalformedUrl) -> Self {
+        DownloadError::MalformedUrl(error)
+    }
+}
+
+impl From<InvalidUrl> for DownloadError {
+    fn from(error: InvalidUrl) -> Self {
+        DownloadError::InvalidUrl(error)
+    }
+}
+
+impl From<UrlExpired> for DownloadError {
+    fn from(error: UrlExpired) -> Self {
+        DownloadError::UrlExpired(error)
+    }
+}
+
+impl From<InvalidManifest> for DownloadError {
+    fn from(error: InvalidManifest) -> Self {
+        DownloadError::InvalidManifest(error)
+    }
+}
+
+impl From<InvalidState> for DownloadError {
+    fn from(error: InvalidState) -> Self {
+        DownloadError::InvalidState(error)
+    }
+}
+
+impl From<InvalidCookie> for DownloadError {
+    fn from(error: InvalidCookie) -> Self {
+        DownloadError::InvalidCookie(error)
+    }
+}
+
+impl From<InvalidCertificate> for DownloadError {
+    fn from(error: InvalidCertificate) -> Self {
+        DownloadError::InvalidCertificate(error)
+    }
+}
+
+impl From<PinMismatch> for DownloadError {
+    fn from(error: PinMismatch) -> Self {
+        DownloadError::PinMismatch(error)
+    }
+}
@@ -0,0 +1,249 @@
+use crate::progress::DownloadCallbackProgress;
+use crate::timing::DownloadTimings;
+use std::borrow::Cow;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Lifecycle events emitted while a file is being downloaded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadEvent {
+    /// The server redirected the request. Sent once per hop, before the next request is made.
+    Redirected {
+        /// The URL that answered with the redirect.
+        from: String,
+        /// The URL the request is sent to next.
+        to: String,
+    },
+    /// The download failed at one URL and starts over from the next of its mirrors. Sent before
+    /// the first request to the mirror, which reports its own [`DownloadEvent::Started`].
+    MirrorFailover {
+        /// The URL that failed.
+        from: String,
+        /// The mirror tried next.
+        to: String,
+        /// A description of the error that made the download move on.
+        error: String,
+    },
+    /// The pre-signed URL of the download expired, and
+    /// [`crate::BatchConfig::refresh_url`] minted a fresh one, which is requested right away.
+    UrlRefreshed {
+        /// The URL that expired.
+        from: String,
+        /// The fresh URL requested next.
+        to: String,
+    },
+    /// A pinned host presented a certificate without any of its pinned keys, so the download
+    /// stops before anything is written. Someone may be intercepting the connection. Followed
+    /// by [`DownloadEvent::Failed`] with a [`crate::PinMismatch`].
+    PinMismatch {
+        /// The URL whose connection presented the certificate.
+        url: String,
+        /// The pinned host.
+        host: String,
+    },
+    /// A partial file could not be resumed, because the server doesn't accept ranges or
+    /// answered the ranged request with the whole file, so the download starts over from the
+    /// first byte. Sent before [`DownloadEvent::Started`].
+    ResumeRestarted {
+        /// The URL being downloaded.
+        url: String,
+        /// Bytes of the partial file that are thrown away and downloaded again.
+        discarded_bytes: u64,
+    },
+    /// The server responded and the body is about to be streamed.
+    Started {
+        /// The URL being downloaded.
+        url: String,
+        /// Total size of the file in bytes, or `None` if the server did not report it.
+        total_bytes: Option<u64>,
+    },
+    /// A chunk of the body has been written to disk.
+    Progress(DownloadCallbackProgress),
+    /// The file has been fully downloaded.
+    Completed {
+        /// Where the file was saved.
+        path: PathBuf,
+        /// Number of bytes written.
+        bytes: u64,
+        /// SHA-256 of the file as hex, when [`crate::DownloadConfig::compute_sha256`] is set.
+        sha256: Option<String>,
+        /// How long the phases of the download took.
+        timings: DownloadTimings,
+    },
+    /// An attempt failed with a transient error and the download will be retried.
+    Retrying {
+        /// The number of the attempt about to start, beginning at 2.
+        attempt: u32,
+        /// How long the downloader waits before the next attempt.
+        delay: Duration,
+        /// A description of the error that caused the retry.
+        error: String,
+    },
+    /// The server asked to be left alone for a while with the `Retry-After` of a `429` or `503`
+    /// response, or an earlier download from the same host was asked to. Sent before the
+    /// download waits, whether for its next attempt or before its first request.
+    Throttled {
+        /// The URL that is held back.
+        url: String,
+        /// How long the download waits, capped at [`crate::RetryConfig::max_retry_after`].
+        delay: Duration,
+    },
+    /// The destination already existed and [`crate::OverwritePolicy::SkipExisting`] kept it.
+    ///
+    /// Nothing was downloaded; this is the final event for the file.
+    Skipped {
+        /// The existing file that was left in place.
+        path: PathBuf,
+    },
+    /// The server reported that the file is unchanged since it was last downloaded, so the
+    /// existing file was kept. Only sent when [`crate::DownloadConfig::conditional_requests`]
+    /// is enabled; this is the final event for the file.
+    NotModified {
+        /// The existing file that was left in place.
+        path: PathBuf,
+    },
+    /// The download was cancelled before it finished.
+    Cancelled {
+        /// The destination the file was being saved to.
+        path: PathBuf,
+    },
+    /// A batch with [`crate::BatchConfig::adaptive_concurrency`] changed how many downloads it
+    /// runs at the same time. Only sent to the batch callback, since it concerns no single
+    /// request.
+    ConcurrencyChanged {
+        /// Downloads allowed at the same time until now.
+        from: usize,
+        /// Downloads allowed at the same time from now on.
+        to: usize,
+        /// Bytes per second the batch received during the sample that led to the change.
+        bytes_per_sec: f64,
+        /// Retries and failures during that sample.
+        errors: u64,
+    },
+    /// The download failed and will not be continued.
+    Failed {
+        /// A description of the error that stopped the download.
+        error: String,
+        /// How long the phases of the download took until it failed.
+        timings: DownloadTimings,
+    },
+}
+
+impl DownloadEvent {
+    /// Returns the event as sent for the request at `index` of a batch, which waited `queued`
+    /// in the batch's queue before its download started.
+    ///
+    /// The index becomes the [`DownloadCallbackProgress::id`] of the request's progress, and
+    /// the wait is added to the [`DownloadTimings`] of its final event.
+    pub(crate) fn for_request(&self, index: usize, queued: Duration) -> Cow<'_, Self> {
+        let mut event = match self {
+            DownloadEvent::Progress(_)
+            | DownloadEvent::Completed { .. }
+            | DownloadEvent::Failed { .. } => self.clone(),
+            _ => return Cow::Borrowed(self),
+        };
+        match &mut event {
+            DownloadEvent::Progress(progress) => progress.id = index as u64,
+            DownloadEvent::Completed { timings, .. } | DownloadEvent::Failed { timings, .. } => {
+                timings.queued = queued;
+            }
+            _ => {}
+        }
+        Cow::Owned(event)
+    }
+
+    /// Returns the timings carried by a [`DownloadEvent::Completed`] or
+    /// [`DownloadEvent::Failed`].
+    pub(crate) fn timings(&self) -> Option<DownloadTimings> {
+        match self {
+            DownloadEvent::Completed { timings, .. } | DownloadEvent::Failed { timings, .. } => {
+                Some(*timings)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Callback receiving every event of a batch together with the position of the request it
+/// belongs to, as [`crate::BatchConfig::on_file_event`].
+pub type FileEventCallback = Arc<dyn Fn(usize, &DownloadEvent) + Send + Sync>;
+
+/// What the callback of a single download returns: nothing, or whether the download should go
+/// on.
+///
+/// Callbacks passed to [`crate::download_file`] and its variants may return
+/// [`ControlFlow::Break`] to cancel their own download, for instance once it turns out to be
+/// larger than expected. The download stops before reading its next chunk, reports
+/// [`DownloadEvent::Cancelled`], removes its `.part` file unless
+/// [`crate::DownloadConfig::keep_partial_on_cancel`] is set, and returns
+/// [`crate::DownloadError::Cancelled`] rather than the error of a failed download. A download
+/// whose whole body was already read finishes anyway. Closures returning `()` never stop their
+/// download.
+///
+/// ```no_run
+/// use parallel_downloads::{download_file, DownloadEvent};
+/// use std::ops::ControlFlow;
+///
+/// let result = download_file(
+///     "https://www.rust-lang.org/static/images/rust-logo-blk.svg",
+///     "./rust-logo.svg",
+///     |event| match event {
+///         DownloadEvent::Progress(progress) if progress.bytes_downloaded() > 1 << 20 => {
+///             ControlFlow::Break(())
+///         }
+///         _ => ControlFlow::Continue(()),
+///     },
+/// );
+/// match result {
+///     Err(error) if error.is_cancelled() => println!("larger than 1 MiB"),
+///     result => result.unwrap(),
+/// }
+/// ```
+pub trait EventFlow {
+    /// Returns whether the download should go on.
+    fn flow(self) -> ControlFlow<()>;
+}
+
+impl EventFlow for () {
+    fn flow(self) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+}
+
+impl EventFlow for ControlFlow<()> {
+    fn flow(self) -> ControlFlow<()> {
+        self
+    }
+}
+
+/// Adapts a progress-only closure into an event callback.
+///
+/// Before [`DownloadEvent`] existed, download functions accepted a closure taking
+/// `&DownloadCallbackProgress`. Wrapping such a closure with this function keeps it working
+/// by forwarding [`DownloadEvent::Progress`] and ignoring every other event.
+///
+/// ```no_run
+/// use parallel_downloads::{download_file, progress_only};
+///
+/// download_file(
+///     "https://www.rust-lang.org/static/images/rust-logo-blk.svg",
+///     "./rust-logo.svg",
+///     progress_only(|progress| match progress.percent() {
+///         Some(percent) => println!("{:.1}%", percent),
+///         // Chunked responses have no known size, so only the byte count is available.
+///         None => println!("{} bytes", progress.bytes_downloaded()),
+///     }),
+/// )
+/// .unwrap();
+/// ```
+pub fn progress_only(
+    callback: impl Fn(&DownloadCallbackProgress) + 'static + Send + Sync,
+) -> impl Fn(&DownloadEvent) + 'static + Send + Sync {
+    move |event| {
+        if let DownloadEvent::Progress(progress) = event {
+            callback(progress);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use crate::host_limit::host_key;
+use crate::result::{DownloadOutcome, DownloadResult};
+use log::{debug, warn};
+use prometheus::{
+    exponential_buckets, Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry,
+    TextEncoder,
+};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+/// Prometheus counters and histograms of the downloads of a batch, labelled by host and by
+/// how each download ended.
+///
+/// Registered once with [`PrometheusMetrics::register`], then shared with every batch that
+/// should be measured through [`crate::BatchConfig::prometheus`]. Every download is recorded
+/// as soon as it ends. Only available with the `prometheus` feature.
+#[derive(Clone)]
+pub struct PrometheusMetrics {
+    /// `downloads_total`, by `host` and `status`.
+    downloads: IntCounterVec,
+    /// `download_bytes_total`, by `host`.
+    bytes: IntCounterVec,
+    /// `download_retries_total`, by `host`.
+    retries: IntCounterVec,
+    /// `download_duration_seconds`, by `host` and `status`.
+    duration: HistogramVec,
+    /// `download_size_bytes`, by `host`.
+    size: HistogramVec,
+}
+
+impl fmt::Debug for PrometheusMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrometheusMetrics").finish_non_exhaustive()
+    }
+}
+
+impl PrometheusMetrics {
+    /// Creates the metrics and registers them with `registry`.
+    ///
+    /// | Metric | Kind | Labels |
+    /// |---|---|---|
+    /// | `downloads_total` | counter | `host`, `status` |
+    /// | `download_bytes_total` | counter | `host` |
+    /// | `download_retries_total` | counter | `host` |
+    /// | `download_duration_seconds` | histogram | `host`, `status` |
+    /// | `download_size_bytes` | histogram | `host` |
+    ///
+    /// `status` is `completed`, `failed`, `skipped`, `not_modified`, `cancelled`, `aborted` or `dry_run`. Bytes and
+    /// sizes only count completed downloads.
+    ///
+    /// # Arguments
+    ///
+    /// * `registry` - The registry the metrics are exposed through.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` with the registered metrics.
+    /// * `Err` if the registry already holds metrics with the same names.
+    pub fn register(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let metrics = Self {
+            downloads: IntCounterVec::new(
+                Opts::new(
+                    "downloads_total",
+                    "Downloads that ended, by how they ended.",
+                ),
+                &["host", "status"],
+            )?,
+            bytes: IntCounterVec::new(
+                Opts::new("download_bytes_total", "Bytes of the completed downloads."),
+                &["host"],
+            )?,
+            retries: IntCounterVec::new(
+                Opts::new(
+                    "download_retries_total",
+                    "Attempts made after the first, including mirrors tried.",
+                ),
+                &["host"],
+            )?,
+            duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "download_duration_seconds",
+                    "How long downloads took, by how they ended.",
+                )
+                .buckets(exponential_buckets(0.05, 2.0, 14)?),
+                &["host", "status"],
+            )?,
+            size: HistogramVec::new(
+                HistogramOpts::new("download_size_bytes", "Sizes of the completed downloads.")
+                    .buckets(exponential_buckets(1024.0, 4.0, 12)?),
+                &["host"],
+            )?,
+        };
+        registry.register(Box::new(metrics.downloads.clone()))?;
+        registry.register(Box::new(metrics.bytes.clone()))?;
+        registry.register(Box::new(metrics.retries.clone()))?;
+        registry.register(Box::new(metrics.duration.clone()))?;
+        registry.register(Box::new(metrics.size.clone()))?;
+        Ok(metrics)
+    }
+
+    /// Records a download that ended.
+    pub(crate) fn observe(&self, result: &DownloadResult) {
+        let host = host_key(&result.url).unwrap_or_default();
+        let status = match &result.outcome {
+            DownloadOutcome::Completed { .. } => "completed",
+            DownloadOutcome::Failed { .. } => "failed",
+            DownloadOutcome::Skipped { .. } => "skipped",
+            DownloadOutcome::NotModified { .. } => "not_modified",
+            DownloadOutcome::Cancelled => "cancelled",
+            DownloadOutcome::Aborted => "aborted",
+            DownloadOutcome::DryRun { .. } => "dry_run",
+        };
+        self.downloads.with_label_values(&[&host, status]).inc();
+        self.duration
+            .with_label_values(&[&host, status])
+            .observe(result.duration.as_secs_f64());
+        let retries = result.timings.attempts.saturating_sub(1);
+        self.retries
+            .with_label_values(&[&host])
+            .inc_by(u64::from(retries));
+        if let DownloadOutcome::Completed { bytes, .. } = &result.outcome {
+            self.bytes.with_label_values(&[&host]).inc_by(*bytes);
+            self.size.with_label_values(&[&host]).observe(*bytes as f64);
+        }
+    }
+}
+
+/// Serves the metrics of `registry` at `/metrics` on `address`, in the Prometheus text format,
+/// from a background thread.
+///
+/// Meant for long-running batches to be scraped from the machine they run on; there is no
+/// TLS or authentication. Requests are answered one at a time. Only available with the
+/// `prometheus` feature.
+///
+/// # Arguments
+///
+/// * `address` - Where to listen, such as `127.0.0.1:9898`. Port `0` picks a free port.
+/// * `registry` - The registry to expose.
+///
+/// # Returns
+///
+/// * `Ok` with the address the server is listening on.
+/// * `Err` if the address can't be bound.
+pub fn serve_metrics(address: impl ToSocketAddrs, registry: Registry) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(address)?;
+    let address = listener.local_addr()?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            // A scraper that disconnects early only loses its own answer.
+            let result = stream.and_then(|stream| answer(stream, &registry));
+            if let Err(e) = result {
+                debug!("Failed to answer a metrics request: {}", e);
+            }
+        }
+        warn!("The metrics server on {} stopped", address);
+    });
+    Ok(address)
+}
+
+/// Answers one HTTP request for the metrics of `registry`.
+fn answer(stream: TcpStream, registry: &Registry) -> io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // The headers aren't needed, but must be read before the connection is closed.
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let (status, content_type, body) = if path == "/metrics" || path.starts_with("/metrics?") {
+        let encoder = TextEncoder::new();
+        let mut body = Vec::new();
+        encoder
+            .encode(&registry.gather(), &mut body)
+            .map_err(io::Error::other)?;
+        ("200 OK", encoder.format_type().to_string(), body)
+    } else {
+        (
+            "404 Not Found",
+            "text/plain".to_string(),
+            b"Not found; metrics are at /metrics\n".to_vec(),
+        )
+    };
+
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
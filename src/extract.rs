@@ -0,0 +1,292 @@
+use crate::error::DownloadError;
+use crate::request::DownloadRequest;
+use crate::result::DownloadOutcome;
+use crate::tar;
+use crate::xz::{self, XzReader};
+use crate::zip;
+use flate2::bufread::MultiGzDecoder;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// The bytes every gzip stream starts with.
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// Returned when a downloaded archive can't be unpacked.
+///
+/// The archive itself stays where it was downloaded, along with whatever was extracted before
+/// the failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractError {
+    /// The archive that was being unpacked.
+    pub archive: PathBuf,
+    /// The name of the entry that failed, if the failure is specific to one.
+    pub entry: Option<String>,
+    /// Why the archive couldn't be unpacked.
+    pub reason: String,
+}
+
+impl ExtractError {
+    /// Describes why `archive`, or its entry named `entry`, couldn't be unpacked.
+    pub(crate) fn new(archive: &Path, entry: Option<&str>, reason: impl Into<String>) -> Self {
+        Self {
+            archive: archive.to_path_buf(),
+            entry: entry.map(str::to_string),
+            reason: reason.into(),
+        }
+    }
+
+    /// Describes an error reading `archive`, which means it is truncated or corrupt.
+    pub(crate) fn unreadable(archive: &Path, entry: Option<&str>, error: &io::Error) -> Self {
+        let reason = match error.kind() {
+            io::ErrorKind::UnexpectedEof => "the archive is truncated".to_string(),
+            io::ErrorKind::Unsupported => error.to_string(),
+            _ => format!("the archive is corrupt: {}", error),
+        };
+        Self::new(archive, entry, reason)
+    }
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.entry {
+            Some(entry) => write!(
+                f,
+                "failed to extract `{}` from {}: {}",
+                entry,
+                self.archive.display(),
+                self.reason
+            ),
+            None => write!(
+                f,
+                "failed to extract {}: {}",
+                self.archive.display(),
+                self.reason
+            ),
+        }
+    }
+}
+
+impl Error for ExtractError {}
+
+/// Unpacks the archive a completed download produced, if its request asked for it.
+///
+/// The files extracted are listed in the outcome. An archive that can't be unpacked turns the
+/// outcome into a failure with an [`ExtractError`], or a [`DownloadError::Io`] if writing the
+/// extracted files failed; any other outcome is returned unchanged.
+pub(crate) fn extract_completed(
+    mut outcome: DownloadOutcome,
+    request: &DownloadRequest,
+) -> DownloadOutcome {
+    if let DownloadOutcome::Completed {
+        path, extracted, ..
+    } = &mut outcome
+    {
+        if request.extract {
+            let directory = match &request.extract_to {
+                Some(directory) => directory.clone(),
+                None => path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            };
+            match extract(path, &directory, request.extract_max_size) {
+                Ok(files) => *extracted = files,
+                Err(error) => return DownloadOutcome::Failed { error },
+            }
+        }
+    }
+    outcome
+}
+
+/// Unpacks a completed download like [`extract_completed`], on tokio's blocking threads.
+#[cfg(feature = "async")]
+pub(crate) async fn extract_completed_async(
+    outcome: DownloadOutcome,
+    request: &DownloadRequest,
+) -> DownloadOutcome {
+    if !request.extract || !matches!(outcome, DownloadOutcome::Completed { .. }) {
+        return outcome;
+    }
+    let request = request.clone();
+    tokio::task::spawn_blocking(move || extract_completed(outcome, &request))
+        .await
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic.into_panic()))
+}
+
+/// Unpacks the zip, tar, `.tar.gz` or `.tar.xz` archive at `archive` into `directory`.
+///
+/// The format is told by the content of the file rather than its name. Existing files are
+/// overwritten, and symbolic links and special files in the archive are skipped.
+///
+/// # Arguments
+///
+/// * `archive` - The archive to unpack.
+/// * `directory` - Where to unpack it, created if missing. Entries whose paths are absolute or
+///   climb out of it with `..` are refused.
+/// * `max_size` - How many bytes the extracted files may add up to, if limited.
+///
+/// # Returns
+///
+/// * `Ok` with the path of every file extracted, in the order of the archive.
+/// * `Err` with an [`ExtractError`] if the archive is of an unknown format, corrupt, holds an
+///   entry that would land outside `directory`, or unpacks to more than `max_size`.
+/// * `Err` with a [`DownloadError::Io`] if opening the archive or writing a file failed.
+pub(crate) fn extract(
+    archive: &Path,
+    directory: &Path,
+    max_size: Option<u64>,
+) -> Result<Vec<PathBuf>, DownloadError> {
+    let mut file = File::open(archive).map_err(DownloadError::io(archive))?;
+    // Enough to hold the first header of a tar archive.
+    let mut head = Vec::with_capacity(512);
+    (&mut file)
+        .take(512)
+        .read_to_end(&mut head)
+        .map_err(DownloadError::io(archive))?;
+    file.rewind().map_err(DownloadError::io(archive))?;
+    fs::create_dir_all(directory).map_err(DownloadError::io(directory))?;
+
+    let unreadable = |error: io::Error| ExtractError::unreadable(archive, None, &error);
+    let budget = &mut Budget { max_size, used: 0 };
+    if head.starts_with(&zip::MAGIC) || head.starts_with(&zip::END_MAGIC) {
+        zip::extract(file, archive, directory, budget)
+    } else if head.starts_with(&GZIP_MAGIC) {
+        // Members concatenated after the first are read as well, as `gzip -d` would.
+        let reader = MultiGzDecoder::new(BufReader::new(file));
+        tar::extract(reader, archive, directory, budget)
+    } else if head.starts_with(&xz::MAGIC) {
+        let reader = XzReader::new(BufReader::new(file)).map_err(unreadable)?;
+        tar::extract(reader, archive, directory, budget)
+    } else if tar::is_header(&head) {
+        tar::extract(BufReader::new(file), archive, directory, budget)
+    } else {
+        Err(ExtractError::new(
+            archive,
+            None,
+            "it is not a zip, tar, .tar.gz or .tar.xz archive",
+        )
+        .into())
+    }
+}
+
+/// How many bytes the files extracted from an archive add up to, against the limit they may
+/// reach.
+#[derive(Debug)]
+pub(crate) struct Budget {
+    /// How many bytes the files may add up to, if limited.
+    max_size: Option<u64>,
+    /// How many bytes were extracted so far.
+    used: u64,
+}
+
+impl Budget {
+    /// Counts `size` more bytes of the entry named `name` of `archive`.
+    ///
+    /// # Returns
+    ///
+    /// * `Err` with an [`ExtractError`] if the extracted files now add up to more than the
+    ///   limit.
+    pub(crate) fn spend(
+        &mut self,
+        archive: &Path,
+        name: &str,
+        size: u64,
+    ) -> Result<(), DownloadError> {
+        self.used = self.used.saturating_add(size);
+        match self.max_size {
+            Some(max_size) if self.used > max_size => Err(ExtractError::new(
+                archive,
+                Some(name),
+                format!("the extracted files add up to more than {} bytes", max_size),
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns where the entry named `name` of `archive` goes in `directory`.
+///
+/// # Returns
+///
+/// * `Ok(Some)` with the path of the entry.
+/// * `Ok(None)` if the name is empty or only `.`, so the entry is the directory itself.
+/// * `Err` with an [`ExtractError`] if the name is absolute or climbs out with `..`.
+pub(crate) fn entry_path(
+    archive: &Path,
+    directory: &Path,
+    name: &str,
+) -> Result<Option<PathBuf>, DownloadError> {
+    let mut path = directory.to_path_buf();
+    let mut inside = false;
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(part) => {
+                path.push(part);
+                inside = true;
+            }
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(ExtractError::new(
+                    archive,
+                    Some(name),
+                    "its path leads outside the target directory",
+                )
+                .into());
+            }
+        }
+    }
+    Ok(inside.then_some(path))
+}
+
+/// Writes the entry named `name` of `archive`, read from `reader`, to a new file at `path`.
+///
+/// # Returns
+///
+/// * `Ok` with the number of bytes written.
+/// * `Err` with an [`ExtractError`] if reading the entry failed or its bytes overran
+///   `budget`, or a [`DownloadError::Io`] if creating or writing the file failed.
+pub(crate) fn write_entry(
+    reader: &mut impl Read,
+    archive: &Path,
+    name: &str,
+    path: &Path,
+    budget: &mut Budget,
+) -> Result<u64, DownloadError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(DownloadError::io(parent))?;
+    }
+    let mut file = File::create(path).map_err(DownloadError::io(path))?;
+    let mut buffer = vec![0; 64 * 1024];
+    let mut written = 0;
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(ExtractError::unreadable(archive, Some(name), &error).into()),
+        };
+        budget.spend(archive, name, read as u64)?;
+        file.write_all(&buffer[..read])
+            .map_err(DownloadError::io(path))?;
+        written += read as u64;
+    }
+    Ok(written)
+}
+
+/// Keeps the executable bits of the Unix `mode` an entry was archived with.
+pub(crate) fn set_executable(path: &Path, mode: u32) -> Result<(), DownloadError> {
+    #[cfg(unix)]
+    if mode & 0o111 != 0 {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut permissions = fs::metadata(path)
+            .map_err(DownloadError::io(path))?
+            .permissions();
+        permissions.set_mode(permissions.mode() | (mode & 0o111));
+        fs::set_permissions(path, permissions).map_err(DownloadError::io(path))?;
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+    Ok(())
+}
@@ -0,0 +1,477 @@
+use crate::auth::Auth;
+use crate::backend::HttpResponse;
+use crate::error::DownloadError;
+use crate::timeout::TimeoutConfig;
+use crate::url_check::{redact_url, InvalidUrl};
+use http::header::{HeaderMap, HeaderValue, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, RANGE};
+use http::{Method, StatusCode, Version};
+use percent_encoding::percent_decode_str;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use url::Url;
+
+/// The port of an `ftp://` URL that doesn't name one.
+const DEFAULT_PORT: u16 = 21;
+
+/// Returns `true` if `url` names a file on an FTP server.
+pub(crate) fn is_ftp_url(url: &str) -> bool {
+    url.trim()
+        .get(..4)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("ftp:"))
+}
+
+/// A reply of an FTP server: its three-digit code and the text after it.
+#[derive(Debug)]
+struct Reply {
+    code: u16,
+    text: String,
+}
+
+impl Reply {
+    /// Returns `true` if the code is in the `class`xx range, such as 2 for completion.
+    fn is(&self, class: u16) -> bool {
+        self.code / 100 == class
+    }
+
+    /// The HTTP status a server would have answered with instead, so a failed command ends
+    /// the download like the matching HTTP error: missing files aren't retried, a busy server
+    /// is.
+    fn status(&self) -> StatusCode {
+        match self.code {
+            530 | 532 => StatusCode::UNAUTHORIZED,
+            550 | 551 | 553 => StatusCode::NOT_FOUND,
+            code if code / 100 == 4 => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+/// The control connection of an FTP session, over which commands are sent and replies read.
+#[derive(Debug)]
+struct Control {
+    reader: BufReader<TcpStream>,
+}
+
+impl Control {
+    /// Connects to the server of `url` and reads its greeting.
+    fn connect(url: &Url, timeouts: &TimeoutConfig) -> io::Result<Self> {
+        let host = url.host_str().unwrap_or_default();
+        let port = url.port().unwrap_or(DEFAULT_PORT);
+        let addresses: Vec<SocketAddr> = (host.trim_matches(['[', ']']), port)
+            .to_socket_addrs()?
+            .collect();
+        let stream = connect_any(&addresses, timeouts)?;
+        let mut control = Self {
+            reader: BufReader::new(stream),
+        };
+        let greeting = control.reply()?;
+        if !greeting.is(2) {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                greeting.text,
+            ));
+        }
+        Ok(control)
+    }
+
+    /// Sends `command` and reads the reply to it.
+    fn send(&mut self, command: &str) -> io::Result<Reply> {
+        let stream = self.reader.get_mut();
+        stream.write_all(command.as_bytes())?;
+        stream.write_all(b"\r\n")?;
+        self.reply()
+    }
+
+    /// Reads the next reply, following its continuation lines up to the one that ends it.
+    fn reply(&mut self) -> io::Result<Reply> {
+        let mut line = String::new();
+        let mut first = None;
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+            let first = *first.get_or_insert(code);
+            // A reply ends with the line carrying its code followed by a space.
+            if code.is_some() && code == first && line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(Reply {
+                    code: code.unwrap_or_default(),
+                    text: line[3..].trim().to_string(),
+                });
+            }
+            if first.is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "malformed FTP reply",
+                ));
+            }
+        }
+    }
+
+    /// Logs in as `user`, sending `password` if the server asks for one.
+    fn login(&mut self, user: &str, password: &str) -> io::Result<Reply> {
+        let reply = self.send(&format!("USER {}", user))?;
+        if reply.code == 331 {
+            return self.send(&format!("PASS {}", password));
+        }
+        Ok(reply)
+    }
+
+    /// Opens a data connection in passive mode, falling back to extended passive mode for
+    /// servers, often over IPv6, that don't offer the classic one.
+    fn passive(&mut self, timeouts: &TimeoutConfig) -> io::Result<Result<TcpStream, Reply>> {
+        // Connect to the host of the control connection whatever address the reply names,
+        // which behind NAT is often a private one.
+        let host = self.reader.get_ref().peer_addr()?.ip();
+        let reply = self.send("PASV")?;
+        let port = if reply.code == 227 {
+            passive_port(&reply.text)
+        } else {
+            let reply = self.send("EPSV")?;
+            if reply.code != 229 {
+                return Ok(Err(reply));
+            }
+            extended_passive_port(&reply.text)
+        };
+        let port = port.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "malformed passive mode reply")
+        })?;
+        connect_any(&[SocketAddr::new(host, port)], timeouts).map(Ok)
+    }
+}
+
+/// Connects to the first of `addresses` that accepts, applying the connect and read timeouts.
+fn connect_any(addresses: &[SocketAddr], timeouts: &TimeoutConfig) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "the host has no address");
+    for address in addresses {
+        let stream = match timeouts.connect {
+            Some(limit) => TcpStream::connect_timeout(address, limit),
+            None => TcpStream::connect(address),
+        };
+        match stream {
+            Ok(stream) => {
+                stream.set_read_timeout(timeouts.read)?;
+                stream.set_write_timeout(timeouts.read)?;
+                return Ok(stream);
+            }
+            Err(error) => last_error = error,
+        }
+    }
+    Err(last_error)
+}
+
+/// Reads the port out of a `227 Entering Passive Mode (h1,h2,h3,h4,p1,p2)` reply.
+fn passive_port(text: &str) -> Option<u16> {
+    let start = text.find(|c: char| c.is_ascii_digit())?;
+    let numbers: Vec<u16> = text[start..]
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|number| !number.is_empty())
+        .take(6)
+        .map(|number| number.parse().ok())
+        .collect::<Option<_>>()?;
+    match numbers[..] {
+        [_, _, _, _, high, low] if high < 256 && low < 256 => Some(high << 8 | low),
+        _ => None,
+    }
+}
+
+/// Reads the port out of a `229 Entering Extended Passive Mode (|||port|)` reply.
+fn extended_passive_port(text: &str) -> Option<u16> {
+    let (_, rest) = text.split_once("|||")?;
+    rest.split('|').next()?.parse().ok()
+}
+
+/// Makes socket read timeouts, which surface as `WouldBlock` on some systems, look like the
+/// timeouts they are so they are retried.
+fn timed_out(error: io::Error) -> io::Error {
+    if error.kind() == io::ErrorKind::WouldBlock {
+        io::Error::new(io::ErrorKind::TimedOut, error)
+    } else {
+        error
+    }
+}
+
+/// Fetches the file named by the `ftp://` URL `url` the way an HTTP server would answer a
+/// `method` request for it.
+///
+/// The session logs in with the user and password of the URL, or of `auth` when it holds
+/// [`Auth::Basic`] credentials, and anonymously otherwise. The file is sent in binary mode
+/// over a passive data connection. Its size, when the server answers `SIZE`, becomes the
+/// `Content-Length`. A `Range` request from an offset, as sent to resume a download, restarts
+/// the transfer there with `REST`, and is answered with the whole file if the server doesn't
+/// support it. A command the server refuses is answered with an error status: `404 Not
+/// Found` for a missing file, `401 Unauthorized` for a refused login, and `503 Service
+/// Unavailable`, which is retried, for transient failures.
+///
+/// # Arguments
+///
+/// * `method` - `GET` to retrieve the file, or `HEAD` to only ask for its size.
+/// * `url` - The URL of the file.
+/// * `headers` - The request headers, of which only `Range` is honoured.
+/// * `auth` - The credentials of the download, used when the URL has none.
+/// * `timeouts` - The connect and read timeouts, applied to both connections.
+///
+/// # Returns
+///
+/// * `Ok` with the answer, whose body streams the file from the data connection.
+/// * `Err` with a [`DownloadError::Body`] if a connection failed, which is retried when it
+///   looks transient.
+/// * `Err` with a [`DownloadError::InvalidUrl`] before connecting if the user, password or
+///   path holds a line break or NUL, which would inject commands into the session.
+pub(crate) fn open(
+    method: &Method,
+    url: &str,
+    headers: &HeaderMap,
+    auth: Option<&Auth>,
+    timeouts: &TimeoutConfig,
+) -> Result<HttpResponse, DownloadError> {
+    let failed = |error: io::Error| DownloadError::body(url, timed_out(error));
+    let parsed = Url::parse(url.trim())
+        .map_err(|e| failed(io::Error::new(io::ErrorKind::InvalidInput, e.to_string())))?;
+    let decode = |text: &str| percent_decode_str(text).decode_utf8_lossy().into_owned();
+    let (user, password) = match (parsed.username(), auth) {
+        ("", Some(Auth::Basic { user, password })) => (user.clone(), password.clone()),
+        ("", _) => ("anonymous".to_string(), "anonymous@".to_string()),
+        (user, _) => (decode(user), decode(parsed.password().unwrap_or_default())),
+    };
+    let path = decode(parsed.path().trim_start_matches('/'));
+    // A line break would end the command early and smuggle in another, such as a `DELE`.
+    if [&user, &password, &path]
+        .iter()
+        .any(|text| text.contains(['\r', '\n', '\0']))
+    {
+        return Err(InvalidUrl {
+            index: None,
+            url: redact_url(url),
+            reason: "its user, password or path contains a line break or NUL".to_string(),
+        }
+        .into());
+    }
+
+    let mut control = Control::connect(&parsed, timeouts).map_err(failed)?;
+    let answer = |status: StatusCode, headers: HeaderMap| HttpResponse {
+        status,
+        headers,
+        url: parsed.clone(),
+        version: Version::HTTP_11,
+        remote_addr: None,
+        content_length: Some(0),
+        peer_certificate: None,
+        body: Box::new(io::empty()),
+    };
+
+    let reply = control.login(&user, &password).map_err(failed)?;
+    if !reply.is(2) {
+        return Ok(answer(reply.status(), HeaderMap::new()));
+    }
+    let reply = control.send("TYPE I").map_err(failed)?;
+    if !reply.is(2) {
+        return Ok(answer(reply.status(), HeaderMap::new()));
+    }
+    // Servers that can't tell the size leave the length unknown.
+    let reply = control.send(&format!("SIZE {}", path)).map_err(failed)?;
+    let size: Option<u64> = (reply.code == 213)
+        .then(|| reply.text.parse().ok())
+        .flatten();
+
+    let mut answered = HeaderMap::new();
+    answered.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if method == Method::HEAD {
+        if let Some(size) = size {
+            answered.insert(CONTENT_LENGTH, HeaderValue::from(size));
+        }
+        let _ = control.send("QUIT");
+        return Ok(answer(StatusCode::OK, answered));
+    }
+
+    let (start, end) = requested_range(headers);
+    if let Some(size) = size.filter(|size| start > 0 && start >= *size) {
+        answered.insert(CONTENT_RANGE, header_value(format!("bytes */{}", size)));
+        return Ok(answer(StatusCode::RANGE_NOT_SATISFIABLE, answered));
+    }
+    // Resume the transfer where the partial file stops, if the server can.
+    let ranged = start > 0
+        && control
+            .send(&format!("REST {}", start))
+            .map_err(failed)?
+            .code
+            == 350;
+    let offset = if ranged { start } else { 0 };
+
+    let data = match control.passive(timeouts).map_err(failed)? {
+        Ok(data) => data,
+        Err(reply) => return Ok(answer(reply.status(), HeaderMap::new())),
+    };
+    let reply = control.send(&format!("RETR {}", path)).map_err(failed)?;
+    if !reply.is(1) {
+        return Ok(answer(reply.status(), HeaderMap::new()));
+    }
+
+    // A range with an end, such as the first byte of a probe, is cut short once it has been
+    // read.
+    let limit = end.filter(|end| *end >= offset).map(|end| end + 1 - offset);
+    let length = match (size, limit) {
+        (Some(size), Some(limit)) => Some(limit.min(size.saturating_sub(offset))),
+        (Some(size), None) => Some(size.saturating_sub(offset)),
+        (None, limit) => limit,
+    };
+    if let Some(length) = length {
+        answered.insert(CONTENT_LENGTH, HeaderValue::from(length));
+    }
+    let status = match (ranged, size) {
+        (true, Some(size)) => {
+            let last = offset + length.unwrap_or(size - offset);
+            answered.insert(
+                CONTENT_RANGE,
+                header_value(format!(
+                    "bytes {}-{}/{}",
+                    offset,
+                    last.saturating_sub(1),
+                    size
+                )),
+            );
+            StatusCode::PARTIAL_CONTENT
+        }
+        (true, None) => StatusCode::PARTIAL_CONTENT,
+        (false, _) => StatusCode::OK,
+    };
+
+    Ok(HttpResponse {
+        status,
+        headers: answered,
+        url: parsed,
+        version: Version::HTTP_11,
+        remote_addr: None,
+        content_length: length,
+        peer_certificate: None,
+        body: Box::new(Retrieval {
+            data: Some(data.take(limit.unwrap_or(u64::MAX))),
+            control: Some(control),
+            cut_short: limit.is_some(),
+        }),
+    })
+}
+
+/// Reads the `bytes=<start>-[<end>]` range of `headers`, if any, as its first byte and its
+/// last one when given.
+fn requested_range(headers: &HeaderMap) -> (u64, Option<u64>) {
+    let range = headers
+        .get(RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().strip_prefix("bytes="))
+        .and_then(|value| value.split_once('-'));
+    let Some((start, end)) = range else {
+        return (0, None);
+    };
+    match start.trim().parse() {
+        Ok(start) => (start, end.trim().parse().ok()),
+        Err(_) => (0, None),
+    }
+}
+
+/// Builds a header value out of digits and ASCII punctuation.
+fn header_value(value: String) -> HeaderValue {
+    HeaderValue::try_from(value).expect("header value is ASCII")
+}
+
+/// The body of an FTP retrieval, read from its data connection.
+///
+/// Once the data connection ends, the server confirms on the control connection that the
+/// whole file was sent; a transfer it reports as aborted fails the read, so the download is
+/// retried rather than saved incomplete.
+struct Retrieval {
+    /// The data connection, until it ends.
+    data: Option<io::Take<TcpStream>>,
+    /// The control connection, until the transfer is confirmed.
+    control: Option<Control>,
+    /// Whether the transfer is closed early, after the end of the requested range, in which
+    /// case the server reports it aborted.
+    cut_short: bool,
+}
+
+impl Read for Retrieval {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let Some(data) = &mut self.data else {
+            return Ok(0);
+        };
+        let read = data.read(buffer).map_err(timed_out)?;
+        if read > 0 || buffer.is_empty() {
+            return Ok(read);
+        }
+
+        // The server only confirms the transfer once the data connection is closed.
+        self.data = None;
+        if let Some(mut control) = self.control.take() {
+            if !self.cut_short {
+                let reply = control.reply().map_err(timed_out)?;
+                if !reply.is(2) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        format!("the transfer failed: {} {}", reply.code, reply.text),
+                    ));
+                }
+            }
+            let _ = control.send("QUIT");
+        }
+        Ok(0)
+    }
+}
+
+/// Fetches the file of an `ftp://` URL for an async download, running the session on tokio's
+/// blocking threads; see [`open`].
+#[cfg(feature = "async")]
+pub(crate) async fn open_async(
+    method: &Method,
+    url: &str,
+    headers: &HeaderMap,
+    auth: Option<&Auth>,
+    timeouts: &TimeoutConfig,
+) -> Result<reqwest::Response, DownloadError> {
+    use futures_util::stream;
+    use reqwest::ResponseBuilderExt;
+
+    let (method, url, headers, auth, timeouts) = (
+        method.clone(),
+        url.to_string(),
+        headers.clone(),
+        auth.cloned(),
+        *timeouts,
+    );
+    let session = tokio::task::spawn_blocking(move || {
+        open(&method, &url, &headers, auth.as_ref(), &timeouts)
+    });
+    let answer = session
+        .await
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic.into_panic()))?;
+
+    // Read the body on a blocking thread too, handing its chunks over as they arrive.
+    let (sender, receiver) = tokio::sync::mpsc::channel::<io::Result<Vec<u8>>>(4);
+    let mut body = answer.body;
+    tokio::task::spawn_blocking(move || loop {
+        let mut chunk = vec![0; 64 * 1024];
+        let read = body.read(&mut chunk).map(|read| {
+            chunk.truncate(read);
+            chunk
+        });
+        let done = read.as_ref().map_or(true, Vec::is_empty);
+        if sender.blocking_send(read).is_err() || done {
+            break;
+        }
+    });
+    let chunks = stream::unfold(receiver, |mut receiver| async move {
+        match receiver.recv().await {
+            Some(Ok(chunk)) if chunk.is_empty() => None,
+            Some(chunk) => Some((chunk, receiver)),
+            None => None,
+        }
+    });
+
+    let mut response = http::Response::builder()
+        .status(answer.status)
+        .url(answer.url)
+        .body(reqwest::Body::wrap_stream(chunks))
+        .expect("the status and URL are valid");
+    response.headers_mut().extend(answer.headers);
+    Ok(response.into())
+}
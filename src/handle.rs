@@ -0,0 +1,176 @@
+use crate::control::Control;
+use crate::error::DownloadError;
+use crate::metrics::{BatchMetrics, Metrics};
+use crate::priority::Priority;
+use crate::queue::WorkQueue;
+use crate::result::DownloadResult;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// Cancels a running batch from anywhere, such as a signal handler or another thread.
+///
+/// Obtained from [`BatchHandle::canceller`] or [`crate::Downloader::canceller`]. Cancelling
+/// through it is the same as calling [`BatchHandle::cancel`], including the clean-up of
+/// partial files.
+#[derive(Debug, Clone)]
+pub struct Canceller {
+    /// Cancellation state shared with the workers.
+    control: Arc<Control>,
+}
+
+impl Canceller {
+    /// Cancels the batch; see [`BatchHandle::cancel`].
+    pub fn cancel(&self) {
+        self.control.cancel();
+    }
+
+    /// Returns `true` once the batch has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.control.is_cancelled()
+    }
+}
+
+/// A handle to a batch running in the background, returned by [`crate::start_batch`] and
+/// [`crate::start_batch_stream`].
+///
+/// Dropping the handle does not stop the batch; call [`BatchHandle::cancel`] for that.
+pub struct BatchHandle {
+    /// Cancellation and pause state shared with the workers.
+    control: Arc<Control>,
+    /// The worker threads, each returning the results of the requests it handled.
+    workers: Vec<JoinHandle<Vec<(usize, DownloadResult)>>>,
+    /// The error that stopped a streamed batch from taking more requests, if any.
+    failure: Arc<Mutex<Option<DownloadError>>>,
+    /// The requests that haven't started yet.
+    queue: Arc<Mutex<WorkQueue>>,
+    /// Counters updated by the workers.
+    metrics: Arc<Metrics>,
+}
+
+impl fmt::Debug for BatchHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BatchHandle")
+            .field("control", &self.control)
+            .field("workers", &self.workers.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl BatchHandle {
+    /// Wraps the shared control and worker threads of a freshly started batch.
+    pub(crate) fn new(
+        control: Arc<Control>,
+        workers: Vec<JoinHandle<Vec<(usize, DownloadResult)>>>,
+        failure: Arc<Mutex<Option<DownloadError>>>,
+        queue: Arc<Mutex<WorkQueue>>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            control,
+            workers,
+            failure,
+            queue,
+            metrics,
+        }
+    }
+
+    /// Cancels the batch.
+    ///
+    /// In-flight downloads stop at the next chunk boundary and queued downloads are not
+    /// started. Both are reported as [`crate::DownloadOutcome::Cancelled`]. Partial files are
+    /// removed unless [`crate::DownloadConfig::keep_partial_on_cancel`] is set.
+    pub fn cancel(&self) {
+        self.control.cancel();
+    }
+
+    /// Returns a [`Canceller`] that can cancel the batch after the handle has been moved, for
+    /// example into [`BatchHandle::join`] on another thread.
+    pub fn canceller(&self) -> Canceller {
+        Canceller {
+            control: Arc::clone(&self.control),
+        }
+    }
+
+    /// Pauses the batch.
+    ///
+    /// Every worker parks at its next chunk boundary, so no new bytes are requested and no
+    /// progress events are emitted until [`BatchHandle::resume`] is called. Downloads continue
+    /// from the same byte offsets afterwards. A paused batch can still be cancelled. Servers
+    /// may drop connections that stay idle for too long; those downloads are then retried.
+    pub fn pause(&self) {
+        self.control.pause();
+    }
+
+    /// Resumes a batch paused with [`BatchHandle::pause`].
+    pub fn resume(&self) {
+        self.control.resume();
+    }
+
+    /// Returns `true` while the batch is paused.
+    pub fn is_paused(&self) -> bool {
+        self.control.is_paused()
+    }
+
+    /// Changes the priority of a request that hasn't started downloading yet.
+    ///
+    /// Blocks briefly while a worker is picking its next request.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The position of the request in the batch.
+    /// * `priority` - The new priority.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the request was still waiting, and `false` if it has already started or
+    /// there is no request at `index`.
+    pub fn set_priority(&self, index: usize, priority: Priority) -> bool {
+        self.queue.lock().unwrap().set_priority(index, priority)
+    }
+
+    /// Returns the current counters of the batch, such as the bytes received and the
+    /// requests that failed so far.
+    ///
+    /// Reading them takes no lock, so the batch can be polled as often as needed.
+    pub fn metrics(&self) -> BatchMetrics {
+        self.metrics.snapshot()
+    }
+
+    /// Returns `true` once [`BatchHandle::cancel`] has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.control.is_cancelled()
+    }
+
+    /// Waits for every worker to finish and returns one result per request, in the order the
+    /// requests were submitted.
+    pub fn join(self) -> Vec<DownloadResult> {
+        let mut results: Vec<(usize, DownloadResult)> = self
+            .workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("download worker panicked"))
+            .collect();
+
+        // Workers finish out of order, so restore the submission order.
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    /// Waits for every worker to finish like [`BatchHandle::join`], but fails with the error
+    /// that stopped a streamed batch from taking more requests.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` with one result per request that was taken from the stream, in stream order.
+    /// * `Err` with the error the stream yielded, or a [`crate::DuplicateDestination`] if a
+    ///   streamed request reused an earlier destination.
+    pub fn try_join(self) -> Result<Vec<DownloadResult>, DownloadError> {
+        let failure = Arc::clone(&self.failure);
+        let results = self.join();
+        let failure = failure.lock().unwrap().take();
+        match failure {
+            Some(error) => Err(error),
+            None => Ok(results),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::config::DownloadConfig;
+use crate::request::DownloadRequest;
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use std::error::Error;
+use std::fmt;
+
+/// Returned when a configured request header has an invalid name or value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidHeader {
+    /// The header name as it was configured.
+    pub name: String,
+    /// Why the header was rejected.
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid request header {:?}: {}", self.name, self.reason)
+    }
+}
+
+impl Error for InvalidHeader {}
+
+/// Parses the configured `(name, value)` pairs into a [`HeaderMap`].
+///
+/// Repeated names are all sent, in the order they were given.
+pub(crate) fn header_map(headers: &[(String, String)]) -> Result<HeaderMap, InvalidHeader> {
+    let mut map = HeaderMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        let invalid = |reason: &dyn fmt::Display| InvalidHeader {
+            name: name.clone(),
+            reason: reason.to_string(),
+        };
+        let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| invalid(&e))?;
+        let header_value = HeaderValue::from_str(value).map_err(|e| invalid(&e))?;
+        map.append(header_name, header_value);
+    }
+    Ok(map)
+}
+
+/// Combines the headers of the batch with those of one request.
+///
+/// A name set by the request replaces every value the batch set for it; names are compared
+/// case-insensitively.
+pub(crate) fn merge_headers(
+    base: &[(String, String)],
+    overrides: &[(String, String)],
+) -> Vec<(String, String)> {
+    let overridden = |name: &str| {
+        overrides
+            .iter()
+            .any(|(other, _)| other.eq_ignore_ascii_case(name))
+    };
+    base.iter()
+        .filter(|(name, _)| !overridden(name))
+        .chain(overrides)
+        .cloned()
+        .collect()
+}
+
+/// Whether the header `name` carries credentials, such as `Authorization`, `Cookie`, or an
+/// `X-Api-Key`, and so must not be written to disk.
+pub(crate) fn is_sensitive(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    matches!(
+        name.as_str(),
+        "authorization" | "proxy-authorization" | "cookie"
+    ) || ["auth", "key", "token", "secret", "password", "session"]
+        .iter()
+        .any(|part| name.contains(part))
+}
+
+/// Checks the headers of every request in a batch before anything is downloaded.
+pub(crate) fn check_headers(
+    requests: &[DownloadRequest],
+    config: &DownloadConfig,
+) -> Result<(), InvalidHeader> {
+    header_map(&config.headers)?;
+    for request in requests
+        .iter()
+        .filter(|request| !request.headers.is_empty())
+    {
+        header_map(&request.effective_config(config).headers)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use crate::spans::warn;
+use crate::transfer::Transferred;
+use http::header::HeaderMap;
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// What a hook may fail with.
+pub type HookResult = Result<(), Box<dyn Error + Send + Sync>>;
+
+/// Runs on every file that finished downloading, for [`CompleteHook`].
+type HookFn = Arc<dyn Fn(&CompletedFile) -> HookResult + Send + Sync>;
+
+/// What [`CompleteHook`] learns about a file that finished downloading.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletedFile {
+    /// Where the file was saved. It is already in place when the hook runs.
+    pub path: PathBuf,
+    /// The URL the request asked for.
+    pub url: String,
+    /// The URL the file was served from, after following redirects and mirrors.
+    pub final_url: String,
+    /// Number of bytes written.
+    pub bytes: u64,
+    /// SHA-256 of the file as hex, when [`crate::DownloadConfig::compute_sha256`] is set.
+    pub sha256: Option<String>,
+    /// The `(name, value)` pairs of the response headers [`CompleteHook::with_headers`]
+    /// selected, in the order they were selected. Headers the response lacked are left out,
+    /// and a header sent more than once appears once per value.
+    pub headers: Vec<(String, String)>,
+}
+
+/// Runs a function on every file of a batch as soon as it is downloaded, for
+/// [`crate::BatchConfig::on_complete`].
+///
+/// The function is called on the worker that ran the download, right after the file was moved
+/// into place, so it may block, for example to submit the file for a scan. An error it returns
+/// and a panic it raises are caught and kept as the [`crate::DownloadResult::hook_error`] of
+/// the file, which still counts as downloaded. Files kept on disk, such as skipped files and
+/// [`crate::BatchConfig::dedup`] copies, aren't passed to it. Clones share the same function.
+#[derive(Clone)]
+pub struct CompleteHook {
+    /// Runs on every completed file.
+    hook: HookFn,
+    /// The response headers passed to the hook, lowercased.
+    headers: Vec<String>,
+}
+
+impl fmt::Debug for CompleteHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompleteHook")
+            .field("headers", &self.headers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CompleteHook {
+    /// Creates a hook calling `hook` with every file that finished downloading.
+    pub fn new(hook: impl Fn(&CompletedFile) -> HookResult + Send + Sync + 'static) -> Self {
+        Self {
+            hook: Arc::new(hook),
+            headers: Vec::new(),
+        }
+    }
+
+    /// Passes the response headers called `names` to the hook, in
+    /// [`CompletedFile::headers`]. Names are matched ignoring case.
+    pub fn with_headers<S: Into<String>>(mut self, names: impl IntoIterator<Item = S>) -> Self {
+        self.headers = names
+            .into_iter()
+            .map(|name| name.into().to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    /// Describes the file `transferred` from the request for `url` to the hook.
+    pub(crate) fn completed_file(&self, url: &str, transferred: &Transferred) -> CompletedFile {
+        CompletedFile {
+            path: transferred.path.clone(),
+            url: url.to_string(),
+            final_url: transferred.final_url.clone(),
+            bytes: transferred.bytes,
+            sha256: transferred.sha256.clone(),
+            headers: select_headers(&transferred.headers, &self.headers),
+        }
+    }
+
+    /// Runs the hook on `file`, catching a panic as well as an error.
+    ///
+    /// # Returns
+    ///
+    /// * `None` if the hook succeeded.
+    /// * `Some` with a [`HookError`] describing its error or panic.
+    pub(crate) fn run(&self, file: &CompletedFile) -> Option<HookError> {
+        let error = match catch_unwind(AssertUnwindSafe(|| (self.hook)(file))) {
+            Ok(Ok(())) => return None,
+            Ok(Err(error)) => HookError {
+                path: file.path.clone(),
+                reason: error.to_string(),
+                panicked: false,
+            },
+            Err(panic) => HookError {
+                path: file.path.clone(),
+                reason: panic_message(panic.as_ref())
+                    .unwrap_or_else(|| "the hook panicked".to_string()),
+                panicked: true,
+            },
+        };
+        warn!("{}", error);
+        Some(error)
+    }
+}
+
+/// Returned when a [`CompleteHook`] failed or panicked on a downloaded file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookError {
+    /// The file the hook ran on.
+    pub path: PathBuf,
+    /// The message of the error or panic.
+    pub reason: String,
+    /// Whether the hook panicked rather than returning an error.
+    pub panicked: bool,
+}
+
+impl fmt::Display for HookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failed = if self.panicked { "panicked" } else { "failed" };
+        write!(
+            f,
+            "the completion hook {} on {}: {}",
+            failed,
+            self.path.display(),
+            self.reason
+        )
+    }
+}
+
+impl Error for HookError {}
+
+/// Picks the values of the headers called `names` out of `headers`.
+fn select_headers(headers: &HeaderMap, names: &[String]) -> Vec<(String, String)> {
+    names
+        .iter()
+        .flat_map(|name| {
+            headers.get_all(name.as_str()).iter().map(move |value| {
+                (
+                    name.clone(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Returns the message a panic was raised with, if it was a string.
+pub(crate) fn panic_message(panic: &(dyn Any + Send)) -> Option<String> {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => Some(message.to_string()),
+        None => panic.downcast_ref::<String>().cloned(),
+    }
+}
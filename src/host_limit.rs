@@ -0,0 +1,141 @@
+use crate::control::Control;
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+use url::Url;
+
+/// How long a blocked worker waits for a slot before checking for cancellation again.
+const CANCEL_POLL: Duration = Duration::from_millis(100);
+
+/// Limits how many downloads of a batch are in flight against each host at the same time.
+#[derive(Debug)]
+pub(crate) struct HostLimiter {
+    /// Maximum number of downloads per host.
+    max_per_host: usize,
+    /// Number of downloads currently holding a slot, by host.
+    active: Mutex<HashMap<String, usize>>,
+    /// Signalled whenever a slot is released.
+    freed: Condvar,
+    /// Wakes async downloads waiting for a slot.
+    #[cfg(feature = "async")]
+    freed_async: tokio::sync::Notify,
+}
+
+impl HostLimiter {
+    /// Creates a limiter for the given limit, or `None` when it is unlimited.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_per_host` - The maximum number of downloads per host; `None` or `Some(0)` means
+    ///   unlimited.
+    pub(crate) fn new(max_per_host: Option<usize>) -> Option<Self> {
+        let max_per_host = max_per_host.filter(|max| *max > 0)?;
+        Some(Self {
+            max_per_host,
+            active: Mutex::new(HashMap::new()),
+            freed: Condvar::new(),
+            #[cfg(feature = "async")]
+            freed_async: tokio::sync::Notify::new(),
+        })
+    }
+
+    /// Takes a slot for `host` if it has one free.
+    pub(crate) fn try_acquire(&self, host: &str) -> Option<HostSlot<'_>> {
+        let mut active = self.active.lock().unwrap();
+        let count = active.entry(host.to_string()).or_insert(0);
+        if *count >= self.max_per_host {
+            return None;
+        }
+        *count += 1;
+        Some(HostSlot {
+            limiter: self,
+            host: host.to_string(),
+        })
+    }
+
+    /// Blocks until a slot for `host` is free, or returns `None` once the batch is cancelled.
+    pub(crate) fn acquire(&self, host: &str, control: &Control) -> Option<HostSlot<'_>> {
+        loop {
+            if let Some(slot) = self.try_acquire(host) {
+                return Some(slot);
+            }
+            if control.is_cancelled() {
+                return None;
+            }
+            self.wait(CANCEL_POLL);
+        }
+    }
+
+    /// Waits until a slot for `host` is free without blocking the runtime.
+    #[cfg(feature = "async")]
+    pub(crate) async fn acquire_async(&self, host: &str) -> HostSlot<'_> {
+        loop {
+            // Register for the wake-up before checking, so a release in between isn't missed.
+            let freed = self.freed_async.notified();
+            tokio::pin!(freed);
+            freed.as_mut().enable();
+            if let Some(slot) = self.try_acquire(host) {
+                return slot;
+            }
+            freed.await;
+        }
+    }
+
+    /// Blocks until any slot is released or `timeout` has passed.
+    pub(crate) fn wait(&self, timeout: Duration) {
+        let active = self.active.lock().unwrap();
+        let _ = self.freed.wait_timeout(active, timeout).unwrap();
+    }
+
+    /// Gives back a slot for `host`.
+    fn release(&self, host: &str) {
+        let mut active = self.active.lock().unwrap();
+        if let Some(count) = active.get_mut(host) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(host);
+            }
+        }
+        self.freed.notify_all();
+        #[cfg(feature = "async")]
+        self.freed_async.notify_waiters();
+    }
+}
+
+/// A download's claim on one of its host's slots, released when dropped.
+#[derive(Debug)]
+pub(crate) struct HostSlot<'a> {
+    limiter: &'a HostLimiter,
+    host: String,
+}
+
+impl HostSlot<'_> {
+    /// Moves the slot to the host of `url` after a redirect, blocking until that host has a
+    /// free slot.
+    ///
+    /// The old slot is released first, so downloads redirected across each other's hosts can
+    /// never wait on one another. Keeps nothing if the batch is cancelled while waiting.
+    pub(crate) fn follow(slot: &mut Option<Self>, url: &str, control: &Control) {
+        let Some(current) = slot.as_ref() else {
+            return;
+        };
+        let Some(host) = host_key(url).filter(|host| *host != current.host) else {
+            return;
+        };
+        let limiter = current.limiter;
+        *slot = None;
+        *slot = limiter.acquire(&host, control);
+    }
+}
+
+impl Drop for HostSlot<'_> {
+    fn drop(&mut self) {
+        self.limiter.release(&self.host);
+    }
+}
+
+/// Returns the host `url` is downloaded from, in lowercase, or `None` if it has none.
+pub(crate) fn host_key(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    Some(url.host_str()?.to_ascii_lowercase())
+}
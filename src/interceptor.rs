@@ -0,0 +1,154 @@
+use crate::backend::HttpRequest;
+use crate::config::DownloadConfig;
+use http::header::RANGE;
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+
+/// What a [`RequestInterceptor`] learns about the request it is handed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestContext {
+    /// The URL the request is about to be sent to, which is a mirror or a redirect target
+    /// rather than the requested URL when the download got there.
+    pub url: String,
+    /// Which attempt at the URL the request belongs to, starting at 1. Each mirror counts its
+    /// own attempts, and requests made outside a download's retries, such as size probes,
+    /// count as the first.
+    pub attempt: u32,
+    /// The byte the request asks the body to start at: where a resumed download or a segment
+    /// continues, and `0` for a request of the whole file.
+    pub offset: u64,
+}
+
+/// Rewrites every HTTP request of a download right before it is sent, for instance to sign it
+/// with a header derived from its path and the current time.
+///
+/// Interceptors run for every request of a download, including range probes, redirect hops,
+/// retries and every mirror, in the order they were added to [`Interceptors`]. They see the
+/// headers the crate is about to send, `Authorization` included, and may change the method,
+/// URL and headers. `file://`, `data:` and `ftp://` URLs are read without an HTTP request and
+/// never reach them. Use [`interceptor_fn`] for a one-off interceptor.
+pub trait RequestInterceptor: Send + Sync {
+    /// Returns `request` as it should be sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request as the crate, and any earlier interceptor, built it.
+    /// * `context` - Where the request stands within its download.
+    fn intercept(&self, request: HttpRequest, context: &RequestContext) -> HttpRequest;
+}
+
+/// Adapts a closure into a [`RequestInterceptor`], so a one-off interceptor needs no type of
+/// its own.
+pub fn interceptor_fn(
+    intercept: impl Fn(HttpRequest, &RequestContext) -> HttpRequest + Send + Sync + 'static,
+) -> impl RequestInterceptor {
+    FnInterceptor(intercept)
+}
+
+/// A [`RequestInterceptor`] made by [`interceptor_fn`].
+struct FnInterceptor<F>(F);
+
+impl<F: Fn(HttpRequest, &RequestContext) -> HttpRequest + Send + Sync> RequestInterceptor
+    for FnInterceptor<F>
+{
+    fn intercept(&self, request: HttpRequest, context: &RequestContext) -> HttpRequest {
+        (self.0)(request, context)
+    }
+}
+
+/// The ordered [`RequestInterceptor`]s of [`crate::DownloadConfig::interceptors`].
+///
+/// Clones share the same interceptors.
+#[derive(Clone)]
+pub struct Interceptors {
+    /// The interceptors, in the order they run.
+    interceptors: Vec<Arc<dyn RequestInterceptor>>,
+    /// The attempt the requests being sent belong to, stamped by the retry loop.
+    attempt: u32,
+}
+
+impl fmt::Debug for Interceptors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interceptors")
+            .field("len", &self.interceptors.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Interceptors {
+    fn default() -> Self {
+        Self {
+            interceptors: Vec::new(),
+            attempt: 1,
+        }
+    }
+}
+
+impl Interceptors {
+    /// Creates an empty list, which sends every request as the crate built it.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `interceptor` after those already in the list.
+    pub fn push(&mut self, interceptor: impl RequestInterceptor + 'static) {
+        self.interceptors.push(Arc::new(interceptor));
+    }
+
+    /// Returns the list with `interceptor` added after those already in it.
+    pub fn with(mut self, interceptor: impl RequestInterceptor + 'static) -> Self {
+        self.push(interceptor);
+        self
+    }
+
+    /// Returns `true` if the list holds no interceptor.
+    pub fn is_empty(&self) -> bool {
+        self.interceptors.is_empty()
+    }
+
+    /// Returns how many interceptors the list holds.
+    pub fn len(&self) -> usize {
+        self.interceptors.len()
+    }
+
+    /// Runs `request` through every interceptor, in order.
+    pub(crate) fn apply(&self, request: HttpRequest) -> HttpRequest {
+        if self.interceptors.is_empty() {
+            return request;
+        }
+        let context = RequestContext {
+            url: request.url.clone(),
+            attempt: self.attempt,
+            offset: range_start(&request),
+        };
+        self.interceptors
+            .iter()
+            .fold(request, |request, interceptor| {
+                interceptor.intercept(request, &context)
+            })
+    }
+}
+
+/// Returns the settings for attempt number `attempt` of a download with `config`, which tell
+/// its interceptors which attempt they are running for.
+pub(crate) fn for_attempt(config: &DownloadConfig, attempt: u32) -> Cow<'_, DownloadConfig> {
+    if config.interceptors.is_empty() || config.interceptors.attempt == attempt {
+        return Cow::Borrowed(config);
+    }
+    let mut config = config.clone();
+    config.interceptors.attempt = attempt;
+    Cow::Owned(config)
+}
+
+/// Returns the first byte the `Range` header of `request` asks for, or `0` without one.
+fn range_start(request: &HttpRequest) -> u64 {
+    request
+        .headers
+        .get(RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("bytes="))
+        .and_then(|range| range.split('-').next())
+        .and_then(|start| start.trim().parse().ok())
+        .unwrap_or(0)
+}
@@ -0,0 +1,113 @@
+use crate::result::{DownloadOutcome, DownloadResult};
+use crate::spans::warn;
+use serde::Serialize;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Writes one JSON object per line for every download of a batch as it ends, for
+/// [`crate::BatchConfig::json_log`].
+///
+/// Each line holds the `url`, `destination`, `status`, `bytes`, `duration_ms` and `attempts`
+/// of a download, its `error` if it failed, and its `sha256` when
+/// [`crate::DownloadConfig::compute_sha256`] computed one. `status` is `completed`, `failed`,
+/// `skipped`, `not_modified`, `cancelled`, `aborted` or `dry_run`.
+///
+/// Every line is written with a single write and flushed right away, so lines of downloads
+/// ending at the same time never interleave and a consumer tailing the log sees each download
+/// as soon as it ends. Clones share the same output.
+#[derive(Clone)]
+pub struct JsonLog {
+    /// Where the lines go.
+    output: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl fmt::Debug for JsonLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsonLog").finish_non_exhaustive()
+    }
+}
+
+/// One line of a [`JsonLog`].
+#[derive(Serialize)]
+struct Line<'a> {
+    url: &'a str,
+    destination: String,
+    status: &'static str,
+    bytes: u64,
+    duration_ms: u128,
+    attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<&'a str>,
+}
+
+impl JsonLog {
+    /// Creates a log writing its lines to `output`.
+    pub fn new(output: impl Write + Send + 'static) -> Self {
+        Self {
+            output: Arc::new(Mutex::new(Box::new(output))),
+        }
+    }
+
+    /// Creates a log writing its lines to standard output.
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+
+    /// Creates a log appending its lines to the file at `path`, which is created if it doesn't
+    /// exist.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to append to.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` with the log.
+    /// * `Err` if the file can't be opened for writing.
+    pub fn append(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file: File = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(file))
+    }
+
+    /// Writes the line of a download that ended. A log that can't be written to only costs
+    /// its lines, never the download.
+    pub(crate) fn record(&self, result: &DownloadResult) {
+        let (status, sha256) = match &result.outcome {
+            DownloadOutcome::Completed { sha256, .. } => ("completed", sha256.as_deref()),
+            DownloadOutcome::Failed { .. } => ("failed", None),
+            DownloadOutcome::Skipped { .. } => ("skipped", None),
+            DownloadOutcome::NotModified { .. } => ("not_modified", None),
+            DownloadOutcome::Cancelled => ("cancelled", None),
+            DownloadOutcome::Aborted => ("aborted", None),
+            DownloadOutcome::DryRun { .. } => ("dry_run", None),
+        };
+        let line = Line {
+            url: &result.url,
+            destination: result.destination.display().to_string(),
+            status,
+            bytes: result.bytes(),
+            duration_ms: result.duration.as_millis(),
+            attempts: result.timings.attempts,
+            error: result.error().map(ToString::to_string),
+            sha256,
+        };
+        let Ok(mut line) = serde_json::to_vec(&line) else {
+            return;
+        };
+        line.push(b'\n');
+
+        let mut output = self.output.lock().unwrap();
+        if output
+            .write_all(&line)
+            .and_then(|_| output.flush())
+            .is_err()
+        {
+            warn!("failed to write the JSON log line of {}", result.url);
+        }
+    }
+}
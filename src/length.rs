@@ -0,0 +1,158 @@
+use crate::config::DownloadConfig;
+use crate::error::DownloadError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Returned when a downloaded file does not have the size the server advertised.
+///
+/// For a resumed download the expected size is that of the whole file, including the part that
+/// was already on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeMismatch {
+    /// The file that was checked.
+    pub path: PathBuf,
+    /// The size the server reported through `Content-Length` or `Content-Range`.
+    pub expected: u64,
+    /// The size of the file on disk.
+    pub actual: u64,
+}
+
+impl SizeMismatch {
+    /// Returns `true` if the file came out short, which usually means the connection dropped.
+    pub fn is_truncated(&self) -> bool {
+        self.actual < self.expected
+    }
+}
+
+impl fmt::Display for SizeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is {} bytes but the server reported {} bytes",
+            self.path.display(),
+            self.actual,
+            self.expected
+        )
+    }
+}
+
+impl Error for SizeMismatch {}
+
+/// Checks that the `actual` bytes of the body saved at `path` add up to the `expected` size,
+/// if the server reported one.
+///
+/// Responses the client decompressed carry no length, so they are never checked. Once every
+/// byte is known to have reached the file, the bytes received stand for its size, which they
+/// don't for a body the download decompressed itself.
+pub(crate) fn verify_length(
+    path: &Path,
+    actual: u64,
+    expected: Option<u64>,
+) -> Result<(), DownloadError> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+
+    if actual != expected {
+        return Err(SizeMismatch {
+            path: path.to_path_buf(),
+            expected,
+            actual,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Checks that the file at `path` holds exactly the `written` bytes the download wrote to it.
+///
+/// A short write that went unnoticed, or another process truncating the file, would otherwise
+/// pass for a complete download whenever the server reported no length.
+pub(crate) fn verify_written(path: &Path, written: u64) -> Result<(), DownloadError> {
+    let actual = std::fs::metadata(path)
+        .map_err(DownloadError::io(path))?
+        .len();
+    if actual != written {
+        return Err(DownloadError::io(path)(io::Error::other(format!(
+            "{} bytes were written but the file holds {} bytes",
+            written, actual
+        ))));
+    }
+
+    Ok(())
+}
+
+/// Returned when a download would produce a file larger than
+/// [`crate::DownloadConfig::max_size`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeLimitExceeded {
+    /// The URL that was downloaded.
+    pub url: String,
+    /// The largest file the download was allowed to produce.
+    pub limit: u64,
+    /// The size the server advertised, if the download was refused because of it before
+    /// anything was written.
+    pub advertised: Option<u64>,
+    /// Bytes of the file received when the download was stopped, counting a resumed prefix.
+    pub received: u64,
+}
+
+impl fmt::Display for SizeLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.advertised {
+            Some(size) => write!(
+                f,
+                "{} is {} bytes, more than the limit of {} bytes",
+                self.url, size, self.limit
+            ),
+            None => write!(
+                f,
+                "{} sent more than the limit of {} bytes",
+                self.url, self.limit
+            ),
+        }
+    }
+}
+
+impl Error for SizeLimitExceeded {}
+
+/// Refuses a file whose advertised size is over [`crate::DownloadConfig::max_size`], before
+/// anything is written.
+pub(crate) fn check_advertised(
+    url: &str,
+    expected: Option<u64>,
+    received: u64,
+    config: &DownloadConfig,
+) -> Result<(), SizeLimitExceeded> {
+    match (config.max_size, expected) {
+        (Some(limit), Some(size)) if size > limit => Err(SizeLimitExceeded {
+            url: url.to_string(),
+            limit,
+            advertised: Some(size),
+            received,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Stops a download once the `received` bytes of its file are over `max_size`, its
+/// [`crate::DownloadConfig::max_size`], for servers that didn't report the size or reported
+/// too little.
+pub(crate) fn check_received(
+    url: &str,
+    received: u64,
+    max_size: Option<u64>,
+) -> Result<(), SizeLimitExceeded> {
+    match max_size {
+        Some(limit) if received > limit => Err(SizeLimitExceeded {
+            url: url.to_string(),
+            limit,
+            advertised: None,
+            received,
+        }),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,246 @@
+//! Concurrent file downloads with progress events.
+//!
+//! This crate exposes a small blocking API for downloading a single file with
+//! [`download_file`] or many files in parallel with [`download_batch`]. Each download
+//! reports its lifecycle through a callback receiving a [`DownloadEvent`]; closures that
+//! only care about [`DownloadCallbackProgress`] can be adapted with [`progress_only`].
+//! Callbacks of a single download may also return a [`std::ops::ControlFlow`] to cancel it,
+//! as described by [`EventFlow`].
+//!
+//! Besides `http://` and `https://`, `file://` and `data:` URLs are accepted everywhere a URL
+//! is. A local file is copied from the disk and a data URL is decoded without involving the
+//! HTTP client, with the same events, checks and overwrite policy as any other download.
+//!
+//! Enabling the `async` feature adds tokio-based equivalents, `download_file_async` and
+//! `download_batch_async`, built on the non-blocking `reqwest` client. The `progress-bars`
+//! feature adds `ProgressBars`, which draws a batch as indicatif progress bars, and the
+//! `rayon` feature adds `download_batch_rayon`, which runs a batch on a rayon thread pool.
+//! The `ftp` feature adds support for `ftp://` URLs, downloaded over passive FTP with the
+//! same events and checks as HTTP. `sftp://` URLs aren't supported by any feature and are
+//! refused as invalid. The `extract` feature lets a batch request unpack zip, tar,
+//! `.tar.gz` and `.tar.xz` archives once they are downloaded, with `DownloadRequest::extract`,
+//! and the `gzip` feature lets a download decompress a `.gz` file on its way to the
+//! destination, with `DownloadRequest::decompress`. The `ureq` feature adds `UreqBackend`,
+//! which sends the requests of blocking downloads with ureq instead of reqwest, and the
+//! `serde` feature implements serde's `Serialize` for [`BatchReport`].
+//!
+//! reqwest itself is the default `reqwest` feature. Building with `default-features = false`
+//! and only `ureq` leaves it out: every blocking download and batch then builds a
+//! `UreqBackend`. It applies the DNS overrides and a single proxy for both schemes, but not
+//! separate proxies, a local address, HTTP/2 or any TLS setting, and a batch given one of
+//! those fails with an [`InvalidConfig`] rather than sending its requests without it. Hosts
+//! with [`KeyPins`] are refused, since ureq doesn't report the certificates it was shown.
+//!
+//! TLS connections are made with the platform's library through the default `native-tls`
+//! feature. Building with `default-features = false` and the `rustls` feature uses rustls
+//! with the bundled Mozilla roots instead, and `rustls-native-roots` uses rustls with the
+//! platform's certificate store, so the crate builds without OpenSSL. Custom roots, invalid
+//! certificate acceptance and [`KeyPins`] work with either library of the reqwest client; when
+//! both are enabled, rustls is used. With rustls and no custom roots, pins are checked during
+//! the handshake, while otherwise they are checked on the responses, as [`KeyPins`] describes.
+//!
+//! ```no_run
+//! parallel_downloads::download_file(
+//!     "https://www.rust-lang.org/static/images/rust-logo-blk.svg",
+//!     "./rust-logo.svg",
+//!     |event| println!("{:?}", event),
+//! )
+//! .unwrap();
+//! ```
+
+#[cfg(all(
+    feature = "reqwest",
+    not(any(
+        feature = "native-tls",
+        feature = "rustls",
+        feature = "rustls-native-roots"
+    ))
+))]
+compile_error!("enable one of the `native-tls`, `rustls` and `rustls-native-roots` features");
+#[cfg(not(any(feature = "reqwest", feature = "ureq")))]
+compile_error!("enable one of the `reqwest` and `ureq` features");
+
+mod adaptive;
+mod auth;
+mod backend;
+mod backlog;
+mod batch;
+#[cfg(feature = "async")]
+mod batch_async;
+mod batch_progress;
+#[cfg(feature = "rayon")]
+mod batch_rayon;
+mod checksum;
+mod client;
+mod config;
+mod content_type;
+mod context;
+mod control;
+mod cookies;
+mod data_url;
+#[cfg(feature = "gzip")]
+mod decompress;
+mod dedup;
+mod directories;
+mod disk_space;
+mod dns;
+mod download;
+#[cfg(feature = "async")]
+mod download_async;
+mod downloader;
+mod dry_run;
+mod encoding;
+mod error;
+mod event;
+#[cfg(feature = "prometheus")]
+mod exporter;
+#[cfg(feature = "extract")]
+mod extract;
+#[cfg(feature = "ftp")]
+mod ftp;
+mod handle;
+mod headers;
+mod hook;
+mod host_limit;
+mod interceptor;
+mod json_log;
+mod length;
+mod local_file;
+mod manifest;
+mod memory;
+mod metrics;
+mod naming;
+mod overwrite;
+#[cfg(any(feature = "rustls", feature = "rustls-native-roots"))]
+mod pin_verifier;
+mod pinning;
+mod pool;
+mod preflight;
+mod presigned;
+mod priority;
+mod progress;
+#[cfg(feature = "progress-bars")]
+mod progress_bars;
+mod protocol;
+mod proxy;
+mod queue;
+mod rate_limit;
+mod redirect;
+mod report;
+mod request;
+mod result;
+mod resume;
+mod retry;
+#[cfg(feature = "test-util")]
+mod scripted;
+mod segment;
+mod skip;
+mod spans;
+mod speed;
+mod state;
+mod summary;
+#[cfg(feature = "extract")]
+mod tar;
+mod temp_file;
+mod throttle;
+mod timeout;
+mod timing;
+mod tls;
+mod transfer;
+#[cfg(feature = "ureq")]
+mod ureq_backend;
+mod url_check;
+mod url_list;
+mod validators;
+mod write_buffer;
+mod writer;
+#[cfg(feature = "extract")]
+mod xz;
+#[cfg(feature = "extract")]
+mod zip;
+
+pub use adaptive::AdaptiveConcurrency;
+pub use auth::Auth;
+pub use backend::{HttpBackend, HttpRequest, HttpResponse};
+pub use batch::{
+    download_batch, download_batch_requests, download_batch_stream, download_batch_to_dir,
+    download_batch_with_config, start_batch, start_batch_stream,
+};
+#[cfg(feature = "async")]
+pub use batch_async::{
+    download_batch_async, download_batch_async_with_config, download_batch_requests_async,
+    download_batch_to_dir_async,
+};
+pub use batch_progress::{BatchProgress, BatchProgressCallback};
+#[cfg(feature = "rayon")]
+pub use batch_rayon::download_batch_rayon;
+pub use checksum::{Checksum, ChecksumMismatch};
+pub use config::{BatchConfig, DownloadConfig, InvalidConfig};
+pub use content_type::{ContentTypeFilter, UnexpectedContentType};
+pub use cookies::{Cookie, CookieJar, InvalidCookie};
+#[cfg(feature = "gzip")]
+pub use decompress::{ChecksumTarget, Decompress, DecompressError};
+pub use dedup::{DedupConfig, DuplicateLink};
+pub use disk_space::InsufficientDiskSpace;
+pub use dns::{DnsConfig, IpFamily};
+pub use download::{
+    download_file, download_file_with_client, download_file_with_config, download_file_with_mirrors,
+};
+#[cfg(feature = "async")]
+pub use download_async::{
+    download_file_async, download_file_async_with_client, download_file_async_with_config,
+    download_file_async_with_mirrors,
+};
+pub use downloader::Downloader;
+pub use dry_run::PlannedAction;
+pub use encoding::ContentEncoding;
+pub use error::DownloadError;
+pub use event::{progress_only, DownloadEvent, EventFlow, FileEventCallback};
+#[cfg(feature = "prometheus")]
+pub use exporter::{serve_metrics, PrometheusMetrics};
+#[cfg(feature = "extract")]
+pub use extract::ExtractError;
+pub use handle::{BatchHandle, Canceller};
+pub use headers::InvalidHeader;
+pub use hook::{CompleteHook, CompletedFile, HookError, HookResult};
+pub use interceptor::{interceptor_fn, Interceptors, RequestContext, RequestInterceptor};
+pub use json_log::JsonLog;
+pub use length::{SizeLimitExceeded, SizeMismatch};
+pub use manifest::{download_manifest, read_manifest, InvalidManifest};
+pub use memory::{download_to_memory, download_to_memory_with_config};
+pub use metrics::BatchMetrics;
+pub use naming::{
+    file_name_from_content_disposition, file_name_from_url, sanitize_file_name, FileNameConfig,
+};
+pub use overwrite::{DestinationExists, OverwritePolicy};
+pub use pinning::{InvalidPin, KeyPins, PinMismatch};
+pub use pool::PoolConfig;
+pub use preflight::{preflight, RemoteFile};
+pub use presigned::{UrlExpired, UrlRefresher};
+pub use priority::Priority;
+pub use progress::{DownloadCallbackProgress, ProgressThrottle};
+#[cfg(feature = "progress-bars")]
+pub use progress_bars::ProgressBars;
+pub use protocol::{HttpConfig, HttpVersion, ProtocolVersion};
+pub use proxy::ProxyConfig;
+#[cfg(feature = "reqwest")]
+pub use proxy::ProxyUnreachable;
+pub use redirect::{RedirectPolicy, RedirectRefused, TooManyRedirects};
+pub use report::{BatchReport, ReportedFailure, ReportedFile};
+pub use request::{DownloadRequest, DuplicateDestination};
+pub use result::{DownloadOutcome, DownloadResult};
+pub use retry::{ExponentialBackoff, FixedDelay, RetriesExhausted, RetryConfig, RetryPolicy};
+#[cfg(feature = "test-util")]
+pub use scripted::{Answer, BodyPart, ScriptedBackend, ScriptedResponse};
+pub use segment::{RangeIgnored, SegmentConfig, SegmentPanicked};
+pub use state::{resume_batch, InvalidState};
+pub use summary::BatchFailed;
+pub use timeout::{Timeout, TimeoutConfig, TimeoutPhase};
+pub use timing::DownloadTimings;
+pub use tls::{InvalidCertificate, TlsConfig};
+#[cfg(feature = "ureq")]
+pub use ureq_backend::UreqBackend;
+pub use url_check::InvalidUrl;
+pub use url_list::{MalformedUrl, UrlList};
+pub use write_buffer::WriteConfig;
+pub use writer::{download_to_writer, download_to_writer_with_config, StreamedBody};
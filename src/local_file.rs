@@ -0,0 +1,173 @@
+use crate::backend::HttpResponse;
+use crate::error::DownloadError;
+use crate::url_check::InvalidUrl;
+use http::header::{HeaderMap, HeaderValue, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, RANGE};
+use http::{Method, StatusCode, Version};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use url::Url;
+
+/// A local file opened to answer a request for a `file://` URL as a server would.
+///
+/// Downloads of such URLs never reach the HTTP client: the file is read straight from the
+/// disk, which keeps the chunk loop, the progress events, the checks of the finished file and
+/// the overwrite policy of a download. The file is announced with `Content-Length` and
+/// `Accept-Ranges: bytes`, so size limits, preallocation and resumption work as they do over
+/// HTTP, and a `Range` request is answered with `206 Partial Content` from the requested
+/// offset.
+#[derive(Debug)]
+pub(crate) struct LocalFile {
+    /// `200 OK`, `206 Partial Content` for a range, or `416 Range Not Satisfiable` for a range
+    /// starting past the end of the file.
+    pub(crate) status: StatusCode,
+    /// The headers a server would send with the file.
+    pub(crate) headers: HeaderMap,
+    /// The URL of the file.
+    pub(crate) url: Url,
+    /// The file, positioned at the first byte to send.
+    pub(crate) file: File,
+    /// How many bytes the body holds: none for a `HEAD` request or an unsatisfiable range.
+    pub(crate) length: u64,
+}
+
+/// Returns `true` if `url` names a local file rather than a resource to request.
+pub(crate) fn is_file_url(url: &str) -> bool {
+    url.trim()
+        .get(..5)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("file:"))
+}
+
+/// Opens the file named by the `file://` URL `url` to answer a `method` request.
+///
+/// # Arguments
+///
+/// * `method` - `GET`, or `HEAD` to only learn about the file.
+/// * `url` - The URL of the file. On Windows, drive letters such as `file:///C:/data/a.bin`
+///   and shares such as `file://server/share/a.bin` are understood.
+/// * `headers` - The request headers, of which only a single `Range` is honoured.
+///
+/// # Returns
+///
+/// * `Ok` with the opened file.
+/// * `Err` with an [`InvalidUrl`] if the URL doesn't name a path on this system.
+/// * `Err` with a [`DownloadError::Io`] if the file is missing, unreadable or a directory.
+pub(crate) fn open(
+    method: &Method,
+    url: &str,
+    headers: &HeaderMap,
+) -> Result<LocalFile, DownloadError> {
+    let invalid = |reason: &str| {
+        DownloadError::from(InvalidUrl {
+            index: None,
+            url: url.to_string(),
+            reason: reason.to_string(),
+        })
+    };
+    let parsed = Url::parse(url.trim()).map_err(|e| invalid(&e.to_string()))?;
+    let path: PathBuf = parsed
+        .to_file_path()
+        .map_err(|_| invalid("it doesn't name a local path"))?;
+
+    let mut file = File::open(&path).map_err(DownloadError::io(&path))?;
+    let metadata = file.metadata().map_err(DownloadError::io(&path))?;
+    if metadata.is_dir() {
+        return Err(DownloadError::io(&path)(io::Error::new(
+            io::ErrorKind::IsADirectory,
+            "the source is a directory",
+        )));
+    }
+    let size = metadata.len();
+
+    let mut answer = HeaderMap::new();
+    answer.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    let (status, start, length) = match requested_range(headers, size) {
+        None => (StatusCode::OK, 0, size),
+        Some(Some((start, end))) => {
+            answer.insert(
+                CONTENT_RANGE,
+                header_value(format!("bytes {}-{}/{}", start, end, size)),
+            );
+            (StatusCode::PARTIAL_CONTENT, start, end + 1 - start)
+        }
+        Some(None) => {
+            answer.insert(CONTENT_RANGE, header_value(format!("bytes */{}", size)));
+            (StatusCode::RANGE_NOT_SATISFIABLE, 0, 0)
+        }
+    };
+    answer.insert(CONTENT_LENGTH, header_value(length.to_string()));
+    file.seek(SeekFrom::Start(start))
+        .map_err(DownloadError::io(&path))?;
+
+    Ok(LocalFile {
+        status,
+        headers: answer,
+        url: parsed,
+        file,
+        length: if method == Method::HEAD { 0 } else { length },
+    })
+}
+
+impl LocalFile {
+    /// Answers a blocking download with the file.
+    pub(crate) fn into_response(self) -> HttpResponse {
+        HttpResponse {
+            status: self.status,
+            headers: self.headers,
+            url: self.url,
+            version: Version::HTTP_11,
+            remote_addr: None,
+            content_length: Some(self.length),
+            peer_certificate: None,
+            body: Box::new(self.file.take(self.length)),
+        }
+    }
+
+    /// Answers an async download with the file, read on tokio's blocking threads.
+    #[cfg(feature = "async")]
+    pub(crate) fn into_async_response(self) -> reqwest::Response {
+        use futures_util::stream;
+        use reqwest::ResponseBuilderExt;
+        use tokio::io::AsyncReadExt;
+
+        // Read the body in chunks the size of a typical network read.
+        let file = tokio::fs::File::from_std(self.file).take(self.length);
+        let chunks = stream::try_unfold(file, |mut file| async move {
+            let mut chunk = vec![0; 64 * 1024];
+            let read = file.read(&mut chunk).await?;
+            chunk.truncate(read);
+            Ok::<_, io::Error>((read > 0).then_some((chunk, file)))
+        });
+
+        let mut response = http::Response::builder()
+            .status(self.status)
+            .url(self.url)
+            .body(reqwest::Body::wrap_stream(chunks))
+            .expect("the status and URL are valid");
+        response.headers_mut().extend(self.headers);
+        response.into()
+    }
+}
+
+/// Reads the single `bytes=<start>-[<end>]` range of `headers` against a file of `size` bytes.
+///
+/// Returns `None` without a usable `Range` header, so the whole file is sent, `Some(None)` for
+/// a range starting past the end of the file, and otherwise the first and last byte to send.
+fn requested_range(headers: &HeaderMap, size: u64) -> Option<Option<(u64, u64)>> {
+    let value = headers.get(RANGE)?.to_str().ok()?;
+    let (start, end) = value.trim().strip_prefix("bytes=")?.split_once('-')?;
+    let start: u64 = start.trim().parse().ok()?;
+    let end = match end.trim() {
+        "" => u64::MAX,
+        end => end.parse().ok().filter(|end| *end >= start)?,
+    };
+    if start >= size {
+        return Some(None);
+    }
+    Some(Some((start, end.min(size - 1))))
+}
+
+/// Builds a header value out of digits and ASCII punctuation.
+fn header_value(value: String) -> HeaderValue {
+    HeaderValue::try_from(value).expect("header value is ASCII")
+}
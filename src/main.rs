@@ -1,159 +1,346 @@
-use log::info;
-use std::cmp::min;
+use clap::Parser;
+use log::{error, info, warn};
+use parallel_downloads::{
+    read_manifest, start_batch, start_batch_stream, BatchConfig, BatchReport, Canceller,
+    DownloadError, DownloadOutcome, DownloadRequest, DownloadResult, JsonLog, UrlList,
+};
+#[cfg(feature = "prometheus")]
+use parallel_downloads::{serve_metrics, PrometheusMetrics};
 use std::error::Error;
-use std::io::Write;
-use std::path::{Path, PathBuf};
-use std::str::FromStr;
-use std::sync::{Arc, Mutex};
-
-/// Struct representing the progress of a file download.
-#[derive(Debug)]
-struct DownloadCallbackProgress {
-    /// Total bytes downloaded so far.
-    bytes_downloaded: u64,
-    /// Total size of the file in bytes.
-    total_bytes: u64,
-}
-
-const DOWNLOAD_URL: &str = "https://www.rust-lang.org/static/images/rust-logo-blk.svg"; // Sample URL for testing
-const URL_BATCH_SIZE: usize = 1000; // Number of URLs in a batch
-
-fn main() -> Result<(), Box<dyn Error>> {
-    // Record the start time for benchmarking purposes.
-    let current_time = std::time::SystemTime::now();
+use std::path::PathBuf;
+use std::process::{self, ExitCode};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Instant;
 
-    // Set the logging level to "info" and initialize the logger.
-    std::env::set_var("RUST_LOG", "info");
-    env_logger::init();
+/// Exit code of a run stopped with Ctrl+C, the one shells use for `SIGINT`.
+const INTERRUPTED_EXIT_CODE: u8 = 130;
 
-    // Create a list of URLs to download, each URL being the same in this example.
-    let urls = vec![DOWNLOAD_URL; URL_BATCH_SIZE];
+/// The running batch, cancelled by the first Ctrl+C.
+static BATCH: OnceLock<Canceller> = OnceLock::new();
 
-    // Start downloading all URLs in batches, and handle any errors that may occur.
-    download_batch(urls)?;
+/// Set once Ctrl+C has been pressed.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
 
-    // Record the end time and calculate the total elapsed duration.
-    let end_time = std::time::SystemTime::now();
-    let elapsed = end_time.duration_since(current_time).unwrap();
+/// Downloads files over HTTP in parallel.
+///
+/// URLs are taken from the command line and from `--input-file`, and every file is saved in
+/// the output directory under the name the server suggests or the last segment of its URL.
+/// The exit code is 0 when every download succeeded, 1 when any of them failed, and 2 when
+/// the batch could not be started.
+///
+/// Ctrl+C lets the chunks being written finish, removes the partial files unless
+/// `--keep-partial` is given, prints what was completed, and exits with 130. Pressing it
+/// again exits immediately.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// URLs to download.
+    urls: Vec<String>,
+
+    /// Reads more URLs from a file, one per line. Blank lines and lines starting with `#` are
+    /// ignored. Use `-` to read from stdin. The file is read as downloads start, so it can be
+    /// arbitrarily long.
+    #[arg(short, long, value_name = "FILE")]
+    input_file: Option<PathBuf>,
+
+    /// Downloads the files listed in a JSON manifest instead, an array of objects with a `url`,
+    /// a `dest` relative to the output directory, and optionally a `sha256`, `headers` and
+    /// `size`.
+    #[arg(short, long, value_name = "FILE", conflicts_with_all = ["urls", "input_file"])]
+    manifest: Option<PathBuf>,
+
+    /// Stops at the first malformed line of the input file instead of skipping it.
+    #[arg(long, requires = "input_file")]
+    strict_input: bool,
+
+    /// The directory the files are saved in.
+    #[arg(short, long, value_name = "DIR", default_value = ".")]
+    output_dir: PathBuf,
+
+    /// Maximum number of downloads running at the same time.
+    #[arg(short, long, value_name = "N", default_value_t = 8)]
+    concurrency: usize,
+
+    /// How many times a download is retried after a transient error.
+    #[arg(short, long, value_name = "N", default_value_t = 2)]
+    retries: u32,
+
+    /// Caps the combined throughput of all downloads, in bytes per second. Accepts the
+    /// suffixes `K`, `M` and `G` for multiples of 1024, as in `500K` or `2M`.
+    #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+    rate_limit: Option<u64>,
+
+    /// Stops every other download as soon as one fails.
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Checks every URL and destination without downloading anything, and prints a table
+    /// of what would happen.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Stops at the first invalid URL instead of failing only its download.
+    #[arg(long)]
+    strict_urls: bool,
+
+    /// Keeps the partial `.part` files of downloads interrupted with Ctrl+C instead of
+    /// deleting them.
+    #[arg(long)]
+    keep_partial: bool,
+
+    /// Only prints errors.
+    #[arg(short, long, conflicts_with = "json")]
+    quiet: bool,
+
+    /// Prints one JSON object per download to stdout, as soon as it ends, instead of log lines.
+    #[arg(long)]
+    json: bool,
+
+    /// Appends one JSON object per download to this file, as soon as it ends.
+    #[arg(long, value_name = "FILE", conflicts_with = "json")]
+    json_log: Option<PathBuf>,
+
+    /// Serves Prometheus metrics of the downloads at `/metrics` on this address, such as
+    /// `127.0.0.1:9898`, while the batch runs.
+    #[cfg(feature = "prometheus")]
+    #[arg(long, value_name = "ADDR")]
+    metrics_addr: Option<String>,
+}
 
-    // Log the total time taken to download the files.
-    info!("Elapsed time: {:?}", elapsed);
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    // Log to stderr, quieter when the output is meant for machines or was asked to be quiet.
+    let level = if args.quiet {
+        "error"
+    } else if args.json {
+        "warn"
+    } else {
+        "info"
+    };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level)).init();
+
+    // Without a handler Ctrl+C still works, it just leaves partial files behind.
+    if let Err(e) = ctrlc::set_handler(on_interrupt) {
+        warn!("cannot handle Ctrl+C: {}", e);
+    }
 
-    Ok(())
+    match run(&args) {
+        Ok(Status::Succeeded) => ExitCode::SUCCESS,
+        Ok(Status::Failed) => ExitCode::from(1),
+        Ok(Status::Interrupted) => ExitCode::from(INTERRUPTED_EXIT_CODE),
+        Err(e) => {
+            error!("{}", e);
+            ExitCode::from(2)
+        }
+    }
 }
 
-/// Downloads a batch of files concurrently.
-///
-/// # Arguments
-///
-/// * `urls` - A vector of string slices containing the URLs to download.
-///
-/// # Returns
-///
-/// * `Ok(())` if all downloads succeed.
-/// * `Err` if any error occurs.
-fn download_batch(urls: Vec<&str>) -> Result<(), Box<dyn Error>> {
-    // Convert all URLs into `String` to ensure each is an owned value.
-    let urls: Vec<String> = urls.into_iter().map(|url| url.to_string()).collect();
-
-    // Define the maximum number of threads to use, which is the smaller of 50 or the total number of URLs.
-    let thread_count = min(50, urls.len());
-
-    // Preallocate space for thread handles to avoid dynamic resizing later.
-    let mut handles = Vec::with_capacity(thread_count);
-
-    // Shared counter for assigning unique file names, protected by a `Mutex` to ensure thread safety.
-    let index = Arc::new(Mutex::new(0));
-
-    // Split the list of URLs into smaller chunks, where each chunk will be handled in parallel.
-    let chunks = urls.chunks(thread_count);
-
-    for chunk in chunks {
-        // Convert the current chunk into a `Vec` to support threaded operations.
-        let chunk = chunk.to_vec();
-
-        for url in chunk {
-            // Clone the shared index so each thread can safely access and increment it.
-            let index = Arc::clone(&index);
-
-            // Spawn a new thread to perform the file download.
-            let handle = std::thread::spawn(move || {
-                // Acquire a lock on the shared index and increment it to generate a unique file name.
-                let mut index = index.lock().unwrap();
-                *index += 1;
-                let index = *index;
-
-                // Build the file path where the downloaded file will be saved.
-                download_file(
-                    url.clone(),
-                    PathBuf::from_str(format!("./test-{}.svg", index).as_str()).unwrap(),
-                    |_progress| {}, // Provide a no-op progress callback for simplicity.
-                )
-                    .unwrap(); // Handle any errors from the download with an unwrap (not ideal for production code).
-            });
-
-            // Store the thread handle so it can be joined later.
-            handles.push(handle);
-        }
+/// How a run that got to download its batch ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    /// Every download succeeded.
+    Succeeded,
+    /// At least one download failed.
+    Failed,
+    /// The batch was cancelled with Ctrl+C.
+    Interrupted,
+}
 
-        // Wait for all threads created in this chunk to complete before processing the next chunk.
-        for handle in handles.drain(..) {
-            handle.join().unwrap();
+/// Cancels the running batch on the first Ctrl+C and exits on the second.
+fn on_interrupt() {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        process::exit(INTERRUPTED_EXIT_CODE.into());
+    }
+    match BATCH.get() {
+        Some(batch) => {
+            warn!("interrupted; finishing the current chunks, press Ctrl+C again to exit now");
+            batch.cancel();
         }
-
-        // Clear the thread handles vector to prepare for the next batch of downloads.
-        handles.clear();
+        // Nothing has been downloaded yet, so there is nothing to clean up.
+        None => process::exit(INTERRUPTED_EXIT_CODE.into()),
     }
-
-    Ok(())
 }
 
-/// Downloads a file from the given URL and saves it to the specified path.
-///
-/// # Arguments
-///
-/// * `url` - A reference to a string or string-like value specifying the download URL.
-/// * `path` - A reference to a `Path` or `PathBuf` specifying where the file will be saved.
-/// * `callback` - A function or closure that is called to report download progress.
-///
-/// # Returns
-///
-/// * `Ok(())` if the download succeeds.
-/// * `Err` if any error occurs.
-fn download_file(
-    url: impl AsRef<str>,
-    path: impl AsRef<Path>,
-    callback: impl Fn(&DownloadCallbackProgress) + 'static + Send + Sync,
-) -> Result<(), Box<dyn Error>> {
-    // Initialize a blocking HTTP client. This client is used to fetch the file.
-    let client = reqwest::blocking::Client::new();
+/// Downloads every URL given in `args`, returning how the batch ended.
+fn run(args: &Args) -> Result<Status, Box<dyn Error>> {
+    // Record the start time to report how long the batch took.
+    let started = Instant::now();
 
-    // Perform an HTTP GET request to the given URL and store the server's response.
-    let mut response = client.get(url.as_ref()).send()?;
+    if args.urls.is_empty() && args.input_file.is_none() && args.manifest.is_none() {
+        return Err(
+            "no URLs given; pass them as arguments, with --input-file, or with --manifest".into(),
+        );
+    }
 
-    // Create or overwrite a local file at the specified path for saving the downloaded content.
-    let mut file = std::fs::File::create(path)?;
+    // Take the URLs given as arguments first, then stream the ones listed in the input file.
+    let mut urls: Box<dyn Iterator<Item = Result<String, DownloadError>> + Send> =
+        Box::new(args.urls.clone().into_iter().map(Ok));
+    let mut skipped = None;
+    if let Some(input_file) = &args.input_file {
+        let list = UrlList::open(input_file, args.strict_input)?;
+        skipped = Some(list.skipped());
+        urls = Box::new(urls.chain(list));
+    }
 
-    // Initialize a variable to track the number of bytes successfully downloaded.
-    let mut bytes_downloaded = 0;
+    // Map the flags onto the library's settings.
+    let mut config = BatchConfig {
+        concurrency: args.concurrency,
+        max_bytes_per_sec: args.rate_limit,
+        fail_fast: args.fail_fast,
+        dry_run: args.dry_run,
+        strict_urls: args.strict_urls,
+        ..BatchConfig::default()
+    };
+    config.download.retry.max_attempts = args.retries + 1;
+    // Let servers rename files named after their URL, but never a manifest's destinations.
+    config.download.name_from_content_disposition = args.manifest.is_none();
+    config.download.keep_partial_on_cancel = args.keep_partial;
+    if args.json {
+        config.json_log = Some(JsonLog::stdout());
+    } else if let Some(path) = &args.json_log {
+        config.json_log = Some(JsonLog::append(path)?);
+    }
+    #[cfg(feature = "prometheus")]
+    if let Some(address) = &args.metrics_addr {
+        let registry = prometheus::Registry::new();
+        config.prometheus = Some(PrometheusMetrics::register(&registry)?);
+        let address = serve_metrics(address.as_str(), registry)?;
+        info!("serving metrics at http://{}/metrics", address);
+    }
 
-    // Retrieve the total size of the file from the server response, defaulting to 0 if unavailable.
-    let total_bytes = response.content_length().unwrap_or(0);
+    let results = if let Some(manifest) = &args.manifest {
+        // Manifest entries name their own destinations, relative to the output directory.
+        let requests = read_manifest(manifest, Some(&args.output_dir))?;
+        let handle = start_batch(requests, config, |_event| {})?;
+        let _ = BATCH.set(handle.canceller());
+        handle.join()
+    } else {
+        // Name every file after its URL, falling back to a 1-based index.
+        let output_dir = args.output_dir.clone();
+        let requests = urls.enumerate().map(move |(index, url)| {
+            url.map(|url| DownloadRequest::in_directory(url, &output_dir, index + 1))
+        });
+        let handle = start_batch_stream(requests, config, |_event| {})?;
+        let _ = BATCH.set(handle.canceller());
+        handle.try_join()?
+    };
+
+    // Lines that were not URLs are reported but don't fail the run.
+    for malformed in skipped
+        .iter()
+        .flat_map(|skipped| skipped.lock().unwrap().clone())
+    {
+        warn!("skipped {}", malformed);
+    }
 
-    // Allocate a buffer to temporarily store chunks of the downloaded content.
-    let mut buffer = Vec::new();
+    // Report where every download went unless the JSON lines did, then a summary of the run.
+    // Failures are listed by the summary.
+    if args.dry_run && !args.json {
+        print!("{}", dry_run_table(&results));
+    } else if !args.json {
+        for result in &results {
+            if let Some(path) = result.path() {
+                info!("{} -> {}", result.url, path.display());
+            }
+        }
+    }
+    let report = BatchReport::new(&results, started.elapsed());
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        warn!("interrupted: {}", report);
+        return Ok(Status::Interrupted);
+    }
+    let succeeded = results.iter().filter(|result| result.is_success()).count();
+    if succeeded == results.len() {
+        info!("{}", report);
+    } else {
+        warn!("{}", report);
+    }
 
-    // Copy the response body into the buffer.
-    response.copy_to(&mut buffer)?;
+    Ok(if succeeded == results.len() {
+        Status::Succeeded
+    } else {
+        Status::Failed
+    })
+}
 
-    // Write the data from the buffer into the local file, and update the bytes_downloaded count.
-    bytes_downloaded += file.write(&buffer)? as u64;
+/// Lays out what a dry run found as a table with one row per download: what would happen,
+/// the size the server reported, the URL, and the destination or the error.
+fn dry_run_table(results: &[DownloadResult]) -> String {
+    let mut rows = vec![[
+        "ACTION".to_string(),
+        "SIZE".to_string(),
+        "URL".to_string(),
+        "DESTINATION".to_string(),
+    ]];
+    for result in results {
+        let destination = result.destination.display().to_string();
+        rows.push(match &result.outcome {
+            DownloadOutcome::DryRun { action, remote, .. } => [
+                action.describe(),
+                remote
+                    .as_ref()
+                    .and_then(|remote| remote.content_length)
+                    .map_or_else(|| "-".to_string(), |size| size.to_string()),
+                result.url.clone(),
+                destination,
+            ],
+            DownloadOutcome::Failed { error } => [
+                "fail".to_string(),
+                "-".to_string(),
+                result.url.clone(),
+                format!("{}: {}", destination, error),
+            ],
+            _ => [
+                "cancelled".to_string(),
+                "-".to_string(),
+                result.url.clone(),
+                destination,
+            ],
+        });
+    }
 
-    // Invoke the callback function to report the download progress.
-    callback(&DownloadCallbackProgress {
-        bytes_downloaded,
-        total_bytes,
-    });
+    // Pad every column but the last to its widest cell.
+    let mut widths = [0; 3];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for [action, size, url, destination] in rows {
+        table.push_str(&format!(
+            "{:<a$}  {:>s$}  {:<u$}  {}\n",
+            action,
+            size,
+            url,
+            destination,
+            a = widths[0],
+            s = widths[1],
+            u = widths[2]
+        ));
+    }
+    table
+}
 
-    Ok(())
-}
\ No newline at end of file
+/// Parses a byte rate such as `1048576`, `500K` or `2M`.
+fn parse_rate(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (digits, multiplier) = match value.char_indices().last() {
+        Some((index, 'k' | 'K')) => (&value[..index], 1024),
+        Some((index, 'm' | 'M')) => (&value[..index], 1024 * 1024),
+        Some((index, 'g' | 'G')) => (&value[..index], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| {
+            format!(
+                "invalid rate `{}`; expected a number like 500K or 2M",
+                value
+            )
+        })
+}
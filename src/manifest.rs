@@ -0,0 +1,153 @@
+use crate::batch::download_batch_requests;
+use crate::checksum::Checksum;
+use crate::config::BatchConfig;
+use crate::error::DownloadError;
+use crate::event::DownloadEvent;
+use crate::overwrite::OverwritePolicy;
+use crate::priority::Priority;
+use crate::request::{resolve_destinations, DownloadRequest};
+use crate::result::DownloadResult;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// One entry of a JSON manifest, as written in the file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    url: String,
+    dest: PathBuf,
+    #[serde(default)]
+    sha256: Option<String>,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default)]
+    size: Option<u64>,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    mirrors: Vec<String>,
+}
+
+/// Returned when a manifest is not valid JSON or one of its entries is malformed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidManifest {
+    /// The manifest that was being read.
+    pub path: PathBuf,
+    /// What is wrong with it, including where.
+    pub message: String,
+}
+
+impl fmt::Display for InvalidManifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid manifest {}: {}",
+            self.path.display(),
+            self.message
+        )
+    }
+}
+
+impl Error for InvalidManifest {}
+
+/// Reads the requests listed in a JSON manifest.
+///
+/// The manifest is an array of objects with a `url` and a `dest`, and optionally a `sha256`
+/// the file must match, extra `headers` as an object of names to values, the expected `size`
+/// in bytes, a `priority` of `"high"`, `"normal"` or `"low"`, and `mirrors` to fall back to
+/// as an array of URLs:
+///
+/// ```json
+/// [
+///   { "url": "https://example.com/a.iso", "dest": "images/a.iso", "sha256": "9f86d0…", "size": 1048576,
+///     "mirrors": ["https://mirror.example.org/a.iso"] },
+///   { "url": "https://example.com/b.txt", "dest": "b.txt", "headers": { "Accept": "text/plain" } }
+/// ]
+/// ```
+///
+/// # Arguments
+///
+/// * `path` - The manifest to read.
+/// * `base_dir` - The directory relative `dest` paths are resolved against. Defaults to the
+///   directory containing the manifest.
+///
+/// # Returns
+///
+/// * `Ok` with one [`DownloadRequest`] per entry, in the order of the manifest.
+/// * `Err` with an [`InvalidManifest`] if the file is not a valid manifest.
+/// * `Err` with a [`crate::DuplicateDestination`] naming the indices of two entries that
+///   resolve to the same file.
+pub fn read_manifest(
+    path: impl AsRef<Path>,
+    base_dir: Option<&Path>,
+) -> Result<Vec<DownloadRequest>, DownloadError> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path).map_err(DownloadError::io(path))?;
+    let base_dir = base_dir.unwrap_or(path.parent().unwrap_or(Path::new(".")));
+
+    let invalid = |message: String| InvalidManifest {
+        path: path.to_path_buf(),
+        message,
+    };
+    let entries: Vec<Entry> =
+        serde_json::from_str(&contents).map_err(|e| invalid(e.to_string()))?;
+
+    let mut requests = entries
+        .into_iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let mut request = DownloadRequest::new(entry.url, base_dir.join(entry.dest));
+            if let Some(sha256) = entry.sha256 {
+                if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(invalid(format!(
+                        "entry {} has a sha256 that is not 64 hex digits",
+                        index
+                    )));
+                }
+                request.checksum = Some(Checksum::Sha256(sha256));
+            }
+            request.headers = entry.headers.into_iter().collect();
+            request.expected_size = entry.size;
+            request.priority = entry.priority;
+            request.mirrors = entry.mirrors;
+            Ok(request)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Catch entries that would overwrite each other before anything is downloaded. Entries
+    // name their destinations on purpose, so they are never renamed.
+    resolve_destinations(&mut requests, OverwritePolicy::default())?;
+
+    Ok(requests)
+}
+
+/// Downloads every file listed in a JSON manifest concurrently.
+///
+/// See [`read_manifest`] for the format. Each entry's checksum, headers, and size apply to its
+/// own download, on top of the settings in `config`.
+///
+/// # Arguments
+///
+/// * `path` - The manifest to read.
+/// * `base_dir` - The directory relative `dest` paths are resolved against. Defaults to the
+///   directory containing the manifest.
+/// * `config` - The batch settings, including the per-file [`crate::DownloadConfig`].
+/// * `callback` - A function or closure that receives the [`DownloadEvent`]s of every file in the batch.
+///
+/// # Returns
+///
+/// * `Ok` with one [`DownloadResult`] per entry, in the order of the manifest.
+/// * `Err` if the manifest could not be read, or for the same reasons as
+///   [`download_batch_requests`].
+pub fn download_manifest(
+    path: impl AsRef<Path>,
+    base_dir: Option<&Path>,
+    config: BatchConfig,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+) -> Result<Vec<DownloadResult>, DownloadError> {
+    let requests = read_manifest(path, base_dir)?;
+    download_batch_requests(requests, config, callback)
+}
@@ -0,0 +1,76 @@
+use crate::backend::HttpBackend;
+use crate::client::blocking_client;
+use crate::config::{BatchConfig, DownloadConfig};
+use crate::context::Context;
+use crate::download::with_retries;
+use crate::error::DownloadError;
+use crate::event::DownloadEvent;
+use crate::interceptor::for_attempt;
+use crate::timing::Stopwatch;
+use crate::writer::{report_end, stream_body};
+
+/// Downloads the body of the given URL into memory instead of a file.
+///
+/// This is a convenience wrapper around [`download_to_memory_with_config`] that builds a new
+/// client and uses the default [`DownloadConfig`].
+///
+/// # Arguments
+///
+/// * `url` - A reference to a string or string-like value specifying the download URL.
+/// * `callback` - A function or closure that receives every [`DownloadEvent`] for this download.
+///
+/// # Returns
+///
+/// * `Ok` with the body if the download succeeds.
+/// * `Err` if any error occurs.
+pub fn download_to_memory(
+    url: impl AsRef<str>,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+) -> Result<Vec<u8>, DownloadError> {
+    let client = blocking_client(&mut BatchConfig::default())?;
+    download_to_memory_with_config(&client, url, &DownloadConfig::default(), callback)
+}
+
+/// Downloads the body of the given URL into memory using an existing client and explicit
+/// settings.
+///
+/// The body is streamed and reported as [`crate::download_to_writer_with_config`] streams
+/// it, except that every failure is retried as a file download's would be, starting the body
+/// over. Set [`DownloadConfig::max_size`] when the server isn't trusted, since the whole body is
+/// held in memory.
+///
+/// # Arguments
+///
+/// * `client` - The blocking HTTP client used to issue the request, or any other
+///   [`crate::HttpBackend`].
+/// * `url` - A reference to a string or string-like value specifying the download URL.
+/// * `config` - The settings applied to this download.
+/// * `callback` - A function or closure that receives every [`DownloadEvent`] for this download.
+///
+/// # Returns
+///
+/// * `Ok` with the body if the download succeeds.
+/// * `Err` if any error occurs. When retries were attempted, the error is a
+///   [`crate::RetriesExhausted`] carrying the attempt count and the final underlying error.
+pub fn download_to_memory_with_config(
+    client: &impl HttpBackend,
+    url: impl AsRef<str>,
+    config: &DownloadConfig,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+) -> Result<Vec<u8>, DownloadError> {
+    let url = url.as_ref();
+    let context = Context::default();
+    let stopwatch = Stopwatch::start();
+    let callback = |event: &DownloadEvent| {
+        stopwatch.observe(event);
+        callback(event);
+    };
+    let mut body = Vec::new();
+    let result = with_retries(url, config, &context, &callback, |attempt| {
+        // Unlike an arbitrary writer, the buffer can start over for every attempt.
+        body.clear();
+        let config = &*for_attempt(config, attempt);
+        stream_body(client, url, &mut body, config, &context, &callback)
+    });
+    report_end(result, &stopwatch, &callback).map(|_| body)
+}
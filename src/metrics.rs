@@ -0,0 +1,167 @@
+use crate::result::DownloadOutcome;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Seconds of traffic kept for [`BatchMetrics::bytes_per_sec`], counting the current one.
+const RECENT_SECONDS: usize = 4;
+
+/// Counters of a running batch, read with [`crate::BatchHandle::metrics`].
+///
+/// Unlike [`crate::BatchProgress`], which is pushed to a callback, metrics are meant to be
+/// polled, for example by a monitoring thread.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BatchMetrics {
+    /// Bytes received across all workers so far, including those of attempts that were
+    /// retried.
+    pub bytes_downloaded: u64,
+    /// Bytes received per second over the last three seconds or so.
+    pub bytes_per_sec: f64,
+    /// Bytes received per second since the batch started.
+    pub average_bytes_per_sec: f64,
+    /// Requests whose download has started so far.
+    pub requests_started: u64,
+    /// Requests that ended with their file in place, including existing files that were kept.
+    pub requests_succeeded: u64,
+    /// Requests that failed.
+    pub requests_failed: u64,
+    /// Attempts that failed and were retried, across all requests.
+    pub retries: u64,
+    /// Workers downloading a request right now.
+    pub active_workers: usize,
+    /// Time since the batch started.
+    pub elapsed: Duration,
+}
+
+/// Lock-free counters behind [`BatchMetrics`], updated from every worker's chunk loop and
+/// retry path.
+#[derive(Debug)]
+pub(crate) struct Metrics {
+    /// When the batch started.
+    started: Instant,
+    bytes_downloaded: AtomicU64,
+    requests_started: AtomicU64,
+    requests_succeeded: AtomicU64,
+    requests_failed: AtomicU64,
+    retries: AtomicU64,
+    active_workers: AtomicUsize,
+    /// Bytes received during each of the last seconds, at the second modulo
+    /// [`RECENT_SECONDS`].
+    recent_bytes: [AtomicU64; RECENT_SECONDS],
+    /// The second since `started` each entry of `recent_bytes` counts.
+    recent_second: [AtomicU64; RECENT_SECONDS],
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            bytes_downloaded: AtomicU64::new(0),
+            requests_started: AtomicU64::new(0),
+            requests_succeeded: AtomicU64::new(0),
+            requests_failed: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+            active_workers: AtomicUsize::new(0),
+            recent_bytes: Default::default(),
+            recent_second: Default::default(),
+        }
+    }
+}
+
+impl Metrics {
+    /// Reads the current counters into a [`BatchMetrics`].
+    pub(crate) fn snapshot(&self) -> BatchMetrics {
+        let elapsed = self.started.elapsed();
+        let bytes_downloaded = self.bytes_downloaded.load(Ordering::Relaxed);
+
+        // Only the seconds still inside the window count towards the current speed.
+        let now = elapsed.as_secs();
+        let oldest = now.saturating_sub(RECENT_SECONDS as u64 - 1);
+        let recent: u64 = (0..RECENT_SECONDS)
+            .filter(|&slot| self.recent_second[slot].load(Ordering::Relaxed) >= oldest)
+            .map(|slot| self.recent_bytes[slot].load(Ordering::Relaxed))
+            .sum();
+        let window = elapsed.as_secs_f64() - oldest as f64;
+
+        BatchMetrics {
+            bytes_downloaded,
+            bytes_per_sec: per_sec(recent, window),
+            average_bytes_per_sec: per_sec(bytes_downloaded, elapsed.as_secs_f64()),
+            requests_started: self.requests_started.load(Ordering::Relaxed),
+            requests_succeeded: self.requests_succeeded.load(Ordering::Relaxed),
+            requests_failed: self.requests_failed.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            active_workers: self.active_workers.load(Ordering::Relaxed),
+            elapsed,
+        }
+    }
+
+    /// Counts bytes that have just been received.
+    pub(crate) fn received(&self, bytes: u64) {
+        self.bytes_downloaded.fetch_add(bytes, Ordering::Relaxed);
+
+        // The first chunk of a new second takes over the entry of the second it replaces.
+        // Chunks of the same second racing with it may go uncounted, which only makes the
+        // current speed slightly low.
+        let second = self.started.elapsed().as_secs();
+        let slot = (second % RECENT_SECONDS as u64) as usize;
+        if self.recent_second[slot].swap(second, Ordering::Relaxed) == second {
+            self.recent_bytes[slot].fetch_add(bytes, Ordering::Relaxed);
+        } else {
+            self.recent_bytes[slot].store(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a request whose download starts now.
+    pub(crate) fn request_started(&self) {
+        self.requests_started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a finished request according to how it ended. Cancelled and aborted requests
+    /// count as neither succeeded nor failed.
+    pub(crate) fn request_ended(&self, outcome: &DownloadOutcome) {
+        match outcome {
+            DownloadOutcome::Completed { .. }
+            | DownloadOutcome::Skipped { .. }
+            | DownloadOutcome::NotModified { .. }
+            | DownloadOutcome::DryRun { .. } => {
+                self.requests_succeeded.fetch_add(1, Ordering::Relaxed);
+            }
+            DownloadOutcome::Failed { .. } => {
+                self.requests_failed.fetch_add(1, Ordering::Relaxed);
+            }
+            DownloadOutcome::Cancelled | DownloadOutcome::Aborted => {}
+        }
+    }
+
+    /// Counts an attempt that failed and is about to be retried.
+    pub(crate) fn retried(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a worker as busy until the returned guard is dropped.
+    pub(crate) fn worker_busy(&self) -> BusyWorker<'_> {
+        self.active_workers.fetch_add(1, Ordering::Relaxed);
+        BusyWorker { metrics: self }
+    }
+}
+
+/// Keeps a worker counted in [`BatchMetrics::active_workers`] while it is alive.
+#[derive(Debug)]
+pub(crate) struct BusyWorker<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Drop for BusyWorker<'_> {
+    fn drop(&mut self) {
+        self.metrics.active_workers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns `bytes` spread over `seconds`, or `0.0` when no time has passed.
+fn per_sec(bytes: u64, seconds: f64) -> f64 {
+    if seconds > 0.0 {
+        bytes as f64 / seconds
+    } else {
+        0.0
+    }
+}
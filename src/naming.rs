@@ -0,0 +1,190 @@
+use http::header::{HeaderMap, CONTENT_DISPOSITION};
+use percent_encoding::percent_decode_str;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Names Windows reserves for devices, with or without an extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// How names suggested by URLs and servers are made safe to use as file names.
+///
+/// The same rules apply on every platform, so a batch saves its files under the same names
+/// wherever it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileNameConfig {
+    /// Replaces characters that aren't allowed in file names, and is put in front of names
+    /// Windows reserves for devices. A replacement that isn't allowed itself is replaced by
+    /// `_`. Defaults to `_`.
+    pub replacement: char,
+    /// Longest name in bytes. Longer names are shortened, keeping their extension. Defaults to
+    /// 255, the limit of most filesystems.
+    pub max_length: usize,
+}
+
+impl Default for FileNameConfig {
+    fn default() -> Self {
+        Self {
+            replacement: '_',
+            max_length: 255,
+        }
+    }
+}
+
+/// Derives a file name from the last path segment of `url`.
+///
+/// The query string and fragment are ignored and percent-encoding is decoded. The name is
+/// made safe with the default [`FileNameConfig`]; see [`sanitize_file_name`]. Returns `None`
+/// when the URL cannot be parsed or its path ends in `/`.
+///
+/// # Arguments
+///
+/// * `url` - The URL to derive the name from.
+pub fn file_name_from_url(url: &str) -> Option<String> {
+    url_file_name(url, &FileNameConfig::default())
+}
+
+/// Derives a file name from `url` like [`file_name_from_url`], made safe according to
+/// `config`.
+pub(crate) fn url_file_name(url: &str, config: &FileNameConfig) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let segment = url.path_segments()?.next_back()?;
+    let name = percent_decode_str(segment).decode_utf8().ok()?;
+    sanitize_file_name(&name, config)
+}
+
+/// Extracts the file name from a `Content-Disposition` header value.
+///
+/// Both the plain `filename=` parameter and the RFC 5987 `filename*=UTF-8''...` form are
+/// understood, with the extended form taking precedence when both are present. The name is
+/// made safe with the default [`FileNameConfig`]; see [`sanitize_file_name`].
+///
+/// # Arguments
+///
+/// * `value` - The raw header value, for example `attachment; filename="report.pdf"`.
+pub fn file_name_from_content_disposition(value: &str) -> Option<String> {
+    content_disposition_file_name(value, &FileNameConfig::default())
+}
+
+/// Extracts the file name from a `Content-Disposition` header value like
+/// [`file_name_from_content_disposition`], made safe according to `config`.
+fn content_disposition_file_name(value: &str, config: &FileNameConfig) -> Option<String> {
+    let mut plain = None;
+    let mut extended = None;
+
+    for parameter in value.split(';').skip(1) {
+        let Some((key, value)) = parameter.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "filename" => plain = Some(value.trim_matches('"').to_string()),
+            "filename*" => {
+                // The extended form is `charset'language'percent-encoded-value`.
+                let encoded = value.splitn(3, '\'').nth(2).unwrap_or(value);
+                extended = percent_decode_str(encoded.trim_matches('"'))
+                    .decode_utf8()
+                    .ok()
+                    .map(|name| name.into_owned());
+            }
+            _ => {}
+        }
+    }
+
+    extended
+        .or(plain)
+        .and_then(|name| sanitize_file_name(&name, config))
+}
+
+/// Returns the destination for a download whose server suggested a file name.
+///
+/// When `headers` carries a `Content-Disposition` file name, it replaces the file name of
+/// `path` while keeping its directory, after being made safe according to `config`. Otherwise
+/// `path` is returned unchanged.
+pub(crate) fn content_disposition_destination(
+    path: &Path,
+    headers: &HeaderMap,
+    config: &FileNameConfig,
+) -> PathBuf {
+    let name = headers
+        .get(CONTENT_DISPOSITION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| content_disposition_file_name(value, config));
+
+    match name {
+        Some(name) => path.with_file_name(name),
+        None => path.to_path_buf(),
+    }
+}
+
+/// Turns a name suggested by a URL or a server into one that is safe to use as a file name.
+///
+/// Only the part after the last `/` or `\\` is kept, so the name can never escape the
+/// directory it is saved in. Characters Windows doesn't allow in file names (`<>:"|?*` and
+/// control characters) are replaced, leading spaces and trailing dots and spaces are trimmed,
+/// names Windows reserves for devices such as `CON` or `nul.txt` get the replacement put in
+/// front, and names longer than [`FileNameConfig::max_length`] are shortened.
+///
+/// # Arguments
+///
+/// * `name` - The suggested name.
+/// * `config` - The replacement character and the longest allowed name.
+///
+/// # Returns
+///
+/// * `Some` with the safe name.
+/// * `None` if nothing usable is left, such as for `..` or a name of only spaces.
+pub fn sanitize_file_name(name: &str, config: &FileNameConfig) -> Option<String> {
+    let replacement = if is_forbidden(config.replacement) {
+        '_'
+    } else {
+        config.replacement
+    };
+
+    let name = name.rsplit(['/', '\\']).next()?;
+    let name: String = name
+        .chars()
+        .map(|c| if is_forbidden(c) { replacement } else { c })
+        .collect();
+    // Windows drops trailing dots and spaces, which would make "a." and "a" the same file.
+    let mut name = name.trim_start().trim_end_matches(['.', ' ']).to_string();
+
+    let stem = name.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        name.insert(0, replacement);
+    }
+
+    let name = truncate(&name, config.max_length);
+    let name = name.trim_end_matches(['.', ' ']);
+    match name {
+        "" => None,
+        name => Some(name.to_string()),
+    }
+}
+
+/// Returns `true` for characters that can't appear in a file name on every platform.
+fn is_forbidden(c: char) -> bool {
+    c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*')
+}
+
+/// Shortens `name` to at most `max_length` bytes, cutting the stem rather than a short
+/// extension and never splitting a character.
+fn truncate(name: &str, max_length: usize) -> String {
+    if name.len() <= max_length {
+        return name.to_string();
+    }
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot < max_length / 2 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let mut end = max_length - extension.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &stem[..end], extension)
+}
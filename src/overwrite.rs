@@ -0,0 +1,162 @@
+use crate::error::DownloadError;
+use crate::temp_file::lock_path;
+use fs2::FileExt;
+use std::error::Error;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+/// What to do when a download's destination already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverwritePolicy {
+    /// Replace the existing file once the new one has been downloaded.
+    #[default]
+    Overwrite,
+    /// Leave the existing file alone and report the download as skipped.
+    SkipExisting,
+    /// Save the new file next to the existing one as `name (1).ext`, `name (2).ext`, and so on.
+    ///
+    /// Requests of a batch that share a destination are renamed the same way before the batch
+    /// starts, instead of being rejected as a [`crate::DuplicateDestination`]. The name the
+    /// file was finally saved under is reported in [`crate::DownloadEvent::Completed`] and by
+    /// [`crate::DownloadResult::path`].
+    RenameWithSuffix,
+    /// Fail the download with a [`DestinationExists`] error.
+    Error,
+}
+
+/// Returned when a destination already exists and the policy is [`OverwritePolicy::Error`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DestinationExists {
+    /// The destination that was already present.
+    pub path: PathBuf,
+}
+
+impl fmt::Display for DestinationExists {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "destination {} already exists", self.path.display())
+    }
+}
+
+impl Error for DestinationExists {}
+
+/// A destination reserved for a download according to its [`OverwritePolicy`].
+///
+/// Every policy other than [`OverwritePolicy::Overwrite`] reserves the name by holding an
+/// exclusive lock on the `<name>.lock` file next to it, so two workers, or two processes,
+/// can never pick the same name while nothing is written to the destination itself until the
+/// download is moved into place. A name is free when its lock can be taken and no file exists
+/// under it. The lock file is removed when the claim is dropped, once the download was moved
+/// into place or failed; one left behind by a process
+/// that crashed is unlocked, and so doesn't keep its name taken.
+#[derive(Debug)]
+pub(crate) struct Claim {
+    path: PathBuf,
+    lock: Option<File>,
+}
+
+impl Claim {
+    /// Applies `policy` to `path`, returning the destination the download should be saved to.
+    ///
+    /// Returns `Ok(None)` when [`OverwritePolicy::SkipExisting`] keeps an existing file, and
+    /// fails with [`DestinationExists`] when [`OverwritePolicy::Error`] refuses to replace one.
+    /// A destination another download holds the lock of counts as existing.
+    pub(crate) fn new(path: &Path, policy: OverwritePolicy) -> Result<Option<Self>, DownloadError> {
+        if policy == OverwritePolicy::Overwrite {
+            return Ok(Some(Self {
+                path: path.to_path_buf(),
+                lock: None,
+            }));
+        }
+
+        if let Some(claim) = reserve(path)? {
+            return Ok(Some(claim));
+        }
+
+        match policy {
+            OverwritePolicy::SkipExisting => Ok(None),
+            OverwritePolicy::Error => Err(DestinationExists {
+                path: path.to_path_buf(),
+            }
+            .into()),
+            // Count upwards until a free name is found; the lock makes this race-free.
+            _ => {
+                let mut number = 1;
+                loop {
+                    if let Some(claim) = reserve(&numbered(path, number))? {
+                        return Ok(Some(claim));
+                    }
+                    number += 1;
+                }
+            }
+        }
+    }
+
+    /// The destination the download should be saved to.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        // Remove the lock file before unlocking it, so no other claim locks a file that is
+        // about to disappear. Cleanup is best effort; the lock file is empty.
+        if let Some(lock) = self.lock.take() {
+            let _ = std::fs::remove_file(lock_path(&self.path));
+            let _ = FileExt::unlock(&lock);
+        }
+    }
+}
+
+/// Locks the lock file of `path`, returning a claim holding it if the name is free, or `None`
+/// if another download holds the lock or a file already exists at `path`.
+fn reserve(path: &Path) -> Result<Option<Claim>, DownloadError> {
+    let lock_path = lock_path(path);
+    let lock = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)
+        .map_err(DownloadError::io(&lock_path))?;
+    if lock.try_lock_exclusive().is_err() || !is_current(&lock, &lock_path) {
+        return Ok(None);
+    }
+    let claim = Claim {
+        path: path.to_path_buf(),
+        lock: Some(lock),
+    };
+    match path.try_exists() {
+        Ok(false) => Ok(Some(claim)),
+        Ok(true) => Ok(None),
+        Err(e) => Err(DownloadError::io(path)(e)),
+    }
+}
+
+/// Returns `true` if `lock` is still the file at `lock_path`, and wasn't removed by the claim
+/// that held it between being opened and being locked.
+#[cfg(unix)]
+fn is_current(lock: &File, lock_path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (lock.metadata(), std::fs::metadata(lock_path)) {
+        (Ok(locked), Ok(current)) => locked.dev() == current.dev() && locked.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+/// Returns `true`; files that are open can't be removed on this platform, so the lock file
+/// is always the one at `lock_path`.
+#[cfg(not(unix))]
+fn is_current(_lock: &File, _lock_path: &Path) -> bool {
+    true
+}
+
+/// Returns `path` with ` (<number>)` inserted before its extension.
+pub(crate) fn numbered(path: &Path, number: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{} ({}).{}", stem, number, extension.to_string_lossy()),
+        None => format!("{} ({})", stem, number),
+    };
+    path.with_file_name(name)
+}
@@ -0,0 +1,202 @@
+use crate::config::{BatchConfig, InvalidConfig};
+use crate::pinning::{KeyPins, PinMismatch};
+use crate::protocol::HttpVersion;
+use reqwest::tls::Version;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, OtherError, RootCertStore,
+    SignatureScheme,
+};
+use std::error::Error;
+use std::iter;
+use std::sync::Arc;
+
+/// Checks the keys of pinned hosts during the TLS handshake, so nothing is sent over a
+/// connection whose key doesn't match, whichever connection of the pool a request ends up on.
+///
+/// Every certificate of the chain the server presents is compared, so the key of an
+/// intermediate CA can be pinned as well as that of the server.
+#[derive(Debug)]
+struct PinVerifier {
+    /// Verifies the chain and the host name, or `None` if every certificate is accepted.
+    chain: Option<Arc<WebPkiServerVerifier>>,
+    /// The pins the keys are checked against.
+    pins: KeyPins,
+    /// The signature algorithms of the handshake.
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let Some(chain) = &self.chain {
+            chain.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        }
+        let host = server_name.to_str();
+        let chain = iter::once(end_entity).chain(intermediates);
+        match self
+            .pins
+            .check_chain(&host, chain.map(|certificate| &**certificate))
+        {
+            Ok(()) => Ok(ServerCertVerified::assertion()),
+            // The URL isn't known here; the error it surfaces as fills it in.
+            Err(found) => Err(rustls::Error::InvalidCertificate(CertificateError::Other(
+                OtherError(Arc::new(PinMismatch {
+                    url: String::new(),
+                    host: host.into_owned(),
+                    found,
+                })),
+            ))),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Returns whether the client built for `config` checks its pins during the handshake.
+///
+/// The rustls configuration is built by the crate only when there are pins to check and no
+/// custom roots: reqwest keeps the certificates of [`crate::TlsConfig::root_certificates`] to
+/// itself, so with them the pins are checked on the responses instead.
+pub(crate) fn checks_pins(config: &BatchConfig) -> bool {
+    !config.download.pinned_keys.is_empty() && config.tls.root_certificates.is_empty()
+}
+
+/// Builds the rustls configuration of a client checking the pins of `config` during the
+/// handshake, applying the TLS settings and HTTP versions reqwest would have applied.
+///
+/// # Returns
+///
+/// * `Err` with an [`InvalidConfig`] if the TLS versions leave none rustls speaks, or no root
+///   certificate could be loaded.
+pub(crate) fn client_config(config: &BatchConfig) -> Result<ClientConfig, InvalidConfig> {
+    let invalid = |reason: &dyn Error| InvalidConfig {
+        setting: "tls",
+        reason: reason.to_string(),
+    };
+    let tls = &config.tls;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let versions: Vec<_> = [
+        (Version::TLS_1_2, &rustls::version::TLS12),
+        (Version::TLS_1_3, &rustls::version::TLS13),
+    ]
+    .into_iter()
+    .filter(|(version, _)| tls.min_version.is_none_or(|min| *version >= min))
+    .filter(|(version, _)| tls.max_version.is_none_or(|max| *version <= max))
+    .map(|(_, supported)| supported)
+    .collect();
+    let builder = ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_protocol_versions(&versions)
+        .map_err(|e| invalid(&e))?;
+
+    let chain = if tls.danger_accept_invalid_certs {
+        None
+    } else {
+        let roots = Arc::new(root_store().map_err(|e| invalid(&*e))?);
+        let verifier = WebPkiServerVerifier::builder_with_provider(roots, Arc::clone(&provider))
+            .build()
+            .map_err(|e| invalid(&e))?;
+        Some(verifier)
+    };
+    let verifier = PinVerifier {
+        chain,
+        pins: config.download.pinned_keys.clone(),
+        provider,
+    };
+    let mut client_config = builder
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    client_config.alpn_protocols = match config.http.version {
+        HttpVersion::Negotiate => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+        HttpVersion::Http1Only => vec![b"http/1.1".to_vec()],
+        HttpVersion::Http2PriorKnowledge => vec![b"h2".to_vec()],
+    };
+    Ok(client_config)
+}
+
+/// Loads the roots the `rustls` features trust: the bundled Mozilla roots, the platform's
+/// store, or both.
+fn root_store() -> Result<RootCertStore, Box<dyn Error>> {
+    let mut roots = RootCertStore::empty();
+    #[cfg(feature = "rustls")]
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    #[cfg(feature = "rustls-native-roots")]
+    {
+        let native = rustls_native_certs::load_native_certs();
+        if native.certs.is_empty() {
+            if let Some(error) = native.errors.into_iter().next() {
+                return Err(error.into());
+            }
+        }
+        roots.add_parsable_certificates(native.certs);
+    }
+    Ok(roots)
+}
+
+/// Finds the mismatch a [`PinVerifier`] rejected the handshake of a request to `url` with,
+/// among the causes of `error`.
+pub(crate) fn rejected_pin(url: &str, error: &reqwest::Error) -> Option<PinMismatch> {
+    let mut cause: Option<&(dyn Error + 'static)> = Some(error);
+    while let Some(error) = cause {
+        if let Some(rustls::Error::InvalidCertificate(CertificateError::Other(other))) =
+            error.downcast_ref::<rustls::Error>()
+        {
+            if let Some(mismatch) = other.0.downcast_ref::<PinMismatch>() {
+                return Some(PinMismatch {
+                    url: url.to_string(),
+                    ..mismatch.clone()
+                });
+            }
+        }
+        // An I/O error doesn't list the error it carries as its source.
+        cause = match error.downcast_ref::<std::io::Error>() {
+            Some(error) => error
+                .get_ref()
+                .map(|payload| payload as &(dyn Error + 'static)),
+            None => error.source(),
+        };
+    }
+    None
+}
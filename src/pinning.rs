@@ -0,0 +1,252 @@
+use crate::event::DownloadEvent;
+use crate::spans::error;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use url::Url;
+
+/// The public keys a download accepts from each pinned host, used by
+/// [`crate::DownloadConfig::pinned_keys`].
+///
+/// A pin is the SHA-256 of a certificate's DER-encoded `SubjectPublicKeyInfo`, the same
+/// fingerprint `curl --pinnedpubkey` and HPKP use. The certificate a pinned host presents must
+/// carry one of its pins on top of passing the usual verification, so a certificate issued by
+/// a compromised CA is rejected; hosts without pins are only verified as usual. A pinned host
+/// must be reached over `https://`: any other URL on it fails before anything is sent.
+///
+/// How strong the check is depends on the client. A batch building its own client with one of
+/// the `rustls` features checks the pins during every TLS handshake, so no request goes out
+/// over a connection whose key doesn't match, and the key of any certificate of the presented
+/// chain may be pinned, including that of an intermediate CA.
+///
+/// Every other client, such as one built with `native-tls`, given certificates in
+/// [`crate::TlsConfig::root_certificates`] or passed in by the caller, only reports the
+/// server's own certificate with each response, so pin the key of the server rather than that
+/// of its CA. The check is then a best effort: every redirect hop and mirror on a pinned host
+/// is first sent a bare `HEAD` without credentials, cookies, configured headers or
+/// interceptors, and only once its answer carried a pinned key does the request itself go
+/// out. The client may send that request over another pooled connection than the probe, so
+/// its answer is checked again before anything is written, but whatever the request carried
+/// has then already reached the server. Clients passed in by the caller need `tls_info`
+/// enabled on their builder, or pinned hosts always fail.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyPins {
+    /// The accepted fingerprints, by lowercase host name.
+    pins: HashMap<String, Vec<[u8; 32]>>,
+    /// Whether the client of the download checks the pins during the handshake, so neither
+    /// probes nor checks of the responses are needed.
+    checked_in_handshake: bool,
+}
+
+impl KeyPins {
+    /// Accepts the key with the given fingerprint from `host`, besides any already pinned for
+    /// it.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host name the key belongs to, matched case-insensitively and on any port.
+    /// * `fingerprint` - The SHA-256 of the key as base64, optionally prefixed with `sha256//`
+    ///   as curl writes it, or as 64 hex digits.
+    ///
+    /// # Returns
+    ///
+    /// * `Err` with an [`InvalidPin`] if the fingerprint isn't a SHA-256 in either form.
+    pub fn add(&mut self, host: &str, fingerprint: &str) -> Result<(), InvalidPin> {
+        let pin = parse_fingerprint(fingerprint).ok_or_else(|| InvalidPin {
+            fingerprint: fingerprint.to_string(),
+        })?;
+        self.pins
+            .entry(host.to_ascii_lowercase())
+            .or_default()
+            .push(pin);
+        Ok(())
+    }
+
+    /// Returns `true` if no host is pinned.
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty()
+    }
+
+    /// Returns `true` if the host of `url` has pins, so nothing secret may be sent to it
+    /// before its key is checked.
+    pub(crate) fn is_pinned(&self, url: &Url) -> bool {
+        url.host_str()
+            .is_some_and(|host| self.pins.contains_key(&host.to_ascii_lowercase()))
+    }
+
+    /// Returns `true` if the client of the download checks the pins during the handshake.
+    pub(crate) fn checked_in_handshake(&self) -> bool {
+        self.checked_in_handshake
+    }
+
+    /// Records that the client of the download checks the pins during the handshake.
+    #[cfg(any(feature = "rustls", feature = "rustls-native-roots"))]
+    pub(crate) fn check_in_handshake(&mut self) {
+        self.checked_in_handshake = true;
+    }
+
+    /// Checks the certificate that served `url` against the pins of its host, reporting a
+    /// mismatch through `callback` before it is returned.
+    ///
+    /// `certificate` is the DER certificate the server presented, if known.
+    pub(crate) fn check(
+        &self,
+        url: &Url,
+        certificate: Option<&[u8]>,
+        callback: &impl Fn(&DownloadEvent),
+    ) -> Result<(), PinMismatch> {
+        let Some(host) = url.host_str() else {
+            return Ok(());
+        };
+        self.check_chain(host, certificate).map_err(|found| {
+            let mismatch = PinMismatch {
+                url: url.to_string(),
+                host: host.to_string(),
+                found,
+            };
+            report(&mismatch, callback);
+            mismatch
+        })
+    }
+
+    /// Checks the DER certificates of a chain `host` presented against its pins.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` if the host has no pins or one of the certificates carries one of them.
+    /// * `Err` with the fingerprint of the first certificate as base64, or `None` if there is
+    ///   no readable one.
+    pub(crate) fn check_chain<'a>(
+        &self,
+        host: &str,
+        chain: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<(), Option<String>> {
+        let Some(pins) = self.pins.get(&host.to_ascii_lowercase()) else {
+            return Ok(());
+        };
+        let mut first = None;
+        for key in chain.into_iter().filter_map(public_key_info) {
+            let found = <[u8; 32]>::from(Sha256::digest(key));
+            if pins.contains(&found) {
+                return Ok(());
+            }
+            first.get_or_insert(found);
+        }
+        Err(first.map(|found| STANDARD.encode(found)))
+    }
+}
+
+/// Logs `mismatch` and reports it through `callback`.
+pub(crate) fn report(mismatch: &PinMismatch, callback: &impl Fn(&DownloadEvent)) {
+    error!("{}", mismatch);
+    callback(&DownloadEvent::PinMismatch {
+        url: mismatch.url.clone(),
+        host: mismatch.host.clone(),
+    });
+}
+
+/// Reads a SHA-256 fingerprint written as base64, with or without curl's `sha256//` prefix, or
+/// as hex.
+fn parse_fingerprint(fingerprint: &str) -> Option<[u8; 32]> {
+    let fingerprint = fingerprint.trim();
+    let bytes = if fingerprint.len() == 64 && fingerprint.bytes().all(|b| b.is_ascii_hexdigit()) {
+        (0..32)
+            .map(|i| u8::from_str_radix(&fingerprint[i * 2..i * 2 + 2], 16).ok())
+            .collect::<Option<Vec<_>>>()?
+    } else {
+        let base64 = fingerprint.strip_prefix("sha256//").unwrap_or(fingerprint);
+        STANDARD.decode(base64).ok()?
+    };
+    bytes.try_into().ok()
+}
+
+/// Returns the DER encoding of the `SubjectPublicKeyInfo` of a DER-encoded X.509 certificate,
+/// or `None` if the certificate is malformed.
+fn public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    // Certificate ::= SEQUENCE { tbsCertificate SEQUENCE { ... }, ... }
+    let (certificate, _, _) = der_element(certificate)?;
+    let (mut fields, _, _) = der_element(certificate)?;
+    // The explicitly tagged version is optional.
+    if fields.first() == Some(&0xa0) {
+        fields = der_element(fields)?.2;
+    }
+    // Skip the serial number, signature algorithm, issuer, validity, and subject.
+    for _ in 0..5 {
+        fields = der_element(fields)?.2;
+    }
+    der_element(fields).map(|(_, key, _)| key)
+}
+
+/// Splits the DER element at the start of `der` off the bytes after it, returning its
+/// contents, its whole encoding, and the rest.
+fn der_element(der: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let (header, length) = match *der.get(1)? {
+        short if short < 0x80 => (2, usize::from(short)),
+        long => {
+            let bytes = usize::from(long & 0x7f);
+            if !(1..=4).contains(&bytes) {
+                return None;
+            }
+            let length = der
+                .get(2..2 + bytes)?
+                .iter()
+                .fold(0, |length, &b| length << 8 | usize::from(b));
+            (2 + bytes, length)
+        }
+    };
+    let end = header.checked_add(length)?;
+    Some((der.get(header..end)?, der.get(..end)?, der.get(end..)?))
+}
+
+/// Returned when a fingerprint passed to [`KeyPins::add`] isn't a SHA-256.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidPin {
+    /// The fingerprint as it was given.
+    pub fingerprint: String,
+}
+
+impl fmt::Display for InvalidPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid key pin {:?}: expected a SHA-256 as base64 or hex",
+            self.fingerprint
+        )
+    }
+}
+
+impl Error for InvalidPin {}
+
+/// Returned when a pinned host presents a certificate whose key isn't one of its pins.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinMismatch {
+    /// The URL whose connection presented the certificate.
+    pub url: String,
+    /// The pinned host.
+    pub host: String,
+    /// The fingerprint of the key that was presented, as base64, or `None` if the connection
+    /// didn't present a readable certificate, such as a plain `http://` one.
+    pub found: Option<String>,
+}
+
+impl fmt::Display for PinMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.found {
+            Some(found) => write!(
+                f,
+                "the certificate of {} for {} has the unpinned key sha256//{}",
+                self.host, self.url, found
+            ),
+            None => write!(
+                f,
+                "{} presented no certificate to check against its pins for {}",
+                self.host, self.url
+            ),
+        }
+    }
+}
+
+impl Error for PinMismatch {}
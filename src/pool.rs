@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+/// Connection pool and TCP settings of the batch's client.
+///
+/// Finished downloads hand their connection back to the pool, so the next download from the
+/// same host skips the TCP and TLS handshakes. Keeping many idle connections suits long
+/// batches against one origin, while a short [`PoolConfig::idle_timeout`] keeps a crawl of
+/// many hosts from holding sockets it won't use again. Clients passed in by the caller keep
+/// their own settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// How many idle connections are kept per host. `0` closes every connection once its
+    /// download is done. Defaults to no limit.
+    pub max_idle_per_host: usize,
+    /// How long an idle connection is kept before it is closed. `None` keeps it until the
+    /// server closes it. Defaults to 90 seconds.
+    pub idle_timeout: Option<Duration>,
+    /// How often TCP keepalive probes are sent on an idle connection, which spots dead peers
+    /// and keeps middleboxes from dropping the connection. `None` sends none. Defaults to
+    /// `None`.
+    pub tcp_keepalive: Option<Duration>,
+    /// Sends small writes right away instead of batching them, by setting `TCP_NODELAY`.
+    /// Defaults to `true`.
+    pub tcp_nodelay: bool,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_idle_per_host: usize::MAX,
+            idle_timeout: Some(Duration::from_secs(90)),
+            tcp_keepalive: None,
+            tcp_nodelay: true,
+        }
+    }
+}
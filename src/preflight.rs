@@ -0,0 +1,164 @@
+use crate::client::{blocking_client, Client};
+use crate::config::BatchConfig;
+use crate::control::Control;
+use crate::download::probe;
+use crate::error::DownloadError;
+use crate::headers::check_headers;
+use crate::host_limit::{host_key, HostLimiter};
+use crate::request::DownloadRequest;
+use crate::resume::{accepts_ranges, content_length, content_range_total};
+use crate::segment::SegmentConfig;
+use http::header::{HeaderMap, CONTENT_TYPE};
+use http::StatusCode;
+use std::cmp::min;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use url::Url;
+
+/// What a server reports about a URL before it is downloaded, as found by [`preflight`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteFile {
+    /// The URL that was asked about.
+    pub url: String,
+    /// The status the server answered with, after following redirects.
+    pub status: StatusCode,
+    /// The size of the file in bytes, if the server answered with success and reported it.
+    pub content_length: Option<u64>,
+    /// Whether the server accepts range requests, so a download can be resumed or split
+    /// into segments.
+    pub accepts_ranges: bool,
+    /// The `Content-Type` the server reported, if any.
+    pub content_type: Option<String>,
+    /// The URL the answer came from after following redirects.
+    pub final_url: String,
+}
+
+impl RemoteFile {
+    /// Reads what a `HEAD` response, or the response to a `GET` of the first byte, says about
+    /// `url`.
+    pub(crate) fn from_response(
+        url: &str,
+        status: StatusCode,
+        final_url: &Url,
+        headers: &HeaderMap,
+    ) -> Self {
+        // A range answer carries the size of the whole file in `Content-Range`.
+        let partial = status == StatusCode::PARTIAL_CONTENT;
+        let content_length = if partial {
+            content_range_total(headers)
+        } else if status.is_success() {
+            content_length(headers)
+        } else {
+            None
+        };
+        Self {
+            url: url.to_string(),
+            status,
+            content_length,
+            accepts_ranges: partial || accepts_ranges(headers),
+            content_type: headers
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            final_url: final_url.to_string(),
+        }
+    }
+
+    /// Hands what the server reported to the download of `request`.
+    ///
+    /// Fills in [`DownloadRequest::expected_size`] unless it is already set, and turns off
+    /// segmented downloads for the request if the server can't serve them, which also spares
+    /// the download its own `HEAD` request.
+    pub fn apply(&self, request: &mut DownloadRequest) {
+        if request.expected_size.is_none() {
+            request.expected_size = self.content_length;
+        }
+        if !self.accepts_ranges || self.content_length.is_none() {
+            request.segments = Some(SegmentConfig {
+                count: 1,
+                ..SegmentConfig::default()
+            });
+        }
+    }
+}
+
+/// Asks the server of every request about its file without downloading it.
+///
+/// Each request gets a `HEAD` request with the same headers, credentials, proxy, timeouts,
+/// and redirect policy its download would use. Servers that refuse `HEAD` are asked for the
+/// first byte of the file with a `GET` instead. Up to [`BatchConfig::concurrency`] requests
+/// are sent at a time, keeping to [`BatchConfig::max_connections_per_host`]. Only
+/// [`DownloadRequest::url`] is asked about, not its mirrors.
+///
+/// Set [`BatchConfig::preflight`] to have a blocking batch do the same before it starts
+/// downloading.
+///
+/// # Arguments
+///
+/// * `requests` - The files to ask about.
+/// * `config` - The batch settings the requests would be downloaded with.
+///
+/// # Returns
+///
+/// * `Ok` with one result per request, in the order of `requests`. A server that answered,
+///   even with an error status, gives a [`RemoteFile`]; a request that couldn't be sent gives
+///   its error.
+/// * `Err` with an [`crate::InvalidHeader`] if a configured header is malformed, an
+///   [`crate::InvalidConfig`] if a setting can't be used, or if the HTTP client could not be
+///   built.
+pub fn preflight(
+    requests: &[DownloadRequest],
+    config: &BatchConfig,
+) -> Result<Vec<Result<RemoteFile, DownloadError>>, DownloadError> {
+    check_headers(requests, &config.download)?;
+    config.download.check()?;
+    // The probes have to know whether the client checks the pins itself.
+    let mut config = config.clone();
+    let client = blocking_client(&mut config)?;
+    let thread_count = min(config.concurrency.max(1), requests.len());
+    Ok(probe_all(&client, requests, &config, thread_count))
+}
+
+/// Asks about every request on up to `thread_count` threads; see [`preflight`].
+pub(crate) fn probe_all(
+    client: &Client,
+    requests: &[DownloadRequest],
+    config: &BatchConfig,
+    thread_count: usize,
+) -> Vec<Result<RemoteFile, DownloadError>> {
+    let hosts = HostLimiter::new(config.max_connections_per_host);
+    // Nothing cancels a preflight; it only lets the host limiter be waited on.
+    let control = Control::default();
+    let found = Mutex::new((0..requests.len()).map(|_| None).collect::<Vec<_>>());
+    let next = AtomicUsize::new(0);
+
+    std::thread::scope(|scope| {
+        for _ in 0..thread_count {
+            scope.spawn(|| loop {
+                // Claim the next request; the thread is done once every request is claimed.
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(request) = requests.get(index) else {
+                    break;
+                };
+                let _slot = match (&hosts, host_key(&request.url)) {
+                    (Some(hosts), Some(host)) => hosts.acquire(&host, &control),
+                    _ => None,
+                };
+                let result = probe(
+                    client,
+                    &request.url,
+                    &request.effective_config(&config.download),
+                    config.cookies.as_ref(),
+                );
+                found.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    found
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every request is probed"))
+        .collect()
+}
@@ -0,0 +1,251 @@
+use crate::backend::HttpResponse;
+use crate::context::Context;
+use crate::error::DownloadError;
+use crate::event::DownloadEvent;
+use crate::url_check::check_url;
+use http::header::HeaderMap;
+use http::StatusCode;
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// How much of an error body is read to tell an expired signature from another refusal.
+/// Storage services answer with a short XML document.
+const BODY_LIMIT: u64 = 8 * 1024;
+
+/// Returned when a pre-signed URL, such as an S3 or GCS signed URL, was refused because its
+/// signature expired.
+///
+/// A URL counts as pre-signed when its query carries `X-Amz-Signature`, `X-Amz-Expires`,
+/// `X-Goog-Signature`, `X-Goog-Expires`, or both `Expires` and `Signature`. A `403` or `400`
+/// answer to one is reported as expired when its body says so, as the XML errors of storage
+/// services do, or when the expiry the query encodes has passed. It is never retried as is,
+/// but a [`crate::BatchConfig::refresh_url`] can mint a fresh URL for another attempt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UrlExpired {
+    /// The URL that was refused.
+    pub url: String,
+    /// When the signature expired, if the URL tells.
+    pub expired_at: Option<SystemTime>,
+}
+
+impl fmt::Display for UrlExpired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the pre-signed URL {} has expired", self.url)?;
+        if let Some(expired_at) = self.expired_at {
+            write!(f, " (at {})", httpdate::fmt_http_date(expired_at))?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for UrlExpired {}
+
+/// Maps an expired URL to a fresh one, for [`UrlRefresher`].
+type RefreshFn = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+/// Mints a fresh URL for a download whose pre-signed URL expired, for
+/// [`crate::BatchConfig::refresh_url`].
+///
+/// The function receives the expired URL and returns the URL to use instead, or `None` to
+/// let the download fail with [`DownloadError::UrlExpired`]. It is called from the worker
+/// running the download, so it may block, for example to ask a service for a new signature.
+/// Clones share the same function.
+#[derive(Clone)]
+pub struct UrlRefresher {
+    /// Maps an expired URL to a fresh one.
+    refresh: RefreshFn,
+}
+
+impl fmt::Debug for UrlRefresher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UrlRefresher").finish_non_exhaustive()
+    }
+}
+
+impl UrlRefresher {
+    /// Creates a refresher calling `refresh` with every URL that expired.
+    pub fn new(refresh: impl Fn(&str) -> Option<String> + Send + Sync + 'static) -> Self {
+        Self {
+            refresh: Arc::new(refresh),
+        }
+    }
+
+    /// Returns the fresh URL to use instead of the expired `url`, if one can be minted.
+    pub(crate) fn refresh(&self, url: &str) -> Option<String> {
+        (self.refresh)(url)
+    }
+}
+
+/// Replaces the URL that `expired` with a fresh one from the refresher of `context`, and
+/// reports it through `callback`.
+///
+/// # Returns
+///
+/// * `Ok` with the fresh URL.
+/// * `Err` with the [`UrlExpired`] if the batch has no refresher or it minted no URL, or with
+///   an [`crate::InvalidUrl`] if the fresh URL is invalid.
+pub(crate) fn refresh(
+    expired: UrlExpired,
+    context: &Context,
+    callback: &impl Fn(&DownloadEvent),
+) -> Result<String, DownloadError> {
+    let fresh = context
+        .refresh_url
+        .as_ref()
+        .and_then(|refresher| refresher.refresh(&expired.url));
+    let Some(fresh) = fresh else {
+        return Err(expired.into());
+    };
+    check_url(&fresh)?;
+    callback(&DownloadEvent::UrlRefreshed {
+        from: expired.url,
+        to: fresh.clone(),
+    });
+    Ok(fresh)
+}
+
+/// Returns `true` if the error `status` answering `url` may be an expired signature, in which
+/// case its body is worth reading.
+pub(crate) fn may_have_expired(url: &str, status: StatusCode) -> bool {
+    matches!(status, StatusCode::FORBIDDEN | StatusCode::BAD_REQUEST) && is_presigned(url)
+}
+
+/// Tells whether the error `body` answering the pre-signed `url` reports an expired signature.
+pub(crate) fn expired(url: &str, body: &[u8]) -> Option<UrlExpired> {
+    let expired_at = expiry(url);
+    let says_expired = String::from_utf8_lossy(body)
+        .to_ascii_lowercase()
+        .contains("expired");
+    let past = expired_at.is_some_and(|expired_at| expired_at <= SystemTime::now());
+    (says_expired || past).then(|| UrlExpired {
+        url: url.to_string(),
+        expired_at,
+    })
+}
+
+/// Describes the error status of `response` to a request for `url`, reading the start of its
+/// body to recognize an expired pre-signed URL.
+pub(crate) fn status_error(url: &str, response: HttpResponse) -> DownloadError {
+    let status = response.status;
+    if may_have_expired(url, status) {
+        let mut body = Vec::new();
+        // An unreadable body leaves only the expiry of the URL to go by.
+        let _ = response.body.take(BODY_LIMIT).read_to_end(&mut body);
+        if let Some(expired) = expired(url, &body) {
+            return expired.into();
+        }
+    }
+    DownloadError::status(url, status, &response.headers)
+}
+
+/// Describes the error status of an async `response` to a request for `url`, like
+/// [`status_error`].
+#[cfg(feature = "async")]
+pub(crate) async fn status_error_async(
+    url: &str,
+    mut response: reqwest::Response,
+) -> DownloadError {
+    let status = response.status();
+    if may_have_expired(url, status) {
+        let mut body = Vec::new();
+        while (body.len() as u64) < BODY_LIMIT {
+            match response.chunk().await {
+                Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+                _ => break,
+            }
+        }
+        if let Some(expired) = expired(url, &body) {
+            return expired.into();
+        }
+    }
+    DownloadError::status(url, status, response.headers())
+}
+
+/// Like [`status_error`] for a response whose body is already gone, so only the expiry of the
+/// URL can tell.
+pub(crate) fn status_error_without_body(
+    url: &str,
+    status: StatusCode,
+    headers: &HeaderMap,
+) -> DownloadError {
+    match expired(url, b"").filter(|_| may_have_expired(url, status)) {
+        Some(expired) => expired.into(),
+        None => DownloadError::status(url, status, headers),
+    }
+}
+
+/// Returns `true` if the query of `url` carries the signature of a pre-signed URL.
+fn is_presigned(url: &str) -> bool {
+    let Ok(url) = Url::parse(url) else {
+        return false;
+    };
+    let has = |name: &str| {
+        url.query_pairs()
+            .any(|(key, _)| key.eq_ignore_ascii_case(name))
+    };
+    has("X-Amz-Signature")
+        || has("X-Amz-Expires")
+        || has("X-Goog-Signature")
+        || has("X-Goog-Expires")
+        || (has("Expires") && has("Signature"))
+}
+
+/// Reads when the signature of `url` expires from its query: `X-Amz-Date` plus
+/// `X-Amz-Expires` seconds, the same for `X-Goog-`, or the Unix time of `Expires`.
+fn expiry(url: &str) -> Option<SystemTime> {
+    let url = Url::parse(url).ok()?;
+    let value = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.into_owned())
+    };
+    for prefix in ["X-Amz-", "X-Goog-"] {
+        let signed = value(&format!("{}Date", prefix)).and_then(|date| parse_basic_time(&date));
+        let lifetime = value(&format!("{}Expires", prefix)).and_then(|secs| secs.parse().ok());
+        if let (Some(signed), Some(lifetime)) = (signed, lifetime) {
+            return Some(signed + Duration::from_secs(lifetime));
+        }
+    }
+    let expires: u64 = value("Expires")?.parse().ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(expires))
+}
+
+/// Parses a UTC time in the `20240131T235959Z` form signatures are dated with.
+fn parse_basic_time(text: &str) -> Option<SystemTime> {
+    let text = text.strip_suffix('Z')?;
+    let (date, time) = text.split_once('T')?;
+    if date.len() != 8 || time.len() != 6 || !text.is_ascii() {
+        return None;
+    }
+    let number = |digits: &str| digits.parse::<u64>().ok();
+    let (year, month, day) = (
+        number(&date[..4])?,
+        number(&date[4..6])?,
+        number(&date[6..])?,
+    );
+    let (hour, minute, second) = (
+        number(&time[..2])?,
+        number(&time[2..4])?,
+        number(&time[4..])?,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Count the days since 1970 with March as the first month, so leap days come last.
+    let (year, month) = if month <= 2 {
+        (year.checked_sub(1)?, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146_097 + day_of_era).checked_sub(719_468)?;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86_400 + hour * 3_600 + minute * 60 + second))
+}
@@ -0,0 +1,18 @@
+use serde::Deserialize;
+
+/// How urgently a request in a batch should be downloaded.
+///
+/// Workers always start the highest-priority request that is waiting, and requests of equal
+/// priority in the order they were queued. A running download is never interrupted for a more
+/// urgent one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Downloaded once nothing more urgent is waiting.
+    Low,
+    /// The default priority.
+    #[default]
+    Normal,
+    /// Downloaded before everything else that is waiting.
+    High,
+}
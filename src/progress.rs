@@ -0,0 +1,230 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Struct representing the progress of a file download.
+///
+/// Its values are read through methods, so new ones can be added without breaking callers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadCallbackProgress {
+    /// The position of the request in its batch.
+    pub(crate) id: u64,
+    /// The URL being downloaded.
+    pub(crate) url: Arc<str>,
+    /// Total bytes downloaded so far.
+    pub(crate) bytes_downloaded: u64,
+    /// Total size of the file in bytes, if known.
+    pub(crate) total_bytes: Option<u64>,
+    /// Time since the request for this download was sent.
+    pub(crate) elapsed: Duration,
+    /// Average transfer speed since the request was sent, in bytes per second.
+    pub(crate) bytes_per_second: f64,
+    /// Transfer speed since the previous progress event, in bytes per second.
+    pub(crate) instant_bytes_per_sec: f64,
+    /// Transfer speed over the last [`crate::DownloadConfig::speed_window`], in bytes per
+    /// second.
+    pub(crate) smoothed_bytes_per_sec: f64,
+    /// Estimated time until the download completes, if known.
+    pub(crate) eta: Option<Duration>,
+}
+
+impl DownloadCallbackProgress {
+    /// Creates a new progress snapshot without timing information, for an unnamed download
+    /// with the id `0`.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes_downloaded` - The number of bytes downloaded so far.
+    /// * `total_bytes` - The total size of the file in bytes, or `None` if unknown.
+    pub fn new(bytes_downloaded: u64, total_bytes: Option<u64>) -> Self {
+        Self {
+            id: 0,
+            url: Arc::from(""),
+            bytes_downloaded,
+            total_bytes,
+            elapsed: Duration::ZERO,
+            bytes_per_second: 0.0,
+            instant_bytes_per_sec: 0.0,
+            smoothed_bytes_per_sec: 0.0,
+            eta: None,
+        }
+    }
+
+    /// Creates a progress snapshot, deriving the speed and ETA from the elapsed time, for an
+    /// unnamed download with the id `0`.
+    ///
+    /// Without samples to go by, the instant and smoothed speeds are the average speed, and
+    /// the ETA follows it.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes_downloaded` - The number of bytes downloaded so far.
+    /// * `total_bytes` - The total size of the file in bytes, or `None` if unknown.
+    /// * `resumed_bytes` - Bytes that were already on disk before this transfer started.
+    /// * `elapsed` - Time since the request was sent.
+    pub fn with_timing(
+        bytes_downloaded: u64,
+        total_bytes: Option<u64>,
+        resumed_bytes: u64,
+        elapsed: Duration,
+    ) -> Self {
+        // Only bytes received during this transfer count towards the speed.
+        let transferred = bytes_downloaded.saturating_sub(resumed_bytes);
+        let seconds = elapsed.as_secs_f64();
+        let bytes_per_second = if seconds > 0.0 {
+            transferred as f64 / seconds
+        } else {
+            0.0
+        };
+
+        // Without a known total or any measurable speed there is nothing to estimate from.
+        let eta = total_bytes.filter(|_| bytes_per_second > 0.0).map(|total| {
+            let remaining = total.saturating_sub(bytes_downloaded);
+            Duration::from_secs_f64(remaining as f64 / bytes_per_second)
+        });
+
+        Self {
+            id: 0,
+            url: Arc::from(""),
+            bytes_downloaded,
+            total_bytes,
+            elapsed,
+            bytes_per_second,
+            instant_bytes_per_sec: bytes_per_second,
+            smoothed_bytes_per_sec: bytes_per_second,
+            eta,
+        }
+    }
+
+    /// Identifies the download within its batch, so one callback can tell the files of a
+    /// batch apart. It is the position of the request in the batch, the same number
+    /// [`crate::DownloadResult::id`] and [`crate::BatchConfig::on_file_event`] carry. Always `0`
+    /// outside a batch.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the URL being downloaded, which is one of its mirrors once the download failed
+    /// over.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Returns the total bytes downloaded so far.
+    pub fn bytes_downloaded(&self) -> u64 {
+        self.bytes_downloaded
+    }
+
+    /// Returns the total size of the file in bytes, or `None` if the server did not report it,
+    /// as with chunked transfers.
+    pub fn total_bytes(&self) -> Option<u64> {
+        self.total_bytes
+    }
+
+    /// Returns the time since the request for this download was sent.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Returns the average transfer speed since the request was sent, in bytes per second.
+    ///
+    /// Bytes that were already on disk from a resumed download are not counted.
+    pub fn bytes_per_second(&self) -> f64 {
+        self.bytes_per_second
+    }
+
+    /// Returns the transfer speed since the previous progress event, in bytes per second.
+    ///
+    /// It follows every burst and stall of the connection, so it suits a live readout more
+    /// than an estimate; see [`DownloadCallbackProgress::smoothed_bytes_per_sec`] for that.
+    pub fn instant_bytes_per_sec(&self) -> f64 {
+        self.instant_bytes_per_sec
+    }
+
+    /// Returns the transfer speed over the last [`crate::DownloadConfig::speed_window`], in
+    /// bytes per second.
+    ///
+    /// Steadier than the instant speed, while still following changes of speed that the
+    /// average since the start hides. It falls towards zero while the download stalls.
+    pub fn smoothed_bytes_per_sec(&self) -> f64 {
+        self.smoothed_bytes_per_sec
+    }
+
+    /// Returns the estimated time until the download completes, derived from the smoothed
+    /// speed, or `None` when the total size or the speed is not known yet, and while the
+    /// download stalls.
+    pub fn eta(&self) -> Option<Duration> {
+        self.eta
+    }
+
+    /// Returns `true` if the total size is known, so a determinate progress bar can be shown.
+    pub fn is_total_known(&self) -> bool {
+        self.total_bytes.is_some()
+    }
+
+    /// Returns how much of the file has been downloaded, from `0.0` to `1.0`.
+    ///
+    /// Returns `None` when the total size is unknown; render an indeterminate progress bar
+    /// from [`DownloadCallbackProgress::bytes_downloaded`] instead. An empty file counts as
+    /// complete.
+    pub fn fraction(&self) -> Option<f64> {
+        self.total_bytes.map(|total| {
+            if total == 0 {
+                1.0
+            } else {
+                (self.bytes_downloaded as f64 / total as f64).min(1.0)
+            }
+        })
+    }
+
+    /// Returns how much of the file has been downloaded as a percentage from `0.0` to `100.0`,
+    /// or `None` when the total size is unknown.
+    pub fn percent(&self) -> Option<f64> {
+        self.fraction().map(|fraction| fraction * 100.0)
+    }
+
+    /// Returns how many bytes are left to download, or `None` when the total size is unknown.
+    ///
+    /// A server sending more than it announced leaves nothing remaining rather than a negative
+    /// count.
+    pub fn remaining_bytes(&self) -> Option<u64> {
+        self.total_bytes
+            .map(|total| total.saturating_sub(self.bytes_downloaded))
+    }
+
+    /// Returns `true` once every byte of a file of known size has been downloaded, including an
+    /// empty file. Always `false` when the total size is unknown, since only the final
+    /// [`crate::DownloadEvent::Completed`] tells such downloads are done.
+    pub fn is_complete(&self) -> bool {
+        self.remaining_bytes() == Some(0)
+    }
+}
+
+/// Limits how often [`crate::DownloadEvent::Progress`] is sent for a single file.
+///
+/// A progress event is sent once at least `min_interval` has passed since the previous one
+/// and at least `min_bytes` more bytes have arrived. The final progress of a completed file is
+/// always reported with its exact byte count, however soon it follows the previous event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressThrottle {
+    /// Minimum time between two progress events. Defaults to 100 ms.
+    pub min_interval: Duration,
+    /// Minimum number of bytes downloaded between two progress events. Defaults to 0.
+    pub min_bytes: u64,
+}
+
+impl ProgressThrottle {
+    /// Reports every chunk as it is written.
+    pub const NONE: Self = Self {
+        min_interval: Duration::ZERO,
+        min_bytes: 0,
+    };
+}
+
+impl Default for ProgressThrottle {
+    fn default() -> Self {
+        Self {
+            min_interval: Duration::from_millis(100),
+            min_bytes: 0,
+        }
+    }
+}
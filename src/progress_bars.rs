@@ -0,0 +1,237 @@
+use crate::batch_progress::BatchProgress;
+use crate::config::BatchConfig;
+use crate::event::DownloadEvent;
+use crate::naming::file_name_from_url;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Renders a batch as indicatif progress bars: one bar per active download plus a summary bar
+/// for the whole batch.
+///
+/// Files of unknown size are shown as spinners. At most `max_visible` file bars are drawn at
+/// once; downloads started beyond that are only counted in the summary bar's message, which
+/// keeps batches of thousands of files readable. Bars are drawn to stderr and hidden when it
+/// is not a terminal.
+///
+/// Only available with the `progress-bars` feature.
+///
+/// ```no_run
+/// use parallel_downloads::{download_batch_with_config, BatchConfig, ProgressBars};
+///
+/// let bars = ProgressBars::new(10);
+/// let mut config = BatchConfig::default();
+/// bars.attach(&mut config);
+///
+/// let results = download_batch_with_config(
+///     vec!["https://www.rust-lang.org/static/images/rust-logo-blk.svg"],
+///     config,
+///     |_| {},
+/// );
+/// bars.finish();
+/// ```
+pub struct ProgressBars {
+    /// Draws every bar below each other.
+    multi: MultiProgress,
+    /// Shows the bytes and files of the whole batch.
+    summary: ProgressBar,
+    /// The bars of the downloads that are currently running.
+    files: Mutex<FileBars>,
+    /// Maximum number of file bars drawn at the same time.
+    max_visible: usize,
+}
+
+/// The downloads currently running, by their index in the batch.
+#[derive(Default)]
+struct FileBars {
+    /// Downloads that have a bar of their own.
+    visible: HashMap<usize, ProgressBar>,
+    /// Downloads started while every bar was taken, which are only counted.
+    hidden: HashSet<usize>,
+    /// Finished and total files of the batch, as last shown in the summary bar.
+    counts: String,
+}
+
+impl fmt::Debug for ProgressBars {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressBars")
+            .field("max_visible", &self.max_visible)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ProgressBars {
+    /// Creates the summary bar and prepares to draw up to `max_visible` file bars.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_visible` - The maximum number of file bars drawn at once. `0` only shows the
+    ///   summary bar.
+    pub fn new(max_visible: usize) -> Arc<Self> {
+        let multi = MultiProgress::new();
+        let summary = multi.add(ProgressBar::new(0).with_style(summary_style()));
+        Arc::new(Self {
+            multi,
+            summary,
+            files: Mutex::new(FileBars::default()),
+            max_visible,
+        })
+    }
+
+    /// Routes the batch's events to these bars.
+    ///
+    /// Sets [`BatchConfig::on_file_event`] and [`BatchConfig::on_batch_progress`]. Callbacks
+    /// that were already configured keep receiving their events.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The settings of the batch to display.
+    pub fn attach(self: &Arc<Self>, config: &mut BatchConfig) {
+        let bars = Arc::clone(self);
+        let previous = config.on_file_event.take();
+        config.on_file_event = Some(Arc::new(move |index, event| {
+            if let Some(previous) = &previous {
+                previous(index, event);
+            }
+            bars.file_event(index, event);
+        }));
+
+        let bars = Arc::clone(self);
+        let previous = config.on_batch_progress.take();
+        config.on_batch_progress = Some(Arc::new(move |progress| {
+            if let Some(previous) = &previous {
+                previous(progress);
+            }
+            bars.batch_progress(progress);
+        }));
+    }
+
+    /// Removes every file bar and leaves the summary bar showing the final totals.
+    ///
+    /// Call this once the batch has returned, including when it was cancelled, so bars of
+    /// downloads that never reported a final event don't linger.
+    pub fn finish(&self) {
+        let mut files = self.files.lock().unwrap();
+        for (_, bar) in files.visible.drain() {
+            bar.finish_and_clear();
+            self.multi.remove(&bar);
+        }
+        files.hidden.clear();
+        self.summary.abandon();
+    }
+
+    /// Updates the bar of the download at `index`.
+    fn file_event(&self, index: usize, event: &DownloadEvent) {
+        let mut files = self.files.lock().unwrap();
+        match event {
+            DownloadEvent::Started { url, total_bytes } => {
+                // A retried download starts over on the bar it already has.
+                if let Some(bar) = files.visible.get(&index) {
+                    reset(bar, *total_bytes);
+                } else if !files.hidden.contains(&index) {
+                    if files.visible.len() < self.max_visible {
+                        let bar = self.multi.add(ProgressBar::new(0));
+                        bar.set_prefix(file_name_from_url(url).unwrap_or_else(|| url.clone()));
+                        reset(&bar, *total_bytes);
+                        files.visible.insert(index, bar);
+                    } else {
+                        files.hidden.insert(index);
+                    }
+                }
+            }
+            DownloadEvent::Progress(progress) => {
+                if let Some(bar) = files.visible.get(&index) {
+                    if let Some(total) = progress.total_bytes {
+                        bar.set_length(total);
+                    }
+                    bar.set_position(progress.bytes_downloaded);
+                }
+            }
+            DownloadEvent::Completed { .. }
+            | DownloadEvent::Failed { .. }
+            | DownloadEvent::Skipped { .. }
+            | DownloadEvent::NotModified { .. }
+            | DownloadEvent::Cancelled { .. } => {
+                files.hidden.remove(&index);
+                if let Some(bar) = files.visible.remove(&index) {
+                    bar.finish_and_clear();
+                    self.multi.remove(&bar);
+                }
+            }
+            DownloadEvent::Redirected { .. }
+            | DownloadEvent::MirrorFailover { .. }
+            | DownloadEvent::UrlRefreshed { .. }
+            | DownloadEvent::PinMismatch { .. }
+            | DownloadEvent::Throttled { .. }
+            | DownloadEvent::ConcurrencyChanged { .. }
+            | DownloadEvent::ResumeRestarted { .. }
+            | DownloadEvent::Retrying { .. } => {}
+        }
+        self.summary.set_message(summary_message(&files));
+    }
+
+    /// Updates the summary bar with the totals of the batch.
+    fn batch_progress(&self, progress: &BatchProgress) {
+        let done = progress.files_completed + progress.files_failed + progress.files_skipped;
+        self.summary.set_length(progress.total_bytes);
+        self.summary.set_position(progress.bytes_downloaded);
+
+        let mut files = self.files.lock().unwrap();
+        files.counts = format!("{}/{} files", done, progress.total_files);
+        self.summary.set_message(summary_message(&files));
+
+        if done == progress.total_files {
+            self.summary.finish();
+        }
+    }
+}
+
+/// Restarts `bar` for a download of `total_bytes`, turning it into a spinner when the size is
+/// unknown.
+fn reset(bar: &ProgressBar, total_bytes: Option<u64>) {
+    match total_bytes {
+        Some(total) => {
+            bar.set_style(file_style());
+            bar.set_length(total);
+        }
+        None => {
+            bar.set_style(spinner_style());
+            bar.unset_length();
+        }
+    }
+    bar.reset();
+}
+
+/// Combines the file counts of the summary bar with the number of downloads without a bar.
+fn summary_message(files: &FileBars) -> String {
+    match files.hidden.len() {
+        0 => files.counts.clone(),
+        hidden => format!("{} (+{} more)", files.counts, hidden),
+    }
+}
+
+/// Style of the bar for the whole batch.
+fn summary_style() -> ProgressStyle {
+    // The templates are fixed, so they always parse.
+    ProgressStyle::with_template(
+        "{bar:40.green/blue} {bytes}/{total_bytes} {bytes_per_sec} ETA {eta} {msg}",
+    )
+    .expect("summary template is valid")
+    .progress_chars("=> ")
+}
+
+/// Style of the bar of a download whose size is known.
+fn file_style() -> ProgressStyle {
+    ProgressStyle::with_template(
+        "{prefix:30!} {bar:30.cyan/blue} {bytes}/{total_bytes} {bytes_per_sec} ETA {eta}",
+    )
+    .expect("file template is valid")
+    .progress_chars("=> ")
+}
+
+/// Style of the spinner of a download whose size is unknown.
+fn spinner_style() -> ProgressStyle {
+    ProgressStyle::with_template("{prefix:30!} {spinner} {bytes} {bytes_per_sec}")
+        .expect("spinner template is valid")
+}
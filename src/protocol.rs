@@ -0,0 +1,80 @@
+use http::Version;
+use std::fmt;
+
+/// Which HTTP versions the batch's client speaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HttpVersion {
+    /// HTTP/1.1, or HTTP/2 when an `https://` server offers it during the TLS handshake.
+    #[default]
+    Negotiate,
+    /// HTTP/1.1 only, with a connection per download in flight.
+    Http1Only,
+    /// HTTP/2 from the first byte, without negotiating it first, which also works over plain
+    /// `http://`. Downloads from servers that don't speak HTTP/2 fail.
+    Http2PriorKnowledge,
+}
+
+/// HTTP protocol settings of the batch's client.
+///
+/// Over HTTP/2, every download from the same origin shares a single connection as streams
+/// multiplexed over it. The server announces how many streams it accepts per connection;
+/// [`crate::BatchConfig::max_connections_per_host`] caps how many downloads the batch runs
+/// against a host at once, and so the streams it opens. The async API runs every download of
+/// a batch as a future on the caller's runtime, so hundreds of streams need no thread each.
+/// Clients passed in by the caller keep their own settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HttpConfig {
+    /// Which HTTP versions are spoken. Defaults to [`HttpVersion::Negotiate`].
+    pub version: HttpVersion,
+    /// Bytes a single HTTP/2 stream may receive before the client acknowledges them. `None`
+    /// keeps the default of the HTTP/2 implementation.
+    pub http2_stream_window: Option<u32>,
+    /// Bytes all HTTP/2 streams of a connection together may receive before the client
+    /// acknowledges them. `None` keeps the default of the HTTP/2 implementation.
+    pub http2_connection_window: Option<u32>,
+    /// Grows the HTTP/2 windows with the measured bandwidth of the connection, overriding
+    /// the fixed window sizes. Defaults to `false`.
+    pub http2_adaptive_window: bool,
+}
+
+/// The HTTP version a file was served with, as reported by [`crate::DownloadOutcome`] and
+/// [`crate::StreamedBody`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum ProtocolVersion {
+    /// HTTP/0.9.
+    Http09,
+    /// HTTP/1.0.
+    Http10,
+    /// HTTP/1.1.
+    Http11,
+    /// HTTP/2, with downloads from the same origin multiplexed over one connection.
+    Http2,
+    /// HTTP/3.
+    Http3,
+}
+
+impl From<Version> for ProtocolVersion {
+    fn from(version: Version) -> Self {
+        match version {
+            Version::HTTP_09 => ProtocolVersion::Http09,
+            Version::HTTP_10 => ProtocolVersion::Http10,
+            Version::HTTP_2 => ProtocolVersion::Http2,
+            Version::HTTP_3 => ProtocolVersion::Http3,
+            // `http` has no other versions, but doesn't let a match say so.
+            _ => ProtocolVersion::Http11,
+        }
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ProtocolVersion::Http09 => "HTTP/0.9",
+            ProtocolVersion::Http10 => "HTTP/1.0",
+            ProtocolVersion::Http11 => "HTTP/1.1",
+            ProtocolVersion::Http2 => "HTTP/2.0",
+            ProtocolVersion::Http3 => "HTTP/3.0",
+        })
+    }
+}
@@ -0,0 +1,157 @@
+use crate::error::DownloadError;
+#[cfg(feature = "reqwest")]
+use reqwest::{NoProxy, Proxy};
+#[cfg(feature = "reqwest")]
+use std::error::Error;
+#[cfg(feature = "reqwest")]
+use std::fmt;
+#[cfg(feature = "reqwest")]
+use url::Url;
+
+/// Proxy settings used when a batch builds its HTTP client. ureq clients only apply the
+/// environment's proxy or one proxy for both schemes, as `UreqBackend::new` describes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyConfig {
+    /// Proxy for `http://` URLs, such as `http://proxy.local:3128`.
+    pub http: Option<String>,
+    /// Proxy for `https://` URLs.
+    pub https: Option<String>,
+    /// SOCKS5 proxy (`socks5://` or `socks5h://`) for all traffic not covered by
+    /// [`ProxyConfig::http`] or [`ProxyConfig::https`].
+    pub socks5: Option<String>,
+    /// Hosts, domains, or IP ranges that are always reached directly, in the same format as the
+    /// `NO_PROXY` environment variable.
+    pub no_proxy: Vec<String>,
+    /// Honour the `HTTP_PROXY`, `HTTPS_PROXY`, and `NO_PROXY` environment variables.
+    ///
+    /// Only consulted when no proxy is configured explicitly. Defaults to `true`.
+    pub use_env: bool,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            http: None,
+            https: None,
+            socks5: None,
+            no_proxy: Vec::new(),
+            use_env: true,
+        }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl ProxyConfig {
+    /// Returns `true` if at least one proxy is configured explicitly.
+    fn is_explicit(&self) -> bool {
+        self.http.is_some() || self.https.is_some() || self.socks5.is_some()
+    }
+
+    /// Builds the proxies to register with the client, in the order they are matched.
+    ///
+    /// Returns `None` when the client should keep its default environment-based behaviour and
+    /// an empty list when all proxies, including those from the environment, are disabled.
+    pub(crate) fn proxies(&self) -> reqwest::Result<Option<Vec<Proxy>>> {
+        if !self.is_explicit() {
+            return Ok(if self.use_env { None } else { Some(Vec::new()) });
+        }
+
+        let no_proxy = NoProxy::from_string(&self.no_proxy.join(","));
+        let mut proxies = Vec::new();
+        if let Some(url) = &self.http {
+            proxies.push(Proxy::http(url)?.no_proxy(no_proxy.clone()));
+        }
+        if let Some(url) = &self.https {
+            proxies.push(Proxy::https(url)?.no_proxy(no_proxy.clone()));
+        }
+        // Registered last so the scheme-specific proxies take precedence.
+        if let Some(url) = &self.socks5 {
+            proxies.push(Proxy::all(url)?.no_proxy(no_proxy));
+        }
+        Ok(Some(proxies))
+    }
+
+    /// Returns the explicitly configured proxy that a request to `url` is sent through.
+    fn proxy_for(&self, url: &str) -> Option<&str> {
+        let scheme_proxy = match Url::parse(url).ok()?.scheme() {
+            "http" => self.http.as_deref(),
+            "https" => self.https.as_deref(),
+            _ => None,
+        };
+        scheme_proxy.or(self.socks5.as_deref())
+    }
+}
+
+/// Returned when a download could not connect to its configured proxy. Only with the
+/// `reqwest` feature.
+#[cfg(feature = "reqwest")]
+#[derive(Debug)]
+pub struct ProxyUnreachable {
+    /// The proxy that was used, with any credentials removed.
+    pub proxy: String,
+    /// The underlying connection error.
+    pub source: reqwest::Error,
+}
+
+#[cfg(feature = "reqwest")]
+impl fmt::Display for ProxyUnreachable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "could not connect to proxy {}: {}",
+            self.proxy, self.source
+        )
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl Error for ProxyUnreachable {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Reports connection failures of a proxied request as a [`ProxyUnreachable`].
+///
+/// Every connection of a proxied request goes to the proxy, so a connect error means the proxy
+/// itself could not be reached. Other errors are returned unchanged.
+#[cfg(feature = "reqwest")]
+pub(crate) fn explain_proxy_error(
+    error: DownloadError,
+    url: &str,
+    config: &ProxyConfig,
+) -> DownloadError {
+    match (config.proxy_for(url), error) {
+        (Some(proxy), DownloadError::Request { source, .. }) if source.is_connect() => {
+            ProxyUnreachable {
+                proxy: redact(proxy),
+                source,
+            }
+            .into()
+        }
+        (_, error) => error,
+    }
+}
+
+/// Returns `error` unchanged, since only reqwest clients connect through proxies.
+#[cfg(not(feature = "reqwest"))]
+pub(crate) fn explain_proxy_error(
+    error: DownloadError,
+    _url: &str,
+    _config: &ProxyConfig,
+) -> DownloadError {
+    error
+}
+
+/// Strips the user name and password from a proxy URL so it can be shown in errors.
+#[cfg(feature = "reqwest")]
+fn redact(proxy: &str) -> String {
+    match Url::parse(proxy) {
+        Ok(mut url) => {
+            let _ = url.set_username("");
+            let _ = url.set_password(None);
+            url.to_string()
+        }
+        Err(_) => "<invalid proxy URL>".to_string(),
+    }
+}
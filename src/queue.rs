@@ -0,0 +1,339 @@
+use crate::backlog::Backlog;
+use crate::control::Control;
+use crate::error::DownloadError;
+use crate::host_limit::{host_key, HostLimiter, HostSlot};
+use crate::overwrite::OverwritePolicy;
+use crate::priority::Priority;
+use crate::request::{DownloadRequest, UniqueDestinations};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Most requests read ahead of a stream because their host is busy, before workers wait for a
+/// free slot instead of reading further.
+const MAX_READ_AHEAD: usize = 1024;
+
+/// How long a worker waits for a host slot or a new request before checking for cancellation
+/// again.
+const POLL: Duration = Duration::from_millis(100);
+
+/// A request taken from the queue, its position in the batch, the slot it holds on its host,
+/// if hosts are limited, and when it was queued.
+pub(crate) type Job<'h> = (usize, DownloadRequest, Option<HostSlot<'h>>, Instant);
+
+/// Where the requests of a [`WorkQueue`] come from.
+enum Source {
+    /// A fixed batch or a lazily read stream.
+    Iter(Box<dyn Iterator<Item = Result<DownloadRequest, DownloadError>> + Send>),
+    /// Requests enqueued on a [`crate::Downloader`] while the batch runs, with when they were
+    /// enqueued.
+    Channel(Receiver<(DownloadRequest, Instant)>),
+}
+
+/// The outcome of asking a [`Source`] for its next request.
+enum Pull {
+    /// The next request and its position in the batch, boxed as requests are large.
+    Request(usize, Box<DownloadRequest>),
+    /// The stream failed and no more requests are taken from it.
+    Failed(DownloadError),
+    /// No request is available yet, but more may still arrive.
+    Pending,
+    /// Every request has been taken.
+    Drained,
+}
+
+/// The requests a blocking batch's workers pull from: fully known up front, streamed, or
+/// enqueued while the batch runs.
+///
+/// Workers share the queue behind a lock that is held while picking the next request. Waiting
+/// requests are started by [`Priority`], and in the order they were queued within the same
+/// priority. With a per-host limit, requests whose host is busy are held back while requests
+/// for other hosts go ahead of them.
+///
+/// A fixed batch and a channel are read in full as they arrive, so priorities apply across
+/// everything queued. A stream is only read ahead while hosts are busy, so its priorities
+/// only order the requests read so far.
+pub(crate) struct WorkQueue {
+    /// The requests that haven't been taken yet.
+    source: Source,
+    /// Position in the batch of the next request.
+    next_index: usize,
+    /// Destinations of the streamed requests taken so far. `None` when the requests were
+    /// checked for duplicates before they were queued.
+    destinations: Option<UniqueDestinations>,
+    /// Set once the stream failed or ran dry, so no more requests are taken from it.
+    exhausted: bool,
+    /// Requests waiting for a worker, most urgent first and then in batch order.
+    pending: BTreeMap<(Reverse<Priority>, usize), DownloadRequest>,
+    /// Number of requests in a batch known up front, or `None` for a stream or channel.
+    total: Option<usize>,
+    /// Requests held back until an identical request is done, by that request's position.
+    repeats: HashMap<usize, Vec<(usize, DownloadRequest)>>,
+    /// When each request that hasn't started yet was queued, by its position.
+    queued: HashMap<usize, Instant>,
+    /// The places of a channel's requests, given back as they start.
+    backlog: Option<Arc<Backlog>>,
+}
+
+impl WorkQueue {
+    /// Queues a batch whose requests have already been checked.
+    pub(crate) fn new(requests: Vec<DownloadRequest>) -> Self {
+        let mut queue = Self::from_source(Source::Iter(Box::new(std::iter::empty())), None);
+        for (index, request) in requests.into_iter().enumerate() {
+            queue.hold(index, request);
+        }
+        queue.next_index = queue.pending.len();
+        queue.total = Some(queue.next_index);
+        queue
+    }
+
+    /// Queues requests that are read from `requests` only as workers become free, renaming
+    /// requests that share a destination when they, or the batch's `overwrite`, say so.
+    pub(crate) fn streaming(
+        requests: impl Iterator<Item = Result<DownloadRequest, DownloadError>> + Send + 'static,
+        overwrite: OverwritePolicy,
+    ) -> Self {
+        Self::from_source(
+            Source::Iter(Box::new(requests)),
+            Some(UniqueDestinations::new(overwrite)),
+        )
+    }
+
+    /// Queues requests sent to `receiver`, which have already been checked, until every sender
+    /// is dropped, giving back each request's place in `backlog` once it starts.
+    pub(crate) fn channel(
+        receiver: Receiver<(DownloadRequest, Instant)>,
+        backlog: Arc<Backlog>,
+    ) -> Self {
+        let mut queue = Self::from_source(Source::Channel(receiver), None);
+        queue.backlog = Some(backlog);
+        queue
+    }
+
+    /// Creates an empty queue reading from `source`.
+    fn from_source(source: Source, destinations: Option<UniqueDestinations>) -> Self {
+        Self {
+            source,
+            next_index: 0,
+            destinations,
+            exhausted: false,
+            pending: BTreeMap::new(),
+            total: None,
+            repeats: HashMap::new(),
+            queued: HashMap::new(),
+            backlog: None,
+        }
+    }
+
+    /// Returns the size of a batch known up front, or `None` if requests keep arriving.
+    pub(crate) fn total(&self) -> Option<usize> {
+        self.total
+    }
+
+    /// Changes the priority of the request at `index` if it is still waiting for a worker.
+    ///
+    /// Returns `false` if the request has already started or isn't known.
+    pub(crate) fn set_priority(&mut self, index: usize, priority: Priority) -> bool {
+        self.absorb();
+        let Some(mut request) = self.take(index) else {
+            return false;
+        };
+        request.priority = priority;
+        self.hold(index, request);
+        true
+    }
+
+    /// Takes the request at `index` out of the queue if it is still waiting.
+    pub(crate) fn take(&mut self, index: usize) -> Option<DownloadRequest> {
+        let key = self.pending.keys().find(|(_, i)| *i == index).copied()?;
+        self.pending.remove(&key)
+    }
+
+    /// Holds back every repeated request, given with the position of the identical request it
+    /// waits for, until that request is taken with [`WorkQueue::take_repeats`].
+    pub(crate) fn hold_repeats(&mut self, repeats: Vec<(usize, usize)>) {
+        for (index, first) in repeats {
+            if let Some(request) = self.take(index) {
+                self.repeats
+                    .entry(first)
+                    .or_default()
+                    .push((index, request));
+            }
+        }
+    }
+
+    /// Takes the requests held back until the request at `index` is done, with when they were
+    /// queued.
+    pub(crate) fn take_repeats(&mut self, index: usize) -> Vec<(usize, DownloadRequest, Instant)> {
+        self.repeats
+            .remove(&index)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(index, request)| (index, request, self.started(index)))
+            .collect()
+    }
+
+    /// Queues the request at `index` again, such as a repeat taken out with
+    /// [`WorkQueue::take_repeats`], keeping the time it was first `queued` at.
+    pub(crate) fn requeue(&mut self, index: usize, request: DownloadRequest, queued: Instant) {
+        self.queued.insert(index, queued);
+        self.hold(index, request);
+    }
+
+    /// Puts the request at `index` among the waiting requests.
+    fn hold(&mut self, index: usize, request: DownloadRequest) {
+        // A request queued again keeps the time it was first queued at.
+        self.queued.entry(index).or_insert_with(Instant::now);
+        self.pending
+            .insert((Reverse(request.priority), index), request);
+    }
+
+    /// Returns when the request at `index` was queued, now that it starts.
+    fn started(&mut self, index: usize) -> Instant {
+        // A channel's requests are never held back as repeats, so each starts only once.
+        if let Some(backlog) = &self.backlog {
+            backlog.release();
+        }
+        self.queued.remove(&index).unwrap_or_else(Instant::now)
+    }
+
+    /// Moves every request that has already arrived on a channel to the waiting requests, so
+    /// they are ordered by priority together.
+    fn absorb(&mut self) {
+        if !matches!(self.source, Source::Channel(_)) {
+            return;
+        }
+        while self.pending.len() < MAX_READ_AHEAD {
+            match self.pull(false) {
+                Pull::Request(index, request) => self.hold(index, *request),
+                // Channel requests were checked when they were enqueued, so pulling never fails.
+                Pull::Failed(_) | Pull::Pending | Pull::Drained => break,
+            }
+        }
+    }
+
+    /// Picks the next request to download, taking a slot on its host from `hosts`.
+    ///
+    /// The most urgent waiting request whose host has a free slot is picked. Requests for busy
+    /// hosts are skipped over until they have one, and the worker blocks while every waiting
+    /// request's host is busy or, for an open [`WorkQueue::channel`], until a request arrives.
+    /// Once the batch is cancelled, nothing more is read from a stream, and waiting requests
+    /// are handed out without a slot so they can end quickly.
+    ///
+    /// Returns `None` once the queue is drained, and the error that stopped a stream the first
+    /// time it fails, including when a streamed request reuses an earlier destination.
+    pub(crate) fn next<'h>(
+        &mut self,
+        hosts: Option<&'h HostLimiter>,
+        control: &Control,
+    ) -> Option<Result<Job<'h>, DownloadError>> {
+        loop {
+            self.absorb();
+
+            // The most urgent waiting request whose host has room goes first.
+            let ready = self.pending.iter().find_map(|(key, request)| match hosts {
+                Some(hosts) => try_slot(hosts, request).map(|slot| (*key, slot)),
+                None => Some((*key, None)),
+            });
+            if let Some((key, slot)) = ready {
+                let request = self.pending.remove(&key)?;
+                return Some(Ok((key.1, request, slot, self.started(key.1))));
+            }
+
+            // Read ahead until a request for a host with room turns up. Only wait for new
+            // requests while nothing is waiting, so a freed slot is noticed promptly.
+            while self.pending.len() < MAX_READ_AHEAD && !control.is_cancelled() {
+                let (index, request) = match self.pull(self.pending.is_empty()) {
+                    Pull::Request(index, request) => (index, *request),
+                    Pull::Failed(e) => return Some(Err(e)),
+                    Pull::Pending | Pull::Drained => break,
+                };
+                let Some(hosts) = hosts else {
+                    return Some(Ok((index, request, None, self.started(index))));
+                };
+                match try_slot(hosts, &request) {
+                    Some(slot) => return Some(Ok((index, request, slot, self.started(index)))),
+                    None => self.hold(index, request),
+                }
+            }
+
+            if control.is_cancelled() {
+                let ((_, index), request) = self.pending.pop_first()?;
+                return Some(Ok((index, request, None, self.started(index))));
+            }
+            match hosts {
+                Some(hosts) if !self.pending.is_empty() => hosts.wait(POLL),
+                _ if self.exhausted => return None,
+                _ => {}
+            }
+        }
+    }
+
+    /// Takes the next request from the source and its position in the batch.
+    ///
+    /// A channel is waited on for a short while if `wait` is set, and only checked otherwise.
+    fn pull(&mut self, wait: bool) -> Pull {
+        if self.exhausted {
+            return Pull::Drained;
+        }
+
+        let next = match &mut self.source {
+            Source::Iter(requests) => requests.next(),
+            Source::Channel(receiver) => {
+                let received = if wait {
+                    receiver.recv_timeout(POLL).map_err(|e| match e {
+                        RecvTimeoutError::Timeout => TryRecvError::Empty,
+                        RecvTimeoutError::Disconnected => TryRecvError::Disconnected,
+                    })
+                } else {
+                    receiver.try_recv()
+                };
+                match received {
+                    Ok((request, queued)) => {
+                        self.queued.insert(self.next_index, queued);
+                        Some(Ok(request))
+                    }
+                    Err(TryRecvError::Empty) => return Pull::Pending,
+                    Err(TryRecvError::Disconnected) => None,
+                }
+            }
+        };
+
+        let mut request = match next {
+            Some(Ok(request)) => request,
+            Some(Err(e)) => {
+                self.exhausted = true;
+                return Pull::Failed(e);
+            }
+            None => {
+                self.exhausted = true;
+                return Pull::Drained;
+            }
+        };
+
+        let index = self.next_index;
+        // A streamed request counts as queued once it is read.
+        self.queued.entry(index).or_insert_with(Instant::now);
+        if let Some(destinations) = &mut self.destinations {
+            if let Err(e) = destinations.insert(index, &mut request) {
+                self.exhausted = true;
+                return Pull::Failed(e.into());
+            }
+        }
+        self.next_index += 1;
+        Pull::Request(index, Box::new(request))
+    }
+}
+
+/// Takes a slot on the host of `request`.
+///
+/// Returns `Some(None)` for a URL without a host, which isn't limited, and `None` while the
+/// host is busy.
+fn try_slot<'h>(hosts: &'h HostLimiter, request: &DownloadRequest) -> Option<Option<HostSlot<'h>>> {
+    match host_key(&request.url) {
+        Some(host) => hosts.try_acquire(&host).map(Some),
+        None => Some(None),
+    }
+}
@@ -0,0 +1,84 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Longest burst, in seconds of throughput, that an idle limiter lets through at once.
+const MAX_BURST_SECONDS: f64 = 0.1;
+
+/// A token bucket limiting the combined throughput of every download sharing it.
+///
+/// Readers wait until the bucket is out of debt, read a chunk, and then pay for the bytes they
+/// actually received. A chunk may briefly push the bucket into debt, which later readers then
+/// wait out, so the long-run rate matches the configured limit.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    /// The sustained rate the bucket refills at.
+    bytes_per_sec: f64,
+    /// The current token balance and when it was last refilled.
+    state: Mutex<Bucket>,
+}
+
+/// Mutable bookkeeping of a [`RateLimiter`].
+#[derive(Debug)]
+struct Bucket {
+    /// Available bytes; negative while readers are in debt.
+    tokens: f64,
+    /// When `tokens` was last brought up to date.
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a limiter for the given rate, or `None` when the rate is unlimited.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes_per_sec` - The maximum throughput; `None` or `Some(0)` means unlimited.
+    pub(crate) fn new(bytes_per_sec: Option<u64>) -> Option<Self> {
+        let bytes_per_sec = bytes_per_sec.filter(|rate| *rate > 0)? as f64;
+        Some(Self {
+            bytes_per_sec,
+            state: Mutex::new(Bucket {
+                tokens: 0.0,
+                updated: Instant::now(),
+            }),
+        })
+    }
+
+    /// Returns how long a reader has to wait before it may read the next chunk.
+    pub(crate) fn delay(&self) -> Duration {
+        let mut bucket = self.state.lock().unwrap();
+        self.refill(&mut bucket);
+
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / self.bytes_per_sec)
+        }
+    }
+
+    /// Blocks the current thread until a reader may read the next chunk.
+    pub(crate) fn wait(&self) {
+        loop {
+            let delay = self.delay();
+            if delay.is_zero() {
+                return;
+            }
+            std::thread::sleep(delay);
+        }
+    }
+
+    /// Pays for `bytes` that have just been read.
+    pub(crate) fn consume(&self, bytes: u64) {
+        let mut bucket = self.state.lock().unwrap();
+        self.refill(&mut bucket);
+        bucket.tokens -= bytes as f64;
+    }
+
+    /// Adds the tokens accumulated since the last update, capped at the burst size.
+    fn refill(&self, bucket: &mut Bucket) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        let capacity = self.bytes_per_sec * MAX_BURST_SECONDS;
+        bucket.tokens = (bucket.tokens + elapsed * self.bytes_per_sec).min(capacity);
+        bucket.updated = now;
+    }
+}
@@ -0,0 +1,186 @@
+use crate::auth::Auth;
+use crate::error::DownloadError;
+use http::header::{HeaderMap, AUTHORIZATION, COOKIE, LOCATION, PROXY_AUTHORIZATION};
+use http::StatusCode;
+use std::error::Error;
+use std::fmt;
+use url::Url;
+
+/// How a download follows HTTP redirects.
+///
+/// Redirects are followed by the downloader itself so every hop can be checked against this
+/// policy and reported as a [`crate::DownloadEvent::Redirected`] event. Clients built by this
+/// crate never follow redirects on their own; a client passed to
+/// [`crate::download_file_with_client`] should be built with
+/// `reqwest::redirect::Policy::none()` for these settings to apply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedirectPolicy {
+    /// Whether redirects are followed at all. When `false`, a redirect fails the download
+    /// with a [`RedirectRefused`] error. Defaults to `true`.
+    pub follow: bool,
+    /// Maximum number of redirects followed for one request before failing with
+    /// [`TooManyRedirects`]. Defaults to 10.
+    pub max_hops: usize,
+    /// Refuses redirects that leave the host of the original URL. Defaults to `false`.
+    pub same_host_only: bool,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self {
+            follow: true,
+            max_hops: 10,
+            same_host_only: false,
+        }
+    }
+}
+
+/// Returned when a request was redirected more often than [`RedirectPolicy::max_hops`] allows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TooManyRedirects {
+    /// The URL that was originally requested.
+    pub url: String,
+    /// The configured limit.
+    pub max_hops: usize,
+}
+
+impl fmt::Display for TooManyRedirects {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} was redirected more than {} times",
+            self.url, self.max_hops
+        )
+    }
+}
+
+impl Error for TooManyRedirects {}
+
+/// Returned when the [`RedirectPolicy`] does not allow following a redirect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedirectRefused {
+    /// The URL that answered with the redirect.
+    pub from: String,
+    /// Where the redirect pointed.
+    pub to: String,
+}
+
+impl fmt::Display for RedirectRefused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "refused to follow redirect from {} to {}",
+            self.from, self.to
+        )
+    }
+}
+
+impl Error for RedirectRefused {}
+
+/// Follows the redirects of one request, shared by the blocking and the async download paths.
+///
+/// Holds the headers and credentials for the next hop, which lose anything sensitive once a
+/// redirect leaves the original origin.
+pub(crate) struct Redirects<'a> {
+    /// The policy every hop is checked against.
+    policy: &'a RedirectPolicy,
+    /// Headers sent with the next request.
+    headers: HeaderMap,
+    /// Credentials sent with the next request.
+    auth: Option<&'a Auth>,
+    /// The URL that was originally requested, known once the first response arrived.
+    original: Option<Url>,
+    /// Redirects followed so far.
+    hops: usize,
+}
+
+impl<'a> Redirects<'a> {
+    /// Starts following redirects for a request carrying `headers` and `auth`.
+    pub(crate) fn new(
+        policy: &'a RedirectPolicy,
+        headers: HeaderMap,
+        auth: Option<&'a Auth>,
+    ) -> Self {
+        Self {
+            policy,
+            headers,
+            auth,
+            original: None,
+            hops: 0,
+        }
+    }
+
+    /// Headers to send with the next request.
+    pub(crate) fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Credentials to send with the next request.
+    pub(crate) fn auth(&self) -> Option<&'a Auth> {
+        self.auth
+    }
+
+    /// Inspects a response from `url`, returning where to go next if it is a redirect.
+    ///
+    /// Fails with [`TooManyRedirects`] or [`RedirectRefused`] when the policy forbids the hop.
+    /// A redirect without a usable `Location` is treated as the final response.
+    pub(crate) fn next(
+        &mut self,
+        url: &Url,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> Result<Option<Url>, DownloadError> {
+        let original = self.original.get_or_insert_with(|| url.clone());
+        let Some(target) = redirect_target(url, status, headers) else {
+            return Ok(None);
+        };
+
+        let refused = || RedirectRefused {
+            from: url.to_string(),
+            to: target.to_string(),
+        };
+        if !self.policy.follow {
+            return Err(refused().into());
+        }
+        if self.policy.same_host_only && target.host_str() != original.host_str() {
+            return Err(refused().into());
+        }
+        if self.hops >= self.policy.max_hops {
+            return Err(TooManyRedirects {
+                url: original.to_string(),
+                max_hops: self.policy.max_hops,
+            }
+            .into());
+        }
+        self.hops += 1;
+
+        // Never leak credentials or cookies to another origin.
+        if url.origin() != target.origin() {
+            self.auth = None;
+            for name in [AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION] {
+                self.headers.remove(name);
+            }
+        }
+
+        Ok(Some(target))
+    }
+}
+
+/// Where a redirect response from `url` points, or `None` if it is not a followable redirect.
+fn redirect_target(url: &Url, status: StatusCode, headers: &HeaderMap) -> Option<Url> {
+    let is_redirect = matches!(
+        status,
+        StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::SEE_OTHER
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT
+    );
+    if !is_redirect {
+        return None;
+    }
+
+    // Relative locations are resolved against the URL that sent them.
+    let location = headers.get(LOCATION)?.to_str().ok()?;
+    url.join(location).ok()
+}
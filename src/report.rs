@@ -0,0 +1,208 @@
+use crate::result::{DownloadOutcome, DownloadResult};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+
+/// A summary of a finished batch, built from its results with [`BatchReport::new`].
+///
+/// Its [`fmt::Display`] output is a few lines meant for logs. With the `serde` feature, it
+/// serializes with serde for tools that read the run's outcome.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct BatchReport {
+    /// Files that were downloaded.
+    pub succeeded: usize,
+    /// Downloads that failed.
+    pub failed: usize,
+    /// Existing files that were kept, because of the overwrite policy or because the server
+    /// reported them unchanged.
+    pub skipped: usize,
+    /// Downloads the batch was cancelled before.
+    pub cancelled: usize,
+    /// Downloads a [`crate::BatchConfig::fail_fast`] batch gave up on after another one failed.
+    pub aborted: usize,
+    /// Downloads a [`crate::BatchConfig::dry_run`] found nothing wrong with.
+    pub planned: usize,
+    /// Bytes written by the downloaded files.
+    pub bytes: u64,
+    /// How long the batch ran, from start to finish.
+    pub elapsed: Duration,
+    /// The download that took longest, or `None` if nothing was downloaded.
+    pub slowest: Option<ReportedFile>,
+    /// The largest file downloaded, or `None` if nothing was downloaded.
+    pub largest: Option<ReportedFile>,
+    /// Every download that failed, in the order the requests were given.
+    pub failures: Vec<ReportedFailure>,
+}
+
+/// A downloaded file singled out by a [`BatchReport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ReportedFile {
+    /// The URL that was requested.
+    pub url: String,
+    /// Number of bytes written.
+    pub bytes: u64,
+    /// How long the download took, including retries.
+    pub duration: Duration,
+}
+
+/// A failed download listed by a [`BatchReport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ReportedFailure {
+    /// The URL that was requested.
+    pub url: String,
+    /// The error that stopped the download, as text.
+    pub error: String,
+}
+
+impl BatchReport {
+    /// Summarizes the results of a batch.
+    ///
+    /// # Arguments
+    ///
+    /// * `results` - The results the batch returned, or those carried by a
+    ///   [`crate::BatchFailed`].
+    /// * `elapsed` - How long the batch ran, which the results can't tell since their
+    ///   downloads overlap.
+    pub fn new(results: &[DownloadResult], elapsed: Duration) -> Self {
+        let mut report = Self {
+            succeeded: 0,
+            failed: 0,
+            skipped: 0,
+            cancelled: 0,
+            aborted: 0,
+            planned: 0,
+            bytes: 0,
+            elapsed,
+            slowest: None,
+            largest: None,
+            failures: Vec::new(),
+        };
+        for result in results {
+            match &result.outcome {
+                DownloadOutcome::Completed { bytes, .. } => {
+                    report.succeeded += 1;
+                    report.bytes += bytes;
+                    let file = ReportedFile {
+                        url: result.url.clone(),
+                        bytes: *bytes,
+                        duration: result.duration,
+                    };
+                    // Ties go to the earlier request.
+                    if report
+                        .slowest
+                        .as_ref()
+                        .is_none_or(|slowest| file.duration > slowest.duration)
+                    {
+                        report.slowest = Some(file.clone());
+                    }
+                    if report
+                        .largest
+                        .as_ref()
+                        .is_none_or(|largest| file.bytes > largest.bytes)
+                    {
+                        report.largest = Some(file);
+                    }
+                }
+                DownloadOutcome::Skipped { .. } | DownloadOutcome::NotModified { .. } => {
+                    report.skipped += 1;
+                }
+                DownloadOutcome::Cancelled => report.cancelled += 1,
+                DownloadOutcome::Aborted => report.aborted += 1,
+                DownloadOutcome::DryRun { .. } => report.planned += 1,
+                DownloadOutcome::Failed { error } => {
+                    report.failed += 1;
+                    report.failures.push(ReportedFailure {
+                        url: result.url.clone(),
+                        error: error.to_string(),
+                    });
+                }
+            }
+        }
+        report
+    }
+
+    /// Returns how many downloads the batch had.
+    pub fn total(&self) -> usize {
+        self.succeeded + self.failed + self.skipped + self.cancelled + self.aborted + self.planned
+    }
+
+    /// Returns the average throughput of the whole batch in bytes per second: the bytes of
+    /// every downloaded file over the time the batch ran.
+    pub fn bytes_per_sec(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.bytes as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for BatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} downloads in {:.1?}: {} succeeded, {} failed, {} skipped, {} cancelled",
+            self.total(),
+            self.elapsed,
+            self.succeeded,
+            self.failed,
+            self.skipped,
+            self.cancelled
+        )?;
+        if self.aborted > 0 {
+            write!(f, ", {} aborted", self.aborted)?;
+        }
+        if self.planned > 0 {
+            write!(f, ", {} checked by a dry run", self.planned)?;
+        }
+        write!(
+            f,
+            "\n  transferred {} at {}/s",
+            Size(self.bytes),
+            Size(self.bytes_per_sec() as u64)
+        )?;
+        if let Some(slowest) = &self.slowest {
+            write!(
+                f,
+                "\n  slowest: {} in {:.1?}",
+                slowest.url, slowest.duration
+            )?;
+        }
+        if let Some(largest) = &self.largest {
+            write!(
+                f,
+                "\n  largest: {} with {}",
+                largest.url,
+                Size(largest.bytes)
+            )?;
+        }
+        for failure in &self.failures {
+            write!(f, "\n  failed: {}: {}", failure.url, failure.error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Displays a byte count in binary units, such as `1.5 MiB`.
+struct Size(u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut value = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{:.1} {}", value, UNITS[unit])
+    }
+}
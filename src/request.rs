@@ -0,0 +1,311 @@
+use crate::auth::Auth;
+use crate::checksum::Checksum;
+use crate::config::DownloadConfig;
+#[cfg(feature = "gzip")]
+use crate::decompress::{ChecksumTarget, Decompress};
+use crate::headers::merge_headers;
+use crate::naming::{url_file_name, FileNameConfig};
+use crate::overwrite::{numbered, OverwritePolicy};
+use crate::priority::Priority;
+use crate::segment::SegmentConfig;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+/// A single file to download as part of a batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadRequest {
+    /// The URL to download.
+    pub url: String,
+    /// Where the downloaded file will be saved.
+    pub destination: PathBuf,
+    /// Caps the throughput of this download, overriding
+    /// [`crate::DownloadConfig::max_bytes_per_sec`] when set. `Some(0)` means unlimited.
+    pub max_bytes_per_sec: Option<u64>,
+    /// The digest this file must match, overriding [`crate::DownloadConfig::checksum`] when set.
+    pub checksum: Option<Checksum>,
+    /// What to do when the destination already exists, overriding
+    /// [`crate::DownloadConfig::overwrite`] when set.
+    pub overwrite: Option<OverwritePolicy>,
+    /// Extra headers for this request, merged with [`crate::DownloadConfig::headers`]. A name
+    /// set here replaces the batch's values for it.
+    pub headers: Vec<(String, String)>,
+    /// Credentials for this request, overriding [`crate::DownloadConfig::auth`] when set.
+    pub auth: Option<Auth>,
+    /// The size of the file in bytes, if known in advance. Used by
+    /// [`crate::BatchConfig::preflight_disk_space`] instead of asking the server.
+    pub expected_size: Option<u64>,
+    /// How urgently this request is downloaded relative to the rest of the batch. Defaults to
+    /// [`Priority::Normal`].
+    pub priority: Priority,
+    /// Other URLs serving the same file, tried in order when [`DownloadRequest::url`] fails.
+    /// The URL that finally served the file is reported as
+    /// [`crate::DownloadResult::final_url`].
+    pub mirrors: Vec<String>,
+    /// How this file may be split across connections, overriding
+    /// [`crate::DownloadConfig::segments`] when set.
+    pub segments: Option<SegmentConfig>,
+    /// The largest file this download may produce, overriding
+    /// [`crate::DownloadConfig::max_size`] when set. `Some(u64::MAX)` lifts the batch's limit.
+    pub max_size: Option<u64>,
+    /// Decompresses the body on its way to [`DownloadRequest::destination`], overriding
+    /// [`crate::DownloadConfig::decompress`] when set.
+    #[cfg(feature = "gzip")]
+    pub decompress: Option<Decompress>,
+    /// Which bytes [`DownloadRequest::checksum`] applies to when the file is decompressed,
+    /// overriding [`crate::DownloadConfig::checksum_target`] when set.
+    #[cfg(feature = "gzip")]
+    pub checksum_target: Option<ChecksumTarget>,
+    /// Unpacks the file once it is downloaded and verified, if it is a zip, tar, `.tar.gz` or
+    /// `.tar.xz` archive. The files extracted are listed in
+    /// [`crate::DownloadOutcome::Completed::extracted`], and an archive that can't be unpacked
+    /// fails the download with a [`crate::ExtractError`]. Extracting counts towards the
+    /// [`crate::DownloadResult::duration`].
+    #[cfg(feature = "extract")]
+    pub extract: bool,
+    /// Where [`DownloadRequest::extract`] unpacks the archive. Defaults to the directory the
+    /// archive was saved in.
+    #[cfg(feature = "extract")]
+    pub extract_to: Option<PathBuf>,
+    /// How many bytes the files [`DownloadRequest::extract`] unpacks may add up to, so a small
+    /// archive can't fill the disk. Past it, extraction stops and the download fails with an
+    /// [`crate::ExtractError`]. `None` leaves the extracted size unlimited.
+    #[cfg(feature = "extract")]
+    pub extract_max_size: Option<u64>,
+}
+
+impl DownloadRequest {
+    /// Creates a request to download `url` into `destination`.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL to download.
+    /// * `destination` - Where the downloaded file will be saved.
+    pub fn new(url: impl Into<String>, destination: impl Into<PathBuf>) -> Self {
+        Self {
+            url: url.into(),
+            destination: destination.into(),
+            max_bytes_per_sec: None,
+            checksum: None,
+            overwrite: None,
+            headers: Vec::new(),
+            auth: None,
+            expected_size: None,
+            priority: Priority::Normal,
+            mirrors: Vec::new(),
+            segments: None,
+            max_size: None,
+            #[cfg(feature = "gzip")]
+            decompress: None,
+            #[cfg(feature = "gzip")]
+            checksum_target: None,
+            #[cfg(feature = "extract")]
+            extract: false,
+            #[cfg(feature = "extract")]
+            extract_to: None,
+            #[cfg(feature = "extract")]
+            extract_max_size: None,
+        }
+    }
+
+    /// Creates a request that saves `url` into `directory` under a name derived from the URL.
+    ///
+    /// The name is the percent-decoded last path segment of the URL, ignoring any query
+    /// string, made safe with the default [`FileNameConfig`]. URLs without a usable segment
+    /// (such as those ending in `/`) are saved as `download-<index>`. The name is reported as
+    /// the [`crate::DownloadResult::destination`], so results can be mapped back to URLs.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL to download.
+    /// * `directory` - The directory the file will be saved in.
+    /// * `index` - A number unique within the batch, used for the fallback name.
+    pub fn in_directory(url: impl Into<String>, directory: impl AsRef<Path>, index: usize) -> Self {
+        Self::in_directory_with(url, directory, index, &FileNameConfig::default())
+    }
+
+    /// Creates a request that saves `url` into `directory` like
+    /// [`DownloadRequest::in_directory`], making the name safe according to `file_names`.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL to download.
+    /// * `directory` - The directory the file will be saved in.
+    /// * `index` - A number unique within the batch, used for the fallback name.
+    /// * `file_names` - How the name derived from the URL is made safe.
+    pub fn in_directory_with(
+        url: impl Into<String>,
+        directory: impl AsRef<Path>,
+        index: usize,
+        file_names: &FileNameConfig,
+    ) -> Self {
+        let url = url.into();
+        let name = url_file_name(&url, file_names).unwrap_or_else(|| format!("download-{}", index));
+        let destination = directory.as_ref().join(name);
+        Self::new(url, destination)
+    }
+
+    /// Returns the settings for this request, applying its overrides on top of `config`.
+    pub(crate) fn effective_config<'a>(
+        &self,
+        config: &'a DownloadConfig,
+    ) -> Cow<'a, DownloadConfig> {
+        #[cfg(feature = "gzip")]
+        let decompression = self.decompress.is_some() || self.checksum_target.is_some();
+        #[cfg(not(feature = "gzip"))]
+        let decompression = false;
+
+        // Avoid cloning the shared settings when the request overrides nothing.
+        if !decompression
+            && self.max_bytes_per_sec.is_none()
+            && self.checksum.is_none()
+            && self.overwrite.is_none()
+            && self.headers.is_empty()
+            && self.auth.is_none()
+            && self.segments.is_none()
+            && self.max_size.is_none()
+        {
+            return Cow::Borrowed(config);
+        }
+
+        let mut config = config.clone();
+        if self.max_bytes_per_sec.is_some() {
+            config.max_bytes_per_sec = self.max_bytes_per_sec;
+        }
+        if self.checksum.is_some() {
+            config.checksum = self.checksum.clone();
+        }
+        if let Some(overwrite) = self.overwrite {
+            config.overwrite = overwrite;
+        }
+        if !self.headers.is_empty() {
+            config.headers = merge_headers(&config.headers, &self.headers);
+        }
+        if self.auth.is_some() {
+            config.auth = self.auth.clone();
+        }
+        if let Some(segments) = self.segments {
+            config.segments = segments;
+        }
+        if self.max_size.is_some() {
+            config.max_size = self.max_size;
+        }
+        #[cfg(feature = "gzip")]
+        if self.decompress.is_some() {
+            config.decompress = self.decompress;
+        }
+        #[cfg(feature = "gzip")]
+        if let Some(target) = self.checksum_target {
+            config.checksum_target = target;
+        }
+        Cow::Owned(config)
+    }
+}
+
+/// Returned when two requests in the same batch would write to the same file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateDestination {
+    /// The destination shared by both requests.
+    pub destination: PathBuf,
+    /// Index of the first request using the destination.
+    pub first: usize,
+    /// Index of the later request using the same destination.
+    pub second: usize,
+}
+
+impl fmt::Display for DuplicateDestination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "requests {} and {} both download to {}",
+            self.first,
+            self.second,
+            self.destination.display()
+        )
+    }
+}
+
+impl Error for DuplicateDestination {}
+
+/// Rejects batches where more than one request targets the same destination, unless the
+/// later request renames on collisions.
+///
+/// Paths are compared after dropping `.` components, so `./a.txt` and `a.txt` are treated as
+/// the same file. A request whose overwrite policy, or the batch's `overwrite` when it sets
+/// none, is [`OverwritePolicy::RenameWithSuffix`] is moved to the first free
+/// `name (1).ext`, `name (2).ext`, and so on instead.
+pub(crate) fn resolve_destinations(
+    requests: &mut [DownloadRequest],
+    overwrite: OverwritePolicy,
+) -> Result<(), DuplicateDestination> {
+    let mut destinations = UniqueDestinations::with_capacity(requests.len(), overwrite);
+    for (index, request) in requests.iter_mut().enumerate() {
+        destinations.insert(index, request)?;
+    }
+    Ok(())
+}
+
+/// The destinations of the requests seen so far, used to reject duplicates one request at a
+/// time when the whole batch isn't known up front.
+#[derive(Debug, Default)]
+pub(crate) struct UniqueDestinations {
+    /// Every normalized destination and the index of the request using it.
+    seen: HashMap<PathBuf, usize>,
+    /// The batch's overwrite policy, for requests that don't set their own.
+    overwrite: OverwritePolicy,
+}
+
+impl UniqueDestinations {
+    /// Creates an empty set for a batch whose requests default to `overwrite`.
+    pub(crate) fn new(overwrite: OverwritePolicy) -> Self {
+        Self::with_capacity(0, overwrite)
+    }
+
+    /// Creates an empty set with room for `capacity` destinations.
+    pub(crate) fn with_capacity(capacity: usize, overwrite: OverwritePolicy) -> Self {
+        Self {
+            seen: HashMap::with_capacity(capacity),
+            overwrite,
+        }
+    }
+
+    /// Records the destination of the request at `index`, failing if an earlier request
+    /// already uses it.
+    ///
+    /// A request that renames on collisions is moved to a numbered destination no earlier
+    /// request uses instead; see [`resolve_destinations`].
+    pub(crate) fn insert(
+        &mut self,
+        index: usize,
+        request: &mut DownloadRequest,
+    ) -> Result<(), DuplicateDestination> {
+        if request.overwrite.unwrap_or(self.overwrite) == OverwritePolicy::RenameWithSuffix {
+            let original = request.destination.clone();
+            let mut number = 1;
+            while self.seen.contains_key(&normalize(&request.destination)) {
+                request.destination = numbered(&original, number);
+                number += 1;
+            }
+        }
+
+        let key = normalize(&request.destination);
+        if let Some(&first) = self.seen.get(&key) {
+            return Err(DuplicateDestination {
+                destination: request.destination.clone(),
+                first,
+                second: index,
+            });
+        }
+        self.seen.insert(key, index);
+        Ok(())
+    }
+}
+
+/// Strips `.` components so equivalent relative paths compare equal.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir))
+        .collect()
+}
@@ -0,0 +1,221 @@
+use crate::control::Control;
+use crate::dry_run::PlannedAction;
+use crate::error::DownloadError;
+use crate::hook::HookError;
+use crate::preflight::RemoteFile;
+use crate::protocol::ProtocolVersion;
+use crate::timing::DownloadTimings;
+use crate::transfer::Finished;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How a single download in a batch ended.
+#[derive(Debug)]
+pub enum DownloadOutcome {
+    /// The file was downloaded successfully.
+    Completed {
+        /// Where the file was saved.
+        path: PathBuf,
+        /// Number of bytes written.
+        bytes: u64,
+        /// SHA-256 of the file as hex, when [`crate::DownloadConfig::compute_sha256`] is set.
+        sha256: Option<String>,
+        /// The URL the file was served from after following redirects.
+        final_url: String,
+        /// The HTTP version the file was served with, such as [`ProtocolVersion::Http2`] when the
+        /// connection was multiplexed. `None` for `file://`, `data:` and `ftp://` URLs, and for a
+        /// [`crate::BatchConfig::dedup`] copy of a file that was kept on disk rather than
+        /// downloaded.
+        http_version: Option<ProtocolVersion>,
+        /// The address of the server that sent the file, whose family tells whether IPv4 or
+        /// IPv6 was used. `None` for `file://`, `data:` and `ftp://` URLs, for backends that
+        /// can't tell, and for a [`crate::BatchConfig::dedup`] copy of a file that was kept on
+        /// disk.
+        remote_addr: Option<SocketAddr>,
+        /// The files `DownloadRequest::extract` unpacked from the archive, empty when
+        /// the request didn't ask for it and always empty without the `extract` feature.
+        extracted: Vec<PathBuf>,
+        /// How [`crate::BatchConfig::on_complete`] failed or panicked on the file, if it did.
+        hook_error: Option<HookError>,
+    },
+    /// The destination already existed and was kept by [`crate::OverwritePolicy::SkipExisting`].
+    Skipped {
+        /// The existing file that was left in place.
+        path: PathBuf,
+    },
+    /// The server reported that the existing file is unchanged, so it was kept.
+    NotModified {
+        /// The existing file that was left in place.
+        path: PathBuf,
+    },
+    /// The batch was cancelled before this download finished.
+    Cancelled,
+    /// Another download of a [`crate::BatchConfig::fail_fast`] batch failed before this one
+    /// finished, so it was stopped or never started.
+    Aborted,
+    /// A [`crate::BatchConfig::dry_run`] checked the request without downloading it, and
+    /// found nothing that would stop its download.
+    DryRun {
+        /// Where the file would be saved.
+        path: PathBuf,
+        /// What would happen to the destination.
+        action: PlannedAction,
+        /// What the server reported about the file, or `None` if it would be skipped without
+        /// asking.
+        remote: Option<RemoteFile>,
+    },
+    /// The download failed.
+    Failed {
+        /// The error that stopped the download.
+        error: DownloadError,
+    },
+}
+
+impl DownloadOutcome {
+    /// Classifies how a download ended.
+    pub(crate) fn from_result(result: Result<Finished, DownloadError>) -> Self {
+        match result {
+            Ok(Finished::Downloaded(transferred)) => DownloadOutcome::Completed {
+                path: transferred.path,
+                bytes: transferred.bytes,
+                sha256: transferred.sha256,
+                final_url: transferred.final_url,
+                http_version: transferred.http_version,
+                remote_addr: transferred.remote_addr,
+                extracted: Vec::new(),
+                hook_error: transferred.hook_error,
+            },
+            Ok(Finished::Kept(skipped)) => skipped.outcome(),
+            Err(DownloadError::Cancelled) => DownloadOutcome::Cancelled,
+            Err(error) => DownloadOutcome::Failed { error },
+        }
+    }
+
+    /// Ends a fail-fast batch at its first failure, or tells a download the batch gave up on
+    /// as [`DownloadOutcome::Aborted`] rather than cancelled.
+    ///
+    /// # Arguments
+    ///
+    /// * `control` - The cancellation state of the batch.
+    /// * `fail_fast` - Whether a failure cancels every other download of the batch.
+    pub(crate) fn or_abort(self, control: &Control, fail_fast: bool) -> Self {
+        match self {
+            DownloadOutcome::Failed { .. } if fail_fast => {
+                control.abort();
+                self
+            }
+            DownloadOutcome::Cancelled if control.is_aborted() => DownloadOutcome::Aborted,
+            outcome => outcome,
+        }
+    }
+}
+
+/// The result of one request in a batch.
+#[derive(Debug)]
+pub struct DownloadResult {
+    /// Identifies the download within its batch: the position of its request, as carried by
+    /// [`crate::DownloadCallbackProgress::id`] and [`crate::BatchConfig::on_file_event`].
+    pub id: u64,
+    /// The URL that was requested.
+    pub url: String,
+    /// The destination the request asked for.
+    pub destination: PathBuf,
+    /// How the download ended.
+    pub outcome: DownloadOutcome,
+    /// How long the download took, including retries.
+    pub duration: Duration,
+    /// How long the request waited in the queue and how long the phases of its download took.
+    pub timings: DownloadTimings,
+}
+
+impl DownloadResult {
+    /// Returns `true` if the file was downloaded, an existing file was kept on purpose, or a
+    /// dry run found nothing that would stop the download.
+    pub fn is_success(&self) -> bool {
+        matches!(
+            self.outcome,
+            DownloadOutcome::Completed { .. }
+                | DownloadOutcome::Skipped { .. }
+                | DownloadOutcome::NotModified { .. }
+                | DownloadOutcome::DryRun { .. }
+        )
+    }
+
+    /// Where the file ended up, or `None` if the download failed, was cancelled, or only ran
+    /// dry.
+    ///
+    /// This can differ from [`DownloadResult::destination`] when the server renamed the file or
+    /// the overwrite policy picked a new name.
+    pub fn path(&self) -> Option<&Path> {
+        match &self.outcome {
+            DownloadOutcome::Completed { path, .. }
+            | DownloadOutcome::Skipped { path }
+            | DownloadOutcome::NotModified { path } => Some(path),
+            DownloadOutcome::Cancelled
+            | DownloadOutcome::Aborted
+            | DownloadOutcome::DryRun { .. }
+            | DownloadOutcome::Failed { .. } => None,
+        }
+    }
+
+    /// Number of bytes written, or 0 if nothing was downloaded.
+    pub fn bytes(&self) -> u64 {
+        match self.outcome {
+            DownloadOutcome::Completed { bytes, .. } => bytes,
+            _ => 0,
+        }
+    }
+
+    /// The URL the file was served from after following redirects, or `None` if nothing was
+    /// downloaded.
+    pub fn final_url(&self) -> Option<&str> {
+        match &self.outcome {
+            DownloadOutcome::Completed { final_url, .. } => Some(final_url),
+            _ => None,
+        }
+    }
+
+    /// The HTTP version the file was served with, or `None` if nothing was downloaded or it
+    /// wasn't served over HTTP; see [`DownloadOutcome::Completed`].
+    pub fn http_version(&self) -> Option<ProtocolVersion> {
+        match self.outcome {
+            DownloadOutcome::Completed { http_version, .. } => http_version,
+            _ => None,
+        }
+    }
+
+    /// The address of the server the file was downloaded from, or `None` if nothing was
+    /// downloaded or the address isn't known; see [`DownloadOutcome::Completed`].
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        match self.outcome {
+            DownloadOutcome::Completed { remote_addr, .. } => remote_addr,
+            _ => None,
+        }
+    }
+
+    /// The files unpacked from the downloaded archive; see `DownloadRequest::extract`.
+    pub fn extracted(&self) -> &[PathBuf] {
+        match &self.outcome {
+            DownloadOutcome::Completed { extracted, .. } => extracted,
+            _ => &[],
+        }
+    }
+
+    /// How [`crate::BatchConfig::on_complete`] failed or panicked on the downloaded file, if
+    /// it did. The file is in place even then.
+    pub fn hook_error(&self) -> Option<&HookError> {
+        match &self.outcome {
+            DownloadOutcome::Completed { hook_error, .. } => hook_error.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The error that stopped the download, if it failed.
+    pub fn error(&self) -> Option<&DownloadError> {
+        match &self.outcome {
+            DownloadOutcome::Failed { error } => Some(error),
+            _ => None,
+        }
+    }
+}
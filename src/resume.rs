@@ -0,0 +1,48 @@
+use http::header::{HeaderMap, HeaderValue, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE};
+use std::path::Path;
+
+/// Returns the size of the partially downloaded file at `path`, or 0 if there is none.
+pub(crate) fn existing_length(path: &Path) -> u64 {
+    std::fs::metadata(path)
+        .ok()
+        .filter(|metadata| metadata.is_file())
+        .map_or(0, |metadata| metadata.len())
+}
+
+/// Returns `true` if the response headers advertise `Accept-Ranges: bytes`.
+pub(crate) fn accepts_ranges(headers: &HeaderMap) -> bool {
+    headers
+        .get(ACCEPT_RANGES)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .split(',')
+                .any(|unit| unit.trim().eq_ignore_ascii_case("bytes"))
+        })
+}
+
+/// Builds the value of a `Range` header requesting everything from `offset` onwards.
+pub(crate) fn range_from(offset: u64) -> HeaderValue {
+    // The value only ever contains ASCII digits, so it is always a valid header.
+    HeaderValue::try_from(format!("bytes={}-", offset)).expect("range header value is ASCII")
+}
+
+/// Extracts the complete resource size from a `Content-Range: bytes <start>-<end>/<total>` header.
+pub(crate) fn content_range_total(headers: &HeaderMap) -> Option<u64> {
+    let value = headers.get(CONTENT_RANGE)?.to_str().ok()?;
+    let (_, total) = value.rsplit_once('/')?;
+    total.trim().parse().ok()
+}
+
+/// Reads the `Content-Length` header.
+///
+/// Unlike the client's own length, this also works for `HEAD` responses, which have no body.
+pub(crate) fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
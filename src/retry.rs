@@ -0,0 +1,208 @@
+use crate::error::DownloadError;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Decides whether a failed attempt is retried, and after how long.
+///
+/// Set one as [`RetryConfig::policy`] to pick retries per error, for example to retry DNS
+/// failures more often than the rest or never retry a `403`. [`FixedDelay`] and
+/// [`ExponentialBackoff`] cover the common cases. A `Retry-After` the server sent still
+/// lengthens the delay a policy picks, up to [`RetryConfig::max_retry_after`].
+pub trait RetryPolicy: Send + Sync {
+    /// Returns how long to wait before the next attempt, or `None` to give up.
+    ///
+    /// # Arguments
+    ///
+    /// * `attempt` - The number of the attempt that just failed, starting at 1.
+    /// * `error` - The error the attempt failed with. Cancellation is never passed.
+    fn next_delay(&self, attempt: u32, error: &DownloadError) -> Option<Duration>;
+}
+
+/// Controls how transient download failures are retried.
+///
+/// The delay before attempt `n + 1` is `base_delay * backoff_multiplier^(n - 1)`, so with the
+/// defaults the waits are 500 ms, 1 s, 2 s, and so on. Errors that aren't
+/// [`DownloadError::is_retriable`] are never retried, unless a [`RetryConfig::policy`] says
+/// otherwise.
+#[derive(Clone)]
+pub struct RetryConfig {
+    /// Total number of attempts, including the first one. `1` disables retries.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub base_delay: Duration,
+    /// Factor the delay is multiplied by after every failed retry.
+    pub backoff_multiplier: f64,
+    /// Longest wait honored when a `429` or `503` response asks for one with `Retry-After`,
+    /// so a server can't stall the batch indefinitely. Defaults to 60 s.
+    ///
+    /// The next attempt waits for the longer of the requested delay, capped at this, and the
+    /// backoff delay. Every other download from the same host is held back just as long.
+    pub max_retry_after: Duration,
+    /// Decides about every failed attempt instead of `max_attempts`, `base_delay` and
+    /// `backoff_multiplier`. Defaults to `None`.
+    pub policy: Option<Arc<dyn RetryPolicy>>,
+}
+
+impl fmt::Debug for RetryConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryConfig")
+            .field("max_attempts", &self.max_attempts)
+            .field("base_delay", &self.base_delay)
+            .field("backoff_multiplier", &self.backoff_multiplier)
+            .field("max_retry_after", &self.max_retry_after)
+            .field("policy", &self.policy.is_some())
+            .finish()
+    }
+}
+
+impl PartialEq for RetryConfig {
+    fn eq(&self, other: &Self) -> bool {
+        // Policies are only equal when they are the same policy.
+        let same_policy = match (&self.policy, &other.policy) {
+            (Some(policy), Some(other)) => Arc::ptr_eq(policy, other),
+            (None, None) => true,
+            _ => false,
+        };
+        self.max_attempts == other.max_attempts
+            && self.base_delay == other.base_delay
+            && self.backoff_multiplier == other.backoff_multiplier
+            && self.max_retry_after == other.max_retry_after
+            && same_policy
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            backoff_multiplier: 2.0,
+            max_retry_after: Duration::from_secs(60),
+            policy: None,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Returns a configuration that never retries.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Returns how long to wait after the given failed attempt (1-based) before trying again.
+    ///
+    /// # Arguments
+    ///
+    /// * `attempt` - The number of the attempt that just failed, starting at 1.
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1) as i32;
+        self.base_delay
+            .mul_f64(self.backoff_multiplier.max(0.0).powi(exponent))
+    }
+}
+
+/// Retries up to [`RetryConfig::max_attempts`] times with its exponential backoff, or as its
+/// [`RetryConfig::policy`] decides.
+impl RetryPolicy for RetryConfig {
+    fn next_delay(&self, attempt: u32, error: &DownloadError) -> Option<Duration> {
+        match &self.policy {
+            Some(policy) => policy.next_delay(attempt, error),
+            None => (attempt < self.max_attempts && error.is_retriable())
+                .then(|| self.delay_for(attempt)),
+        }
+    }
+}
+
+/// Retries transient errors after the same delay every time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedDelay {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay before every retry.
+    pub delay: Duration,
+}
+
+impl RetryPolicy for FixedDelay {
+    fn next_delay(&self, attempt: u32, error: &DownloadError) -> Option<Duration> {
+        (attempt < self.max_attempts && error.is_retriable()).then_some(self.delay)
+    }
+}
+
+/// Retries transient errors after a delay that grows with every attempt, up to a maximum.
+///
+/// The delay before attempt `n + 1` is `base_delay * multiplier^(n - 1)`, capped at
+/// `max_delay`. With `jitter` set, each delay is instead picked at random between zero and
+/// that value, so downloads that failed together don't all retry at the same moment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExponentialBackoff {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub base_delay: Duration,
+    /// Factor the delay is multiplied by after every failed retry.
+    pub multiplier: f64,
+    /// Longest delay between two attempts.
+    pub max_delay: Duration,
+    /// Picks each delay at random up to the computed one.
+    pub jitter: bool,
+}
+
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(500),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy for ExponentialBackoff {
+    fn next_delay(&self, attempt: u32, error: &DownloadError) -> Option<Duration> {
+        if attempt >= self.max_attempts || !error.is_retriable() {
+            return None;
+        }
+        let exponent = attempt.saturating_sub(1) as i32;
+        let factor = self.multiplier.max(0.0).powi(exponent);
+        // Very long delays overflow a `Duration`, and are capped anyway.
+        let delay = Duration::try_from_secs_f64(self.base_delay.as_secs_f64() * factor)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+        Some(if self.jitter {
+            delay.mul_f64(fastrand::f64())
+        } else {
+            delay
+        })
+    }
+}
+
+/// Returned when a download still fails after every configured attempt.
+#[derive(Debug)]
+pub struct RetriesExhausted {
+    /// Number of attempts that were made.
+    pub attempts: u32,
+    /// The error from the final attempt.
+    pub last_error: Box<DownloadError>,
+}
+
+impl fmt::Display for RetriesExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "download failed after {} attempts: {}",
+            self.attempts, self.last_error
+        )
+    }
+}
+
+impl Error for RetriesExhausted {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.last_error.as_ref())
+    }
+}
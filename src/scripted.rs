@@ -0,0 +1,204 @@
+use crate::backend::{HttpBackend, HttpRequest, HttpResponse};
+use crate::error::DownloadError;
+use http::header::{HeaderMap, HeaderValue, CONTENT_LENGTH};
+use http::{Method, StatusCode, Version};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read};
+use std::sync::Mutex;
+use std::time::Duration;
+use url::Url;
+
+/// An [`HttpBackend`] answering from a script instead of the network, for testing code built
+/// on the downloader. Only available with the `test-util` feature.
+///
+/// Every URL has a queue of answers, taken one per request. The last answer of a URL keeps
+/// answering once the others are used up, and a URL without answers refuses the connection.
+/// Every request is recorded and can be inspected with [`ScriptedBackend::requests`].
+#[derive(Debug, Default)]
+pub struct ScriptedBackend {
+    /// The answers still to give, by URL.
+    answers: Mutex<HashMap<String, VecDeque<Answer>>>,
+    /// Every request received so far.
+    requests: Mutex<Vec<HttpRequest>>,
+}
+
+/// One scripted answer to a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Answer {
+    /// Responds with the given status, headers and body.
+    Response(ScriptedResponse),
+    /// Fails before any response arrives, as a connection error of the given kind would.
+    Error(io::ErrorKind),
+}
+
+/// A response of a [`ScriptedBackend`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptedResponse {
+    /// The status of the response.
+    pub status: StatusCode,
+    /// The headers of the response.
+    pub headers: HeaderMap,
+    /// The body, delivered part by part. Answers to `HEAD` requests leave it out.
+    pub body: Vec<BodyPart>,
+    /// The DER certificate the server presented, checked against
+    /// [`crate::DownloadConfig::pinned_keys`]. `None`, as over plain HTTP, unless set.
+    pub peer_certificate: Option<Vec<u8>>,
+}
+
+/// A part of the body of a [`ScriptedResponse`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BodyPart {
+    /// Bytes delivered by a single read.
+    Data(Vec<u8>),
+    /// A pause before the next part, like a slow server.
+    Delay(Duration),
+    /// A read error of the given kind, like a connection that dropped mid-body.
+    Error(io::ErrorKind),
+}
+
+impl ScriptedResponse {
+    /// A `200 OK` delivering `body` in one chunk, with its `Content-Length`.
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Self::chunked(vec![BodyPart::Data(body.into())])
+    }
+
+    /// A `200 OK` delivering `body` part by part, with the `Content-Length` of its data.
+    pub fn chunked(body: Vec<BodyPart>) -> Self {
+        let length: usize = body
+            .iter()
+            .map(|part| match part {
+                BodyPart::Data(data) => data.len(),
+                _ => 0,
+            })
+            .sum();
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+        Self {
+            status: StatusCode::OK,
+            headers,
+            body,
+            peer_certificate: None,
+        }
+    }
+
+    /// An empty response with the given status.
+    pub fn status(status: StatusCode) -> Self {
+        Self {
+            status,
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+            peer_certificate: None,
+        }
+    }
+}
+
+impl From<ScriptedResponse> for Answer {
+    fn from(response: ScriptedResponse) -> Self {
+        Answer::Response(response)
+    }
+}
+
+impl ScriptedBackend {
+    /// Creates a backend with no answers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an answer for `url`, given after those already queued for it.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL answered, exactly as it is requested.
+    /// * `answer` - A [`ScriptedResponse`] or an [`Answer::Error`].
+    pub fn push(&self, url: impl Into<String>, answer: impl Into<Answer>) {
+        self.answers
+            .lock()
+            .unwrap()
+            .entry(url.into())
+            .or_default()
+            .push_back(answer.into());
+    }
+
+    /// Returns every request received so far, in the order they were sent.
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Takes the next answer for `url`, keeping the last one for later requests.
+    fn next_answer(&self, url: &str) -> Option<Answer> {
+        let mut answers = self.answers.lock().unwrap();
+        let queue = answers.get_mut(url)?;
+        if queue.len() > 1 {
+            queue.pop_front()
+        } else {
+            queue.front().cloned()
+        }
+    }
+}
+
+impl HttpBackend for ScriptedBackend {
+    fn send(&self, request: HttpRequest) -> Result<HttpResponse, DownloadError> {
+        self.requests.lock().unwrap().push(request.clone());
+        let failure = |kind: io::ErrorKind| {
+            DownloadError::body(&request.url, io::Error::new(kind, "scripted failure"))
+        };
+
+        let url = Url::parse(&request.url).map_err(|_| failure(io::ErrorKind::InvalidInput))?;
+        let mut response = match self.next_answer(&request.url) {
+            Some(Answer::Response(response)) => response,
+            Some(Answer::Error(kind)) => return Err(failure(kind)),
+            None => return Err(failure(io::ErrorKind::ConnectionRefused)),
+        };
+        if request.method == Method::HEAD {
+            response.body.clear();
+        }
+
+        let content_length = response
+            .headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse().ok());
+        Ok(HttpResponse {
+            status: response.status,
+            headers: response.headers,
+            url,
+            version: Version::HTTP_11,
+            remote_addr: None,
+            content_length,
+            peer_certificate: response.peer_certificate,
+            body: Box::new(ScriptedBody {
+                parts: response.body.into(),
+            }),
+        })
+    }
+}
+
+/// Reads the parts of a scripted body in order.
+struct ScriptedBody {
+    /// The parts not read yet.
+    parts: VecDeque<BodyPart>,
+}
+
+impl Read for ScriptedBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.parts.front_mut() {
+                None => return Ok(0),
+                Some(BodyPart::Data(data)) if data.is_empty() => {}
+                Some(BodyPart::Data(data)) => {
+                    // A part larger than the buffer is handed out over several reads.
+                    let read = data.len().min(buf.len());
+                    buf[..read].copy_from_slice(&data[..read]);
+                    data.drain(..read);
+                    return Ok(read);
+                }
+                Some(BodyPart::Delay(delay)) => std::thread::sleep(*delay),
+                Some(BodyPart::Error(kind)) => {
+                    let kind = *kind;
+                    self.parts.pop_front();
+                    return Err(io::Error::new(kind, "scripted body error"));
+                }
+            }
+            self.parts.pop_front();
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use http::header::HeaderValue;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+/// Settings for downloading one large file over several connections at once.
+///
+/// When more than one segment is allowed, the downloader first sends a `HEAD` request. If the
+/// server reports the file's size and advertises `Accept-Ranges: bytes`, the file is split into
+/// byte ranges that are downloaded in parallel straight into their place in the temporary
+/// file, with progress reported for the file as a whole. Otherwise it is downloaded over a
+/// single connection as usual.
+///
+/// Segmented downloads are never used when resuming a partial file or when a conditional
+/// request can be made. Segments don't arrive in file order, so the SHA-256 of
+/// [`crate::DownloadConfig::compute_sha256`] and a configured checksum are computed once the
+/// file is complete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentConfig {
+    /// Maximum number of connections used for one file. `1` disables segmented downloads,
+    /// which is the default.
+    pub count: usize,
+    /// Smallest number of bytes worth a connection of its own. Files are split into fewer
+    /// segments when they are too small to give every segment at least this much. Defaults to
+    /// 4 MiB.
+    pub min_segment_size: u64,
+}
+
+impl Default for SegmentConfig {
+    fn default() -> Self {
+        Self {
+            count: 1,
+            min_segment_size: 4 * 1024 * 1024,
+        }
+    }
+}
+
+impl SegmentConfig {
+    /// Returns `true` if a file may be split into more than one segment.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.count > 1
+    }
+
+    /// Splits a file of `total` bytes into consecutive byte ranges, one per connection.
+    ///
+    /// Returns `None` if the file is too small to be worth more than one segment.
+    pub(crate) fn plan(&self, total: u64) -> Option<Vec<Range<u64>>> {
+        let by_size = total / self.min_segment_size.max(1);
+        let count = (self.count as u64).min(by_size);
+        if count < 2 {
+            return None;
+        }
+
+        // Every segment gets the same share; the last one also takes the remainder.
+        let share = total / count;
+        let ranges = (0..count)
+            .map(|index| {
+                let start = index * share;
+                let end = if index + 1 == count {
+                    total
+                } else {
+                    start + share
+                };
+                start..end
+            })
+            .collect();
+        Some(ranges)
+    }
+}
+
+/// Returned when a server answered a segment's range request with the whole file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeIgnored {
+    /// The URL that was requested.
+    pub url: String,
+}
+
+impl fmt::Display for RangeIgnored {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} advertised range support but ignored a range request",
+            self.url
+        )
+    }
+}
+
+impl Error for RangeIgnored {}
+
+/// Returned when the thread reading a segment of a file panicked, such as in a progress
+/// callback it called. The download fails like it would for any other segment error, with
+/// the panic kept off the caller's thread.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentPanicked {
+    /// The URL that was being downloaded.
+    pub url: String,
+    /// The message the panic was raised with, if it was a string.
+    pub message: Option<String>,
+}
+
+impl fmt::Display for SegmentPanicked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a segment of {} panicked", self.url)?;
+        match &self.message {
+            Some(message) => write!(f, ": {}", message),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Error for SegmentPanicked {}
+
+/// Builds the value of a `Range` header requesting exactly `range`.
+pub(crate) fn range_header(range: &Range<u64>) -> HeaderValue {
+    // The value only ever contains ASCII digits, so it is always a valid header.
+    HeaderValue::try_from(format!("bytes={}-{}", range.start, range.end - 1))
+        .expect("range header value is ASCII")
+}
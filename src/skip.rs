@@ -0,0 +1,52 @@
+use crate::event::DownloadEvent;
+use crate::result::DownloadOutcome;
+use std::path::PathBuf;
+
+/// Why an existing file was kept instead of being downloaded again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SkipReason {
+    /// The destination existed and [`crate::OverwritePolicy::SkipExisting`] kept it.
+    Exists,
+    /// The server answered a conditional request with `304 Not Modified`.
+    NotModified,
+}
+
+/// A download that ended early and left an existing file in place.
+///
+/// It is reported as a [`DownloadEvent::Skipped`] or [`DownloadEvent::NotModified`] event and
+/// never fails a batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Skipped {
+    /// The existing file that was left in place.
+    pub(crate) path: PathBuf,
+    /// Why the file was kept.
+    pub(crate) reason: SkipReason,
+}
+
+impl Skipped {
+    /// A download skipped because [`crate::OverwritePolicy::SkipExisting`] kept `path`.
+    pub(crate) fn existing(path: PathBuf) -> Self {
+        Self {
+            path,
+            reason: SkipReason::Exists,
+        }
+    }
+
+    /// The terminal event reported for this download.
+    pub(crate) fn event(&self) -> DownloadEvent {
+        let path = self.path.clone();
+        match self.reason {
+            SkipReason::Exists => DownloadEvent::Skipped { path },
+            SkipReason::NotModified => DownloadEvent::NotModified { path },
+        }
+    }
+
+    /// The batch outcome recorded for this download.
+    pub(crate) fn outcome(&self) -> DownloadOutcome {
+        let path = self.path.clone();
+        match self.reason {
+            SkipReason::Exists => DownloadOutcome::Skipped { path },
+            SkipReason::NotModified => DownloadOutcome::NotModified { path },
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use crate::event::DownloadEvent;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::path::Path;
+
+// With the `tracing` feature, the crate's warnings are tracing events, so they land in the
+// span of the download that raised them. Without a tracing subscriber they still reach the
+// `log` logger.
+#[cfg(not(feature = "tracing"))]
+pub(crate) use log::{debug, error, warn};
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::{debug, error, warn};
+
+/// The span of a batch, which the span of each of its downloads is a child of.
+///
+/// Without the `tracing` feature, spans are empty and record nothing.
+#[derive(Debug, Clone)]
+pub(crate) struct BatchSpan {
+    /// The `batch` span, carrying the number of files when known.
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+/// The span of a single download, which the events of the download are recorded in.
+#[derive(Debug, Clone)]
+pub(crate) struct DownloadSpan {
+    /// The `download` span, carrying the URL, destination and, in a batch, id.
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+#[cfg(feature = "tracing")]
+impl BatchSpan {
+    /// Opens the span of a batch of `files` requests, or of a stream of them.
+    pub(crate) fn new(files: Option<usize>) -> Self {
+        Self {
+            span: tracing::info_span!("batch", files),
+        }
+    }
+
+    /// Opens the span of the download with the given id, in this batch.
+    pub(crate) fn download(&self, id: usize, url: &str, destination: &Path) -> DownloadSpan {
+        DownloadSpan {
+            span: tracing::info_span!(
+                parent: &self.span,
+                "download",
+                id,
+                url,
+                destination = %destination.display()
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl DownloadSpan {
+    /// Opens the span of a download outside a batch, as a child of the current span.
+    pub(crate) fn new(url: &str, destination: &Path) -> Self {
+        Self {
+            span: tracing::info_span!("download", url, destination = %destination.display()),
+        }
+    }
+
+    /// Runs `f` inside the span.
+    pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.span.in_scope(f)
+    }
+
+    /// Runs `future` inside the span every time it is polled.
+    #[cfg(feature = "async")]
+    pub(crate) fn instrument<F: Future>(&self, future: F) -> impl Future<Output = F::Output> {
+        tracing::Instrument::instrument(future, self.span.clone())
+    }
+
+    /// Records the start and the end of the download as events of the span. Retries are
+    /// already reported by the download's warnings.
+    pub(crate) fn record(&self, event: &DownloadEvent) {
+        let span = &self.span;
+        match event {
+            DownloadEvent::Started { total_bytes, .. } => {
+                tracing::info!(parent: span, total_bytes, "download started");
+            }
+            DownloadEvent::Completed { bytes, timings, .. } => tracing::info!(
+                parent: span,
+                bytes,
+                attempts = timings.attempts,
+                queued_ms = timings.queued.as_millis() as u64,
+                first_byte_ms = timings.time_to_first_byte.map(|ttfb| ttfb.as_millis() as u64),
+                duration_ms = timings.total.as_millis() as u64,
+                "download completed"
+            ),
+            DownloadEvent::Failed { error, timings } => tracing::warn!(
+                parent: span,
+                error = %error,
+                attempts = timings.attempts,
+                duration_ms = timings.total.as_millis() as u64,
+                "download failed"
+            ),
+            DownloadEvent::Skipped { .. } | DownloadEvent::NotModified { .. } => {
+                tracing::info!(parent: span, "existing file kept");
+            }
+            DownloadEvent::Cancelled { .. } => tracing::info!(parent: span, "download cancelled"),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl BatchSpan {
+    /// Opens the span of a batch of `files` requests, or of a stream of them.
+    pub(crate) fn new(_files: Option<usize>) -> Self {
+        Self {}
+    }
+
+    /// Opens the span of the download with the given id, in this batch.
+    pub(crate) fn download(&self, _id: usize, _url: &str, _destination: &Path) -> DownloadSpan {
+        DownloadSpan {}
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl DownloadSpan {
+    /// Opens the span of a download outside a batch, as a child of the current span.
+    pub(crate) fn new(_url: &str, _destination: &Path) -> Self {
+        Self {}
+    }
+
+    /// Runs `f` inside the span.
+    pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        f()
+    }
+
+    /// Runs `future` inside the span every time it is polled.
+    #[cfg(feature = "async")]
+    pub(crate) fn instrument<F: Future>(&self, future: F) -> F {
+        future
+    }
+
+    /// Records the start and the end of the download as events of the span.
+    pub(crate) fn record(&self, _event: &DownloadEvent) {}
+}
@@ -0,0 +1,77 @@
+use std::time::{Duration, Instant};
+
+/// Number of buckets the window of a [`SpeedWindow`] is split into.
+const BUCKETS: usize = 20;
+
+/// The bytes a download received recently, to derive a speed that doesn't jump with every
+/// chunk.
+///
+/// The window is split into a fixed number of buckets, each counting the bytes of an equal
+/// slice of time, so recording a chunk never allocates. Buckets older than the window are
+/// emptied as time passes, whether or not bytes arrive, so the speed of a stalled download
+/// falls towards zero instead of keeping its last value.
+#[derive(Debug)]
+pub(crate) struct SpeedWindow {
+    /// Bytes received during each slice of the window, as a ring.
+    buckets: [u64; BUCKETS],
+    /// The bucket of the current slice.
+    current: usize,
+    /// When the current slice began.
+    current_start: Instant,
+    /// How long each slice lasts.
+    slice: Duration,
+    /// When the window began counting, so a young download isn't averaged over time it
+    /// didn't exist.
+    started: Instant,
+}
+
+impl SpeedWindow {
+    /// Creates an empty window covering the last `window` of a download starting `now`.
+    pub(crate) fn new(window: Duration, now: Instant) -> Self {
+        Self {
+            buckets: [0; BUCKETS],
+            current: 0,
+            current_start: now,
+            slice: (window / BUCKETS as u32).max(Duration::from_millis(1)),
+            started: now,
+        }
+    }
+
+    /// Counts `bytes` received at `now`.
+    pub(crate) fn record(&mut self, bytes: u64, now: Instant) {
+        self.advance(now);
+        self.buckets[self.current] += bytes;
+    }
+
+    /// Returns the bytes per second received over the window up to `now`.
+    pub(crate) fn bytes_per_sec(&mut self, now: Instant) -> f64 {
+        self.advance(now);
+        // The older slices are complete, the current one only as far as `now`.
+        let covered = (self.slice * (BUCKETS as u32 - 1)
+            + now.saturating_duration_since(self.current_start))
+        .min(now.saturating_duration_since(self.started))
+        .as_secs_f64();
+        if covered > 0.0 {
+            self.buckets.iter().sum::<u64>() as f64 / covered
+        } else {
+            0.0
+        }
+    }
+
+    /// Moves on to the slice `now` falls in, emptying the buckets of the slices passed.
+    fn advance(&mut self, now: Instant) {
+        let passed =
+            now.saturating_duration_since(self.current_start).as_nanos() / self.slice.as_nanos();
+        if passed >= BUCKETS as u128 {
+            // Nothing in the window is recent enough to count any more.
+            self.buckets = [0; BUCKETS];
+            self.current_start = now;
+            return;
+        }
+        for _ in 0..passed {
+            self.current = (self.current + 1) % BUCKETS;
+            self.buckets[self.current] = 0;
+        }
+        self.current_start += self.slice * passed as u32;
+    }
+}
@@ -0,0 +1,411 @@
+use crate::batch::download_batch_requests;
+use crate::checksum::Checksum;
+use crate::config::BatchConfig;
+use crate::error::DownloadError;
+use crate::event::DownloadEvent;
+use crate::headers::is_sensitive;
+use crate::request::DownloadRequest;
+use crate::result::DownloadResult;
+use crate::spans::warn;
+use crate::temp_file::temp_path;
+use crate::validators::Validators;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Version of the state file format written by this crate.
+const VERSION: u32 = 1;
+
+/// Least time between two rewrites of the state file while the batch runs, so a batch of
+/// many small files isn't slowed down by rewriting it after every one.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where a request of a batch with a state file stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    /// Not started yet.
+    Pending,
+    /// Started, and possibly partially written to its temporary file.
+    InProgress,
+    /// Saved to disk, or kept because it already was.
+    Completed,
+    /// Failed, and retried by the next run.
+    Failed,
+    /// Cancelled, and continued by the next run.
+    Cancelled,
+}
+
+/// One request of a batch, as recorded in the state file.
+///
+/// Headers carrying credentials, such as `Authorization` or an API key, are left out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    url: String,
+    destination: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<(String, String)>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    mirrors: Vec<String>,
+    status: Status,
+    /// Bytes written so far, including any resumed prefix.
+    #[serde(default)]
+    bytes: u64,
+    /// Where a completed file was saved, which the server may have renamed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
+}
+
+impl Entry {
+    /// Records `request` as not started yet.
+    fn pending(request: &DownloadRequest) -> Self {
+        let sha256 = match &request.checksum {
+            Some(Checksum::Sha256(digest)) => Some(digest.clone()),
+            _ => None,
+        };
+        Self {
+            url: request.url.clone(),
+            destination: request.destination.clone(),
+            sha256,
+            // Credentials stay in memory; the caller supplies them again when resuming.
+            headers: request
+                .headers
+                .iter()
+                .filter(|(name, _)| !is_sensitive(name))
+                .cloned()
+                .collect(),
+            mirrors: request.mirrors.clone(),
+            status: Status::Pending,
+            bytes: 0,
+            path: None,
+            etag: None,
+            last_modified: None,
+        }
+    }
+
+    /// Rebuilds the request this entry was recorded from.
+    fn request(&self) -> DownloadRequest {
+        let mut request = DownloadRequest::new(self.url.clone(), self.destination.clone());
+        request.checksum = self.sha256.clone().map(Checksum::Sha256);
+        request.headers = self.headers.clone();
+        request.mirrors = self.mirrors.clone();
+        request
+    }
+}
+
+/// The state file as read from disk.
+#[derive(Debug, Deserialize)]
+struct Contents {
+    version: u32,
+    entries: Vec<Entry>,
+}
+
+/// The state file as written to disk, borrowing the entries of a running batch.
+#[derive(Debug, Serialize)]
+struct Snapshot<'a> {
+    version: u32,
+    entries: &'a [Entry],
+}
+
+/// Returned when a batch state file is not valid JSON or was written by a newer version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidState {
+    /// The state file that was being read.
+    pub path: PathBuf,
+    /// What is wrong with it.
+    pub message: String,
+}
+
+impl fmt::Display for InvalidState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid batch state {}: {}",
+            self.path.display(),
+            self.message
+        )
+    }
+}
+
+impl Error for InvalidState {}
+
+/// The progress of a running batch, kept in memory and written to its state file now and
+/// then.
+#[derive(Debug)]
+pub(crate) struct BatchState {
+    /// The state file.
+    path: PathBuf,
+    /// Position of each request in the batch, by destination.
+    by_destination: HashMap<PathBuf, usize>,
+    /// The entries and when they were last written.
+    inner: Mutex<Inner>,
+    /// Held while the file is being written, so only one worker writes it at a time.
+    writing: Mutex<()>,
+}
+
+/// The part of a [`BatchState`] that changes as the batch runs.
+#[derive(Debug)]
+struct Inner {
+    /// One entry per request of the batch, in batch order.
+    entries: Vec<Entry>,
+    /// Whether an entry changed since the file was last written.
+    dirty: bool,
+    /// When the file was last written.
+    flushed: Instant,
+}
+
+impl BatchState {
+    /// Records `requests` in the state file at `path`, carrying over what an earlier run with
+    /// the same file recorded for each of them.
+    ///
+    /// A request is matched to an earlier entry by its URL and destination. Entries of the
+    /// earlier run that are no longer part of the batch are dropped. The file is written before
+    /// any download starts.
+    pub(crate) fn open(path: &Path, requests: &[DownloadRequest]) -> Result<Self, DownloadError> {
+        let mut earlier: HashMap<(String, PathBuf), Entry> = read_entries(path)?
+            .into_iter()
+            .map(|entry| ((entry.url.clone(), entry.destination.clone()), entry))
+            .collect();
+
+        let entries: Vec<Entry> = requests
+            .iter()
+            .map(|request| {
+                let key = (request.url.clone(), request.destination.clone());
+                let mut entry = Entry::pending(request);
+                if let Some(earlier) = earlier.remove(&key) {
+                    entry.status = earlier.status;
+                    entry.bytes = earlier.bytes;
+                    entry.path = earlier.path;
+                    entry.etag = earlier.etag;
+                    entry.last_modified = earlier.last_modified;
+                }
+                entry
+            })
+            .collect();
+
+        let state = Self {
+            path: path.to_path_buf(),
+            by_destination: requests
+                .iter()
+                .enumerate()
+                .map(|(index, request)| (request.destination.clone(), index))
+                .collect(),
+            inner: Mutex::new(Inner {
+                entries,
+                dirty: true,
+                flushed: Instant::now(),
+            }),
+            writing: Mutex::new(()),
+        };
+        state.write(true).map_err(DownloadError::io(path))?;
+        Ok(state)
+    }
+
+    /// Returns where the request at `index` was saved if an earlier run completed it and the
+    /// file is still there.
+    pub(crate) fn completed(&self, index: usize) -> Option<PathBuf> {
+        let inner = self.inner.lock().unwrap();
+        let entry = inner.entries.get(index)?;
+        if entry.status != Status::Completed {
+            return None;
+        }
+        let path = entry.path.as_ref().unwrap_or(&entry.destination);
+        path.is_file().then(|| path.clone())
+    }
+
+    /// Updates the entry of the request at `index` with one of its events, writing the file
+    /// if it hasn't been written for a while.
+    pub(crate) fn record(&self, index: usize, event: &DownloadEvent) {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            let Some(entry) = inner.entries.get_mut(index) else {
+                return;
+            };
+            match event {
+                DownloadEvent::Started { .. } => entry.status = Status::InProgress,
+                DownloadEvent::Progress(progress) => entry.bytes = progress.bytes_downloaded,
+                DownloadEvent::Completed { path, bytes, .. } => {
+                    entry.status = Status::Completed;
+                    entry.bytes = *bytes;
+                    entry.path = Some(path.clone());
+                }
+                DownloadEvent::Skipped { path } | DownloadEvent::NotModified { path } => {
+                    entry.status = Status::Completed;
+                    entry.path = Some(path.clone());
+                }
+                DownloadEvent::Cancelled { .. } => entry.status = Status::Cancelled,
+                DownloadEvent::Failed { .. } => entry.status = Status::Failed,
+                DownloadEvent::Redirected { .. }
+                | DownloadEvent::MirrorFailover { .. }
+                | DownloadEvent::UrlRefreshed { .. }
+                | DownloadEvent::PinMismatch { .. }
+                | DownloadEvent::Throttled { .. }
+                | DownloadEvent::ConcurrencyChanged { .. }
+                | DownloadEvent::ResumeRestarted { .. }
+                | DownloadEvent::Retrying { .. } => return,
+            }
+            inner.dirty = true;
+        }
+        self.save(false);
+    }
+
+    /// Returns the validators the file at `destination` was first requested with, so a
+    /// resumed request can check it is still the same file.
+    pub(crate) fn validators(&self, destination: &Path) -> Option<Validators> {
+        let index = *self.by_destination.get(destination)?;
+        let inner = self.inner.lock().unwrap();
+        let entry = inner.entries.get(index)?;
+        let validators = Validators {
+            etag: entry.etag.clone(),
+            last_modified: entry.last_modified.clone(),
+        };
+        (validators != Validators::default()).then_some(validators)
+    }
+
+    /// Stores the validators the server sent for the file at `destination`.
+    pub(crate) fn remember_validators(&self, destination: &Path, validators: Validators) {
+        let Some(&index) = self.by_destination.get(destination) else {
+            return;
+        };
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(entry) = inner.entries.get_mut(index) {
+            entry.etag = validators.etag;
+            entry.last_modified = validators.last_modified;
+            inner.dirty = true;
+        }
+    }
+
+    /// Writes the file if anything changed since it was last written.
+    pub(crate) fn flush(&self) {
+        self.save(true);
+    }
+
+    /// Writes the file like [`BatchState::write`], logging failures since the batch goes on
+    /// without it.
+    fn save(&self, force: bool) {
+        if let Err(e) = self.write(force) {
+            warn!(
+                "Failed to save the batch state to {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    /// Replaces the file with the current entries if any of them changed.
+    ///
+    /// Unless `force` is set, the file is only written once [`FLUSH_INTERVAL`] has passed
+    /// since the last time, and not at all while another worker is writing it. The entries are
+    /// written to a temporary file that is then renamed over the state file, so a crash never
+    /// leaves a half-written state behind.
+    fn write(&self, force: bool) -> io::Result<()> {
+        let _writing = match self.writing.try_lock() {
+            Ok(guard) => guard,
+            Err(_) if force => self.writing.lock().unwrap(),
+            Err(_) => return Ok(()),
+        };
+        let contents = {
+            let mut inner = self.inner.lock().unwrap();
+            if !inner.dirty || (!force && inner.flushed.elapsed() < FLUSH_INTERVAL) {
+                return Ok(());
+            }
+            inner.dirty = false;
+            inner.flushed = Instant::now();
+            serde_json::to_vec_pretty(&Snapshot {
+                version: VERSION,
+                entries: &inner.entries,
+            })?
+        };
+
+        let temp = temp_path(&self.path);
+        let mut file = File::create(&temp)?;
+        file.write_all(&contents)?;
+        file.sync_all()?;
+        std::fs::rename(&temp, &self.path)
+    }
+}
+
+/// Reads the entries of the state file at `path`, or none if it doesn't exist yet.
+fn read_entries(path: &Path) -> Result<Vec<Entry>, DownloadError> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(DownloadError::io(path)(e)),
+    };
+
+    let invalid = |message: String| InvalidState {
+        path: path.to_path_buf(),
+        message,
+    };
+    let contents: Contents = serde_json::from_str(&contents).map_err(|e| invalid(e.to_string()))?;
+    if contents.version > VERSION {
+        return Err(invalid(format!(
+            "version {} is newer than the supported version {}",
+            contents.version, VERSION
+        ))
+        .into());
+    }
+    Ok(contents.entries)
+}
+
+/// Continues the batch recorded in a state file.
+///
+/// Every request of the earlier run is queued again with the state file set as
+/// [`BatchConfig::state_file`]. Files it completed are skipped if they are still on disk, and
+/// partially downloaded files are resumed with `Range` requests. Each entry's URL,
+/// destination, SHA-256 checksum and headers are restored; other per-request settings of the
+/// earlier batch are not stored, so pass batch-wide ones in `config`.
+///
+/// Headers carrying credentials, such as `Authorization`, `Cookie` or an `X-Api-Key`, are
+/// never written to the state file. Supply them again through the
+/// [`crate::DownloadConfig::headers`] or [`crate::DownloadConfig::auth`] of `config`, or
+/// rerun [`download_batch_requests`] with the original requests and the same
+/// [`BatchConfig::state_file`] to resume with each request's own headers.
+///
+/// # Arguments
+///
+/// * `state_path` - The state file written by the earlier run.
+/// * `config` - The batch settings, including the per-file [`crate::DownloadConfig`].
+/// * `callback` - A function or closure that receives the [`DownloadEvent`]s of every file in the batch.
+///
+/// # Returns
+///
+/// * `Ok` with one [`DownloadResult`] per entry, in the order of the state file. Files kept
+///   from the earlier run are reported as [`crate::DownloadOutcome::Skipped`].
+/// * `Err` with an I/O error if the state file doesn't exist, or an [`InvalidState`] if it is
+///   malformed.
+/// * `Err` for the same reasons as [`download_batch_requests`].
+pub fn resume_batch(
+    state_path: impl AsRef<Path>,
+    config: BatchConfig,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+) -> Result<Vec<DownloadResult>, DownloadError> {
+    let state_path = state_path.as_ref();
+    if !state_path.is_file() {
+        return Err(DownloadError::io(state_path)(io::Error::from(
+            io::ErrorKind::NotFound,
+        )));
+    }
+    let requests = read_entries(state_path)?
+        .iter()
+        .map(Entry::request)
+        .collect();
+
+    let config = BatchConfig {
+        state_file: Some(state_path.to_path_buf()),
+        ..config
+    };
+    download_batch_requests(requests, config, callback)
+}
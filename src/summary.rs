@@ -0,0 +1,88 @@
+use crate::error::DownloadError;
+use crate::result::{DownloadOutcome, DownloadResult};
+use crate::spans::warn;
+use std::error::Error;
+use std::fmt;
+
+/// Returned by a batch in strict mode (see [`crate::BatchConfig::continue_on_error`]) when at
+/// least one download failed.
+///
+/// Every download still ran to completion, unless [`crate::BatchConfig::fail_fast`] stopped
+/// the batch, so the results of the successful ones are kept alongside the failures.
+#[derive(Debug)]
+pub struct BatchFailed {
+    /// One result per request, in the order the requests were given.
+    pub results: Vec<DownloadResult>,
+}
+
+impl BatchFailed {
+    /// The results of the downloads that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &DownloadResult> {
+        failures(&self.results)
+    }
+
+    /// The results of the downloads that [`crate::BatchConfig::fail_fast`] stopped or never
+    /// started because another download failed.
+    pub fn aborted(&self) -> impl Iterator<Item = &DownloadResult> {
+        aborted(&self.results)
+    }
+}
+
+impl fmt::Display for BatchFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_summary(f, &self.results)
+    }
+}
+
+impl Error for BatchFailed {}
+
+/// Finishes a batch: logs a summary of its failures and, in strict mode, turns them into an error.
+pub(crate) fn finish_batch(
+    results: Vec<DownloadResult>,
+    continue_on_error: bool,
+) -> Result<Vec<DownloadResult>, DownloadError> {
+    if failures(&results).next().is_none() {
+        return Ok(results);
+    }
+
+    let failed = BatchFailed { results };
+    warn!("{}", failed);
+    if continue_on_error {
+        Ok(failed.results)
+    } else {
+        Err(failed.into())
+    }
+}
+
+/// The results in `results` whose download failed.
+fn failures(results: &[DownloadResult]) -> impl Iterator<Item = &DownloadResult> {
+    results.iter().filter(|result| result.error().is_some())
+}
+
+/// The results in `results` whose download was aborted by a fail-fast batch.
+fn aborted(results: &[DownloadResult]) -> impl Iterator<Item = &DownloadResult> {
+    results
+        .iter()
+        .filter(|result| matches!(result.outcome, DownloadOutcome::Aborted))
+}
+
+/// Writes how many downloads failed and were aborted, followed by one line per failed URL and
+/// its error.
+fn write_summary(f: &mut fmt::Formatter<'_>, results: &[DownloadResult]) -> fmt::Result {
+    write!(
+        f,
+        "{} of {} downloads failed",
+        failures(results).count(),
+        results.len()
+    )?;
+    match aborted(results).count() {
+        0 => f.write_str(":")?,
+        count => write!(f, " and {} were aborted because of them:", count)?,
+    }
+    for result in failures(results) {
+        if let Some(error) = result.error() {
+            write!(f, "\n  {}: {}", result.url, error)?;
+        }
+    }
+    Ok(())
+}
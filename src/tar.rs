@@ -0,0 +1,253 @@
+use crate::error::DownloadError;
+use crate::extract::{entry_path, set_executable, write_entry, Budget, ExtractError};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// The size of a tar header, and the unit entries are padded to.
+const BLOCK: u64 = 512;
+
+/// The largest pax header or GNU long name read into memory.
+const MAX_METADATA: u64 = 1024 * 1024;
+
+/// Returns `true` if `block` is a tar header, judged by its checksum.
+pub(crate) fn is_header(block: &[u8]) -> bool {
+    block.len() >= BLOCK as usize
+        && parse_number(&block[148..156]).is_some_and(|sum| sum == checksum(block))
+}
+
+/// Unpacks the tar archive read from `reader` into `directory`; see [`crate::extract`].
+///
+/// POSIX, pax and GNU archives are understood, including long names. Hard links are copied
+/// from the file they point to.
+///
+/// # Arguments
+///
+/// * `reader` - The archive, already decompressed.
+/// * `archive` - The path of the archive, for errors.
+/// * `directory` - Where to unpack it.
+/// * `budget` - How many bytes the extracted files may add up to.
+pub(crate) fn extract(
+    mut reader: impl Read,
+    archive: &Path,
+    directory: &Path,
+    budget: &mut Budget,
+) -> Result<Vec<PathBuf>, DownloadError> {
+    let unreadable = |error: io::Error| ExtractError::unreadable(archive, None, &error);
+    let mut extracted = Vec::new();
+    // Names and sizes set by a pax or GNU header for the entry that follows it.
+    let mut long_name: Option<String> = None;
+    let mut long_size: Option<u64> = None;
+
+    let mut header = [0; BLOCK as usize];
+    loop {
+        // An archive usually ends with two zeroed blocks, though not every writer adds them.
+        if !read_block(&mut reader, &mut header).map_err(unreadable)? || header == [0; 512] {
+            break;
+        }
+        if !is_header(&header) {
+            return Err(
+                ExtractError::new(archive, None, "a header doesn't match its checksum").into(),
+            );
+        }
+        let size = parse_number(&header[124..136])
+            .ok_or_else(|| ExtractError::new(archive, None, "a header holds an invalid size"))?;
+
+        // Headers describing the next entry.
+        if matches!(header[156], b'x' | b'L') && size > MAX_METADATA {
+            return Err(
+                ExtractError::new(archive, None, "a pax or long name header is too long").into(),
+            );
+        }
+        match header[156] {
+            b'x' => {
+                let records = read_data(&mut reader, size).map_err(unreadable)?;
+                for (key, value) in pax_records(&records) {
+                    match key {
+                        "path" => long_name = Some(value.to_string()),
+                        "size" => long_size = value.parse().ok(),
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            b'L' => {
+                let name = read_data(&mut reader, size).map_err(unreadable)?;
+                let name = String::from_utf8_lossy(&name);
+                long_name = Some(name.trim_end_matches('\0').to_string());
+                continue;
+            }
+            b'g' | b'K' => {
+                skip_data(&mut reader, size).map_err(unreadable)?;
+                continue;
+            }
+            _ => {}
+        }
+
+        let name = long_name.take().unwrap_or_else(|| header_name(&header));
+        let size = long_size.take().unwrap_or(size);
+        let path = entry_path(archive, directory, &name)?;
+        match (header[156], path) {
+            (b'0' | b'\0' | b'7', Some(path)) => {
+                let written =
+                    write_entry(&mut (&mut reader).take(size), archive, &name, &path, budget)?;
+                if written < size {
+                    return Err(ExtractError::new(
+                        archive,
+                        Some(&name),
+                        "the archive is truncated",
+                    )
+                    .into());
+                }
+                skip_padding(&mut reader, size).map_err(unreadable)?;
+                if let Some(mode) = parse_number(&header[100..108]) {
+                    set_executable(&path, mode as u32)?;
+                }
+                extracted.push(path);
+            }
+            (b'5', Some(path)) => {
+                fs::create_dir_all(&path).map_err(DownloadError::io(&path))?;
+            }
+            (b'1', Some(path)) => {
+                let target = field(&header[157..257]);
+                let source = entry_path(archive, directory, &target)?.ok_or_else(|| {
+                    ExtractError::new(archive, Some(&name), "the hard link has no target")
+                })?;
+                let length = fs::metadata(&source)
+                    .map_err(DownloadError::io(&source))?
+                    .len();
+                budget.spend(archive, &name, length)?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent).map_err(DownloadError::io(parent))?;
+                }
+                fs::copy(&source, &path).map_err(DownloadError::io(&source))?;
+                extracted.push(path);
+            }
+            // Symbolic links, devices and FIFOs are never created.
+            _ => {
+                skip_data(&mut reader, size).map_err(unreadable)?;
+            }
+        }
+    }
+    // Read a compressed archive to its end, so its integrity check runs.
+    io::copy(&mut reader, &mut io::sink()).map_err(unreadable)?;
+    Ok(extracted)
+}
+
+/// Fills `block` with the next block of the archive.
+///
+/// Returns `false` if the archive ended right before it.
+fn read_block(reader: &mut impl Read, block: &mut [u8; BLOCK as usize]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < block.len() {
+        match reader.read(&mut block[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(read) => filled += read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(true)
+}
+
+/// Reads the `size` bytes of an entry's data and the padding after them.
+fn read_data(reader: &mut impl Read, size: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(size).read_to_end(&mut data)?;
+    if (data.len() as u64) < size {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    skip_padding(reader, size)?;
+    Ok(data)
+}
+
+/// Skips the `size` bytes of an entry's data and the padding after them.
+fn skip_data(reader: &mut impl Read, size: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(size), &mut io::sink())?;
+    if skipped < size {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    skip_padding(reader, size)
+}
+
+/// Skips the padding after `size` bytes of data.
+fn skip_padding(reader: &mut impl Read, size: u64) -> io::Result<()> {
+    let padding = (BLOCK - size % BLOCK) % BLOCK;
+    io::copy(&mut reader.take(padding), &mut io::sink())?;
+    Ok(())
+}
+
+/// The sum of the bytes of a header, counting its checksum field as spaces.
+fn checksum(header: &[u8]) -> u64 {
+    header[..BLOCK as usize]
+        .iter()
+        .enumerate()
+        .map(|(index, &byte)| match index {
+            148..=155 => u64::from(b' '),
+            _ => u64::from(byte),
+        })
+        .sum()
+}
+
+/// Parses a numeric header field, in octal or, for large values, GNU base-256.
+fn parse_number(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 != 0 {
+        return field[1..]
+            .iter()
+            .try_fold(u64::from(field[0] & 0x7F), |value, &byte| {
+                value.checked_mul(256).map(|value| value | u64::from(byte))
+            });
+    }
+    let text = std::str::from_utf8(field).ok()?;
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+/// Reads a NUL-terminated text field.
+fn field(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Reads the name of an entry, joining the prefix of a POSIX header to it.
+fn header_name(header: &[u8]) -> String {
+    let name = field(&header[..100]);
+    let prefix = field(&header[345..500]);
+    if &header[257..263] == b"ustar\0" && !prefix.is_empty() {
+        format!("{}/{}", prefix, name)
+    } else {
+        name
+    }
+}
+
+/// Splits the `<length> <key>=<value>\n` records of a pax header.
+fn pax_records(records: &[u8]) -> Vec<(&str, &str)> {
+    let mut parsed = Vec::new();
+    let mut rest = records;
+    while let Some(space) = rest.iter().position(|&byte| byte == b' ') {
+        let Some(length) = std::str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|length| length.parse::<usize>().ok())
+            .filter(|length| *length > space && *length <= rest.len())
+        else {
+            break;
+        };
+        let record = &rest[space + 1..length];
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        if let Some((key, value)) = std::str::from_utf8(record)
+            .ok()
+            .and_then(|record| record.split_once('='))
+        {
+            parsed.push((key, value));
+        }
+        rest = &rest[length..];
+    }
+    parsed
+}
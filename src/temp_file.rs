@@ -0,0 +1,67 @@
+use crate::config::DownloadConfig;
+use crate::error::DownloadError;
+use std::path::{Path, PathBuf};
+
+/// Suffix appended to the name of a file the crate keeps, such as a state file, while a new
+/// version of it is written.
+const TEMP_SUFFIX: &str = ".tmp";
+
+/// Suffix appended to the destination's file name for the lock reserving it while it is
+/// downloaded.
+const LOCK_SUFFIX: &str = ".lock";
+
+/// Suffix appended to the destination's file name while a download's body is being written,
+/// unless [`DownloadConfig::partial_suffix`] picks another.
+const PARTIAL_SUFFIX: &str = ".part";
+
+/// Returns the temporary file a new version of `path` is written to before it replaces it.
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    with_suffix(path, TEMP_SUFFIX)
+}
+
+/// Returns the lock file reserving `path` for a download while it is in progress.
+pub(crate) fn lock_path(path: &Path) -> PathBuf {
+    with_suffix(path, LOCK_SUFFIX)
+}
+
+/// Returns the partial file a download of `path` is written to before it is moved into place.
+pub(crate) fn partial_path(path: &Path, config: &DownloadConfig) -> PathBuf {
+    let suffix = config
+        .partial_suffix
+        .as_deref()
+        .filter(|suffix| !suffix.is_empty())
+        .unwrap_or(PARTIAL_SUFFIX);
+    with_suffix(path, suffix)
+}
+
+/// Returns `path` with `suffix` appended to its file name.
+///
+/// The file lives in the same directory as `path`, so the final rename never crosses a
+/// filesystem boundary and stays atomic.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Returns `true` if the temporary file of a failed attempt should stay on disk.
+///
+/// Partial files are kept when [`DownloadConfig::resume`] is enabled so a later attempt can
+/// continue them, and after a cancellation when [`DownloadConfig::keep_partial_on_cancel`] is
+/// set. A file that grew beyond its advertised size cannot be resumed and is always removed, as
+/// is one that went over [`DownloadConfig::max_size`]. Everything else is cleaned up.
+pub(crate) fn keep_after_error(error: &DownloadError, config: &DownloadConfig) -> bool {
+    // A decompressed partial file can't be resumed from.
+    if config.decompresses() {
+        return false;
+    }
+    if let DownloadError::SizeLimitExceeded(_) = error {
+        return false;
+    }
+    if let DownloadError::SizeMismatch(mismatch) = error {
+        if !mismatch.is_truncated() {
+            return false;
+        }
+    }
+    config.resume || (config.keep_partial_on_cancel && error.is_cancelled())
+}
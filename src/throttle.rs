@@ -0,0 +1,60 @@
+use crate::host_limit::host_key;
+use http::header::{HeaderMap, RETRY_AFTER};
+use http::StatusCode;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The hosts of a batch that asked it to back off, and until when.
+///
+/// A `429 Too Many Requests` or `503 Service Unavailable` with a `Retry-After` holds back every
+/// download from the same host, not just the one that was answered, so a busy server isn't
+/// hit by the rest of the batch while the first download waits.
+#[derive(Debug, Default)]
+pub(crate) struct HostThrottle {
+    /// When each throttled host accepts requests again.
+    until: Mutex<HashMap<String, Instant>>,
+}
+
+impl HostThrottle {
+    /// Holds back downloads from the host of `url` for `delay`, unless it is already held back
+    /// for longer.
+    pub(crate) fn pause(&self, url: &str, delay: Duration) {
+        let Some(host) = host_key(url) else {
+            return;
+        };
+        let until = Instant::now() + delay;
+        let mut hosts = self.until.lock().unwrap();
+        let entry = hosts.entry(host).or_insert(until);
+        *entry = (*entry).max(until);
+    }
+
+    /// Returns how much longer downloads from the host of `url` are held back, if at all.
+    pub(crate) fn remaining(&self, url: &str) -> Option<Duration> {
+        let host = host_key(url)?;
+        let mut hosts = self.until.lock().unwrap();
+        let until = *hosts.get(&host)?;
+        let remaining = until.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            hosts.remove(&host);
+            return None;
+        }
+        Some(remaining)
+    }
+}
+
+/// Returns how long a `429` or `503` response asked to wait before the next request, from its
+/// `Retry-After` in seconds or as an HTTP date.
+pub(crate) fn retry_after(status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE {
+        return None;
+    }
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    match value.parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        // A date in the past asks for no wait at all.
+        Err(_) => httpdate::parse_http_date(value)
+            .ok()
+            .map(|date| date.duration_since(SystemTime::now()).unwrap_or_default()),
+    }
+}
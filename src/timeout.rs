@@ -0,0 +1,112 @@
+use crate::error::DownloadError;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+/// Limits on how long the phases of a download may take.
+///
+/// `None` disables a limit. The defaults are generous, but finite so a dead server can never
+/// hang a download forever, and one that keeps trickling data is stopped by
+/// [`TimeoutConfig::total`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutConfig {
+    /// How long establishing a connection may take. Defaults to 30 seconds.
+    pub connect: Option<Duration>,
+    /// How long a single read may wait for data from the server. Defaults to 60 seconds.
+    pub read: Option<Duration>,
+    /// How long one download attempt may spend transferring, from sending the request to
+    /// writing the last chunk. Time spent paused or waiting for a rate limit doesn't count.
+    /// Defaults to one hour.
+    pub total: Option<Duration>,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            connect: Some(Duration::from_secs(30)),
+            read: Some(Duration::from_secs(60)),
+            total: Some(Duration::from_secs(60 * 60)),
+        }
+    }
+}
+
+/// The phase of a download that ran out of time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    /// Connecting to the server or proxy.
+    Connect,
+    /// Waiting for the server to send more data.
+    Read,
+    /// The whole download attempt.
+    Total,
+}
+
+impl fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TimeoutPhase::Connect => "connect",
+            TimeoutPhase::Read => "read",
+            TimeoutPhase::Total => "total",
+        })
+    }
+}
+
+/// Returned when a download exceeds one of its [`TimeoutConfig`] limits.
+///
+/// Timeouts are transient and retried according to [`crate::RetryConfig`].
+#[derive(Debug)]
+pub struct Timeout {
+    /// The phase that timed out.
+    pub phase: TimeoutPhase,
+    /// The limit that was exceeded.
+    pub limit: Duration,
+    /// The underlying client error, if the timeout was detected by the HTTP client. Only with
+    /// the `reqwest` feature.
+    #[cfg(feature = "reqwest")]
+    pub source: Option<reqwest::Error>,
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} timeout of {:?} exceeded", self.phase, self.limit)
+    }
+}
+
+impl Error for Timeout {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        #[cfg(feature = "reqwest")]
+        return self.source.as_ref().map(|source| source as _);
+        #[cfg(not(feature = "reqwest"))]
+        None
+    }
+}
+
+/// Reports timeouts detected by the HTTP client as a [`Timeout`] naming the phase.
+///
+/// Other errors, and timeouts for which no limit was configured, are returned unchanged.
+#[cfg(feature = "reqwest")]
+pub(crate) fn explain_timeout(error: DownloadError, config: &TimeoutConfig) -> DownloadError {
+    let DownloadError::Request { source, .. } = &error else {
+        return error;
+    };
+    let (phase, limit) = match () {
+        _ if source.is_timeout() && source.is_connect() => (TimeoutPhase::Connect, config.connect),
+        _ if source.is_timeout() => (TimeoutPhase::Read, config.read),
+        _ => return error,
+    };
+    match (limit, error) {
+        (Some(limit), DownloadError::Request { source, .. }) => Timeout {
+            phase,
+            limit,
+            source: Some(source),
+        }
+        .into(),
+        (_, error) => error,
+    }
+}
+
+/// Returns `error` unchanged, since only reqwest errors carry the phase that timed out.
+#[cfg(not(feature = "reqwest"))]
+pub(crate) fn explain_timeout(error: DownloadError, _config: &TimeoutConfig) -> DownloadError {
+    error
+}
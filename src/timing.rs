@@ -0,0 +1,96 @@
+use crate::event::DownloadEvent;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long the phases of a download took, measured with a monotonic clock.
+///
+/// Reported in [`DownloadEvent::Completed`], [`DownloadEvent::Failed`], and
+/// [`crate::DownloadResult::timings`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DownloadTimings {
+    /// How long the request waited in its batch before the download started. Always zero
+    /// outside a batch.
+    pub queued: Duration,
+    /// Attempts made, counting retries and every URL tried. `0` when nothing was requested,
+    /// such as for a file copied from an identical request of the batch.
+    pub attempts: u32,
+    /// Time from sending the last attempt's request until its response arrived, including
+    /// redirects, or `None` if no response was streamed.
+    pub time_to_first_byte: Option<Duration>,
+    /// How long the last attempt took.
+    pub last_attempt: Duration,
+    /// Time from the start of the download until it ended, including every retry and the
+    /// waits between them, but not [`DownloadTimings::queued`].
+    pub total: Duration,
+}
+
+/// Times a download from the events it sends while it runs.
+pub(crate) struct Stopwatch {
+    /// When the download started.
+    started: Instant,
+    /// What the events have shown so far.
+    laps: Mutex<Laps>,
+}
+
+/// The progress of a [`Stopwatch`] through the attempts of a download.
+struct Laps {
+    /// Attempts started so far.
+    attempts: u32,
+    /// When the current attempt started, or will start once a wait is over.
+    attempt_started: Instant,
+    /// Time to the current attempt's response, once it arrived.
+    first_byte: Option<Duration>,
+}
+
+impl Stopwatch {
+    /// Starts timing a download whose first attempt starts right away.
+    pub(crate) fn start() -> Self {
+        let started = Instant::now();
+        Self {
+            started,
+            laps: Mutex::new(Laps {
+                attempts: 1,
+                attempt_started: started,
+                first_byte: None,
+            }),
+        }
+    }
+
+    /// Notes the phase of the download `event` marks.
+    pub(crate) fn observe(&self, event: &DownloadEvent) {
+        let now = Instant::now();
+        let mut laps = self.laps.lock().unwrap();
+        match event {
+            // The next attempt only starts once the wait is over.
+            DownloadEvent::Retrying { delay, .. } => {
+                laps.attempts += 1;
+                laps.attempt_started = now + *delay;
+                laps.first_byte = None;
+            }
+            DownloadEvent::MirrorFailover { .. } | DownloadEvent::UrlRefreshed { .. } => {
+                laps.attempts += 1;
+                laps.attempt_started = now;
+                laps.first_byte = None;
+            }
+            DownloadEvent::Throttled { delay, .. } => {
+                laps.attempt_started = laps.attempt_started.max(now + *delay);
+            }
+            DownloadEvent::Started { .. } => {
+                laps.first_byte = Some(now.saturating_duration_since(laps.attempt_started));
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the timings of the download so far.
+    pub(crate) fn timings(&self) -> DownloadTimings {
+        let laps = self.laps.lock().unwrap();
+        DownloadTimings {
+            queued: Duration::ZERO,
+            attempts: laps.attempts,
+            time_to_first_byte: laps.first_byte,
+            last_attempt: Instant::now().saturating_duration_since(laps.attempt_started),
+            total: self.started.elapsed(),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+#[cfg(feature = "reqwest")]
+use crate::error::DownloadError;
+#[cfg(feature = "reqwest")]
+use reqwest::tls::Version;
+#[cfg(feature = "reqwest")]
+use reqwest::Certificate;
+use std::error::Error;
+use std::fmt;
+#[cfg(feature = "reqwest")]
+use std::path::Path;
+use std::path::PathBuf;
+
+/// TLS settings of the batch's client, used by every download of the batch.
+///
+/// Certificates are parsed when they are added, so a bad bundle fails while the settings are
+/// put together rather than once the batch is running. Clients passed in by the caller keep
+/// their own settings, and only reqwest clients apply them, so a build with only the `ureq`
+/// feature has no fields besides [`TlsConfig::danger_accept_invalid_certs`] and refuses to
+/// start a batch with it set.
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    /// Certificates trusted in addition to the system's roots, such as the CA of an internal
+    /// server. Add them with [`TlsConfig::add_pem`] or [`TlsConfig::add_pem_file`].
+    #[cfg(feature = "reqwest")]
+    pub root_certificates: Vec<Certificate>,
+    /// Accepts any certificate, including expired, self-signed, and ones for another host.
+    ///
+    /// This is dangerous: anyone between the client and the server can then read and change
+    /// every download. Only use it against servers you control, and prefer adding their CA to
+    /// [`TlsConfig::root_certificates`]. Defaults to `false`.
+    pub danger_accept_invalid_certs: bool,
+    /// The oldest TLS version the client accepts. `None` leaves it to the TLS backend.
+    ///
+    /// rustls only speaks TLS 1.2 and 1.3, so with its features older versions are never
+    /// negotiated, and a [`TlsConfig::max_version`] below 1.2 fails to build the client.
+    #[cfg(feature = "reqwest")]
+    pub min_version: Option<Version>,
+    /// The newest TLS version the client offers. `None` leaves it to the TLS backend.
+    #[cfg(feature = "reqwest")]
+    pub max_version: Option<Version>,
+}
+
+#[cfg(feature = "reqwest")]
+impl TlsConfig {
+    /// Trusts every certificate of a PEM bundle.
+    ///
+    /// # Arguments
+    ///
+    /// * `pem` - One or more PEM-encoded certificates.
+    ///
+    /// # Returns
+    ///
+    /// * `Err` with an [`InvalidCertificate`] if the bundle is malformed or holds no
+    ///   certificate.
+    pub fn add_pem(&mut self, pem: &[u8]) -> Result<(), InvalidCertificate> {
+        self.root_certificates.extend(parse_bundle(pem, None)?);
+        Ok(())
+    }
+
+    /// Trusts every certificate of a PEM file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file holding one or more PEM-encoded certificates.
+    ///
+    /// # Returns
+    ///
+    /// * `Err` with an I/O error if the file can't be read, or an [`InvalidCertificate`] if it
+    ///   is malformed or holds no certificate.
+    pub fn add_pem_file(&mut self, path: impl AsRef<Path>) -> Result<(), DownloadError> {
+        let path = path.as_ref();
+        let pem = std::fs::read(path).map_err(DownloadError::io(path))?;
+        self.root_certificates
+            .extend(parse_bundle(&pem, Some(path))?);
+        Ok(())
+    }
+}
+
+/// Parses the certificates of a PEM bundle, read from `path` if it came from a file.
+#[cfg(feature = "reqwest")]
+fn parse_bundle(pem: &[u8], path: Option<&Path>) -> Result<Vec<Certificate>, InvalidCertificate> {
+    let invalid = |reason: String| InvalidCertificate {
+        path: path.map(Path::to_path_buf),
+        reason,
+    };
+    let certificates = Certificate::from_pem_bundle(pem).map_err(|e| invalid(e.to_string()))?;
+    if certificates.is_empty() {
+        return Err(invalid("no PEM certificate found".to_string()));
+    }
+    Ok(certificates)
+}
+
+/// Returned when a certificate added to a [`TlsConfig`] can't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidCertificate {
+    /// The file the certificate was read from, or `None` if it was given as bytes.
+    pub path: Option<PathBuf>,
+    /// What is wrong with it.
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidCertificate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(
+                f,
+                "invalid certificate in {}: {}",
+                path.display(),
+                self.reason
+            ),
+            None => write!(f, "invalid certificate: {}", self.reason),
+        }
+    }
+}
+
+impl Error for InvalidCertificate {}
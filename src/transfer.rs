@@ -0,0 +1,510 @@
+use crate::batch_progress::FileTally;
+use crate::checksum::to_hex;
+use crate::config::DownloadConfig;
+use crate::context::Context;
+use crate::error::DownloadError;
+use crate::event::DownloadEvent;
+use crate::hook::HookError;
+use crate::length::{check_received, SizeLimitExceeded};
+use crate::progress::{DownloadCallbackProgress, ProgressThrottle};
+use crate::protocol::ProtocolVersion;
+use crate::rate_limit::RateLimiter;
+use crate::skip::Skipped;
+use crate::speed::SpeedWindow;
+use crate::timeout::{Timeout, TimeoutPhase};
+use http::header::HeaderMap;
+use sha2::{Digest, Sha256};
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// What a successful transfer produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Transferred {
+    /// Where the file was saved.
+    pub(crate) path: PathBuf,
+    /// Size of the file on disk.
+    pub(crate) bytes: u64,
+    /// SHA-256 of the file, when computed while streaming.
+    pub(crate) sha256: Option<String>,
+    /// The URL the file was served from after following redirects.
+    pub(crate) final_url: String,
+    /// The HTTP version the file was served with, or `None` if it wasn't served over HTTP.
+    pub(crate) http_version: Option<ProtocolVersion>,
+    /// The address of the server that sent the file, if known.
+    pub(crate) remote_addr: Option<SocketAddr>,
+    /// The headers of the response the file was served with, boxed as they are rarely read.
+    pub(crate) headers: Box<HeaderMap>,
+    /// How [`crate::BatchConfig::on_complete`] failed on the file, if it did.
+    pub(crate) hook_error: Option<HookError>,
+}
+
+/// How a download that did not fail ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Finished {
+    /// The file was downloaded.
+    Downloaded(Transferred),
+    /// An existing file was kept without downloading it again.
+    Kept(Skipped),
+}
+
+/// Bookkeeping of a body being read: the time, rate and size limits, progress events, batch
+/// totals, and content hashing.
+///
+/// Blocking downloads read every body with [`copy_body`], whether it goes to a file, a segment
+/// of one, or the caller's writer. The async path awaits its chunks in a loop of its own but
+/// checks and reports each of them through the same [`Transfer::admit`] and
+/// [`Transfer::record`], so both behave identically.
+pub(crate) struct Transfer<'a, F: Fn(&DownloadEvent)> {
+    /// Receives the per-file events.
+    callback: &'a F,
+    /// The URL being downloaded, as reported with every progress event.
+    url: Arc<str>,
+    /// Shared batch state, including the global rate limit.
+    context: &'a Context,
+    /// Limits this download on its own, independently of the batch-wide limit.
+    file_limiter: Option<RateLimiter>,
+    /// This attempt's contribution to the batch totals.
+    tally: FileTally<'a>,
+    /// Bytes of the file downloaded so far, including any resumed prefix.
+    bytes_downloaded: u64,
+    /// Total size of the file in bytes, or `None` if the server did not report it.
+    total_bytes: Option<u64>,
+    /// Bytes that were already on disk before this transfer started.
+    resumed_bytes: u64,
+    /// When the request for this transfer was sent.
+    started: Instant,
+    /// How long the downloads of the context had been paused when the transfer started.
+    paused_before: Duration,
+    /// How long the transfer has waited for the rate limits so far.
+    throttled: Duration,
+    /// When the latest wait for the rate limits ends, so the overlapping waits of segments
+    /// are only counted once.
+    throttled_until: Option<Instant>,
+    /// When the last progress event was emitted, or `None` before the first one.
+    last_report: Option<Instant>,
+    /// The byte count carried by the last progress event.
+    reported_bytes: u64,
+    /// Limits how often progress events are emitted.
+    throttle: ProgressThrottle,
+    /// The bytes received recently, for the smoothed speed.
+    speed: SpeedWindow,
+    /// Hashes the streamed bytes, when enabled and the whole file passes through this transfer.
+    hasher: Option<Sha256>,
+    /// The overall time limit of this attempt, if any.
+    total_timeout: Option<Duration>,
+    /// Bytes each writer of the file buffers before writing to it.
+    write_buffer: usize,
+    /// Bytes each reader of the body reads at a time.
+    chunk_size: usize,
+    /// The largest file the download may produce, if limited.
+    max_size: Option<u64>,
+}
+
+impl<'a, F: Fn(&DownloadEvent)> Transfer<'a, F> {
+    /// Announces the start of a transfer with [`DownloadEvent::Started`].
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL being downloaded.
+    /// * `total_bytes` - The full size of the file, if the server reported it.
+    /// * `offset` - Bytes already on disk from a resumed download.
+    /// * `started` - When the request was sent, used to derive speed and ETA.
+    pub(crate) fn start(
+        url: &str,
+        total_bytes: Option<u64>,
+        offset: u64,
+        started: Instant,
+        config: &DownloadConfig,
+        context: &'a Context,
+        callback: &'a F,
+    ) -> Self {
+        let mut tally = FileTally::new(context.batch_progress.as_ref());
+        tally.start(total_bytes, offset);
+
+        callback(&DownloadEvent::Started {
+            url: url.to_string(),
+            total_bytes,
+        });
+
+        Self {
+            callback,
+            url: Arc::from(url),
+            context,
+            file_limiter: RateLimiter::new(config.max_bytes_per_sec),
+            tally,
+            bytes_downloaded: offset,
+            total_bytes,
+            resumed_bytes: offset,
+            started,
+            paused_before: context.control.paused_time(),
+            throttled: Duration::ZERO,
+            throttled_until: None,
+            last_report: None,
+            reported_bytes: offset,
+            throttle: config.progress_throttle,
+            speed: SpeedWindow::new(config.speed_window(), Instant::now()),
+            // A resumed file's prefix never passes through here, so its hash would be partial,
+            // and a decompressed file's bytes never do.
+            hasher: (config.compute_sha256 && offset == 0 && !config.decompresses())
+                .then(Sha256::new),
+            total_timeout: config.timeouts.total,
+            write_buffer: config.write.buffer_size,
+            chunk_size: config.chunk_size(),
+            max_size: config.max_size,
+        }
+    }
+
+    /// Returns how many bytes each writer of the file buffers, which segments running on their
+    /// own threads need to know.
+    pub(crate) fn write_buffer(&self) -> usize {
+        self.write_buffer
+    }
+
+    /// Turns off the streamed SHA-256, for transfers whose chunks don't arrive in file order.
+    pub(crate) fn without_hashing(mut self) -> Self {
+        self.hasher = None;
+        self
+    }
+
+    /// Returns how long the attempt has been transferring, leaving out the time it spent
+    /// paused or waiting for the rate limits.
+    fn active_time(&self) -> Duration {
+        let paused = self
+            .context
+            .control
+            .paused_time()
+            .saturating_sub(self.paused_before);
+        self.started
+            .elapsed()
+            .saturating_sub(paused)
+            .saturating_sub(self.throttled)
+    }
+
+    /// Fails with a [`Timeout`] once the attempt has been transferring longer than its overall
+    /// limit.
+    pub(crate) fn check_deadline(&self) -> Result<(), Timeout> {
+        match self.total_timeout {
+            Some(limit) if self.active_time() >= limit => Err(self.deadline_exceeded()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns how much of the overall time limit is left, or `None` without a limit.
+    #[cfg(feature = "async")]
+    pub(crate) fn time_left(&self) -> Result<Option<Duration>, Timeout> {
+        self.check_deadline()?;
+        Ok(self
+            .total_timeout
+            .map(|limit| limit.saturating_sub(self.active_time())))
+    }
+
+    /// The error reported when the overall time limit runs out.
+    pub(crate) fn deadline_exceeded(&self) -> Timeout {
+        Timeout {
+            phase: TimeoutPhase::Total,
+            limit: self.total_timeout.unwrap_or_default(),
+            #[cfg(feature = "reqwest")]
+            source: None,
+        }
+    }
+
+    /// Returns how long to wait before reading the next chunk so both rate limits hold. The
+    /// delay is left out of the overall time limit, apart from what overlaps a wait counted
+    /// already, which another segment is still sitting out.
+    pub(crate) fn limit_delay(&mut self) -> Duration {
+        let delay = self
+            .limiters()
+            .map(RateLimiter::delay)
+            .max()
+            .unwrap_or_default();
+        let now = Instant::now();
+        let counted = self
+            .throttled_until
+            .filter(|until| *until > now)
+            .unwrap_or(now);
+        let until = now + delay;
+        self.throttled += until.saturating_duration_since(counted);
+        self.throttled_until = Some(until.max(counted));
+        delay
+    }
+
+    /// Blocks the current thread until both rate limits allow the next chunk, leaving the
+    /// wait out of the overall time limit.
+    pub(crate) fn wait_for_limits(&mut self) {
+        let waited = Instant::now();
+        for limiter in self.limiters() {
+            limiter.wait();
+        }
+        self.throttled += waited.elapsed();
+    }
+
+    /// Fails if a chunk of `bytes` would take the file over [`DownloadConfig::max_size`], so
+    /// it is refused before it is written.
+    pub(crate) fn admit(&self, bytes: u64) -> Result<(), SizeLimitExceeded> {
+        check_received(&self.url, self.bytes_downloaded + bytes, self.max_size)
+    }
+
+    /// Records a chunk that has just been written and reports the new progress.
+    pub(crate) fn record(&mut self, chunk: &[u8]) {
+        let bytes = chunk.len() as u64;
+
+        // Charge the bytes actually received against both rate limits.
+        for limiter in self.limiters() {
+            limiter.consume(bytes);
+        }
+
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(chunk);
+        }
+
+        self.bytes_downloaded += bytes;
+        self.speed.record(bytes, Instant::now());
+        self.tally.add(bytes);
+        self.context.metrics.received(bytes);
+        if self.report_due() {
+            self.report();
+        }
+    }
+
+    /// Finalizes the streamed SHA-256, if hashing was enabled for this transfer.
+    pub(crate) fn take_sha256(&mut self) -> Option<String> {
+        self.hasher.take().map(|hasher| to_hex(&hasher.finalize()))
+    }
+
+    /// Completes the transfer of the file saved at `path`.
+    ///
+    /// The exact final progress is reported unless the last event already carried it, and at
+    /// least once, even when the response body was empty.
+    pub(crate) fn finish(
+        mut self,
+        path: PathBuf,
+        sha256: Option<String>,
+        final_url: String,
+        http_version: Option<ProtocolVersion>,
+        remote_addr: Option<SocketAddr>,
+        headers: HeaderMap,
+    ) -> Transferred {
+        if self.last_report.is_none() || self.reported_bytes != self.bytes_downloaded {
+            self.report();
+        }
+        self.tally.commit();
+        Transferred {
+            path,
+            bytes: self.bytes_downloaded,
+            sha256,
+            final_url,
+            http_version,
+            remote_addr,
+            headers: Box::new(headers),
+            hook_error: None,
+        }
+    }
+
+    /// Invokes the callback function to report the download progress.
+    fn report(&mut self) {
+        let now = Instant::now();
+        // The instant speed covers the bytes since the previous event, or since the request
+        // for the first one.
+        let (since, counted) = match self.last_report {
+            Some(last_report) => (last_report, self.reported_bytes),
+            None => (self.started, self.resumed_bytes),
+        };
+        let seconds = now.saturating_duration_since(since).as_secs_f64();
+        let instant_bytes_per_sec = if seconds > 0.0 {
+            (self.bytes_downloaded - counted) as f64 / seconds
+        } else {
+            0.0
+        };
+        let smoothed_bytes_per_sec = self.speed.bytes_per_sec(now);
+        let eta = self
+            .total_bytes
+            .filter(|_| smoothed_bytes_per_sec > 0.0)
+            .map(|total| {
+                let remaining = total.saturating_sub(self.bytes_downloaded);
+                Duration::from_secs_f64(remaining as f64 / smoothed_bytes_per_sec)
+            });
+
+        (self.callback)(&DownloadEvent::Progress(DownloadCallbackProgress {
+            url: self.url.clone(),
+            instant_bytes_per_sec,
+            smoothed_bytes_per_sec,
+            eta,
+            ..DownloadCallbackProgress::with_timing(
+                self.bytes_downloaded,
+                self.total_bytes,
+                self.resumed_bytes,
+                self.started.elapsed(),
+            )
+        }));
+        self.last_report = Some(now);
+        self.reported_bytes = self.bytes_downloaded;
+    }
+
+    /// Returns `true` once the throttle allows the next progress event.
+    fn report_due(&self) -> bool {
+        let Some(last_report) = self.last_report else {
+            return true;
+        };
+        last_report.elapsed() >= self.throttle.min_interval
+            && self.bytes_downloaded - self.reported_bytes >= self.throttle.min_bytes
+    }
+
+    /// The global and per-download rate limiters that are configured.
+    fn limiters(&self) -> impl Iterator<Item = &RateLimiter> {
+        self.context.limiter.iter().chain(&self.file_limiter)
+    }
+}
+
+/// Where [`copy_body`] writes the chunks of a body.
+pub(crate) trait BodySink {
+    /// Writes a whole chunk, failing with the error its destination calls for.
+    fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), DownloadError>;
+}
+
+/// The bookkeeping [`copy_body`] does around every chunk: a whole file's [`Transfer`], or the
+/// [`SharedTransfer`] of one of its segments.
+pub(crate) trait Pace {
+    /// Waits until the rate limits allow reading the next chunk, failing once the attempt ran
+    /// out of time. Returns `false` to stop reading before the body ends.
+    fn next_chunk(&mut self) -> Result<bool, DownloadError>;
+
+    /// Fails if a chunk of `bytes` may not be written; see [`Transfer::admit`].
+    fn admit(&self, bytes: u64) -> Result<(), DownloadError>;
+
+    /// Records a chunk that has just been written; see [`Transfer::record`].
+    fn record(&mut self, chunk: &[u8]);
+
+    /// Bytes read from the body at a time.
+    fn chunk_size(&self) -> usize;
+}
+
+impl<F: Fn(&DownloadEvent)> Pace for Transfer<'_, F> {
+    fn next_chunk(&mut self) -> Result<bool, DownloadError> {
+        self.check_deadline()?;
+        self.wait_for_limits();
+        Ok(true)
+    }
+
+    fn admit(&self, bytes: u64) -> Result<(), DownloadError> {
+        Ok(Transfer::admit(self, bytes)?)
+    }
+
+    fn record(&mut self, chunk: &[u8]) {
+        Transfer::record(self, chunk);
+    }
+
+    fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+}
+
+/// The [`Transfer`] of a file whose segments are read on threads of their own, which
+/// every segment reports into so progress and the limits cover the whole file.
+pub(crate) struct SharedTransfer<'s, 'a, F: Fn(&DownloadEvent)> {
+    /// The transfer of the whole file.
+    pub(crate) transfer: &'s Mutex<Transfer<'a, F>>,
+    /// Set once another segment failed, which stops this one without an error of its own.
+    pub(crate) stop: &'s AtomicBool,
+}
+
+impl<'a, F: Fn(&DownloadEvent)> SharedTransfer<'_, 'a, F> {
+    /// Locks the transfer of the whole file.
+    ///
+    /// A segment whose callback panicked poisons the lock. That panic is what the download
+    /// fails with, so the other segments keep the lock working until they notice `stop`,
+    /// rather than panicking over it as well.
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Transfer<'a, F>> {
+        self.transfer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<F: Fn(&DownloadEvent)> Pace for SharedTransfer<'_, '_, F> {
+    fn next_chunk(&mut self) -> Result<bool, DownloadError> {
+        // Sleep off the rate limits without holding the transfer, so the other segments keep
+        // reading meanwhile, and check them again after, since those segments may have spent
+        // what this one waited for.
+        loop {
+            if self.stop.load(Ordering::Relaxed) {
+                return Ok(false);
+            }
+            let delay = {
+                let mut transfer = self.lock();
+                transfer.check_deadline()?;
+                transfer.limit_delay()
+            };
+            if delay.is_zero() {
+                return Ok(true);
+            }
+            std::thread::sleep(delay);
+        }
+    }
+
+    fn admit(&self, bytes: u64) -> Result<(), DownloadError> {
+        Ok(self.lock().admit(bytes)?)
+    }
+
+    fn record(&mut self, chunk: &[u8]) {
+        self.lock().record(chunk);
+    }
+
+    fn chunk_size(&self) -> usize {
+        self.lock().chunk_size
+    }
+}
+
+/// Copies `body` into `sink` one chunk at a time, returning how many bytes were copied.
+///
+/// This is the chunk loop of every blocking download. Between chunks it stops if the download
+/// was cancelled and lets `pace` enforce the time and rate limits; every chunk is checked
+/// against the size limit before it is written and reported once it has been.
+///
+/// # Arguments
+///
+/// * `url` - The URL the body comes from, named by read errors.
+/// * `body` - The body of the response.
+/// * `sink` - Where the chunks are written.
+/// * `pace` - The bookkeeping around every chunk.
+/// * `context` - The state shared with the batch, for cancellation.
+pub(crate) fn copy_body(
+    url: &str,
+    body: &mut impl Read,
+    sink: &mut impl BodySink,
+    pace: &mut impl Pace,
+    context: &Context,
+) -> Result<u64, DownloadError> {
+    // Allocate a fixed-size buffer that is reused for every chunk read from the body.
+    let mut buffer = vec![0; pace.chunk_size()];
+    let mut copied = 0;
+
+    loop {
+        // Stop between chunks if the download was cancelled, then wait for the limits.
+        context.control.checkpoint()?;
+        if !pace.next_chunk()? {
+            break;
+        }
+
+        let read = match body.read(&mut buffer) {
+            Ok(read) => read,
+            // Interrupted reads carry no data and are safe to retry immediately.
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(DownloadError::body(url, e)),
+        };
+        // A zero-length read means the body has been fully consumed.
+        if read == 0 {
+            break;
+        }
+
+        // Write the chunk once it fits the size limit, then report the updated progress.
+        let chunk = &buffer[..read];
+        pace.admit(read as u64)?;
+        sink.write_chunk(chunk)?;
+        pace.record(chunk);
+        copied += read as u64;
+    }
+
+    Ok(copied)
+}
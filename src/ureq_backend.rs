@@ -0,0 +1,236 @@
+use crate::backend::{HttpBackend, HttpRequest, HttpResponse};
+use crate::config::{BatchConfig, InvalidConfig};
+use crate::error::DownloadError;
+use crate::protocol::HttpVersion;
+use crate::spans::warn;
+use http::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH};
+use http::{StatusCode, Version};
+use std::error::Error;
+use std::io;
+use url::Url;
+
+/// An [`HttpBackend`] sending requests with a blocking [`ureq::Agent`] instead of reqwest.
+/// Only available with the `ureq` feature.
+///
+/// Pass it wherever a single blocking download takes a client, such as
+/// [`crate::download_file_with_config`]; retries, redirects, resumption and every check of
+/// the response work as they do with reqwest. Batches build a reqwest client, unless the crate
+/// is built without the `reqwest` feature, in which case they build one of these.
+///
+/// ureq doesn't expose the certificate a server presented, so downloads from hosts with
+/// [`crate::DownloadConfig::pinned_keys`] are refused. Clones share the agent's connection
+/// pool.
+#[derive(Debug, Clone)]
+pub struct UreqBackend {
+    /// The agent every request is sent with.
+    agent: ureq::Agent,
+}
+
+impl UreqBackend {
+    /// Creates a backend applying the settings of `config` that ureq has an equivalent for:
+    /// the [`BatchConfig::user_agent`], the connect and read limits of
+    /// [`crate::DownloadConfig::timeouts`], the idle connections per host and `TCP_NODELAY` of
+    /// [`BatchConfig::pool`], the overrides and [`crate::IpFamily`] of [`BatchConfig::dns`],
+    /// and the environment's proxy or a single [`BatchConfig::proxy`] for both schemes.
+    ///
+    /// ureq only speaks HTTP/1.1 and reads `HTTPS_PROXY`, `HTTP_PROXY` and `ALL_PROXY` but not
+    /// `NO_PROXY`. The other settings, such as separate or SOCKS5 proxies,
+    /// [`BatchConfig::local_address`], a custom resolver and every TLS setting, aren't applied,
+    /// with a warning naming them; build an agent with them and wrap it with
+    /// [`UreqBackend::from`] instead. Batches of a build with only the `ureq` feature refuse
+    /// them with an [`InvalidConfig`].
+    pub fn new(config: &BatchConfig) -> Self {
+        let (backend, ignored) = Self::build(config);
+        if !ignored.is_empty() {
+            let names: Vec<_> = ignored.iter().map(|ignored| ignored.setting).collect();
+            warn!(
+                "ureq can't apply these settings, so they are ignored: {}",
+                names.join(", ")
+            );
+        }
+        backend
+    }
+
+    /// Creates a backend as [`UreqBackend::new`] does, for the batches of a build without
+    /// reqwest.
+    ///
+    /// # Returns
+    ///
+    /// * `Err` with an [`InvalidConfig`] naming the first setting of `config` ureq can't
+    ///   apply.
+    #[cfg(not(feature = "reqwest"))]
+    pub(crate) fn strict(config: &BatchConfig) -> Result<Self, InvalidConfig> {
+        let (backend, ignored) = Self::build(config);
+        match ignored.into_iter().next() {
+            Some(ignored) => Err(ignored),
+            None => Ok(backend),
+        }
+    }
+
+    /// Builds the agent for `config`, returning it with the settings it couldn't apply.
+    fn build(config: &BatchConfig) -> (Self, Vec<InvalidConfig>) {
+        let mut ignored = Vec::new();
+        let mut unsupported = |setting: &'static str, reason: &str| {
+            ignored.push(InvalidConfig {
+                setting,
+                reason: format!("{} with the ureq backend", reason),
+            });
+        };
+        let timeouts = &config.download.timeouts;
+        let mut builder = ureq::AgentBuilder::new()
+            .user_agent(&config.user_agent)
+            .redirects(0)
+            .max_idle_connections_per_host(config.pool.max_idle_per_host)
+            .no_delay(config.pool.tcp_nodelay);
+        if let Some(connect) = timeouts.connect {
+            builder = builder.timeout_connect(connect);
+        }
+        if let Some(read) = timeouts.read {
+            builder = builder.timeout_read(read);
+        }
+
+        let proxy = &config.proxy;
+        match (&proxy.http, &proxy.https) {
+            (None, None) if proxy.socks5.is_none() => {
+                builder = builder.try_proxy_from_env(proxy.use_env);
+            }
+            // ureq sends every request through the one proxy it has.
+            (Some(http), Some(https))
+                if http == https && proxy.socks5.is_none() && proxy.no_proxy.is_empty() =>
+            {
+                match ureq::Proxy::new(http) {
+                    Ok(proxy) => builder = builder.proxy(proxy),
+                    Err(e) => unsupported("proxy", &e.to_string()),
+                }
+            }
+            _ => unsupported(
+                "proxy",
+                "only a single HTTP proxy for both schemes, without exceptions, is supported",
+            ),
+        }
+        if config.http.version == HttpVersion::Http2PriorKnowledge {
+            unsupported("http", "HTTP/2 isn't supported");
+        }
+        #[cfg(feature = "reqwest")]
+        if config.dns.resolver.is_some() {
+            unsupported("dns", "a custom resolver isn't supported");
+        }
+        if let Some(resolver) = config.dns.ureq_resolver() {
+            builder = builder.resolver(resolver);
+        }
+        if config.local_address.is_some() {
+            unsupported(
+                "local_address",
+                "binding to a local address isn't supported",
+            );
+        }
+        let tls = &config.tls;
+        #[cfg(feature = "reqwest")]
+        let custom_tls = !tls.root_certificates.is_empty()
+            || tls.min_version.is_some()
+            || tls.max_version.is_some();
+        #[cfg(not(feature = "reqwest"))]
+        let custom_tls = false;
+        if tls.danger_accept_invalid_certs || custom_tls {
+            unsupported("tls", "TLS settings aren't supported");
+        }
+        let backend = Self {
+            agent: builder.build(),
+        };
+        (backend, ignored)
+    }
+}
+
+impl Default for UreqBackend {
+    fn default() -> Self {
+        Self::new(&BatchConfig::default())
+    }
+}
+
+/// Wraps an agent built by the caller. It should be built with
+/// [`ureq::AgentBuilder::redirects`] set to `0`, since the downloader follows redirects
+/// itself.
+impl From<ureq::Agent> for UreqBackend {
+    fn from(agent: ureq::Agent) -> Self {
+        Self { agent }
+    }
+}
+
+impl HttpBackend for UreqBackend {
+    fn send(&self, request: HttpRequest) -> Result<HttpResponse, DownloadError> {
+        let mut call = self.agent.request(request.method.as_str(), &request.url);
+        for (name, value) in &request.headers {
+            call = call.set(name.as_str(), &String::from_utf8_lossy(value.as_bytes()));
+        }
+        // Error statuses are responses like any other to the downloader.
+        let response = match call.call() {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(ureq::Error::Transport(transport)) => {
+                return Err(DownloadError::body(
+                    &request.url,
+                    transport_error(transport),
+                ))
+            }
+        };
+
+        let url = Url::parse(response.get_url()).map_err(|error| {
+            DownloadError::body(
+                &request.url,
+                io::Error::new(io::ErrorKind::InvalidData, error),
+            )
+        })?;
+        let mut headers = HeaderMap::new();
+        for name in response.headers_names() {
+            let Ok(header) = HeaderName::from_bytes(name.as_bytes()) else {
+                continue;
+            };
+            for value in response.all(&name) {
+                if let Ok(value) = HeaderValue::from_str(value) {
+                    headers.append(header.clone(), value);
+                }
+            }
+        }
+        let content_length = headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse().ok());
+        let version = match response.http_version() {
+            "HTTP/1.0" => Version::HTTP_10,
+            _ => Version::HTTP_11,
+        };
+        Ok(HttpResponse {
+            status: StatusCode::from_u16(response.status()).unwrap_or(StatusCode::BAD_GATEWAY),
+            headers,
+            url,
+            version,
+            remote_addr: Some(response.remote_addr()),
+            content_length,
+            peer_certificate: None,
+            body: response.into_reader(),
+        })
+    }
+}
+
+/// Turns a failure to get a response into an I/O error whose kind tells the downloader
+/// whether to retry. Its message leaves out the URL, which may hold credentials.
+fn transport_error(transport: ureq::Transport) -> io::Error {
+    let kind = match transport
+        .source()
+        .and_then(|source| source.downcast_ref::<io::Error>())
+    {
+        Some(source) => source.kind(),
+        None => match transport.kind() {
+            ureq::ErrorKind::ConnectionFailed => io::ErrorKind::ConnectionRefused,
+            ureq::ErrorKind::Io => io::ErrorKind::ConnectionReset,
+            ureq::ErrorKind::InvalidUrl | ureq::ErrorKind::UnknownScheme => {
+                io::ErrorKind::InvalidInput
+            }
+            _ => io::ErrorKind::Other,
+        },
+    };
+    let message = match (transport.message(), transport.source()) {
+        (Some(message), _) => format!("{}: {}", transport.kind(), message),
+        (None, Some(source)) => format!("{}: {}", transport.kind(), source),
+        (None, None) => transport.kind().to_string(),
+    };
+    io::Error::new(kind, message)
+}
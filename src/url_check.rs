@@ -0,0 +1,132 @@
+use crate::error::DownloadError;
+use crate::protocol::ProtocolVersion;
+use crate::request::DownloadRequest;
+use http::Version;
+use std::error::Error;
+use std::fmt;
+use url::Url;
+
+/// Returned when the URL of a download is malformed or uses a scheme the downloader doesn't
+/// support.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidUrl {
+    /// The position of the request in its batch, when the batch checked its URLs before
+    /// starting, or `None` when the download found the URL invalid.
+    pub index: Option<usize>,
+    /// The URL as it was given.
+    pub url: String,
+    /// Why the URL was rejected.
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(index) = self.index {
+            write!(f, "request {}: ", index)?;
+        }
+        write!(f, "`{}` is not a valid URL: {}", self.url, self.reason)
+    }
+}
+
+impl Error for InvalidUrl {}
+
+/// Parses `text` as an absolute URL of a supported scheme and returns it normalized, or why
+/// it was rejected.
+///
+/// Normalizing lowercases the scheme and host, drops ports that are the scheme's default,
+/// adds the `/` path of a bare host, and strips the fragment, which is never sent to the
+/// server anyway.
+pub(crate) fn normalize_url(text: &str) -> Result<String, String> {
+    let mut url = Url::parse(text.trim()).map_err(|e| e.to_string())?;
+    match url.scheme() {
+        "http" | "https" | "file" | "data" => {}
+        #[cfg(feature = "ftp")]
+        "ftp" => {}
+        "sftp" => {
+            return Err(
+                "unsupported scheme `sftp`: the crate has no SSH client to download over".into(),
+            )
+        }
+        scheme => return Err(format!("unsupported scheme `{}`", scheme)),
+    }
+    url.set_fragment(None);
+    Ok(url.into())
+}
+
+/// The HTTP version a response from `url` was served with, or `None` for `file://`, `data:`
+/// and `ftp://` URLs, whose responses only stand in for HTTP ones.
+pub(crate) fn http_version(url: &Url, version: Version) -> Option<ProtocolVersion> {
+    matches!(url.scheme(), "http" | "https").then(|| version.into())
+}
+
+/// Fails with an [`InvalidUrl`] if `url` wouldn't be accepted by [`normalize_url`], so a
+/// download refuses it before anything is sent or written.
+pub(crate) fn check_url(url: &str) -> Result<(), DownloadError> {
+    match normalize_url(url) {
+        Ok(_) => Ok(()),
+        Err(reason) => Err(InvalidUrl {
+            index: None,
+            url: url.to_string(),
+            reason,
+        }
+        .into()),
+    }
+}
+
+/// Normalizes the URLs and mirrors of a batch's requests before any download starts.
+///
+/// # Arguments
+///
+/// * `requests` - The requests of the batch, whose URLs are normalized in place.
+/// * `strict` - Whether an invalid URL fails the batch. Otherwise it is left as it was, and
+///   its download fails right away with an [`InvalidUrl`] without contacting anyone.
+///
+/// # Returns
+///
+/// * `Ok` once every valid URL is normalized.
+/// * `Err` with an [`InvalidUrl`] naming the first request with an invalid URL in strict mode.
+pub(crate) fn normalize_requests(
+    requests: &mut [DownloadRequest],
+    strict: bool,
+) -> Result<(), DownloadError> {
+    for (index, request) in requests.iter_mut().enumerate() {
+        normalize_request(index, request, strict)?;
+    }
+    Ok(())
+}
+
+/// Normalizes the URL and mirrors of the request at `index`; see [`normalize_requests`].
+pub(crate) fn normalize_request(
+    index: usize,
+    request: &mut DownloadRequest,
+    strict: bool,
+) -> Result<(), DownloadError> {
+    for url in std::iter::once(&mut request.url).chain(&mut request.mirrors) {
+        match normalize_url(url) {
+            Ok(normalized) => *url = normalized,
+            Err(reason) if strict => {
+                return Err(InvalidUrl {
+                    index: Some(index),
+                    url: url.clone(),
+                    reason,
+                }
+                .into());
+            }
+            Err(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Returns `url` with the user name and password of its userinfo removed, so it can be shown
+/// in errors and logs. Text that doesn't parse as a URL is returned as it was.
+pub(crate) fn redact_url(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(mut parsed) if !parsed.username().is_empty() || parsed.password().is_some() => {
+            let _ = parsed.set_username("");
+            let _ = parsed.set_password(None);
+            parsed.into()
+        }
+        _ => url.to_string(),
+    }
+}
@@ -0,0 +1,144 @@
+use crate::error::DownloadError;
+use crate::url_check::normalize_url;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Lines};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A line of a URL list that is not a valid `http` or `https` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MalformedUrl {
+    /// The 1-based number of the line.
+    pub line: usize,
+    /// The trimmed contents of the line.
+    pub text: String,
+    /// Why the line was rejected.
+    pub reason: String,
+}
+
+impl fmt::Display for MalformedUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}: `{}` is not a valid URL: {}",
+            self.line, self.text, self.reason
+        )
+    }
+}
+
+impl Error for MalformedUrl {}
+
+/// Reads newline-delimited URLs lazily, one line at a time.
+///
+/// Whitespace around each line is trimmed, and blank lines and lines starting with `#` are
+/// ignored. Lines are only read as the iterator is advanced, so a list of any length can be
+/// fed to [`crate::download_batch_stream`] without loading it into memory.
+///
+/// In strict mode a malformed line is yielded as a [`DownloadError::MalformedUrl`], which
+/// stops a streamed batch. Otherwise malformed lines are left out and collected in
+/// [`UrlList::skipped`]. Failing to read the list is always yielded as an error.
+pub struct UrlList<R> {
+    /// The lines of the list that haven't been read yet.
+    lines: Lines<R>,
+    /// Where the list is read from, used in I/O errors.
+    path: PathBuf,
+    /// Number of lines read so far.
+    line: usize,
+    /// Whether a malformed line is an error rather than skipped.
+    strict: bool,
+    /// Malformed lines left out so far.
+    skipped: Arc<Mutex<Vec<MalformedUrl>>>,
+}
+
+impl<R> fmt::Debug for UrlList<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UrlList")
+            .field("path", &self.path)
+            .field("line", &self.line)
+            .field("strict", &self.strict)
+            .finish_non_exhaustive()
+    }
+}
+
+impl UrlList<Box<dyn BufRead + Send>> {
+    /// Opens the URL list at `path`, or reads from stdin when `path` is `-`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to read, or `-` for stdin.
+    /// * `strict` - Whether a malformed line is an error rather than skipped.
+    pub fn open(path: impl AsRef<Path>, strict: bool) -> Result<Self, DownloadError> {
+        let path = path.as_ref();
+        let reader: Box<dyn BufRead + Send> = if path.as_os_str() == "-" {
+            Box::new(BufReader::new(io::stdin()))
+        } else {
+            let file = File::open(path).map_err(DownloadError::io(path))?;
+            Box::new(BufReader::new(file))
+        };
+        let mut list = Self::new(reader, strict);
+        list.path = path.to_path_buf();
+        Ok(list)
+    }
+}
+
+impl<R: BufRead> UrlList<R> {
+    /// Reads a URL list from `reader`.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Where the lines come from.
+    /// * `strict` - Whether a malformed line is an error rather than skipped.
+    pub fn new(reader: R, strict: bool) -> Self {
+        Self {
+            lines: reader.lines(),
+            path: PathBuf::from("-"),
+            line: 0,
+            strict,
+            skipped: Arc::default(),
+        }
+    }
+
+    /// Returns the malformed lines left out so far.
+    ///
+    /// The list is shared, so the returned handle keeps filling up while the iterator is
+    /// consumed elsewhere, such as by a streamed batch.
+    pub fn skipped(&self) -> Arc<Mutex<Vec<MalformedUrl>>> {
+        Arc::clone(&self.skipped)
+    }
+}
+
+impl<R: BufRead> Iterator for UrlList<R> {
+    type Item = Result<String, DownloadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(DownloadError::io(&self.path)(e))),
+            };
+            self.line += 1;
+
+            let text = line.trim();
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+
+            match normalize_url(text) {
+                Ok(url) => return Some(Ok(url)),
+                Err(reason) => {
+                    let malformed = MalformedUrl {
+                        line: self.line,
+                        text: text.to_string(),
+                        reason,
+                    };
+                    if self.strict {
+                        return Some(Err(malformed.into()));
+                    }
+                    self.skipped.lock().unwrap().push(malformed);
+                }
+            }
+        }
+    }
+}
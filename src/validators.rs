@@ -0,0 +1,120 @@
+use crate::spans::warn;
+use http::header::{HeaderMap, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Suffix of the sidecar file that stores the validators of a downloaded file.
+const SIDECAR_SUFFIX: &str = ".validators";
+
+/// The `ETag` and `Last-Modified` values a file was downloaded with.
+///
+/// They are kept in a small sidecar next to the file (`<file>.validators`) so a later run can
+/// ask the server whether the file changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Validators {
+    pub(crate) etag: Option<String>,
+    pub(crate) last_modified: Option<String>,
+}
+
+impl Validators {
+    /// Extracts the validators from a response, or `None` if the server sent neither.
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let validators = Self {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        (validators != Self::default()).then_some(validators)
+    }
+
+    /// Reads the validators stored for the file at `path`.
+    ///
+    /// Returns `None` unless both the file and a readable sidecar exist.
+    pub(crate) fn load(path: &Path) -> Option<Self> {
+        if !path.is_file() {
+            return None;
+        }
+        let contents = std::fs::read_to_string(sidecar_path(path)).ok()?;
+
+        let mut validators = Self::default();
+        for line in contents.lines() {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = Some(value.trim().to_string());
+            if name.eq_ignore_ascii_case(ETAG.as_str()) {
+                validators.etag = value;
+            } else if name.eq_ignore_ascii_case(LAST_MODIFIED.as_str()) {
+                validators.last_modified = value;
+            }
+        }
+        (validators != Self::default()).then_some(validators)
+    }
+
+    /// Builds the `If-None-Match` and `If-Modified-Since` headers for a conditional request.
+    pub(crate) fn request_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let values = [
+            (IF_NONE_MATCH, &self.etag),
+            (IF_MODIFIED_SINCE, &self.last_modified),
+        ];
+        for (name, value) in values {
+            // A stored value that is no longer a valid header is simply not sent.
+            if let Some(value) = value.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+                headers.insert(name, value);
+            }
+        }
+        headers
+    }
+
+    /// Builds the value of an `If-Range` header, so a resumed request only gets the rest of
+    /// the file if it is unchanged.
+    ///
+    /// Weak `ETag`s can't be used for ranges, so `Last-Modified` is sent instead of one.
+    pub(crate) fn if_range(&self) -> Option<HeaderValue> {
+        let strong_etag = self.etag.as_deref().filter(|etag| !etag.starts_with("W/"));
+        let value = strong_etag.or(self.last_modified.as_deref())?;
+        HeaderValue::from_str(value).ok()
+    }
+
+    /// Writes the validators to the sidecar of the file at `path`.
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let mut contents = String::new();
+        if let Some(etag) = &self.etag {
+            contents.push_str(&format!("{}: {}\n", ETAG.as_str(), etag));
+        }
+        if let Some(last_modified) = &self.last_modified {
+            contents.push_str(&format!("{}: {}\n", LAST_MODIFIED.as_str(), last_modified));
+        }
+        std::fs::write(sidecar_path(path), contents)
+    }
+}
+
+/// Stores the validators of the file just saved at `path`, or removes a stale sidecar when the
+/// server sent none.
+///
+/// The download itself already succeeded, so failures are only logged.
+pub(crate) fn remember(path: &Path, validators: Option<&Validators>) {
+    let result = match validators {
+        Some(validators) => validators.save(path),
+        None => match std::fs::remove_file(sidecar_path(path)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        },
+    };
+    if let Err(e) = result {
+        warn!("Failed to update validators for {}: {}", path.display(), e);
+    }
+}
+
+/// Returns the sidecar file that stores the validators of the file at `path`.
+fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(SIDECAR_SUFFIX);
+    path.with_file_name(name)
+}
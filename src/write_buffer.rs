@@ -0,0 +1,124 @@
+#[cfg(feature = "gzip")]
+use crate::decompress::{Decoder, DECODE_STEP};
+use crate::disk_space::DiskGuard;
+use crate::error::DownloadError;
+use crate::transfer::BodySink;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// How downloaded bytes are written to the temporary file.
+///
+/// Progress always counts bytes as they are received, whether or not they have left the
+/// buffer yet. The buffer is flushed before the file is verified and moved into place, so
+/// [`crate::DownloadEvent::Completed`] is only sent for a file that is fully written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteConfig {
+    /// Bytes collected in memory before they are written to the file, so small chunks don't
+    /// each cost a write. `0` writes every chunk as it arrives. Defaults to 256 KiB.
+    pub buffer_size: usize,
+    /// Syncs the file to disk before it is moved into place, so a crash right after the
+    /// download completes can't leave a file with missing data behind. Defaults to `false`.
+    pub sync: bool,
+}
+
+impl Default for WriteConfig {
+    fn default() -> Self {
+        Self {
+            buffer_size: 256 * 1024,
+            sync: false,
+        }
+    }
+}
+
+/// Writes the chunks of a download into its temporary file through a buffer of
+/// [`WriteConfig::buffer_size`] bytes, checking now and then that the rest still fits.
+///
+/// With a decoder, what the chunks decompress to is written instead of the chunks.
+pub(crate) struct FileSink<'a> {
+    /// The file, behind its buffer.
+    file: BufWriter<File>,
+    /// Where the file is, named by write errors.
+    path: &'a Path,
+    /// Watches the free space while the file grows, if the download checks it.
+    disk_guard: Option<DiskGuard<'a>>,
+    /// How many bytes were written to the file.
+    written: u64,
+    /// Decompresses the chunks before they are written, if the download asks for it.
+    #[cfg(feature = "gzip")]
+    decoder: Option<Decoder>,
+}
+
+impl<'a> FileSink<'a> {
+    /// Starts writing to `file`, which is open at `path`.
+    pub(crate) fn new(
+        file: File,
+        path: &'a Path,
+        buffer_size: usize,
+        disk_guard: Option<DiskGuard<'a>>,
+    ) -> Self {
+        Self {
+            file: BufWriter::with_capacity(buffer_size, file),
+            path,
+            disk_guard,
+            written: 0,
+            #[cfg(feature = "gzip")]
+            decoder: None,
+        }
+    }
+
+    /// Decompresses the chunks with `decoder` before writing them.
+    #[cfg(feature = "gzip")]
+    pub(crate) fn decoding(mut self, decoder: Option<Decoder>) -> Self {
+        self.decoder = decoder;
+        self
+    }
+
+    /// Ends the decompressed stream if there is one, writes out whatever is still buffered
+    /// and hands the file back with the number of bytes written to it.
+    pub(crate) fn into_file(mut self) -> Result<(File, u64), DownloadError> {
+        self.finish_decoding()?;
+        let file = self
+            .file
+            .into_inner()
+            .map_err(|e| DownloadError::io(self.path)(e.into_error()))?;
+        Ok((file, self.written))
+    }
+
+    /// Writes out the last bytes the decoder holds, once the whole body was decoded.
+    fn finish_decoding(&mut self) -> Result<(), DownloadError> {
+        #[cfg(feature = "gzip")]
+        if let Some(mut decoder) = self.decoder.take() {
+            let rest = decoder.finish()?;
+            self.write_out(rest)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `bytes` to the file as they are.
+    fn write_out(&mut self, bytes: &[u8]) -> Result<(), DownloadError> {
+        self.file
+            .write_all(bytes)
+            .map_err(DownloadError::io(self.path))?;
+        self.written += bytes.len() as u64;
+        match &mut self.disk_guard {
+            Some(guard) => guard.record(bytes.len() as u64),
+            None => Ok(()),
+        }
+    }
+}
+
+impl BodySink for FileSink<'_> {
+    fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), DownloadError> {
+        #[cfg(feature = "gzip")]
+        if let Some(mut decoder) = self.decoder.take() {
+            let result = chunk.chunks(DECODE_STEP).try_for_each(|input| {
+                let decoded = decoder.decode(input)?;
+                self.write_out(decoded)
+            });
+            self.decoder = Some(decoder);
+            return result;
+        }
+        self.write_out(chunk)
+    }
+}
@@ -0,0 +1,296 @@
+use crate::backend::HttpBackend;
+use crate::checksum::BodyHasher;
+use crate::client::blocking_client;
+use crate::config::{BatchConfig, DownloadConfig};
+use crate::content_type::check_content_type;
+use crate::context::Context;
+use crate::download::{send_following, with_retries};
+use crate::encoding::is_encoded;
+use crate::error::DownloadError;
+use crate::event::DownloadEvent;
+use crate::headers::header_map;
+use crate::interceptor::for_attempt;
+use crate::length::check_advertised;
+use crate::presigned::status_error;
+use crate::protocol::ProtocolVersion;
+use crate::proxy::explain_proxy_error;
+use crate::timeout::explain_timeout;
+use crate::timing::Stopwatch;
+use crate::transfer::{copy_body, BodySink, Transfer};
+use crate::url_check::http_version;
+use http::header::{HeaderMap, CONTENT_TYPE};
+use http::{Method, StatusCode};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Instant;
+
+/// What a download into a writer delivered, and what the server said about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamedBody {
+    /// Bytes handed to the writer.
+    pub bytes: u64,
+    /// The status the server answered with, after following redirects.
+    pub status: StatusCode,
+    /// The `Content-Type` the server reported, if any.
+    pub content_type: Option<String>,
+    /// The URL the body was served from after following redirects.
+    pub final_url: String,
+    /// The HTTP version the body was served with, or `None` for `file://`, `data:` and
+    /// `ftp://` URLs.
+    pub http_version: Option<ProtocolVersion>,
+    /// The address of the server that sent the body, whose family tells whether IPv4 or IPv6
+    /// was used. `None` for `file://` and `data:` URLs, and for backends that can't tell.
+    pub remote_addr: Option<SocketAddr>,
+    /// SHA-256 of the body, when [`DownloadConfig::compute_sha256`] is enabled.
+    pub sha256: Option<String>,
+}
+
+/// Downloads the body of the given URL into `writer` instead of a file.
+///
+/// This is a convenience wrapper around [`download_to_writer_with_config`] that builds a new
+/// client and uses the default [`DownloadConfig`].
+///
+/// # Arguments
+///
+/// * `url` - A reference to a string or string-like value specifying the download URL.
+/// * `writer` - Where the body is written, such as a socket or an archive entry.
+/// * `callback` - A function or closure that receives every [`DownloadEvent`] for this download.
+///
+/// # Returns
+///
+/// * `Ok` with the size of the body and what the server reported about it.
+/// * `Err` if any error occurs.
+pub fn download_to_writer(
+    url: impl AsRef<str>,
+    writer: impl Write,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+) -> Result<StreamedBody, DownloadError> {
+    let client = blocking_client(&mut BatchConfig::default())?;
+    download_to_writer_with_config(&client, url, writer, &DownloadConfig::default(), callback)
+}
+
+/// Downloads the body of the given URL into `writer` using an existing client and explicit
+/// settings.
+///
+/// Every chunk is written to `writer` as it arrives, and the writer is flushed once the body
+/// is complete. The body is rate limited and checked against its size limit, length,
+/// checksum, and content type as [`crate::download_file_with_config`] would, and the callback
+/// receives the same events. As no file is written, [`DownloadEvent::Completed`] and
+/// [`DownloadEvent::Cancelled`] carry an empty path, and the settings that only concern
+/// files, such as [`DownloadConfig::resume`] and [`DownloadConfig::overwrite`], are ignored.
+///
+/// Bytes given to the writer can't be taken back, so failures are only retried until the
+/// first byte has been written. A checksum mismatch is found once the whole body has been
+/// written, and the caller should then discard what the writer received.
+///
+/// # Arguments
+///
+/// * `client` - The blocking HTTP client used to issue the request, or any other
+///   [`crate::HttpBackend`].
+/// * `url` - A reference to a string or string-like value specifying the download URL.
+/// * `writer` - Where the body is written, such as a socket or an archive entry.
+/// * `config` - The settings applied to this download.
+/// * `callback` - A function or closure that receives every [`DownloadEvent`] for this download.
+///
+/// # Returns
+///
+/// * `Ok` with the size of the body and what the server reported about it.
+/// * `Err` with [`DownloadError::Writer`] if `writer` failed, or with any other error of the
+///   download. When retries were attempted, the error is a [`crate::RetriesExhausted`]
+///   carrying the attempt count and the final underlying error.
+pub fn download_to_writer_with_config(
+    client: &impl HttpBackend,
+    url: impl AsRef<str>,
+    mut writer: impl Write,
+    config: &DownloadConfig,
+    callback: impl Fn(&DownloadEvent) + 'static + Send + Sync,
+) -> Result<StreamedBody, DownloadError> {
+    let url = url.as_ref();
+    let context = Context::default();
+    let stopwatch = Stopwatch::start();
+    let callback = |event: &DownloadEvent| {
+        stopwatch.observe(event);
+        callback(event);
+    };
+    let mut writer = Counted {
+        inner: &mut writer,
+        bytes: 0,
+    };
+    let result = with_retries(url, config, &context, &callback, |attempt| {
+        let config = &*for_attempt(config, attempt);
+        match stream_body(client, url, &mut writer, config, &context, &callback) {
+            // A failure after the writer got its first byte is final, so it is handed past the
+            // retry loop untouched.
+            Err(error) if writer.bytes > 0 => Ok(Err(error)),
+            result => result.map(Ok),
+        }
+    })
+    .and_then(|result| result);
+    report_end(result, &stopwatch, &callback)
+}
+
+/// Makes one attempt at streaming the body of `url` into `writer`.
+pub(crate) fn stream_body(
+    client: &dyn HttpBackend,
+    url: &str,
+    writer: &mut impl Write,
+    config: &DownloadConfig,
+    context: &Context,
+    callback: &impl Fn(&DownloadEvent),
+) -> Result<StreamedBody, DownloadError> {
+    read_body(client, url, writer, config, context, callback)
+        .map_err(|error| explain_timeout(error, &config.timeouts))
+        .map_err(|error| explain_proxy_error(error, url, &context.proxy))
+}
+
+/// Sends the terminal event of a download that wrote no file and passes its result on.
+pub(crate) fn report_end(
+    result: Result<StreamedBody, DownloadError>,
+    stopwatch: &Stopwatch,
+    callback: &impl Fn(&DownloadEvent),
+) -> Result<StreamedBody, DownloadError> {
+    match &result {
+        Ok(body) => callback(&DownloadEvent::Completed {
+            path: PathBuf::new(),
+            bytes: body.bytes,
+            sha256: body.sha256.clone(),
+            timings: stopwatch.timings(),
+        }),
+        Err(error) if error.is_cancelled() => callback(&DownloadEvent::Cancelled {
+            path: PathBuf::new(),
+        }),
+        Err(error) => callback(&DownloadEvent::Failed {
+            error: error.to_string(),
+            timings: stopwatch.timings(),
+        }),
+    }
+    result
+}
+
+/// Sends the request for `url` and copies its body into `writer`; see [`stream_body`].
+fn read_body(
+    client: &dyn HttpBackend,
+    url: &str,
+    writer: &mut impl Write,
+    config: &DownloadConfig,
+    context: &Context,
+    callback: &impl Fn(&DownloadEvent),
+) -> Result<StreamedBody, DownloadError> {
+    // Remember when the request started so progress can report speed and ETA.
+    let started = Instant::now();
+
+    config.check()?;
+    let headers = header_map(&config.headers)?;
+    let cookies = context.cookies.as_ref();
+    let mut response =
+        send_following(client, Method::GET, url, headers, config, cookies, callback)?;
+    let status = response.status;
+    if status.is_client_error() || status.is_server_error() {
+        return Err(status_error(url, response));
+    }
+    check_content_type(url, &response.headers, config)?;
+
+    // The length of a compressed body says nothing about the data, so its total stays unknown.
+    let expected_bytes = if is_encoded(&response.headers) {
+        None
+    } else {
+        response.content_length
+    };
+    check_advertised(url, expected_bytes, 0, config)?;
+
+    let mut transfer = Transfer::start(url, expected_bytes, 0, started, config, context, callback);
+    let mut sink = WriterSink {
+        writer,
+        hasher: config.checksum.as_ref().map(BodyHasher::new),
+    };
+    let written = copy_body(url, &mut response.body, &mut sink, &mut transfer, context)?;
+    sink.writer.flush().map_err(DownloadError::Writer)?;
+
+    // A body that ended short usually means the connection dropped, which is worth retrying.
+    if let Some(expected) = expected_bytes.filter(|_| !config.ignore_content_length) {
+        if written < expected {
+            return Err(DownloadError::body(
+                url,
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "received {} bytes but the server reported {} bytes",
+                        written, expected
+                    ),
+                ),
+            ));
+        }
+    }
+
+    if let (Some(hasher), Some(checksum)) = (sink.hasher, &config.checksum) {
+        hasher.verify(checksum)?;
+    }
+    let sha256 = transfer.take_sha256();
+    let content_type = response
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let final_url = response.url.to_string();
+    let http_version = http_version(&response.url, response.version);
+    let remote_addr = response.remote_addr;
+    transfer.finish(
+        PathBuf::new(),
+        sha256.clone(),
+        final_url.clone(),
+        http_version,
+        remote_addr,
+        HeaderMap::new(),
+    );
+
+    Ok(StreamedBody {
+        bytes: written,
+        status,
+        content_type,
+        final_url,
+        http_version,
+        remote_addr,
+        sha256,
+    })
+}
+
+/// Hands the chunks of a body to the caller's writer, hashing them for the checksum on the way.
+struct WriterSink<'w, W> {
+    /// The writer the body goes to.
+    writer: &'w mut W,
+    /// Hashes the body for [`DownloadConfig::checksum`], if one is expected.
+    hasher: Option<BodyHasher>,
+}
+
+impl<W: Write> BodySink for WriterSink<'_, W> {
+    fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), DownloadError> {
+        self.writer
+            .write_all(chunk)
+            .map_err(DownloadError::Writer)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(chunk);
+        }
+        Ok(())
+    }
+}
+
+/// A writer that counts the bytes it has been given.
+struct Counted<'w, W> {
+    /// The writer the bytes go to.
+    inner: &'w mut W,
+    /// Bytes written to `inner` so far.
+    bytes: u64,
+}
+
+impl<W: Write> Write for Counted<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,893 @@
+use flate2::Crc;
+use sha2::{Digest, Sha256};
+use std::io::{self, BufRead, Read};
+
+/// The bytes every xz stream starts with.
+pub(crate) const MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0];
+
+/// How many bytes a read decodes ahead of the caller at most, give or take a match.
+const DECODE_AHEAD: usize = 64 * 1024;
+
+/// The ID of the LZMA2 filter, the only one xz streams are decoded with.
+const LZMA2: u64 = 0x21;
+
+/// Returns the error for a stream that can't be decoded.
+fn corrupt(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+/// Decompresses an xz stream, such as a `.tar.xz` archive, as it is read.
+///
+/// Only LZMA2 blocks, which `xz` produces unless asked for other filters, can be decoded; a
+/// block using a BCJ or delta filter fails the read. Decoding stops at the index that ends the
+/// first stream. The CRC-32s of the headers are verified, and so is the CRC-32, CRC-64 or
+/// SHA-256 check of every block; checks of other types are skipped.
+pub(crate) struct XzReader<R> {
+    /// The compressed stream.
+    input: Input<R>,
+    /// The integrity check of the block being decoded.
+    check: Check,
+    /// Where in the stream decoding stands.
+    stage: Stage,
+    /// The chunk being decoded.
+    chunk: Chunk,
+    /// Where the block being decoded started in the stream, for its padding.
+    block_start: u64,
+    /// Whether the next chunk must reset the dictionary, as the first chunk of a block does.
+    needs_dictionary_reset: bool,
+    /// Whether the next LZMA chunk must set new properties.
+    needs_properties: bool,
+    /// The decoder of LZMA chunks.
+    lzma: Lzma,
+    /// The range decoder of the current LZMA chunk.
+    range: RangeDecoder,
+    /// The dictionary, which also holds the decoded bytes not yet read.
+    window: Window,
+    /// How many of the decoded bytes have been read.
+    read: usize,
+}
+
+/// Where in the xz stream decoding stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    /// Expecting the header of a block, or the index after the last one.
+    Block,
+    /// Decoding the LZMA2 chunks of a block.
+    Chunks,
+    /// The index was reached, so there is nothing more to decode.
+    End,
+}
+
+/// The LZMA2 chunk being decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Chunk {
+    /// Between chunks.
+    None,
+    /// A chunk stored as is, with so many bytes left to copy.
+    Stored { left: usize },
+    /// An LZMA chunk with so many decoded bytes left to produce, whose compressed data ends
+    /// at `end` in the stream.
+    Lzma { left: usize, end: u64 },
+}
+
+impl<R: BufRead> XzReader<R> {
+    /// Starts decoding the xz stream `inner`, reading its header.
+    pub(crate) fn new(inner: R) -> io::Result<Self> {
+        let mut input = Input {
+            inner,
+            consumed: 0,
+            crc: None,
+        };
+        let mut header = [0; 12];
+        for byte in &mut header {
+            *byte = input.byte()?;
+        }
+        if header[..6] != MAGIC || header[6] != 0 || header[7] > 0x0F {
+            return Err(corrupt("the xz stream header is malformed"));
+        }
+        let mut crc = Crc::new();
+        crc.update(&header[6..8]);
+        if crc.sum().to_le_bytes() != header[8..] {
+            return Err(corrupt("the xz stream header doesn't match its CRC-32"));
+        }
+        Ok(Self {
+            input,
+            check: Check::new(header[7]),
+            stage: Stage::Block,
+            chunk: Chunk::None,
+            block_start: 0,
+            needs_dictionary_reset: true,
+            needs_properties: true,
+            lzma: Lzma::new(),
+            range: RangeDecoder::default(),
+            window: Window::default(),
+            read: 0,
+        })
+    }
+
+    /// Decodes more of the stream into the window.
+    ///
+    /// Returns `false` once the stream has ended.
+    fn fill(&mut self) -> io::Result<bool> {
+        loop {
+            match self.chunk {
+                Chunk::Lzma { left, end } if left > 0 => {
+                    let mut left = left;
+                    while left > 0 && self.window.pending.len() < DECODE_AHEAD {
+                        let before = self.window.total;
+                        self.lzma.decode(
+                            &mut self.range,
+                            &mut self.window,
+                            left,
+                            &mut self.input,
+                        )?;
+                        left -= (self.window.total - before) as usize;
+                    }
+                    // A finished chunk may leave the last bytes of its range coder unread.
+                    if left == 0 {
+                        let unread = end
+                            .checked_sub(self.input.consumed)
+                            .ok_or_else(|| corrupt("an LZMA chunk overruns its size"))?;
+                        self.input.skip(unread)?;
+                    }
+                    self.chunk = Chunk::Lzma { left, end };
+                    return Ok(true);
+                }
+                Chunk::Stored { left } if left > 0 => {
+                    let count = left.min(DECODE_AHEAD);
+                    for _ in 0..count {
+                        let byte = self.input.byte()?;
+                        self.window.put(byte);
+                    }
+                    self.chunk = Chunk::Stored { left: left - count };
+                    return Ok(true);
+                }
+                _ => {}
+            }
+
+            match self.stage {
+                Stage::Block => self.block_header()?,
+                Stage::Chunks => self.chunk_header()?,
+                Stage::End => return Ok(false),
+            }
+        }
+    }
+
+    /// Reads the header of the next block, or notices the index that follows the last one.
+    fn block_header(&mut self) -> io::Result<()> {
+        let size = self.input.byte()?;
+        if size == 0 {
+            self.stage = Stage::End;
+            return Ok(());
+        }
+        self.block_start = self.input.consumed - 1;
+        // The size counts the header in units of four bytes, the last four being its CRC.
+        let check_at = self.block_start + (u64::from(size) + 1) * 4 - 4;
+        let mut crc = Crc::new();
+        crc.update(&[size]);
+        self.input.crc = Some(crc);
+
+        let flags = self.input.byte()?;
+        if flags & 0x3C != 0 {
+            return Err(corrupt("an xz block header sets reserved flags"));
+        }
+        if flags & 0x40 != 0 {
+            self.input.varint()?;
+        }
+        if flags & 0x80 != 0 {
+            self.input.varint()?;
+        }
+        if flags & 0x03 != 0 || self.input.varint()? != LZMA2 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the xz stream uses filters other than LZMA2, which aren't supported",
+            ));
+        }
+        if self.input.varint()? != 1 {
+            return Err(corrupt("the LZMA2 properties are malformed"));
+        }
+        let dictionary = self.input.byte()?;
+        if dictionary > 40 {
+            return Err(corrupt("the LZMA2 dictionary size is invalid"));
+        }
+        let dictionary_size = match dictionary {
+            40 => u32::MAX,
+            bits => (2 | u32::from(bits & 1)) << (bits / 2 + 11),
+        };
+        self.window.resize(dictionary_size as usize);
+
+        // Skip the padding, then compare the CRC of the header.
+        let padding = check_at
+            .checked_sub(self.input.consumed)
+            .ok_or_else(|| corrupt("an xz block header overruns its size"))?;
+        self.input.skip(padding)?;
+        let crc = self.input.crc.take().map_or(0, |crc| crc.sum());
+        if crc.to_le_bytes()
+            != [
+                self.input.byte()?,
+                self.input.byte()?,
+                self.input.byte()?,
+                self.input.byte()?,
+            ]
+        {
+            return Err(corrupt("an xz block header doesn't match its CRC-32"));
+        }
+        self.stage = Stage::Chunks;
+        self.needs_dictionary_reset = true;
+        self.needs_properties = true;
+        Ok(())
+    }
+
+    /// Reads the control byte of the next LZMA2 chunk, and whatever it says comes with it.
+    fn chunk_header(&mut self) -> io::Result<()> {
+        let control = self.input.byte()?;
+        match control {
+            // The block ends, padded to four bytes and followed by its integrity check.
+            0x00 => {
+                let size = self.input.consumed - self.block_start;
+                self.input.skip((4 - size % 4) % 4)?;
+                match self.check.finish() {
+                    Some(expected) => {
+                        for byte in expected {
+                            if self.input.byte()? != byte {
+                                return Err(corrupt(
+                                    "an xz block doesn't match its integrity check",
+                                ));
+                            }
+                        }
+                    }
+                    None => self.input.skip(self.check.size())?,
+                }
+                self.chunk = Chunk::None;
+                self.stage = Stage::Block;
+            }
+            0x01 | 0x02 => {
+                self.reset_dictionary(control == 0x01)?;
+                let size = usize::from(self.input.u16()?) + 1;
+                self.chunk = Chunk::Stored { left: size };
+            }
+            0x80..=0xFF => {
+                let reset = (control >> 5) & 0x03;
+                let size = (usize::from(control & 0x1F) << 16 | usize::from(self.input.u16()?)) + 1;
+                let compressed = u64::from(self.input.u16()?) + 1;
+                self.reset_dictionary(reset == 3)?;
+                if reset >= 2 {
+                    let properties = self.input.byte()?;
+                    self.lzma.set_properties(properties)?;
+                    self.needs_properties = false;
+                } else if self.needs_properties {
+                    return Err(corrupt("an LZMA chunk comes without properties"));
+                }
+                if reset >= 1 {
+                    self.lzma.reset();
+                }
+                let end = self.input.consumed + compressed;
+                self.range = RangeDecoder::new(&mut self.input)?;
+                self.chunk = Chunk::Lzma { left: size, end };
+            }
+            _ => return Err(corrupt("an LZMA2 chunk has an invalid control byte")),
+        }
+        Ok(())
+    }
+
+    /// Empties the dictionary if `reset` is set, which the first chunk of a block must do.
+    fn reset_dictionary(&mut self, reset: bool) -> io::Result<()> {
+        if reset {
+            self.window.clear();
+            self.needs_dictionary_reset = false;
+        } else if self.needs_dictionary_reset {
+            return Err(corrupt(
+                "an LZMA2 block doesn't start with a dictionary reset",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for XzReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read == self.window.pending.len() {
+            self.check.update(&self.window.pending);
+            self.window.pending.clear();
+            self.read = 0;
+            if buf.is_empty() || !self.fill()? {
+                return Ok(0);
+            }
+        }
+        let pending = &self.window.pending[self.read..];
+        let count = pending.len().min(buf.len());
+        buf[..count].copy_from_slice(&pending[..count]);
+        self.read += count;
+        Ok(count)
+    }
+}
+
+/// The compressed stream, counting the bytes taken from it.
+struct Input<R> {
+    /// The stream.
+    inner: R,
+    /// How many bytes have been taken so far.
+    consumed: u64,
+    /// The CRC-32 of the bytes taken since it was set, while reading a block header.
+    crc: Option<Crc>,
+}
+
+impl<R: BufRead> Input<R> {
+    /// Takes the next byte, failing at the end of the stream.
+    fn byte(&mut self) -> io::Result<u8> {
+        let byte = match self.inner.fill_buf()?.first() {
+            Some(&byte) => byte,
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        };
+        self.inner.consume(1);
+        self.consumed += 1;
+        if let Some(crc) = &mut self.crc {
+            crc.update(&[byte]);
+        }
+        Ok(byte)
+    }
+
+    /// Takes a big-endian 16-bit number.
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_be_bytes([self.byte()?, self.byte()?]))
+    }
+
+    /// Takes a number encoded seven bits per byte, least significant first.
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut value = 0;
+        for shift in (0..63).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(corrupt("a number in the xz stream is too long"))
+    }
+
+    /// Skips `count` bytes.
+    fn skip(&mut self, count: u64) -> io::Result<()> {
+        for _ in 0..count {
+            self.byte()?;
+        }
+        Ok(())
+    }
+}
+
+/// The integrity check of an xz block, computed over the bytes it decodes to.
+enum Check {
+    /// No check.
+    None,
+    /// A CRC-32.
+    Crc32(Crc),
+    /// A CRC-64, as ECMA-182 defines it.
+    Crc64(u64),
+    /// A SHA-256 digest.
+    Sha256(Sha256),
+    /// A check of a type that isn't verified, taking so many bytes.
+    Other(u64),
+}
+
+/// The CRC-64 of every byte value, for the reflected ECMA-182 polynomial.
+const CRC64_TABLE: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut crc = byte as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xC96C_5795_D787_0F42 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[byte] = crc;
+        byte += 1;
+    }
+    table
+};
+
+impl Check {
+    /// Starts the check of the type the stream header names.
+    fn new(id: u8) -> Self {
+        match id {
+            0x00 => Self::None,
+            0x01 => Self::Crc32(Crc::new()),
+            0x04 => Self::Crc64(u64::MAX),
+            0x0A => Self::Sha256(Sha256::new()),
+            // The check types are grouped by three, each group twice the size of the last.
+            id => Self::Other(4 << ((id - 1) / 3)),
+        }
+    }
+
+    /// How many bytes the check takes after a block.
+    fn size(&self) -> u64 {
+        match self {
+            Self::None => 0,
+            Self::Crc32(_) => 4,
+            Self::Crc64(_) => 8,
+            Self::Sha256(_) => 32,
+            Self::Other(size) => *size,
+        }
+    }
+
+    /// Adds decoded bytes to the check.
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Crc32(crc) => crc.update(bytes),
+            Self::Crc64(crc) => {
+                for &byte in bytes {
+                    *crc = CRC64_TABLE[((*crc ^ u64::from(byte)) & 0xFF) as usize] ^ (*crc >> 8);
+                }
+            }
+            Self::Sha256(hasher) => hasher.update(bytes),
+            Self::None | Self::Other(_) => {}
+        }
+    }
+
+    /// Returns the bytes the check of the block that just ended must be, and starts the check
+    /// of the next one.
+    ///
+    /// Returns `None` for a check that isn't verified.
+    fn finish(&mut self) -> Option<Vec<u8>> {
+        match self {
+            Self::None => Some(Vec::new()),
+            Self::Crc32(crc) => {
+                let sum = crc.sum();
+                *crc = Crc::new();
+                Some(sum.to_le_bytes().to_vec())
+            }
+            Self::Crc64(crc) => Some((!std::mem::replace(crc, u64::MAX)).to_le_bytes().to_vec()),
+            Self::Sha256(hasher) => Some(hasher.finalize_reset().to_vec()),
+            Self::Other(_) => None,
+        }
+    }
+}
+
+/// The dictionary of the LZMA2 decoder: the last decoded bytes, which matches copy from.
+#[derive(Debug, Default)]
+struct Window {
+    /// The bytes, filled up to `size` and then overwritten in a circle.
+    buf: Vec<u8>,
+    /// How many bytes the dictionary holds at most.
+    size: usize,
+    /// Where the next byte goes in `buf`.
+    position: usize,
+    /// How many bytes were decoded since the dictionary was last reset.
+    total: u64,
+    /// The decoded bytes, as they are handed to the reader.
+    pending: Vec<u8>,
+}
+
+impl Window {
+    /// Empties the dictionary and sets how many bytes it holds.
+    fn resize(&mut self, size: usize) {
+        self.size = size;
+        self.clear();
+    }
+
+    /// Empties the dictionary.
+    fn clear(&mut self) {
+        self.buf.clear();
+        self.position = 0;
+        self.total = 0;
+    }
+
+    /// Appends a decoded byte.
+    fn put(&mut self, byte: u8) {
+        if self.buf.len() < self.size {
+            self.buf.push(byte);
+        } else {
+            self.buf[self.position] = byte;
+        }
+        self.position += 1;
+        if self.position == self.size {
+            self.position = 0;
+        }
+        self.total += 1;
+        self.pending.push(byte);
+    }
+
+    /// Returns the byte decoded `distance` bytes ago, counting the last one as 1.
+    fn get(&self, distance: usize) -> u8 {
+        let index = if distance <= self.position {
+            self.position - distance
+        } else {
+            self.position + self.size - distance
+        };
+        self.buf[index]
+    }
+
+    /// Returns `true` if a match can copy from `distance` bytes back.
+    fn reaches(&self, distance: usize) -> bool {
+        distance <= self.size && distance as u64 <= self.total
+    }
+}
+
+/// The range decoder the bits of an LZMA chunk are read with.
+#[derive(Debug, Default)]
+struct RangeDecoder {
+    /// The width of the interval the next bits are decoded from.
+    range: u32,
+    /// Where the compressed bits fall within the interval.
+    code: u32,
+}
+
+/// A probability as LZMA models it, in units of 1/2048, starting at one half.
+type Probability = u16;
+
+/// The starting value of every [`Probability`].
+const HALF: Probability = 1024;
+
+impl RangeDecoder {
+    /// Starts decoding from the first five bytes of an LZMA chunk.
+    fn new<R: BufRead>(input: &mut Input<R>) -> io::Result<Self> {
+        if input.byte()? != 0 {
+            return Err(corrupt("an LZMA chunk starts with a nonzero byte"));
+        }
+        let mut code = 0;
+        for _ in 0..4 {
+            code = (code << 8) | u32::from(input.byte()?);
+        }
+        Ok(Self {
+            range: u32::MAX,
+            code,
+        })
+    }
+
+    /// Takes another byte once the range has narrowed too far.
+    fn normalize<R: BufRead>(&mut self, input: &mut Input<R>) -> io::Result<()> {
+        if self.range < 1 << 24 {
+            self.range <<= 8;
+            self.code = (self.code << 8) | u32::from(input.byte()?);
+        }
+        Ok(())
+    }
+
+    /// Decodes a bit whose chance of being 0 is `probability`, which learns from it.
+    fn bit<R: BufRead>(
+        &mut self,
+        probability: &mut Probability,
+        input: &mut Input<R>,
+    ) -> io::Result<usize> {
+        let bound = (self.range >> 11) * u32::from(*probability);
+        let bit = if self.code < bound {
+            self.range = bound;
+            *probability += (2048 - *probability) >> 5;
+            0
+        } else {
+            self.range -= bound;
+            self.code -= bound;
+            *probability -= *probability >> 5;
+            1
+        };
+        self.normalize(input)?;
+        Ok(bit)
+    }
+
+    /// Decodes `count` bits with even chances, most significant first.
+    fn direct<R: BufRead>(&mut self, count: usize, input: &mut Input<R>) -> io::Result<usize> {
+        let mut value = 0;
+        for _ in 0..count {
+            self.range >>= 1;
+            let bit = if self.code >= self.range {
+                self.code -= self.range;
+                1
+            } else {
+                0
+            };
+            value = (value << 1) | bit;
+            self.normalize(input)?;
+        }
+        Ok(value)
+    }
+
+    /// Decodes a `bits`-bit number from a tree of probabilities, most significant bit first.
+    fn tree<R: BufRead>(
+        &mut self,
+        probabilities: &mut [Probability],
+        bits: usize,
+        input: &mut Input<R>,
+    ) -> io::Result<usize> {
+        let mut node = 1;
+        for _ in 0..bits {
+            node = (node << 1) | self.bit(&mut probabilities[node], input)?;
+        }
+        Ok(node - (1 << bits))
+    }
+
+    /// Decodes a `bits`-bit number from a tree of probabilities, least significant bit first.
+    fn reverse_tree<R: BufRead>(
+        &mut self,
+        probabilities: &mut [Probability],
+        bits: usize,
+        input: &mut Input<R>,
+    ) -> io::Result<usize> {
+        let mut node = 1;
+        let mut value = 0;
+        for index in 0..bits {
+            let bit = self.bit(&mut probabilities[node], input)?;
+            node = (node << 1) | bit;
+            value |= bit << index;
+        }
+        Ok(value)
+    }
+}
+
+/// Decodes the length of a match, less the shortest length of 2.
+#[derive(Debug)]
+struct LengthDecoder {
+    /// Whether the length is 8 or more.
+    choice: Probability,
+    /// Whether the length is 16 or more.
+    choice2: Probability,
+    /// Lengths 0 to 7, per position state.
+    low: [[Probability; 8]; 16],
+    /// Lengths 8 to 15, per position state.
+    mid: [[Probability; 8]; 16],
+    /// Lengths 16 to 271.
+    high: [Probability; 256],
+}
+
+impl LengthDecoder {
+    fn new() -> Self {
+        Self {
+            choice: HALF,
+            choice2: HALF,
+            low: [[HALF; 8]; 16],
+            mid: [[HALF; 8]; 16],
+            high: [HALF; 256],
+        }
+    }
+
+    fn decode<R: BufRead>(
+        &mut self,
+        range: &mut RangeDecoder,
+        position_state: usize,
+        input: &mut Input<R>,
+    ) -> io::Result<usize> {
+        if range.bit(&mut self.choice, input)? == 0 {
+            return range.tree(&mut self.low[position_state], 3, input);
+        }
+        if range.bit(&mut self.choice2, input)? == 0 {
+            return Ok(8 + range.tree(&mut self.mid[position_state], 3, input)?);
+        }
+        Ok(16 + range.tree(&mut self.high, 8, input)?)
+    }
+}
+
+/// The model of an LZMA decoder, kept across the chunks of a block until one resets it.
+#[derive(Debug)]
+struct Lzma {
+    /// How many high bits of the previous byte select the literal coder.
+    literal_context: usize,
+    /// How many low bits of the position select the literal coder.
+    literal_position: usize,
+    /// How many low bits of the position select the other probabilities.
+    position_bits: usize,
+    /// The literal coders, 0x300 probabilities each.
+    literal: Vec<Probability>,
+    /// Whether a packet is a match rather than a literal, per state and position state.
+    is_match: [Probability; 192],
+    /// Whether a match reuses one of the last four distances, per state.
+    is_rep: [Probability; 12],
+    /// Whether a repeated match uses the last distance, per state.
+    is_rep0: [Probability; 12],
+    /// Whether a repeated match uses the second to last distance, per state.
+    is_rep1: [Probability; 12],
+    /// Whether a repeated match uses the third rather than the fourth to last distance.
+    is_rep2: [Probability; 12],
+    /// Whether a match at the last distance is longer than a byte.
+    is_rep0_long: [Probability; 192],
+    /// The top bits of a distance, per length up to 5.
+    distance_slot: [[Probability; 64]; 4],
+    /// The low bits of distances below 128.
+    distance_special: [Probability; 115],
+    /// The lowest four bits of distances of 128 and more.
+    align: [Probability; 16],
+    /// The lengths of new matches.
+    length: LengthDecoder,
+    /// The lengths of repeated matches.
+    rep_length: LengthDecoder,
+    /// What the last packets were, from 0 (literals) to 11.
+    state: usize,
+    /// The distances of the last four matches, less one.
+    reps: [usize; 4],
+}
+
+impl Lzma {
+    fn new() -> Self {
+        Self {
+            literal_context: 0,
+            literal_position: 0,
+            position_bits: 0,
+            literal: Vec::new(),
+            is_match: [HALF; 192],
+            is_rep: [HALF; 12],
+            is_rep0: [HALF; 12],
+            is_rep1: [HALF; 12],
+            is_rep2: [HALF; 12],
+            is_rep0_long: [HALF; 192],
+            distance_slot: [[HALF; 64]; 4],
+            distance_special: [HALF; 115],
+            align: [HALF; 16],
+            length: LengthDecoder::new(),
+            rep_length: LengthDecoder::new(),
+            state: 0,
+            reps: [0; 4],
+        }
+    }
+
+    /// Sets the `lc`, `lp` and `pb` properties from their encoded byte.
+    fn set_properties(&mut self, properties: u8) -> io::Result<()> {
+        if properties >= 9 * 5 * 5 {
+            return Err(corrupt("the LZMA properties are invalid"));
+        }
+        let properties = usize::from(properties);
+        self.literal_context = properties % 9;
+        self.literal_position = properties / 9 % 5;
+        self.position_bits = properties / 45;
+        if self.literal_context + self.literal_position > 4 {
+            return Err(corrupt("the LZMA properties are invalid"));
+        }
+        Ok(())
+    }
+
+    /// Forgets everything learned, as a state reset asks.
+    fn reset(&mut self) {
+        let properties = (
+            self.literal_context,
+            self.literal_position,
+            self.position_bits,
+        );
+        *self = Self::new();
+        (
+            self.literal_context,
+            self.literal_position,
+            self.position_bits,
+        ) = properties;
+        self.literal = vec![HALF; 0x300 << (self.literal_context + self.literal_position)];
+    }
+
+    /// Decodes the next packet, a literal or a match, into `window`.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The range decoder of the chunk.
+    /// * `window` - The dictionary, which receives the decoded bytes.
+    /// * `left` - How many bytes the chunk has left to produce, which a match may not exceed.
+    /// * `input` - The compressed stream.
+    fn decode<R: BufRead>(
+        &mut self,
+        range: &mut RangeDecoder,
+        window: &mut Window,
+        left: usize,
+        input: &mut Input<R>,
+    ) -> io::Result<()> {
+        let position_state = window.total as usize & ((1 << self.position_bits) - 1);
+        let state = self.state;
+
+        if range.bit(&mut self.is_match[(state << 4) + position_state], input)? == 0 {
+            self.literal(range, window, input)?;
+            self.state = match state {
+                0..=3 => 0,
+                4..=9 => state - 3,
+                _ => state - 6,
+            };
+            return Ok(());
+        }
+
+        let length = if range.bit(&mut self.is_rep[state], input)? == 1 {
+            if window.total == 0 {
+                return Err(corrupt("an LZMA chunk repeats a match before any data"));
+            }
+            if range.bit(&mut self.is_rep0[state], input)? == 0 {
+                // A single byte from the last distance.
+                if range.bit(&mut self.is_rep0_long[(state << 4) + position_state], input)? == 0 {
+                    self.state = if state < 7 { 9 } else { 11 };
+                    window.put(window.get(self.reps[0] + 1));
+                    return Ok(());
+                }
+            } else {
+                let distance = if range.bit(&mut self.is_rep1[state], input)? == 0 {
+                    self.reps[1]
+                } else {
+                    let distance = if range.bit(&mut self.is_rep2[state], input)? == 0 {
+                        self.reps[2]
+                    } else {
+                        let distance = self.reps[3];
+                        self.reps[3] = self.reps[2];
+                        distance
+                    };
+                    self.reps[2] = self.reps[1];
+                    distance
+                };
+                self.reps[1] = self.reps[0];
+                self.reps[0] = distance;
+            }
+            self.state = if state < 7 { 8 } else { 11 };
+            self.rep_length.decode(range, position_state, input)?
+        } else {
+            self.reps.copy_within(0..3, 1);
+            let length = self.length.decode(range, position_state, input)?;
+            self.state = if state < 7 { 7 } else { 10 };
+            self.reps[0] = self.distance(range, length, input)?;
+            length
+        };
+
+        let length = length + 2;
+        // The end marker's distance of 2^32 - 1 is refused here too, since LZMA2 has none.
+        let distance = self.reps[0]
+            .checked_add(1)
+            .filter(|distance| window.reaches(*distance))
+            .ok_or_else(|| corrupt("an LZMA match reaches before the start of the data"))?;
+        if length > left {
+            return Err(corrupt("an LZMA match overruns its chunk"));
+        }
+        for _ in 0..length {
+            window.put(window.get(distance));
+        }
+        Ok(())
+    }
+
+    /// Decodes a literal byte into `window`.
+    fn literal<R: BufRead>(
+        &mut self,
+        range: &mut RangeDecoder,
+        window: &mut Window,
+        input: &mut Input<R>,
+    ) -> io::Result<()> {
+        let previous = if window.total > 0 { window.get(1) } else { 0 };
+        let coder = ((window.total as usize & ((1 << self.literal_position) - 1))
+            << self.literal_context)
+            + (usize::from(previous) >> (8 - self.literal_context));
+        let probabilities = &mut self.literal[0x300 * coder..][..0x300];
+
+        let mut symbol = 1;
+        // Right after a match, the byte at the last distance predicts the literal until a
+        // bit differs from it.
+        if self.state >= 7 {
+            let mut matched = usize::from(window.get(self.reps[0] + 1));
+            while symbol < 0x100 {
+                let matched_bit = (matched >> 7) & 1;
+                matched <<= 1;
+                let bit =
+                    range.bit(&mut probabilities[((1 + matched_bit) << 8) + symbol], input)?;
+                symbol = (symbol << 1) | bit;
+                if bit != matched_bit {
+                    break;
+                }
+            }
+        }
+        while symbol < 0x100 {
+            symbol = (symbol << 1) | range.bit(&mut probabilities[symbol], input)?;
+        }
+        window.put(symbol as u8);
+        Ok(())
+    }
+
+    /// Decodes the distance of a match of `length`, less one.
+    fn distance<R: BufRead>(
+        &mut self,
+        range: &mut RangeDecoder,
+        length: usize,
+        input: &mut Input<R>,
+    ) -> io::Result<usize> {
+        let slot = range.tree(&mut self.distance_slot[length.min(3)], 6, input)?;
+        if slot < 4 {
+            return Ok(slot);
+        }
+        let bits = (slot >> 1) - 1;
+        let mut distance = (2 | (slot & 1)) << bits;
+        if slot < 14 {
+            distance +=
+                range.reverse_tree(&mut self.distance_special[distance - slot..], bits, input)?;
+        } else {
+            distance += range.direct(bits - 4, input)? << 4;
+            distance += range.reverse_tree(&mut self.align, 4, input)?;
+        }
+        Ok(distance)
+    }
+}
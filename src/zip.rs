@@ -0,0 +1,288 @@
+use crate::error::DownloadError;
+use crate::extract::{entry_path, set_executable, write_entry, Budget, ExtractError};
+use flate2::bufread::DeflateDecoder;
+use flate2::Crc;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// The bytes a zip archive starts with: the header of its first entry.
+pub(crate) const MAGIC: [u8; 4] = *b"PK\x03\x04";
+
+/// The signature of the end of central directory record, which an empty zip archive starts
+/// with.
+pub(crate) const END_MAGIC: [u8; 4] = *b"PK\x05\x06";
+
+/// The signature of a central directory entry.
+const ENTRY: [u8; 4] = *b"PK\x01\x02";
+
+/// The signature of the Zip64 end of central directory locator.
+const ZIP64_LOCATOR: [u8; 4] = *b"PK\x06\x07";
+
+/// The signature of the Zip64 end of central directory record.
+const ZIP64_END: [u8; 4] = *b"PK\x06\x06";
+
+/// The size of the end of central directory record without its comment.
+const END_SIZE: usize = 22;
+
+/// How far from the end the end of central directory record may start, with the longest
+/// comment.
+const END_SEARCH: u64 = END_SIZE as u64 + u16::MAX as u64;
+
+/// The file types of a Unix mode.
+const FILE_TYPE: u32 = 0o170_000;
+
+/// The file type of a symbolic link.
+const SYMLINK: u32 = 0o120_000;
+
+/// An entry of the central directory.
+#[derive(Debug)]
+struct Entry {
+    /// The path of the entry within the archive.
+    name: String,
+    /// The general purpose flags, of which bit 0 marks encryption.
+    flags: u16,
+    /// How the data is compressed: 0 when stored, 8 for deflate.
+    method: u16,
+    /// The CRC-32 of the uncompressed data.
+    crc: u32,
+    /// The size of the compressed data.
+    compressed_size: u64,
+    /// The size of the uncompressed data.
+    size: u64,
+    /// Where the local header of the entry starts.
+    offset: u64,
+    /// The Unix mode the entry was archived with, if it was archived on Unix.
+    mode: Option<u32>,
+}
+
+/// Unpacks the zip archive `file` into `directory`; see [`crate::extract`].
+///
+/// Every entry name is checked before anything is written. Stored and deflated entries are
+/// supported, including Zip64 archives; encrypted entries and other compression methods fail
+/// the extraction. Each file is checked against its CRC-32.
+///
+/// # Arguments
+///
+/// * `file` - The opened archive.
+/// * `archive` - The path of the archive, for errors.
+/// * `directory` - Where to unpack it.
+/// * `budget` - How many bytes the extracted files may add up to.
+pub(crate) fn extract(
+    mut file: File,
+    archive: &Path,
+    directory: &Path,
+    budget: &mut Budget,
+) -> Result<Vec<PathBuf>, DownloadError> {
+    let entries = central_directory(&mut file)
+        .map_err(|error| ExtractError::unreadable(archive, None, &error))?;
+    let paths = entries
+        .iter()
+        .map(|entry| entry_path(archive, directory, &entry.name))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut extracted = Vec::new();
+    for (entry, path) in entries.iter().zip(paths) {
+        let Some(path) = path else {
+            continue;
+        };
+        let unsupported = |reason: &str| {
+            DownloadError::from(ExtractError::new(archive, Some(&entry.name), reason))
+        };
+        if entry.name.ends_with('/') {
+            fs::create_dir_all(&path).map_err(DownloadError::io(&path))?;
+            continue;
+        }
+        if entry.mode.is_some_and(|mode| mode & FILE_TYPE == SYMLINK) {
+            continue;
+        }
+        if entry.flags & 1 != 0 {
+            return Err(unsupported("encrypted entries aren't supported"));
+        }
+
+        let data = open_entry(&mut file, entry)
+            .map_err(|error| ExtractError::unreadable(archive, Some(&entry.name), &error))?;
+        let data: Box<dyn Read + '_> = match entry.method {
+            0 => Box::new(data),
+            8 => Box::new(DeflateDecoder::new(BufReader::new(data))),
+            method => {
+                return Err(unsupported(&format!(
+                    "compression method {} isn't supported",
+                    method
+                )))
+            }
+        };
+        let mut checked = Checked {
+            inner: data,
+            crc: Crc::new(),
+            size: 0,
+            entry,
+        };
+        write_entry(&mut checked, archive, &entry.name, &path, budget)?;
+        if let Some(mode) = entry.mode {
+            set_executable(&path, mode)?;
+        }
+        extracted.push(path);
+    }
+    Ok(extracted)
+}
+
+/// Reads the entries of the central directory at the end of `file`.
+fn central_directory(file: &mut File) -> io::Result<Vec<Entry>> {
+    let length = file.seek(SeekFrom::End(0))?;
+    let tail_start = length.saturating_sub(END_SEARCH);
+    file.seek(SeekFrom::Start(tail_start))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+
+    let end = tail
+        .windows(4)
+        .rposition(|window| window == END_MAGIC)
+        .filter(|end| end + END_SIZE <= tail.len())
+        .ok_or_else(|| invalid("the central directory is missing"))?;
+    let record = &tail[end..];
+    let mut count = u64::from(u16_at(record, 10));
+    let mut offset = u64::from(u32_at(record, 16));
+
+    // A Zip64 archive keeps the real count and offset in a record found by a locator.
+    if end >= 20 && tail[end - 20..end - 16] == ZIP64_LOCATOR {
+        file.seek(SeekFrom::Start(u64_at(&tail, end - 20 + 8)))?;
+        let mut record = [0; 56];
+        file.read_exact(&mut record)?;
+        if record[..4] != ZIP64_END {
+            return Err(invalid("the Zip64 end of central directory is missing"));
+        }
+        count = u64_at(&record, 32);
+        offset = u64_at(&record, 48);
+    }
+
+    file.seek(SeekFrom::Start(offset))?;
+    let mut reader = BufReader::new(&mut *file);
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let mut fixed = [0; 46];
+        reader.read_exact(&mut fixed)?;
+        if fixed[..4] != ENTRY {
+            return Err(invalid("a central directory entry is malformed"));
+        }
+        let mut name = vec![0; usize::from(u16_at(&fixed, 28))];
+        reader.read_exact(&mut name)?;
+        let mut extra = vec![0; usize::from(u16_at(&fixed, 30))];
+        reader.read_exact(&mut extra)?;
+        io::copy(
+            &mut (&mut reader).take(u64::from(u16_at(&fixed, 32))),
+            &mut io::sink(),
+        )?;
+
+        let mut entry = Entry {
+            name: String::from_utf8_lossy(&name).into_owned(),
+            flags: u16_at(&fixed, 8),
+            method: u16_at(&fixed, 10),
+            crc: u32_at(&fixed, 16),
+            compressed_size: u64::from(u32_at(&fixed, 20)),
+            size: u64::from(u32_at(&fixed, 24)),
+            offset: u64::from(u32_at(&fixed, 42)),
+            // The high byte of "version made by" is 3 for Unix.
+            mode: (fixed[5] == 3).then(|| u32_at(&fixed, 38) >> 16),
+        };
+        zip64_sizes(&mut entry, &extra);
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Replaces the sizes and offset of `entry` that overflowed 32 bits with those of its Zip64
+/// extra field, which lists only those, in this order.
+fn zip64_sizes(entry: &mut Entry, extra: &[u8]) {
+    let mut rest = extra;
+    while rest.len() >= 4 {
+        let id = u16_at(rest, 0);
+        let length = usize::from(u16_at(rest, 2)).min(rest.len() - 4);
+        let mut values = rest[4..4 + length]
+            .chunks_exact(8)
+            .map(|value| u64_at(value, 0));
+        if id == 0x0001 {
+            for field in [
+                &mut entry.size,
+                &mut entry.compressed_size,
+                &mut entry.offset,
+            ] {
+                if *field == u64::from(u32::MAX) {
+                    match values.next() {
+                        Some(value) => *field = value,
+                        None => break,
+                    }
+                }
+            }
+            return;
+        }
+        rest = &rest[4 + length..];
+    }
+}
+
+/// Positions `file` at the data of `entry` past its local header, and returns a reader of
+/// its compressed bytes.
+fn open_entry<'a>(file: &'a mut File, entry: &Entry) -> io::Result<io::Take<&'a mut File>> {
+    file.seek(SeekFrom::Start(entry.offset))?;
+    let mut header = [0; 30];
+    file.read_exact(&mut header)?;
+    if header[..4] != MAGIC {
+        return Err(invalid("a local file header is malformed"));
+    }
+    let skip = i64::from(u16_at(&header, 26)) + i64::from(u16_at(&header, 28));
+    file.seek(SeekFrom::Current(skip))?;
+    Ok(file.take(entry.compressed_size))
+}
+
+/// Reads the data of a zip entry, failing as soon as it outgrows the size the central
+/// directory lists, and at its end unless it has that size and CRC-32.
+struct Checked<'a, R> {
+    /// The data, decompressed.
+    inner: R,
+    /// The CRC-32 of the data read so far.
+    crc: Crc,
+    /// How many bytes were read so far.
+    size: u64,
+    /// The entry being read.
+    entry: &'a Entry,
+}
+
+impl<R: Read> Read for Checked<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.crc.update(&buf[..read]);
+        self.size += read as u64;
+        if self.size > self.entry.size {
+            return Err(invalid("the entry is larger than its declared size"));
+        }
+        if read == 0 && !buf.is_empty() {
+            if self.size != self.entry.size {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            if self.crc.sum() != self.entry.crc {
+                return Err(invalid("the entry doesn't match its CRC-32"));
+            }
+        }
+        Ok(read)
+    }
+}
+
+/// Returns the error for an archive that can't be read.
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+/// Reads a little-endian 16-bit number at `at`.
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+/// Reads a little-endian 32-bit number at `at`.
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// Reads a little-endian 64-bit number at `at`.
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from(u32_at(bytes, at)) | u64::from(u32_at(bytes, at + 4)) << 32
+}
@@ -0,0 +1,163 @@
+//! The async API against a local server: segments and batch state files, which it shares with
+//! the blocking one.
+
+mod common;
+
+use common::{pattern, ranged, scratch_dir, MockServer, Response};
+use parallel_downloads::{
+    download_batch_requests_async, download_file_async_with_config, BatchConfig, DownloadConfig,
+    DownloadEvent, DownloadOutcome, DownloadRequest, RetryConfig, SegmentConfig,
+};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+#[test]
+fn segmented_downloads_fetch_every_range_and_assemble_the_file() {
+    let content = pattern(256 * 1024);
+    let server = MockServer::serving(content.clone());
+    let config = DownloadConfig {
+        compute_sha256: true,
+        segments: SegmentConfig {
+            count: 4,
+            min_segment_size: 16 * 1024,
+        },
+        ..DownloadConfig::default()
+    };
+    let path = scratch_dir("async_segments").join("file.bin");
+    let reported = Arc::new(Mutex::new(None));
+    let seen = Arc::clone(&reported);
+    let client = reqwest::Client::new();
+    block_on(download_file_async_with_config(
+        &client,
+        server.url("/file.bin"),
+        &path,
+        &config,
+        move |event| {
+            if let DownloadEvent::Completed { bytes, sha256, .. } = event {
+                *seen.lock().unwrap() = Some((*bytes, sha256.is_some()));
+            }
+        },
+    ))
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), content);
+    assert_eq!(
+        *reported.lock().unwrap(),
+        Some((content.len() as u64, true))
+    );
+    let ranged = server
+        .requests()
+        .iter()
+        .filter(|request| request.method == "GET" && request.header("range").is_some())
+        .count();
+    assert_eq!(ranged, 4, "the file wasn't downloaded in segments");
+}
+
+#[test]
+fn batches_skip_requests_their_state_file_completed() {
+    let server = MockServer::serving(pattern(4 * 1024));
+    let directory = scratch_dir("async_state_completed");
+    let requests = || {
+        vec![DownloadRequest::new(
+            server.url("/file.bin"),
+            directory.join("file.bin"),
+        )]
+    };
+    let config = || BatchConfig {
+        state_file: Some(directory.join("batch.json")),
+        ..BatchConfig::default()
+    };
+
+    block_on(download_batch_requests_async(requests(), config(), |_| {})).unwrap();
+    let results = block_on(download_batch_requests_async(requests(), config(), |_| {})).unwrap();
+
+    assert!(
+        matches!(results[0].outcome, DownloadOutcome::Skipped { .. }),
+        "{:?}",
+        results[0].outcome
+    );
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn batches_resume_partial_files_under_the_version_their_state_file_recorded() {
+    let content = pattern(64 * 1024);
+    let served = content.clone();
+    let calls = AtomicUsize::new(0);
+    // The first answer breaks off halfway, leaving a partial file for the second run.
+    let server = MockServer::start(move |request| {
+        let response = match calls.fetch_add(1, Ordering::SeqCst) {
+            0 => Response::ok(served.clone()).cut_after(served.len() / 2),
+            _ => ranged(&served, request),
+        };
+        response.header("ETag", "\"v1\"")
+    });
+    let directory = scratch_dir("async_state_if_range");
+    let requests = || {
+        vec![DownloadRequest::new(
+            server.url("/file.bin"),
+            directory.join("file.bin"),
+        )]
+    };
+    let config = || BatchConfig {
+        download: DownloadConfig {
+            retry: RetryConfig::none(),
+            ..DownloadConfig::default()
+        },
+        state_file: Some(directory.join("batch.json")),
+        ..BatchConfig::default()
+    };
+
+    let _ = block_on(download_batch_requests_async(requests(), config(), |_| {}));
+    block_on(download_batch_requests_async(requests(), config(), |_| {})).unwrap();
+
+    assert_eq!(std::fs::read(directory.join("file.bin")).unwrap(), content);
+    let resumed = server
+        .requests()
+        .into_iter()
+        .rfind(|request| request.method == "GET")
+        .unwrap();
+    assert_eq!(resumed.header("if-range"), Some("\"v1\""));
+    assert!(resumed
+        .header("range")
+        .is_some_and(|range| range != "bytes=0-"));
+}
+
+#[test]
+fn a_caller_provided_async_client_sends_every_request() {
+    let server = MockServer::start(|request| Response::ok(request.path.clone()));
+    let directory = scratch_dir("async_caller_client");
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert("x-client", "caller".parse().unwrap());
+    let client = reqwest::Client::builder()
+        .default_headers(headers)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let config = BatchConfig {
+        async_client: Some(client),
+        ..BatchConfig::default()
+    };
+    let requests = vec![DownloadRequest::new(
+        server.url("/file.bin"),
+        directory.join("file.bin"),
+    )];
+
+    let results = block_on(download_batch_requests_async(requests, config, |_| {})).unwrap();
+
+    assert!(results[0].is_success(), "{:?}", results[0].outcome);
+    let requests = server.requests();
+    assert!(!requests.is_empty());
+    assert!(requests
+        .iter()
+        .all(|request| request.header("x-client") == Some("caller")));
+}
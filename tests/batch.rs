@@ -0,0 +1,198 @@
+//! Scheduling of a batch across its worker pool, and the client its workers share.
+
+mod common;
+
+use common::{pattern, scratch_dir, MockServer, Response};
+use parallel_downloads::{
+    download_batch_requests, BatchConfig, DownloadEvent, DownloadRequest, PoolConfig,
+};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn a_slow_download_only_holds_up_its_own_worker() {
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let most_in_flight = Arc::new(AtomicUsize::new(0));
+    let (current, most) = (Arc::clone(&in_flight), Arc::clone(&most_in_flight));
+    let server = MockServer::start(move |request| {
+        let now = current.fetch_add(1, Ordering::SeqCst) + 1;
+        most.fetch_max(now, Ordering::SeqCst);
+        let delay = if request.path == "/slow" { 1500 } else { 50 };
+        thread::sleep(Duration::from_millis(delay));
+        current.fetch_sub(1, Ordering::SeqCst);
+        Response::ok(request.path.clone())
+    });
+
+    // The slow file comes first, so each chunk of two would wait for it under chunk-and-join
+    // scheduling.
+    let directory = scratch_dir("batch_slow_url");
+    let mut requests = vec![DownloadRequest::new(
+        server.url("/slow"),
+        directory.join("slow"),
+    )];
+    requests.extend((0..8).map(|index| {
+        let name = format!("fast-{}", index);
+        DownloadRequest::new(server.url(&format!("/{}", name)), directory.join(name))
+    }));
+    let config = BatchConfig {
+        concurrency: 2,
+        ..BatchConfig::default()
+    };
+
+    let started = Instant::now();
+    let finished: Arc<Mutex<HashMap<PathBuf, Duration>>> = Arc::default();
+    let seen = Arc::clone(&finished);
+    let results = download_batch_requests(requests, config, move |event| {
+        if let DownloadEvent::Completed { path, .. } = event {
+            seen.lock().unwrap().insert(path.clone(), started.elapsed());
+        }
+    })
+    .unwrap();
+    assert!(results.iter().all(|result| result.is_success()));
+
+    let finished = finished.lock().unwrap();
+    let slow = finished[&directory.join("slow")];
+    for index in 0..8 {
+        let fast = finished[&directory.join(format!("fast-{}", index))];
+        assert!(
+            fast < slow,
+            "fast-{} finished after {:?}, the slow file after {:?}",
+            index,
+            fast,
+            slow
+        );
+    }
+    assert!(most_in_flight.load(Ordering::SeqCst) <= 2);
+}
+
+#[test]
+fn a_throttled_download_does_not_slow_the_others() {
+    let server = MockServer::start(|_| Response::ok(pattern(128 * 1024)));
+    let directory = scratch_dir("batch_throttled_url");
+    // 128 KiB at 64 KiB/s takes about two seconds, while the others go as fast as they can.
+    let mut throttled = DownloadRequest::new(server.url("/throttled"), directory.join("throttled"));
+    throttled.max_bytes_per_sec = Some(64 * 1024);
+    let mut requests = vec![throttled];
+    requests.extend((0..6).map(|index| {
+        let name = format!("free-{}", index);
+        DownloadRequest::new(server.url(&format!("/{}", name)), directory.join(name))
+    }));
+    let config = BatchConfig {
+        concurrency: 2,
+        ..BatchConfig::default()
+    };
+
+    let started = Instant::now();
+    let finished: Arc<Mutex<HashMap<PathBuf, Duration>>> = Arc::default();
+    let seen = Arc::clone(&finished);
+    let results = download_batch_requests(requests, config, move |event| {
+        if let DownloadEvent::Completed { path, .. } = event {
+            seen.lock().unwrap().insert(path.clone(), started.elapsed());
+        }
+    })
+    .unwrap();
+    assert!(results.iter().all(|result| result.is_success()));
+
+    let finished = finished.lock().unwrap();
+    let throttled = finished[&directory.join("throttled")];
+    assert!(throttled >= Duration::from_millis(1500), "{:?}", throttled);
+    for index in 0..6 {
+        let free = finished[&directory.join(format!("free-{}", index))];
+        assert!(
+            free < throttled / 2,
+            "free-{} finished after {:?}, the throttled file after {:?}",
+            index,
+            free,
+            throttled
+        );
+    }
+}
+
+#[test]
+fn sequential_downloads_from_one_host_share_a_connection() {
+    let server = MockServer::keep_alive(|request| Response::ok(request.path.clone()));
+    let directory = scratch_dir("batch_one_connection");
+    let requests = (0..5)
+        .map(|index| {
+            let name = format!("file-{}", index);
+            DownloadRequest::new(server.url(&format!("/{}", name)), directory.join(name))
+        })
+        .collect();
+    let config = BatchConfig {
+        concurrency: 1,
+        ..BatchConfig::default()
+    };
+
+    let results = download_batch_requests(requests, config, |_| {}).unwrap();
+
+    assert!(results.iter().all(|result| result.is_success()));
+    assert_eq!(server.requests().len(), 5);
+    assert_eq!(server.connections(), 1);
+}
+
+#[test]
+fn a_pool_keeping_no_idle_connections_opens_one_per_request() {
+    let server = MockServer::keep_alive(|request| Response::ok(request.path.clone()));
+    let directory = scratch_dir("batch_no_idle_connections");
+    let requests = (0..5)
+        .map(|index| {
+            let name = format!("file-{}", index);
+            DownloadRequest::new(server.url(&format!("/{}", name)), directory.join(name))
+        })
+        .collect();
+    let config = BatchConfig {
+        concurrency: 1,
+        pool: PoolConfig {
+            max_idle_per_host: 0,
+            ..PoolConfig::default()
+        },
+        ..BatchConfig::default()
+    };
+
+    let results = download_batch_requests(requests, config, |_| {}).unwrap();
+
+    assert!(results.iter().all(|result| result.is_success()));
+    assert_eq!(server.requests().len(), 5);
+    assert_eq!(server.connections(), 5);
+}
+
+/// The default headers of the caller's client, which its requests send and the batch's don't.
+fn caller_headers() -> reqwest::header::HeaderMap {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert("x-client", "caller".parse().unwrap());
+    headers
+}
+
+#[test]
+fn a_caller_provided_client_sends_every_request() {
+    let server = MockServer::start(|request| Response::ok(request.path.clone()));
+    let directory = scratch_dir("batch_caller_client");
+    let requests = (0..3)
+        .map(|index| {
+            let name = format!("file-{}", index);
+            DownloadRequest::new(server.url(&format!("/{}", name)), directory.join(name))
+        })
+        .collect();
+    let client = reqwest::blocking::Client::builder()
+        .default_headers(caller_headers())
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let config = BatchConfig {
+        client: Some(client),
+        ..BatchConfig::default()
+    };
+
+    let results = download_batch_requests(requests, config, |_| {}).unwrap();
+
+    assert!(results.iter().all(|result| result.is_success()));
+    let requests = server.requests();
+    assert!(!requests.is_empty());
+    assert!(requests
+        .iter()
+        .all(|request| request.header("x-client") == Some("caller")));
+}
@@ -0,0 +1,307 @@
+//! Helpers shared by the integration tests: scratch directories and a small HTTP server.
+
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Returns an empty directory of its own for the test `name`, removing what an earlier run
+/// left there.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir()
+        .join(format!("parallel-downloads-tests-{}", std::process::id()))
+        .join(name);
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+/// Returns `length` bytes that differ from one position to the next, so a misplaced chunk
+/// shows up in the content.
+pub fn pattern(length: usize) -> Vec<u8> {
+    (0..length).map(|i| (i % 251) as u8).collect()
+}
+
+/// Returns a counter of `/proc/self/io`, such as `syscw` for write system calls, if the
+/// platform has one.
+pub fn io_counter(name: &str) -> Option<u64> {
+    let io = std::fs::read_to_string("/proc/self/io").ok()?;
+    io.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(": ")?.parse().ok())
+}
+
+/// A request received by a [`MockServer`].
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Returns the first value of the header `name`, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A response of a [`MockServer`].
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Sends the body in writes of this many bytes, waiting `delay` before each.
+    pub pacing: Option<(usize, Duration)>,
+    /// Closes the connection after this many bytes of the body.
+    pub cut_after: Option<usize>,
+}
+
+impl Response {
+    /// A `200 OK` with `body` and its `Content-Length`.
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Self::status(200).body(body)
+    }
+
+    /// An empty response with `status`.
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+            pacing: None,
+            cut_after: None,
+        }
+    }
+
+    /// Replaces the body.
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Adds a header.
+    pub fn header(mut self, name: &str, value: impl ToString) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sends the body `chunk` bytes at a time, waiting `delay` before each write.
+    pub fn paced(mut self, chunk: usize, delay: Duration) -> Self {
+        self.pacing = Some((chunk, delay));
+        self
+    }
+
+    /// Closes the connection once `bytes` of the body have been sent, while still announcing
+    /// the whole length.
+    pub fn cut_after(mut self, bytes: usize) -> Self {
+        self.cut_after = Some(bytes);
+        self
+    }
+}
+
+/// Answers `request` for `content` the way a server supporting ranges would: `206 Partial
+/// Content` for a satisfiable `Range`, `416 Range Not Satisfiable` for one past the end, and
+/// `200 OK` with the whole file otherwise.
+pub fn ranged(content: &[u8], request: &Request) -> Response {
+    let total = content.len();
+    let range = request
+        .header("range")
+        .and_then(|range| range.strip_prefix("bytes="))
+        .and_then(|range| range.split_once('-'))
+        .and_then(|(start, end)| Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok())));
+    match range {
+        Some((start, _)) if start >= total => Response::status(416)
+            .header("Content-Range", format!("bytes */{}", total))
+            .header("Accept-Ranges", "bytes"),
+        Some((start, end)) => {
+            let end = end.map_or(total - 1, |end| end.min(total - 1));
+            Response::status(206)
+                .body(&content[start..=end])
+                .header(
+                    "Content-Range",
+                    format!("bytes {}-{}/{}", start, end, total),
+                )
+                .header("Accept-Ranges", "bytes")
+        }
+        None => Response::ok(content).header("Accept-Ranges", "bytes"),
+    }
+}
+
+type Handler = dyn Fn(&Request) -> Response + Send + Sync;
+
+/// An HTTP/1.1 server on a local port, answering every request with a handler and recording
+/// the requests it received. Every connection serves one request, unless the server was
+/// started with [`MockServer::keep_alive`].
+pub struct MockServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<Request>>>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl MockServer {
+    /// Starts a server answering with `handler`, closing every connection after one request.
+    pub fn start(handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self {
+        Self::listen(handler, false)
+    }
+
+    /// Starts a server answering with `handler` that keeps every connection open for further
+    /// requests until the client closes it.
+    pub fn keep_alive(handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self {
+        Self::listen(handler, true)
+    }
+
+    fn listen(
+        handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
+        keep_alive: bool,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let handler: Arc<Handler> = Arc::new(handler);
+        let (recorded, accepted) = (Arc::clone(&requests), Arc::clone(&connections));
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let (handler, recorded) = (Arc::clone(&handler), Arc::clone(&recorded));
+                thread::spawn(move || {
+                    while serve(&stream, &*handler, &recorded, keep_alive) && keep_alive {}
+                });
+            }
+        });
+        Self {
+            addr,
+            requests,
+            connections,
+        }
+    }
+
+    /// Starts a server answering every request for `content` with [`ranged`].
+    pub fn serving(content: Vec<u8>) -> Self {
+        Self::start(move |request| ranged(&content, request))
+    }
+
+    /// The address the server listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The URL of `path` on the server.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// Every request received so far.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// How many connections the server has accepted so far.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+/// Reads one request from `stream` and writes the answer of `handler`, returning whether the
+/// connection is still usable for another request.
+fn serve(
+    stream: &TcpStream,
+    handler: &Handler,
+    recorded: &Mutex<Vec<Request>>,
+    keep_alive: bool,
+) -> bool {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+        return false;
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return false;
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    let length: usize = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0);
+    let _ = reader
+        .by_ref()
+        .take(length as u64)
+        .read_to_end(&mut Vec::new());
+
+    let request = Request {
+        method,
+        path,
+        headers,
+    };
+    recorded.lock().unwrap().push(request.clone());
+    let response = handler(&request);
+    respond(stream, &request, &response, keep_alive).is_ok() && response.cut_after.is_none()
+}
+
+/// Writes `response` to `stream`, leaving out the body of an answer to `HEAD`.
+fn respond(
+    mut stream: &TcpStream,
+    request: &Request,
+    response: &Response,
+    keep_alive: bool,
+) -> std::io::Result<()> {
+    let connection = if keep_alive { "keep-alive" } else { "close" };
+    let mut head = format!(
+        "HTTP/1.1 {} Mock\r\nConnection: {}\r\n",
+        response.status, connection
+    );
+    if !response
+        .headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+    {
+        head.push_str(&format!("Content-Length: {}\r\n", response.body.len()));
+    }
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    if request.method == "HEAD" {
+        return Ok(());
+    }
+
+    let body = &response.body[..response
+        .cut_after
+        .unwrap_or(usize::MAX)
+        .min(response.body.len())];
+    match response.pacing {
+        Some((chunk, delay)) => {
+            for part in body.chunks(chunk.max(1)) {
+                thread::sleep(delay);
+                stream.write_all(part)?;
+                stream.flush()?;
+            }
+        }
+        None => stream.write_all(body)?,
+    }
+    stream.flush()
+}
@@ -0,0 +1,82 @@
+//! Settings and URLs no download can run with, refused before anything is sent.
+
+mod common;
+
+use common::scratch_dir;
+use parallel_downloads::{
+    download_batch_requests, download_file_with_config, BatchConfig, DownloadConfig, DownloadError,
+    DownloadRequest, ScriptedBackend, ScriptedResponse,
+};
+
+const URL: &str = "http://example.test/file.bin";
+
+fn zero_chunks() -> DownloadConfig {
+    DownloadConfig {
+        chunk_size: Some(0),
+        ..DownloadConfig::default()
+    }
+}
+
+#[test]
+fn a_zero_chunk_size_fails_the_download() {
+    let backend = ScriptedBackend::new();
+    backend.push(URL, ScriptedResponse::ok("content"));
+    let path = scratch_dir("config_download").join("file.bin");
+    let error =
+        download_file_with_config(&backend, URL, &path, &zero_chunks(), |_| ()).unwrap_err();
+    match error {
+        DownloadError::InvalidConfig(error) => assert_eq!(error.setting, "chunk_size"),
+        error => panic!("expected an invalid config, got {:?}", error),
+    }
+    assert!(backend.requests().is_empty());
+    assert!(!path.exists());
+}
+
+#[test]
+fn a_zero_chunk_size_fails_the_batch_before_it_starts() {
+    let path = scratch_dir("config_batch").join("file.bin");
+    let config = BatchConfig {
+        download: zero_chunks(),
+        ..BatchConfig::default()
+    };
+    let requests = vec![DownloadRequest::new("http://127.0.0.1:9/file.bin", &path)];
+    let error = download_batch_requests(requests, config, |_| {}).unwrap_err();
+    assert!(
+        matches!(error, DownloadError::InvalidConfig(_)),
+        "{:?}",
+        error
+    );
+}
+
+#[test]
+fn large_chunk_sizes_are_capped() {
+    let backend = ScriptedBackend::new();
+    backend.push(URL, ScriptedResponse::ok(vec![3; 1024]));
+    let config = DownloadConfig {
+        chunk_size: Some(usize::MAX),
+        ..DownloadConfig::default()
+    };
+    let path = scratch_dir("config_capped").join("file.bin");
+    download_file_with_config(&backend, URL, &path, &config, |_| ()).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), vec![3; 1024]);
+}
+
+#[test]
+fn sftp_urls_are_refused_as_unsupported() {
+    let backend = ScriptedBackend::new();
+    let path = scratch_dir("config_sftp").join("file.bin");
+    let error = download_file_with_config(
+        &backend,
+        "sftp://user@example.test/file.bin",
+        &path,
+        &DownloadConfig::default(),
+        |_| (),
+    )
+    .unwrap_err();
+    match error {
+        DownloadError::InvalidUrl(error) => assert!(error.reason.contains("sftp"), "{}", error),
+        error => panic!("expected an invalid URL, got {:?}", error),
+    }
+    assert!(backend.requests().is_empty());
+    assert!(!path.exists());
+}
@@ -0,0 +1,257 @@
+//! The same downloads through every backend: reqwest and ureq against a local server, and the
+//! scripted backend answering just as the server would. Each must end the same way, leave the
+//! same file and send the same requests.
+
+mod common;
+
+use common::{pattern, scratch_dir, MockServer, Response};
+use parallel_downloads::{
+    download_file_with_config, Answer, BodyPart, DownloadConfig, DownloadError, HttpBackend,
+    RetryConfig, ScriptedBackend, ScriptedResponse, UreqBackend,
+};
+use reqwest::header::{HeaderName, HeaderValue, CONTENT_LENGTH};
+use reqwest::StatusCode;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The answers of every path, given one per request like [`ScriptedBackend`] does: the last
+/// one keeps answering once the others are used up.
+type Script = Vec<(&'static str, Vec<Response>)>;
+
+/// What a download through one backend did.
+#[derive(Debug)]
+struct Outcome {
+    /// How the download ended.
+    result: Result<(), DownloadError>,
+    /// The destination afterwards, if it exists.
+    file: Option<Vec<u8>>,
+    /// The method, path and `Range` header of every request, in the order they were sent.
+    requests: Vec<(String, String, Option<String>)>,
+}
+
+/// Settings retrying quickly, so the scripts can break a download off and let it resume.
+fn config() -> DownloadConfig {
+    DownloadConfig {
+        resume: true,
+        retry: RetryConfig {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            ..RetryConfig::default()
+        },
+        ..DownloadConfig::default()
+    }
+}
+
+/// Downloads `/file.bin` through every backend answering with `script`, checking each
+/// outcome with `check`.
+fn conforms(name: &str, script: Script, check: impl Fn(&str, &Outcome)) {
+    let client = reqwest::blocking::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    check(
+        "reqwest",
+        &served(&format!("{}_reqwest", name), &script, &client),
+    );
+    check(
+        "ureq",
+        &served(&format!("{}_ureq", name), &script, &UreqBackend::default()),
+    );
+    check(
+        "scripted",
+        &scripted(&format!("{}_scripted", name), &script),
+    );
+}
+
+/// Downloads `/file.bin` through `backend` from a local server answering with `script`.
+fn served(name: &str, script: &Script, backend: &impl HttpBackend) -> Outcome {
+    let answers: HashMap<_, VecDeque<_>> = script
+        .iter()
+        .map(|(path, answers)| (path.to_string(), answers.iter().cloned().collect()))
+        .collect();
+    let answers = Mutex::new(answers);
+    let server = MockServer::start(move |request| {
+        let mut answers = answers.lock().unwrap();
+        match answers.get_mut(&request.path) {
+            Some(queue) if queue.len() > 1 => queue.pop_front().unwrap(),
+            Some(queue) => queue[0].clone(),
+            None => Response::status(404),
+        }
+    });
+    let outcome = download(name, backend, &server.url("/file.bin"));
+    Outcome {
+        requests: server
+            .requests()
+            .into_iter()
+            .map(|request| {
+                let range = request.header("range").map(str::to_string);
+                (request.method, request.path, range)
+            })
+            .collect(),
+        ..outcome
+    }
+}
+
+/// Downloads `/file.bin` through a [`ScriptedBackend`] given the answers of `script`.
+fn scripted(name: &str, script: &Script) -> Outcome {
+    let origin = "http://example.com";
+    let backend = ScriptedBackend::new();
+    for (path, answers) in script {
+        for answer in answers {
+            backend.push(format!("{}{}", origin, path), scripted_answer(answer));
+        }
+    }
+    let outcome = download(name, &backend, &format!("{}/file.bin", origin));
+    Outcome {
+        requests: backend
+            .requests()
+            .into_iter()
+            .map(|request| {
+                let path = request.url.strip_prefix(origin).unwrap().to_string();
+                let range = request
+                    .headers
+                    .get("range")
+                    .map(|range| range.to_str().unwrap().to_string());
+                (request.method.to_string(), path, range)
+            })
+            .collect(),
+        ..outcome
+    }
+}
+
+/// The scripted equivalent of `response`, with the `Content-Length` the server adds and the
+/// dropped connection of a cut body.
+fn scripted_answer(response: &Response) -> Answer {
+    let mut answer = ScriptedResponse::status(StatusCode::from_u16(response.status).unwrap());
+    answer
+        .headers
+        .insert(CONTENT_LENGTH, HeaderValue::from(response.body.len()));
+    for (name, value) in &response.headers {
+        answer.headers.insert(
+            HeaderName::from_bytes(name.as_bytes()).unwrap(),
+            HeaderValue::from_str(value).unwrap(),
+        );
+    }
+    answer.body = match response.cut_after {
+        Some(cut) => vec![
+            BodyPart::Data(response.body[..cut].to_vec()),
+            BodyPart::Error(io::ErrorKind::UnexpectedEof),
+        ],
+        None => vec![BodyPart::Data(response.body.clone())],
+    };
+    answer.into()
+}
+
+/// Downloads `url` through `backend` into a scratch directory named `name`.
+fn download(name: &str, backend: &impl HttpBackend, url: &str) -> Outcome {
+    let path = scratch_dir(&format!("conformance_{}", name)).join("file.bin");
+    let result = download_file_with_config(backend, url, &path, &config(), |_| {});
+    Outcome {
+        result,
+        file: std::fs::read(&path).ok(),
+        requests: Vec::new(),
+    }
+}
+
+#[test]
+fn whole_files_are_saved_as_served() {
+    let content = pattern(16 * 1024);
+    let script = vec![("/file.bin", vec![Response::ok(content.clone())])];
+    conforms("whole", script, |backend, outcome| {
+        assert!(outcome.result.is_ok(), "{}: {:?}", backend, outcome.result);
+        assert_eq!(outcome.file.as_ref(), Some(&content), "{}", backend);
+        assert_eq!(
+            outcome.requests,
+            [("GET".to_string(), "/file.bin".to_string(), None)],
+            "{}",
+            backend
+        );
+    });
+}
+
+#[test]
+fn redirects_are_followed_to_the_file() {
+    let content = pattern(4 * 1024);
+    let script = vec![
+        (
+            "/file.bin",
+            vec![Response::status(302).header("Location", "/moved.bin")],
+        ),
+        ("/moved.bin", vec![Response::ok(content.clone())]),
+    ];
+    conforms("redirect", script, |backend, outcome| {
+        assert!(outcome.result.is_ok(), "{}: {:?}", backend, outcome.result);
+        assert_eq!(outcome.file.as_ref(), Some(&content), "{}", backend);
+        let paths: Vec<_> = outcome.requests.iter().map(|(_, path, _)| path).collect();
+        assert_eq!(paths, ["/file.bin", "/moved.bin"], "{}", backend);
+    });
+}
+
+#[test]
+fn error_statuses_fail_without_leaving_a_file() {
+    let script = vec![("/file.bin", vec![Response::status(404)])];
+    conforms("not_found", script, |backend, outcome| {
+        assert!(
+            matches!(
+                outcome.result,
+                Err(DownloadError::Status { status, .. }) if status == StatusCode::NOT_FOUND
+            ),
+            "{}: {:?}",
+            backend,
+            outcome.result
+        );
+        assert_eq!(outcome.file, None, "{}", backend);
+        assert_eq!(outcome.requests.len(), 1, "{}", backend);
+    });
+}
+
+#[test]
+fn server_errors_are_retried() {
+    let content = pattern(4 * 1024);
+    let script = vec![(
+        "/file.bin",
+        vec![Response::status(503), Response::ok(content.clone())],
+    )];
+    conforms("retried", script, |backend, outcome| {
+        assert!(outcome.result.is_ok(), "{}: {:?}", backend, outcome.result);
+        assert_eq!(outcome.file.as_ref(), Some(&content), "{}", backend);
+        assert_eq!(outcome.requests.len(), 2, "{}", backend);
+    });
+}
+
+#[test]
+fn dropped_connections_resume_where_the_body_broke_off() {
+    let content = pattern(64 * 1024);
+    let half = content.len() / 2;
+    let whole = Response::ok(content.clone()).header("Accept-Ranges", "bytes");
+    let rest = Response::status(206)
+        .body(&content[half..])
+        .header(
+            "Content-Range",
+            format!("bytes {}-{}/{}", half, content.len() - 1, content.len()),
+        )
+        .header("Accept-Ranges", "bytes");
+    // The second attempt checks that the server still accepts ranges before asking for one.
+    let script = vec![(
+        "/file.bin",
+        vec![whole.clone().cut_after(half), whole, rest],
+    )];
+    conforms("resumed", script, |backend, outcome| {
+        assert!(outcome.result.is_ok(), "{}: {:?}", backend, outcome.result);
+        assert_eq!(outcome.file.as_ref(), Some(&content), "{}", backend);
+        let sent: Vec<_> = outcome
+            .requests
+            .iter()
+            .map(|(method, _, range)| (method.as_str(), range.as_deref()))
+            .collect();
+        let range = format!("bytes={}-", half);
+        assert_eq!(
+            sent,
+            [("GET", None), ("HEAD", None), ("GET", Some(range.as_str()))],
+            "{}",
+            backend
+        );
+    });
+}
@@ -0,0 +1,164 @@
+//! Gzip bodies decompressed on their way to the destination, from fixtures built by Python's
+//! `gzip`, and refusing bodies that aren't valid or decode past the size limit.
+
+mod common;
+
+use common::{scratch_dir, MockServer};
+use parallel_downloads::{
+    download_file_with_config, Checksum, ChecksumTarget, Decompress, DownloadConfig, DownloadError,
+    DownloadEvent,
+};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Reads a file of `tests/fixtures`.
+fn fixture(name: &str) -> Vec<u8> {
+    std::fs::read(Path::new("tests/fixtures").join(name)).unwrap()
+}
+
+/// The content `data.bin.gz` decompresses to.
+fn data() -> Vec<u8> {
+    (0..200_000)
+        .map(|i| ((i * 7 + i / 251) % 256) as u8)
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Settings decompressing the body as gzip.
+fn gzip() -> DownloadConfig {
+    DownloadConfig {
+        decompress: Some(Decompress::Gzip),
+        ..DownloadConfig::default()
+    }
+}
+
+/// The size and SHA-256 a `Completed` event reported.
+type Completed = (u64, Option<String>);
+
+/// Downloads `body` into the scratch directory `name` with `config`, returning the result with
+/// what its `Completed` event reported, and the destination.
+fn download(
+    name: &str,
+    body: Vec<u8>,
+    config: &DownloadConfig,
+) -> (Result<(), DownloadError>, Option<Completed>, PathBuf) {
+    let server = MockServer::serving(body);
+    let path = scratch_dir(name).join("data.bin");
+    let completed = Arc::new(Mutex::new(None));
+    let seen = Arc::clone(&completed);
+    let client = reqwest::blocking::Client::new();
+    let result = download_file_with_config(
+        &client,
+        server.url("/data.bin.gz"),
+        &path,
+        config,
+        move |event| {
+            if let DownloadEvent::Completed { bytes, sha256, .. } = event {
+                *seen.lock().unwrap() = Some((*bytes, sha256.clone()));
+            }
+        },
+    );
+    let completed = completed.lock().unwrap().clone();
+    (result, completed, path)
+}
+
+#[test]
+fn the_destination_gets_the_decompressed_file_and_its_size_and_hash() {
+    let config = DownloadConfig {
+        compute_sha256: true,
+        ..gzip()
+    };
+    let (result, completed, path) = download("decompress_gzip", fixture("data.bin.gz"), &config);
+
+    result.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), data());
+    assert_eq!(completed, Some((200_000, Some(hex(&data())))));
+}
+
+#[test]
+fn checksums_apply_to_the_compressed_body_by_default() {
+    let body = fixture("data.bin.gz");
+    let config = DownloadConfig {
+        checksum: Some(Checksum::Sha256(hex(&body))),
+        ..gzip()
+    };
+    let (result, _, path) = download("decompress_checksum", body, &config);
+
+    result.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), data());
+}
+
+#[test]
+fn checksums_can_apply_to_the_decompressed_file() {
+    let body = fixture("data.bin.gz");
+    let compressed = DownloadConfig {
+        checksum: Some(Checksum::Sha256(hex(&body))),
+        checksum_target: ChecksumTarget::Decompressed,
+        ..gzip()
+    };
+    let (result, _, path) = download("decompress_checksum_target", body.clone(), &compressed);
+    assert!(
+        matches!(&result, Err(DownloadError::ChecksumMismatch(_))),
+        "{:?}",
+        result
+    );
+    assert!(!path.exists());
+
+    let decompressed = DownloadConfig {
+        checksum: Some(Checksum::Sha256(hex(&data()))),
+        ..compressed
+    };
+    let (result, _, path) = download("decompress_checksum_target", body, &decompressed);
+    result.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), data());
+}
+
+#[test]
+fn a_gzip_bomb_is_stopped_at_the_size_limit() {
+    // 64 MiB of zeros in 64 KB.
+    let config = DownloadConfig {
+        max_size: Some(1024 * 1024),
+        ..gzip()
+    };
+    let (result, _, path) = download("decompress_bomb", fixture("bomb.gz"), &config);
+
+    assert!(
+        matches!(
+            &result,
+            Err(DownloadError::SizeLimitExceeded(error))
+                if error.limit == 1024 * 1024 && error.received < 8 * 1024 * 1024
+        ),
+        "{:?}",
+        result
+    );
+    assert!(!path.exists());
+    assert!(!path.with_file_name("data.bin.part").exists());
+}
+
+#[test]
+fn bodies_that_are_not_valid_gzip_streams_fail_to_decompress() {
+    let mut truncated = fixture("data.bin.gz");
+    truncated.truncate(truncated.len() - 4);
+    for (name, body) in [
+        ("decompress_plain", data()),
+        ("decompress_truncated", truncated),
+    ] {
+        let (result, _, path) = download(name, body, &gzip());
+
+        assert!(
+            matches!(&result, Err(DownloadError::Decompress(error)) if error.path == path),
+            "{}: {:?}",
+            name,
+            result
+        );
+        assert!(!path.exists());
+        assert!(!path.with_file_name("data.bin.part").exists());
+    }
+}
@@ -0,0 +1,120 @@
+//! Host names pointed at addresses of the caller's choosing.
+
+mod common;
+
+use common::{pattern, scratch_dir, MockServer};
+use parallel_downloads::{
+    download_batch_requests, BatchConfig, DnsConfig, DownloadRequest, IpFamily,
+};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+/// Downloads `url` in a batch with `dns`, returning the file.
+fn download(url: String, name: &str, dns: DnsConfig) -> Vec<u8> {
+    let path = scratch_dir(name).join("file.bin");
+    let config = BatchConfig {
+        dns,
+        ..BatchConfig::default()
+    };
+    let request = DownloadRequest::new(url, &path);
+    let results = download_batch_requests(vec![request], config, |_| {}).unwrap();
+    assert!(results[0].is_success(), "{:?}", results[0]);
+    std::fs::read(&path).unwrap()
+}
+
+#[test]
+fn overridden_hosts_connect_to_their_address_and_keep_their_name() {
+    let content = pattern(16 * 1024);
+    let server = MockServer::serving(content.clone());
+    let port = server.addr().port();
+    let dns = DnsConfig {
+        overrides: HashMap::from([(
+            "Files.Example.Test".to_string(),
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+        )]),
+        ..DnsConfig::default()
+    };
+    let url = format!("http://files.example.test:{}/file.bin", port);
+    assert_eq!(download(url, "dns_override", dns), content);
+    let host = format!("files.example.test:{}", port);
+    assert_eq!(server.requests()[0].header("host"), Some(host.as_str()));
+}
+
+/// Resolves every name to the local host, remembering the names it was asked for.
+#[derive(Default)]
+struct Loopback {
+    names: Mutex<Vec<String>>,
+}
+
+impl Resolve for Loopback {
+    fn resolve(&self, name: Name) -> Resolving {
+        self.names.lock().unwrap().push(name.as_str().to_string());
+        let addrs: Addrs = Box::new(std::iter::once(SocketAddr::from(([127, 0, 0, 1], 0))));
+        Box::pin(async move { Ok(addrs) })
+    }
+}
+
+#[test]
+fn a_custom_resolver_looks_up_hosts() {
+    let content = pattern(16 * 1024);
+    let server = MockServer::serving(content.clone());
+    let resolver = Arc::new(Loopback::default());
+    let dns = DnsConfig {
+        resolver: Some(resolver.clone()),
+        ..DnsConfig::default()
+    };
+    let url = format!(
+        "http://staging.example.test:{}/file.bin",
+        server.addr().port()
+    );
+    assert_eq!(download(url, "dns_resolver", dns), content);
+    assert_eq!(
+        *resolver.names.lock().unwrap(),
+        vec!["staging.example.test".to_string()]
+    );
+}
+
+/// Returns how many threads of the process are named `dns-lookup-*`, if the platform lists
+/// them.
+fn lookup_threads() -> Option<usize> {
+    let tasks = std::fs::read_dir("/proc/self/task").ok()?;
+    let count = tasks
+        .flatten()
+        .filter(|task| {
+            std::fs::read_to_string(task.path().join("comm"))
+                .is_ok_and(|name| name.starts_with("dns-lookup"))
+        })
+        .count();
+    Some(count)
+}
+
+#[test]
+fn system_lookups_share_a_few_threads() {
+    let server = MockServer::serving(pattern(1024));
+    let directory = scratch_dir("dns_lookup_threads");
+    let requests = (0..16)
+        .map(|index| {
+            let name = format!("file-{}", index);
+            // `localhost` goes through the system resolver, which the family filters.
+            let url = format!("http://localhost:{}/{}", server.addr().port(), name);
+            DownloadRequest::new(url, directory.join(name))
+        })
+        .collect();
+    let config = BatchConfig {
+        concurrency: 8,
+        dns: DnsConfig {
+            family: IpFamily::Ipv4Only,
+            ..DnsConfig::default()
+        },
+        ..BatchConfig::default()
+    };
+
+    let results = download_batch_requests(requests, config, |_| {}).unwrap();
+
+    assert!(results.iter().all(|result| result.is_success()));
+    if let Some(threads) = lookup_threads() {
+        assert!((1..=4).contains(&threads), "{} lookup threads", threads);
+    }
+}
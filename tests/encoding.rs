@@ -0,0 +1,99 @@
+//! Downloads of bodies the server compressed with `Content-Encoding`.
+
+mod common;
+
+use common::{scratch_dir, MockServer, Response};
+use parallel_downloads::{
+    download_file_with_config, ContentEncoding, DownloadCallbackProgress, DownloadConfig,
+    DownloadEvent, HttpBackend, ProgressThrottle, ScriptedBackend, ScriptedResponse,
+};
+use reqwest::header::{HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// A gzip stream, served as the encoded form of some file.
+fn gzip_body() -> Vec<u8> {
+    std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/archive.tar.gz"))
+        .unwrap()
+}
+
+/// Downloads `url` to `path` with `config` and every chunk reported, returning the progress
+/// events it sent.
+fn download(
+    backend: &impl HttpBackend,
+    url: &str,
+    path: &Path,
+    config: DownloadConfig,
+) -> Vec<DownloadCallbackProgress> {
+    let config = DownloadConfig {
+        progress_throttle: ProgressThrottle::NONE,
+        ..config
+    };
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&events);
+    download_file_with_config(backend, url, path, &config, move |event| {
+        if let DownloadEvent::Progress(progress) = event {
+            seen.lock().unwrap().push(progress.clone());
+        }
+    })
+    .unwrap();
+    let events = events.lock().unwrap().clone();
+    events
+}
+
+#[test]
+fn downloads_ask_for_the_file_as_stored_by_default() {
+    let server = MockServer::start(|_| Response::ok(vec![7; 4096]));
+    let path = scratch_dir("encoding_identity").join("file.bin");
+    let client = reqwest::blocking::Client::new();
+    let events = download(
+        &client,
+        &server.url("/file.bin"),
+        &path,
+        DownloadConfig::default(),
+    );
+    assert_eq!(
+        server.requests()[0].header("accept-encoding"),
+        Some("identity")
+    );
+    assert_eq!(events.last().unwrap().total_bytes(), Some(4096));
+}
+
+#[test]
+fn gzip_bodies_are_saved_with_an_unknown_total() {
+    let body = gzip_body();
+    let served = body.clone();
+    // The server compresses whatever the client asked for.
+    let server =
+        MockServer::start(move |_| Response::ok(served.clone()).header("Content-Encoding", "gzip"));
+    let path = scratch_dir("encoding_gzip").join("file.bin");
+    let config = DownloadConfig {
+        content_encoding: ContentEncoding::Any,
+        ..DownloadConfig::default()
+    };
+    let client = reqwest::blocking::Client::new();
+    let events = download(&client, &server.url("/file.bin"), &path, config);
+    assert_eq!(server.requests()[0].header("accept-encoding"), None);
+    assert!(events.iter().all(|event| event.total_bytes().is_none()));
+    assert_eq!(events.last().unwrap().bytes_downloaded(), body.len() as u64);
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+}
+
+#[test]
+fn decompressed_bodies_longer_than_their_length_pass_the_size_check() {
+    // A decompressing backend hands over more bytes than the compressed `Content-Length`.
+    let url = "http://example.com/file.txt";
+    let backend = ScriptedBackend::new();
+    let mut response = ScriptedResponse::ok(vec![b'a'; 100_000]);
+    response
+        .headers
+        .insert(CONTENT_LENGTH, HeaderValue::from(1_000));
+    response
+        .headers
+        .insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    backend.push(url, response);
+    let path = scratch_dir("encoding_decompressed").join("file.txt");
+    let events = download(&backend, url, &path, DownloadConfig::default());
+    assert!(events.iter().all(|event| event.percent().is_none()));
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 100_000);
+}
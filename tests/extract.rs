@@ -0,0 +1,346 @@
+//! Unpacking downloaded archives, built by Python's `zipfile`, `tarfile`, `gzip` and `lzma`,
+//! from the fixtures directory, and refusing malformed ones.
+
+mod common;
+
+use common::{scratch_dir, MockServer};
+use parallel_downloads::{
+    download_batch_requests, BatchConfig, DownloadError, DownloadOutcome, DownloadRequest,
+    ExtractError,
+};
+use std::path::{Path, PathBuf};
+
+/// The content of `hello.txt` in every fixture.
+fn hello() -> Vec<u8> {
+    b"hello from the fixture\n".repeat(1000)
+}
+
+/// The content of `nested/data.bin`, which compresses well.
+fn data() -> Vec<u8> {
+    (0..100_000).map(|i| (i % 251) as u8).collect()
+}
+
+/// The content of `nested/noise.bin`, which doesn't compress.
+fn noise() -> Vec<u8> {
+    let mut state: u32 = 1;
+    (0..20_000)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345) & 0x7fff_ffff;
+            (state >> 16) as u8
+        })
+        .collect()
+}
+
+/// Downloads `content` as `name` with extraction, returning the outcome and the directory.
+fn download_archive(name: &str, content: Vec<u8>) -> (DownloadOutcome, PathBuf) {
+    download_limited(name, content, None)
+}
+
+/// Downloads `content` as `name`, extracting at most `max_size` bytes from it.
+fn download_limited(
+    name: &str,
+    content: Vec<u8>,
+    max_size: Option<u64>,
+) -> (DownloadOutcome, PathBuf) {
+    let server = MockServer::start(move |_| common::Response::ok(content.clone()));
+    let directory = scratch_dir(&format!("extract_{}", name.replace('.', "_")));
+    let mut request = DownloadRequest::new(server.url(&format!("/{}", name)), directory.join(name));
+    request.extract = true;
+    request.extract_max_size = max_size;
+    let config = BatchConfig {
+        continue_on_error: true,
+        ..BatchConfig::default()
+    };
+    let mut results = download_batch_requests(vec![request], config, |_| {}).unwrap();
+    (results.remove(0).outcome, directory)
+}
+
+/// Unpacks the fixture `name` and checks every file it holds.
+fn check_fixture(name: &str) {
+    let archive = std::fs::read(Path::new("tests/fixtures").join(name)).unwrap();
+    let (outcome, directory) = download_archive(name, archive);
+    let DownloadOutcome::Completed { extracted, .. } = outcome else {
+        panic!("{} wasn't unpacked: {:?}", name, outcome);
+    };
+    assert_eq!(
+        extracted,
+        [
+            directory.join("hello.txt"),
+            directory.join("nested").join("data.bin"),
+            directory.join("nested").join("noise.bin"),
+        ]
+    );
+    assert_eq!(std::fs::read(&extracted[0]).unwrap(), hello());
+    assert_eq!(std::fs::read(&extracted[1]).unwrap(), data());
+    assert_eq!(std::fs::read(&extracted[2]).unwrap(), noise());
+}
+
+#[test]
+fn unpacks_zip() {
+    check_fixture("archive.zip");
+}
+
+#[test]
+fn unpacks_tar() {
+    check_fixture("archive.tar");
+}
+
+#[test]
+fn unpacks_tar_gz() {
+    check_fixture("archive.tar.gz");
+}
+
+#[test]
+fn unpacks_tar_xz() {
+    check_fixture("archive.tar.xz");
+}
+
+#[test]
+fn a_truncated_archive_fails_and_is_kept() {
+    for name in ["archive.zip", "archive.tar.gz", "archive.tar.xz"] {
+        let mut archive = std::fs::read(Path::new("tests/fixtures").join(name)).unwrap();
+        archive.truncate(archive.len() / 2);
+        let (outcome, directory) = download_archive(name, archive);
+        match outcome {
+            DownloadOutcome::Failed {
+                error: DownloadError::Extract(error),
+            } => assert_eq!(error.archive, directory.join(name)),
+            outcome => panic!("{} was unpacked: {:?}", name, outcome),
+        }
+        assert!(directory.join(name).exists());
+    }
+}
+
+/// Reads the fixture `name`.
+fn fixture(name: &str) -> Vec<u8> {
+    std::fs::read(Path::new("tests/fixtures").join(name)).unwrap()
+}
+
+/// Unpacks `content` as `name`, expecting it to be refused with an [`ExtractError`], and
+/// returns the error with the directory it was unpacked into.
+fn refused(name: &str, content: Vec<u8>) -> (ExtractError, PathBuf) {
+    match download_archive(name, content) {
+        (
+            DownloadOutcome::Failed {
+                error: DownloadError::Extract(error),
+            },
+            directory,
+        ) => (error, directory),
+        outcome => panic!("{} was unpacked: {:?}", name, outcome),
+    }
+}
+
+/// A ustar header for an entry named `name` of type `kind`, with `size` as its size field.
+fn tar_header(name: &str, kind: u8, size: [u8; 12]) -> Vec<u8> {
+    let mut header = vec![0; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[124..136].copy_from_slice(&size);
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[148..156].fill(b' ');
+    let sum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    header
+}
+
+/// The octal size field of a tar header.
+fn octal_size(size: usize) -> [u8; 12] {
+    format!("{:011o}\0", size).into_bytes().try_into().unwrap()
+}
+
+/// A tar archive holding a regular file for each of `files`, by name and content.
+fn tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut archive = Vec::new();
+    for (name, content) in files {
+        archive.extend(tar_header(name, b'0', octal_size(content.len())));
+        archive.extend_from_slice(content);
+        archive.resize(archive.len().div_ceil(512) * 512, 0);
+    }
+    archive.resize(archive.len() + 1024, 0);
+    archive
+}
+
+#[test]
+fn entries_leading_outside_the_directory_are_refused() {
+    for (index, name) in [
+        "../escaped.txt",
+        "nested/../../escaped.txt",
+        "/tmp/escaped.txt",
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let archive_name = format!("escape{}.tar", index);
+        let (error, _) = refused(&archive_name, tar(&[(name, b"content")]));
+
+        assert_eq!(error.entry.as_deref(), Some(name));
+        assert_eq!(error.reason, "its path leads outside the target directory");
+    }
+    let renamed = replace_once(&fixture("archive.zip"), b"hello.txt", b"../lo.txt", 1);
+    let (error, directory) = refused("escape.zip", renamed);
+
+    assert_eq!(error.entry.as_deref(), Some("../lo.txt"));
+    assert!(!directory.join("../lo.txt").exists());
+}
+
+#[test]
+fn a_tar_header_that_doesnt_match_its_checksum_is_refused() {
+    let mut archive = tar(&[("first.txt", b"first"), ("second.txt", b"second")]);
+    // The name of the second header.
+    archive[1024] = b'S';
+    let (error, _) = refused("checksum.tar", archive);
+
+    assert_eq!(error.reason, "a header doesn't match its checksum");
+}
+
+#[test]
+fn a_tar_size_overflowing_64_bits_is_refused() {
+    // A base-256 size of 95 bits.
+    let mut archive = tar_header("huge.bin", b'0', [0xFF; 12]);
+    archive.resize(512 + 1024, 0);
+    let (error, _) = refused("overflow.tar", archive);
+
+    assert_eq!(error.reason, "a header holds an invalid size");
+}
+
+#[test]
+fn an_oversized_pax_header_is_refused_before_it_is_read() {
+    for kind in [b'x', b'L'] {
+        let mut archive = tar_header("././@PaxHeader", kind, octal_size(64 * 1024 * 1024));
+        archive.resize(512 + 1024, 0);
+        let (error, _) = refused(&format!("pax_{}.tar", kind as char), archive);
+
+        assert_eq!(error.reason, "a pax or long name header is too long");
+    }
+}
+
+/// Returns `bytes` with the `occurrence`th (from 0) appearance of `from` replaced by `to`.
+fn replace_once(bytes: &[u8], from: &[u8], to: &[u8], occurrence: usize) -> Vec<u8> {
+    let at = bytes
+        .windows(from.len())
+        .enumerate()
+        .filter(|(_, window)| *window == from)
+        .nth(occurrence)
+        .unwrap()
+        .0;
+    let mut replaced = bytes.to_vec();
+    replaced[at..at + to.len()].copy_from_slice(to);
+    replaced
+}
+
+/// Where the central directory entry of `hello.txt` starts in `archive.zip`.
+fn hello_central_entry(archive: &[u8]) -> usize {
+    archive
+        .windows(4)
+        .position(|window| window == b"PK\x01\x02")
+        .unwrap()
+}
+
+#[test]
+fn a_zip_entry_that_doesnt_match_its_crc_is_refused() {
+    let mut archive = fixture("archive.zip");
+    let entry = hello_central_entry(&archive);
+    archive[entry + 16] ^= 0xFF;
+    let (error, _) = refused("crc.zip", archive);
+
+    assert_eq!(error.entry.as_deref(), Some("hello.txt"));
+    assert!(
+        error.reason.contains("doesn't match its CRC-32"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn a_zip_entry_larger_than_its_declared_size_is_refused() {
+    let mut archive = fixture("archive.zip");
+    let entry = hello_central_entry(&archive);
+    archive[entry + 24..entry + 28].copy_from_slice(&100u32.to_le_bytes());
+    let (error, directory) = refused("larger.zip", archive);
+
+    assert_eq!(error.entry.as_deref(), Some("hello.txt"));
+    assert!(
+        error.reason.contains("larger than its declared size"),
+        "{}",
+        error
+    );
+    // The read that outgrew the size failed before any of it was written.
+    let written = std::fs::metadata(directory.join("hello.txt")).unwrap();
+    assert_eq!(written.len(), 0);
+}
+
+#[test]
+fn an_xz_block_with_another_filter_is_refused() {
+    let mut archive = fixture("archive.tar.xz");
+    // The ID of the first filter of the first block header, right after the stream header.
+    assert_eq!(archive[14], 0x21);
+    archive[14] = 0x04;
+    let (error, _) = refused("filter.tar.xz", archive);
+
+    assert!(
+        error.reason.contains("filters other than LZMA2"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn an_xz_block_that_doesnt_match_its_check_is_refused() {
+    let mut archive = fixture("archive.tar.xz");
+    // The footer gives the size of the index, which the CRC-64 of the only block precedes.
+    let footer = archive.len() - 12;
+    let backward = u32::from_le_bytes(archive[footer + 4..footer + 8].try_into().unwrap());
+    let index = footer - (backward as usize + 1) * 4;
+    archive[index - 1] ^= 0xFF;
+    let (error, _) = refused("check.tar.xz", archive);
+
+    assert!(error.reason.contains("integrity check"), "{}", error);
+}
+
+#[test]
+fn an_xz_stream_header_that_doesnt_match_its_crc_is_refused() {
+    let mut archive = fixture("archive.tar.xz");
+    // The check type: CRC-32 instead of CRC-64.
+    archive[7] = 0x01;
+    let (error, _) = refused("header.tar.xz", archive);
+
+    assert!(
+        error.reason.contains("doesn't match its CRC-32"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn extraction_stops_at_the_size_limit() {
+    // 123 KB of files in a 21 KB archive.
+    for name in ["archive.tar.gz", "archive.zip"] {
+        let (outcome, directory) = download_limited(name, fixture(name), Some(50_000));
+        let DownloadOutcome::Failed {
+            error: DownloadError::Extract(error),
+        } = outcome
+        else {
+            panic!("{} was unpacked: {:?}", name, outcome);
+        };
+
+        assert_eq!(error.entry.as_deref(), Some("nested/data.bin"));
+        assert_eq!(
+            error.reason,
+            "the extracted files add up to more than 50000 bytes"
+        );
+        let data = std::fs::metadata(directory.join("nested").join("data.bin")).unwrap();
+        assert!(data.len() <= 50_000, "{}", data.len());
+    }
+}
+
+#[test]
+fn archives_within_the_size_limit_unpack() {
+    let (outcome, _) = download_limited("limit.tar", tar(&[("file.txt", b"content")]), Some(7));
+
+    assert!(
+        matches!(outcome, DownloadOutcome::Completed { .. }),
+        "{:?}",
+        outcome
+    );
+}
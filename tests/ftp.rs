@@ -0,0 +1,159 @@
+//! Downloads of `ftp://` URLs from a stub server that records every command it receives.
+
+mod common;
+
+use common::scratch_dir;
+use parallel_downloads::{download_file, DownloadError};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// An FTP server on a local port serving `files`, which answers `550` for anything else.
+struct StubServer {
+    port: u16,
+    commands: Arc<Mutex<Vec<String>>>,
+}
+
+impl StubServer {
+    fn start(files: &[(&str, &[u8])]) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let files: Arc<HashMap<String, Vec<u8>>> = Arc::new(
+            files
+                .iter()
+                .map(|(name, content)| (name.to_string(), content.to_vec()))
+                .collect(),
+        );
+        let recorded = Arc::clone(&commands);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (recorded, files) = (Arc::clone(&recorded), Arc::clone(&files));
+                thread::spawn(move || serve(stream, &recorded, &files));
+            }
+        });
+        Self { port, commands }
+    }
+
+    fn url(&self, userinfo: &str, path: &str) -> String {
+        format!("ftp://{}127.0.0.1:{}/{}", userinfo, self.port, path)
+    }
+
+    fn commands(&self) -> Vec<String> {
+        self.commands.lock().unwrap().clone()
+    }
+}
+
+/// Answers the commands of one control connection until the client quits.
+fn serve(stream: TcpStream, recorded: &Mutex<Vec<String>>, files: &HashMap<String, Vec<u8>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    let mut data = None;
+    writer.write_all(b"220 ready\r\n").unwrap();
+    let mut line = String::new();
+    while reader.read_line(&mut line).unwrap_or(0) > 0 {
+        let command = line.trim_end_matches(['\r', '\n']).to_string();
+        line.clear();
+        recorded.lock().unwrap().push(command.clone());
+        let (verb, argument) = command.split_once(' ').unwrap_or((&command, ""));
+        let reply = match verb {
+            "USER" => "331 password please".to_string(),
+            "PASS" => "230 logged in".to_string(),
+            "TYPE" => "200 binary".to_string(),
+            "SIZE" => match files.get(argument) {
+                Some(content) => format!("213 {}", content.len()),
+                None => "550 no such file".to_string(),
+            },
+            "PASV" => {
+                let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                let port = listener.local_addr().unwrap().port();
+                data = Some(listener);
+                format!("227 passive (127,0,0,1,{},{})", port >> 8, port & 0xff)
+            }
+            "RETR" => match (files.get(argument), data.take()) {
+                (Some(content), Some(listener)) => {
+                    writer.write_all(b"150 sending\r\n").unwrap();
+                    let (mut connection, _) = listener.accept().unwrap();
+                    connection.write_all(content).unwrap();
+                    drop(connection);
+                    "226 done".to_string()
+                }
+                _ => "550 no such file".to_string(),
+            },
+            "QUIT" => {
+                let _ = writer.write_all(b"221 bye\r\n");
+                return;
+            }
+            _ => "502 not implemented".to_string(),
+        };
+        if writer
+            .write_all(format!("{}\r\n", reply).as_bytes())
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+#[test]
+fn downloads_a_file() {
+    let server = StubServer::start(&[("hello.txt", b"hello over ftp")]);
+    let path = scratch_dir("ftp_downloads_a_file").join("hello.txt");
+    download_file(server.url("u:pw@", "hello.txt"), &path, |_| ()).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"hello over ftp");
+    let commands = server.commands();
+    assert_eq!(commands[..2], ["USER u", "PASS pw"]);
+    assert!(commands.contains(&"RETR hello.txt".to_string()));
+}
+
+#[test]
+fn refuses_line_breaks_in_the_user() {
+    let server = StubServer::start(&[]);
+    let url = server.url("a%0D%0ADELE%20important.txt:pw@", "file.txt");
+    let error = download_file(
+        url,
+        scratch_dir("ftp_refuses_user").join("user.txt"),
+        |_| (),
+    )
+    .unwrap_err();
+    assert!(matches!(error, DownloadError::InvalidUrl(_)), "{:?}", error);
+    assert!(server.commands().is_empty());
+}
+
+#[test]
+fn refuses_line_breaks_and_nul_in_the_password_and_path() {
+    let server = StubServer::start(&[]);
+    for url in [
+        server.url("u:pw%0ADELE%20x@", "file.txt"),
+        server.url("u:pw@", "file.txt%0D%0ADELE%20important.txt"),
+        server.url("u:pw@", "file%00.txt"),
+    ] {
+        let error = download_file(
+            &url,
+            scratch_dir("ftp_refuses_other").join("other.txt"),
+            |_| (),
+        )
+        .unwrap_err();
+        assert!(
+            matches!(error, DownloadError::InvalidUrl(_)),
+            "{}: {:?}",
+            url,
+            error
+        );
+    }
+    assert!(server.commands().is_empty());
+}
+
+#[test]
+fn redacts_credentials_from_errors() {
+    let server = StubServer::start(&[]);
+    let url = server.url("u:secretpw@", "missing.txt");
+    let error =
+        download_file(&url, scratch_dir("ftp_redacts").join("missing.txt"), |_| ()).unwrap_err();
+    let message = error.to_string();
+    assert!(message.contains("404"), "{}", message);
+    assert!(!message.contains("secretpw"), "{}", message);
+    assert!(!format!("{:?}", error).contains("secretpw"), "{:?}", error);
+}
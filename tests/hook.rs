@@ -0,0 +1,86 @@
+//! The completion hook a batch runs on every file once it is in place.
+
+mod common;
+
+use common::{scratch_dir, MockServer, Response};
+use parallel_downloads::{
+    download_batch_requests, BatchConfig, CompleteHook, DownloadRequest, DownloadResult,
+};
+use std::sync::{Arc, Mutex};
+
+/// Downloads `/file.txt` from `server` in a batch running `hook`.
+fn download(server: &MockServer, name: &str, hook: CompleteHook) -> DownloadResult {
+    let path = scratch_dir(name).join("file.txt");
+    let config = BatchConfig {
+        on_complete: Some(hook),
+        ..BatchConfig::default()
+    };
+    let results = download_batch_requests(
+        vec![DownloadRequest::new(server.url("/file.txt"), path)],
+        config,
+        |_| {},
+    )
+    .unwrap();
+    results.into_iter().next().unwrap()
+}
+
+#[test]
+fn the_hook_sees_the_file_in_place_with_the_headers_it_selected() {
+    let server = MockServer::start(|_| {
+        Response::ok("content")
+            .header("ETag", "\"v1\"")
+            .header("X-Scan", "pending")
+    });
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&seen);
+    let hook = CompleteHook::new(move |file| {
+        let content = std::fs::read(&file.path)?;
+        recorded.lock().unwrap().push((file.clone(), content));
+        Ok(())
+    })
+    .with_headers(["etag", "X-Missing"]);
+    let result = download(&server, "hook_complete", hook);
+
+    assert!(result.is_success(), "{:?}", result.outcome);
+    assert_eq!(result.hook_error(), None);
+    let seen = seen.lock().unwrap();
+    let [(file, content)] = seen.as_slice() else {
+        panic!("the hook ran {} times", seen.len());
+    };
+    assert_eq!(content, b"content");
+    assert_eq!(file.path, result.path().unwrap());
+    assert_eq!(file.url, server.url("/file.txt"));
+    assert_eq!(file.bytes, 7);
+    assert_eq!(file.headers, [("etag".to_string(), "\"v1\"".to_string())]);
+}
+
+#[test]
+fn a_failing_hook_is_reported_without_failing_the_file() {
+    let server = MockServer::start(|_| Response::ok("content"));
+    let result = download(
+        &server,
+        "hook_error",
+        CompleteHook::new(|_| Err("the scanner is down".into())),
+    );
+
+    assert!(result.is_success(), "{:?}", result.outcome);
+    let error = result.hook_error().unwrap();
+    assert_eq!(error.reason, "the scanner is down");
+    assert!(!error.panicked);
+    assert!(result.path().unwrap().exists());
+}
+
+#[test]
+fn a_panicking_hook_is_caught_and_reported() {
+    let server = MockServer::start(|_| Response::ok("content"));
+    let result = download(
+        &server,
+        "hook_panic",
+        CompleteHook::new(|_| panic!("the hook broke")),
+    );
+
+    assert!(result.is_success(), "{:?}", result.outcome);
+    let error = result.hook_error().unwrap();
+    assert_eq!(error.reason, "the hook broke");
+    assert!(error.panicked);
+}
@@ -0,0 +1,34 @@
+//! The HTTP version reported for downloads, which only HTTP URLs have.
+
+mod common;
+
+use common::{pattern, scratch_dir, MockServer};
+use parallel_downloads::ProtocolVersion;
+use parallel_downloads::{download_batch_requests, BatchConfig, DownloadRequest};
+
+/// Downloads `url` into `directory` as a batch of one, returning the version it reported.
+fn reported_version(url: String, directory: &std::path::Path) -> Option<ProtocolVersion> {
+    let request = DownloadRequest::new(url, directory.join("file.bin"));
+    let results = download_batch_requests(vec![request], BatchConfig::default(), |_| {}).unwrap();
+    assert!(results[0].is_success(), "{:?}", results[0]);
+    results[0].http_version()
+}
+
+#[test]
+fn http_downloads_report_their_version() {
+    let server = MockServer::serving(pattern(1024));
+    let directory = scratch_dir("http_version_http");
+    let version = reported_version(server.url("/file.bin"), &directory);
+    assert_eq!(version, Some(ProtocolVersion::Http11));
+}
+
+#[test]
+fn file_and_data_urls_report_no_version() {
+    let directory = scratch_dir("http_version_local");
+    let source = directory.join("source.bin");
+    std::fs::write(&source, pattern(1024)).unwrap();
+    let file_url = format!("file://{}", source.display());
+    assert_eq!(reported_version(file_url, &directory), None);
+    let data_url = "data:text/plain,hello".to_string();
+    assert_eq!(reported_version(data_url, &directory), None);
+}
@@ -0,0 +1,119 @@
+//! Interceptors rewriting every request of a download before it reaches the server.
+
+mod common;
+
+use common::{pattern, ranged, scratch_dir, MockServer, Response};
+use parallel_downloads::{
+    download_file_with_config, interceptor_fn, DownloadConfig, Interceptors, RetryConfig,
+    SegmentConfig,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Downloads `url` into the scratch directory `name` with `config`.
+fn download(url: String, name: &str, config: &DownloadConfig) {
+    let path = scratch_dir(name).join("file.bin");
+    let client = reqwest::blocking::Client::new();
+    download_file_with_config(&client, url, &path, config, |_| {}).unwrap();
+}
+
+#[test]
+fn the_server_receives_requests_as_the_interceptors_rewrote_them_in_order() {
+    let server = MockServer::start(|request| Response::ok(request.path.clone()));
+    let interceptors = Interceptors::new()
+        .with(interceptor_fn(|mut request, _| {
+            request.url = request.url.replace("/unsigned", "/signed");
+            request
+                .headers
+                .insert("x-signature", "first".parse().unwrap());
+            request
+        }))
+        .with(interceptor_fn(|mut request, _| {
+            // Sees the header the first interceptor added.
+            let signed = format!(
+                "{}-second",
+                request.headers["x-signature"].to_str().unwrap()
+            );
+            request
+                .headers
+                .insert("x-signature", signed.parse().unwrap());
+            request
+        }));
+    let config = DownloadConfig {
+        interceptors,
+        ..DownloadConfig::default()
+    };
+    download(server.url("/unsigned"), "interceptor_order", &config);
+
+    let requests = server.requests();
+    assert!(!requests.is_empty());
+    for request in requests {
+        assert_eq!(request.path, "/signed");
+        assert_eq!(request.header("x-signature"), Some("first-second"));
+    }
+}
+
+#[test]
+fn retries_are_intercepted_with_their_attempt() {
+    let served = AtomicUsize::new(0);
+    let server = MockServer::start(move |_| {
+        if served.fetch_add(1, Ordering::SeqCst) == 0 {
+            Response::status(503)
+        } else {
+            Response::ok("content")
+        }
+    });
+    let config = DownloadConfig {
+        interceptors: Interceptors::new().with(interceptor_fn(|mut request, context| {
+            let attempt = context.attempt.to_string();
+            request
+                .headers
+                .insert("x-attempt", attempt.parse().unwrap());
+            request
+        })),
+        retry: RetryConfig {
+            base_delay: Duration::from_millis(1),
+            ..RetryConfig::default()
+        },
+        ..DownloadConfig::default()
+    };
+    download(server.url("/file.bin"), "interceptor_retry", &config);
+
+    let attempts: Vec<_> = server
+        .requests()
+        .iter()
+        .map(|request| request.header("x-attempt").map(str::to_string))
+        .collect();
+    assert_eq!(attempts, [Some("1".to_string()), Some("2".to_string())]);
+}
+
+#[test]
+fn segments_are_intercepted_with_their_offset() {
+    let content = pattern(64 * 1024);
+    let server = MockServer::start(move |request| ranged(&content, request));
+    let config = DownloadConfig {
+        interceptors: Interceptors::new().with(interceptor_fn(|mut request, context| {
+            let offset = context.offset.to_string();
+            request.headers.insert("x-offset", offset.parse().unwrap());
+            request
+        })),
+        segments: SegmentConfig {
+            count: 4,
+            min_segment_size: 16 * 1024,
+        },
+        ..DownloadConfig::default()
+    };
+    download(server.url("/file.bin"), "interceptor_segments", &config);
+
+    let ranged: Vec<_> = server
+        .requests()
+        .into_iter()
+        .filter(|request| request.header("range").is_some())
+        .collect();
+    assert_eq!(ranged.len(), 4, "the file wasn't downloaded in segments");
+    for request in ranged {
+        let range = request.header("range").unwrap();
+        let start = range["bytes=".len()..].split('-').next().unwrap();
+        assert_eq!(request.header("x-offset"), Some(start), "{}", range);
+    }
+}
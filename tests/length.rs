@@ -0,0 +1,60 @@
+//! Downloads whose body ends before the length the server announced.
+
+mod common;
+
+use common::{pattern, scratch_dir, MockServer, Response};
+use parallel_downloads::{
+    download_file_with_config, DownloadConfig, DownloadError, RetryConfig, ScriptedBackend,
+    ScriptedResponse,
+};
+use reqwest::header::{HeaderValue, CONTENT_LENGTH};
+
+#[test]
+fn a_connection_closed_early_fails_the_download() {
+    let content = pattern(64 * 1024);
+    let server = MockServer::start(move |_| Response::ok(content.clone()).cut_after(10_000));
+    let path = scratch_dir("length_cut_short").join("file.bin");
+    let config = DownloadConfig {
+        retry: RetryConfig::none(),
+        ..DownloadConfig::default()
+    };
+    let client = reqwest::blocking::Client::new();
+    let error = download_file_with_config(&client, server.url("/file.bin"), &path, &config, |_| {})
+        .unwrap_err();
+    assert!(
+        matches!(&error, DownloadError::Request { source, .. } if source.is_body() || source.is_decode()),
+        "{:?}",
+        error
+    );
+    assert!(
+        !path.exists(),
+        "a truncated file was left at the destination"
+    );
+}
+
+#[test]
+fn a_body_shorter_than_its_length_fails_naming_the_file() {
+    // Unlike reqwest, this backend ends the body quietly, leaving the check to the downloader.
+    let url = "http://example.com/file.bin";
+    let backend = ScriptedBackend::new();
+    let mut response = ScriptedResponse::ok(pattern(10_000));
+    response
+        .headers
+        .insert(CONTENT_LENGTH, HeaderValue::from(64 * 1024));
+    backend.push(url, response);
+    let path = scratch_dir("length_short_body").join("file.bin");
+    let config = DownloadConfig {
+        retry: RetryConfig::none(),
+        ..DownloadConfig::default()
+    };
+    let error = download_file_with_config(&backend, url, &path, &config, |_| {}).unwrap_err();
+    match error {
+        DownloadError::SizeMismatch(mismatch) => {
+            assert!(mismatch.is_truncated());
+            assert_eq!((mismatch.expected, mismatch.actual), (64 * 1024, 10_000));
+            assert!(mismatch.path.starts_with(path.parent().unwrap()));
+        }
+        error => panic!("expected a size mismatch, got {:?}", error),
+    }
+    assert!(!path.exists());
+}
@@ -0,0 +1,85 @@
+//! Overwrite policies reserving destinations without writing to them.
+
+mod common;
+
+use common::scratch_dir;
+use parallel_downloads::{
+    download_file_with_config, BodyPart, DownloadConfig, DownloadEvent, OverwritePolicy,
+    ScriptedBackend, ScriptedResponse,
+};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+const URL: &str = "http://example.test/file.bin";
+
+/// Downloads `URL` to `path` with `policy`, returning where the file was saved and whether
+/// `path` and its lock file existed while the body was being written.
+fn download(path: &std::path::Path, policy: OverwritePolicy) -> (PathBuf, bool, bool) {
+    let backend = ScriptedBackend::new();
+    backend.push(
+        URL,
+        ScriptedResponse::chunked(vec![
+            BodyPart::Data(b"first ".to_vec()),
+            BodyPart::Data(b"second".to_vec()),
+        ]),
+    );
+    let config = DownloadConfig {
+        overwrite: policy,
+        ..DownloadConfig::default()
+    };
+    let seen = Arc::new(Mutex::new(None));
+    let saved = Arc::new(Mutex::new(PathBuf::new()));
+    let (seen_in, saved_in) = (Arc::clone(&seen), Arc::clone(&saved));
+    let watched = path.to_path_buf();
+    download_file_with_config(&backend, URL, path, &config, move |event| match event {
+        DownloadEvent::Progress(_) => {
+            let mut seen = seen_in.lock().unwrap();
+            if seen.is_none() {
+                let lock = watched.with_file_name(format!(
+                    "{}.lock",
+                    watched.file_name().unwrap().to_string_lossy()
+                ));
+                *seen = Some((watched.exists(), lock.exists()));
+            }
+        }
+        DownloadEvent::Completed { path, .. } => *saved_in.lock().unwrap() = path.clone(),
+        _ => {}
+    })
+    .unwrap();
+    let (destination, lock) = seen.lock().unwrap().unwrap();
+    let saved = saved.lock().unwrap().clone();
+    (saved, destination, lock)
+}
+
+#[test]
+fn reserves_the_name_with_a_lock_instead_of_a_placeholder() {
+    let path = scratch_dir("overwrite_lock").join("file.bin");
+    let (saved, destination, lock) = download(&path, OverwritePolicy::Error);
+    assert_eq!(saved, path);
+    assert!(!destination, "the destination existed during the download");
+    assert!(lock, "no lock file reserved the destination");
+    assert_eq!(std::fs::read(&path).unwrap(), b"first second");
+    assert!(!path.with_file_name("file.bin.lock").exists());
+}
+
+#[test]
+fn renames_past_existing_files() {
+    let directory = scratch_dir("overwrite_rename");
+    let path = directory.join("file.bin");
+    std::fs::write(&path, b"old").unwrap();
+    let (saved, _, _) = download(&path, OverwritePolicy::RenameWithSuffix);
+    assert_eq!(saved, directory.join("file (1).bin"));
+    assert_eq!(std::fs::read(&path).unwrap(), b"old");
+    assert_eq!(std::fs::read(&saved).unwrap(), b"first second");
+    assert!(!directory.join("file (1).bin.lock").exists());
+    assert!(!directory.join("file.bin.lock").exists());
+}
+
+#[test]
+fn a_stale_lock_file_does_not_keep_the_name_taken() {
+    let path = scratch_dir("overwrite_stale").join("file.bin");
+    std::fs::write(path.with_file_name("file.bin.lock"), b"").unwrap();
+    let (saved, _, _) = download(&path, OverwritePolicy::Error);
+    assert_eq!(saved, path);
+    assert!(!path.with_file_name("file.bin.lock").exists());
+}
@@ -0,0 +1,175 @@
+//! Key pins checked on the responses: a pinned host gets nothing but a bare probe until the
+//! key it presents is checked.
+
+mod common;
+
+use common::{pattern, scratch_dir, MockServer, Response};
+use parallel_downloads::{
+    download_batch_requests, download_file_with_config, interceptor_fn, Auth, BatchConfig,
+    CookieJar, DownloadConfig, DownloadError, DownloadEvent, DownloadRequest, DownloadResult,
+    HttpRequest, Interceptors, KeyPins, RequestContext, ScriptedBackend, ScriptedResponse,
+};
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+const URL: &str = "https://example.com/file.bin";
+
+/// The SPKI fingerprint of `tests/fixtures/example.com.der`.
+const FIXTURE_PIN: &str = "H8EG/AO/56CVTjFzYQgT6hJkA05nNa9s5EqFd2mtW/4=";
+
+/// A pin matching no key the tests present.
+const OTHER_PIN: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+/// The DER certificate of `tests/fixtures/example.com.der`.
+fn certificate() -> Vec<u8> {
+    std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/example.com.der"))
+        .unwrap()
+}
+
+/// Settings pinning `host` to `pin`, sending a bearer token, an `X-Secret` header and an
+/// `X-Signed` header added by an interceptor.
+fn config(host: &str, pin: &str) -> DownloadConfig {
+    let mut pinned_keys = KeyPins::default();
+    pinned_keys.add(host, pin).unwrap();
+    DownloadConfig {
+        auth: Some(Auth::Bearer("token".to_string())),
+        headers: vec![("X-Secret".to_string(), "secret".to_string())],
+        interceptors: Interceptors::new().with(interceptor_fn(
+            |mut request: HttpRequest, _: &RequestContext| {
+                request
+                    .headers
+                    .insert("x-signed", HeaderValue::from_static("signature"));
+                request
+            },
+        )),
+        pinned_keys,
+        ..DownloadConfig::default()
+    }
+}
+
+/// A backend answering [`URL`] with `content`, presenting the fixture certificate.
+fn backend(content: &[u8]) -> ScriptedBackend {
+    let backend = ScriptedBackend::new();
+    let mut answer = ScriptedResponse::ok(content.to_vec());
+    answer.peer_certificate = Some(certificate());
+    backend.push(URL, answer);
+    backend
+}
+
+/// Whether `request` carries anything configured for the download.
+fn carries_secrets(request: &HttpRequest) -> bool {
+    ["authorization", "cookie", "x-secret", "x-signed"]
+        .iter()
+        .any(|name| request.headers.contains_key(*name))
+}
+
+#[test]
+fn mismatched_pins_stop_at_a_probe_without_credentials() {
+    let backend = backend(&pattern(1024));
+    let path = scratch_dir("pinning_mismatch").join("file.bin");
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&events);
+    let result = download_file_with_config(
+        &backend,
+        URL,
+        &path,
+        &config("example.com", OTHER_PIN),
+        move |event| seen.lock().unwrap().push(event.clone()),
+    );
+
+    assert!(
+        matches!(
+            &result,
+            Err(DownloadError::PinMismatch(mismatch)) if mismatch.found.as_deref() == Some(FIXTURE_PIN)
+        ),
+        "{:?}",
+        result
+    );
+    assert!(!path.exists());
+    let requests = backend.requests();
+    assert_eq!(requests.len(), 1, "{:?}", requests);
+    assert_eq!(requests[0].method, "HEAD");
+    assert!(!carries_secrets(&requests[0]), "{:?}", requests[0].headers);
+    assert!(events.lock().unwrap().iter().any(
+        |event| matches!(event, DownloadEvent::PinMismatch { host, .. } if host == "example.com")
+    ));
+}
+
+#[test]
+fn matching_pins_let_the_request_through_after_the_probe() {
+    let content = pattern(1024);
+    let backend = backend(&content);
+    let path = scratch_dir("pinning_match").join("file.bin");
+    download_file_with_config(
+        &backend,
+        URL,
+        &path,
+        &config("example.com", FIXTURE_PIN),
+        |_| {},
+    )
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), content);
+    let requests = backend.requests();
+    let methods: Vec<_> = requests
+        .iter()
+        .map(|request| request.method.as_str())
+        .collect();
+    assert_eq!(methods, ["HEAD", "GET"]);
+    assert!(!carries_secrets(&requests[0]), "{:?}", requests[0].headers);
+    assert_eq!(
+        requests[1].headers.get(AUTHORIZATION),
+        Some(&HeaderValue::from_static("Bearer token"))
+    );
+    assert!(requests[1].headers.contains_key("x-secret"));
+    assert!(requests[1].headers.contains_key("x-signed"));
+}
+
+/// Downloads `/file.bin` from `server` in a batch sending the cookie `session=1`.
+fn batch(server: &MockServer, name: &str, download: DownloadConfig) -> DownloadResult {
+    let cookies = CookieJar::new();
+    cookies.add("session", "1", "127.0.0.1", "/").unwrap();
+    let path = scratch_dir(name).join("file.bin");
+    let config = BatchConfig {
+        download,
+        cookies: Some(cookies),
+        ..BatchConfig::default()
+    };
+    let results = download_batch_requests(
+        vec![DownloadRequest::new(server.url("/file.bin"), path)],
+        config,
+        |_| {},
+    )
+    .unwrap();
+    results.into_iter().next().unwrap()
+}
+
+#[test]
+fn batches_send_nothing_to_a_pinned_host_over_plain_http() {
+    let server = MockServer::start(|_| Response::ok(pattern(1024)));
+    let result = batch(
+        &server,
+        "pinning_batch_mismatch",
+        config("127.0.0.1", OTHER_PIN),
+    );
+    let error = result.error();
+
+    // A plain `http://` connection presents no certificate at all, so nothing is sent.
+    assert!(
+        matches!(error, Some(DownloadError::PinMismatch(mismatch)) if mismatch.found.is_none()),
+        "{:?}",
+        error
+    );
+    assert!(server.requests().is_empty(), "{:?}", server.requests());
+}
+
+#[test]
+fn batches_send_their_cookies_to_unpinned_hosts() {
+    let server = MockServer::start(|_| Response::ok(pattern(1024)));
+    let result = batch(&server, "pinning_batch_unpinned", DownloadConfig::default());
+
+    assert!(result.is_success(), "{:?}", result.outcome);
+    let requests = server.requests();
+    assert_eq!(requests[0].header("cookie"), Some("session=1"));
+}
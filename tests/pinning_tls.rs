@@ -0,0 +1,172 @@
+//! Key pins checked during the TLS handshake by the rustls client a batch builds.
+
+mod common;
+
+use common::{pattern, scratch_dir};
+use parallel_downloads::{
+    download_batch_requests, BatchConfig, DownloadConfig, DownloadError, DownloadEvent,
+    DownloadRequest, DownloadResult, KeyPins, TlsConfig,
+};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// The SPKI fingerprint of `tests/fixtures/localhost.der`.
+const SERVER_PIN: &str = "uoF8laOoaeNZ+/IUzB0226KHI7pjydYYnODF8+utDFs=";
+
+/// The SPKI fingerprint of `tests/fixtures/test-ca.der`, which issued the server's certificate.
+const CA_PIN: &str = "rgYo024Ox6oMwKQaEiQSRPlN8ZxcHKsiy8FR82mVddU=";
+
+/// A pin matching no key the server presents.
+const OTHER_PIN: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+/// Reads a file of `tests/fixtures`.
+fn fixture(name: &str) -> Vec<u8> {
+    std::fs::read(
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name),
+    )
+    .unwrap()
+}
+
+/// An HTTPS server on `127.0.0.1` presenting the chain of `localhost.der` and `test-ca.der`,
+/// answering every request with `content`.
+struct TlsServer {
+    port: u16,
+    /// The request lines that arrived over a completed handshake.
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl TlsServer {
+    fn serving(content: Vec<u8>) -> Self {
+        let chain = vec![
+            CertificateDer::from(fixture("localhost.der")),
+            CertificateDer::from(fixture("test-ca.der")),
+        ];
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(fixture("localhost.key.der")));
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .unwrap();
+        let config = Arc::new(config);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                let connection = ServerConnection::new(Arc::clone(&config)).unwrap();
+                let mut stream = BufReader::new(StreamOwned::new(connection, stream));
+                let mut line = String::new();
+                // A handshake the client aborts fails the first read.
+                if stream.read_line(&mut line).is_err() || line.is_empty() {
+                    continue;
+                }
+                recorded.lock().unwrap().push(line.trim_end().to_string());
+                let mut header = String::new();
+                while stream.read_line(&mut header).is_ok_and(|n| n > 2) {
+                    header.clear();
+                }
+                let stream = stream.get_mut();
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    content.len()
+                );
+                let _ = stream.write_all(head.as_bytes());
+                let _ = stream.write_all(&content);
+                stream.conn.send_close_notify();
+                let _ = stream.flush();
+            }
+        });
+        TlsServer { port, requests }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("https://127.0.0.1:{}{}", self.port, path)
+    }
+
+    fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Downloads `/file.bin` from `server` in a batch pinning `127.0.0.1` to `pin`, accepting the
+/// untrusted test CA, and returns its result with the events it reported.
+fn batch(server: &TlsServer, name: &str, pin: &str) -> (DownloadResult, Vec<DownloadEvent>) {
+    let mut pinned_keys = KeyPins::default();
+    pinned_keys.add("127.0.0.1", pin).unwrap();
+    let config = BatchConfig {
+        download: DownloadConfig {
+            pinned_keys,
+            ..DownloadConfig::default()
+        },
+        tls: TlsConfig {
+            danger_accept_invalid_certs: true,
+            ..TlsConfig::default()
+        },
+        ..BatchConfig::default()
+    };
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&events);
+    let path = scratch_dir(name).join("file.bin");
+    let results = download_batch_requests(
+        vec![DownloadRequest::new(server.url("/file.bin"), path)],
+        config,
+        move |event| seen.lock().unwrap().push(event.clone()),
+    )
+    .unwrap();
+    let events = events.lock().unwrap().clone();
+    (results.into_iter().next().unwrap(), events)
+}
+
+#[test]
+fn mismatched_pins_abort_the_handshake_before_any_request() {
+    let server = TlsServer::serving(pattern(1024));
+    let (result, events) = batch(&server, "pinning_tls_mismatch", OTHER_PIN);
+
+    let error = result.error();
+    assert!(
+        matches!(
+            error,
+            Some(DownloadError::PinMismatch(mismatch))
+                if mismatch.found.as_deref() == Some(SERVER_PIN)
+                    && mismatch.url == server.url("/file.bin")
+        ),
+        "{:?}",
+        error
+    );
+    assert_eq!(server.requests(), Vec::<String>::new());
+    assert!(events.iter().any(
+        |event| matches!(event, DownloadEvent::PinMismatch { host, .. } if host == "127.0.0.1")
+    ));
+}
+
+#[test]
+fn a_pinned_intermediate_key_lets_the_request_through_without_a_probe() {
+    let server = TlsServer::serving(pattern(1024));
+    let (result, _) = batch(&server, "pinning_tls_ca", CA_PIN);
+
+    assert!(result.is_success(), "{:?}", result.outcome);
+    assert_eq!(server.requests(), ["GET /file.bin HTTP/1.1"]);
+}
+
+#[test]
+fn a_pinned_server_key_lets_the_request_through_without_a_probe() {
+    let content = pattern(1024);
+    let server = TlsServer::serving(content.clone());
+    let path = scratch_dir("pinning_tls_server").join("file.bin");
+    let (result, _) = batch(&server, "pinning_tls_server", SERVER_PIN);
+
+    assert!(result.is_success(), "{:?}", result.outcome);
+    assert_eq!(server.requests(), ["GET /file.bin HTTP/1.1"]);
+    assert_eq!(std::fs::read(path).unwrap(), content);
+}
@@ -0,0 +1,108 @@
+//! Pre-signed URLs refused because their signature expired, and the refresher minting fresh
+//! ones.
+
+mod common;
+
+use common::{scratch_dir, MockServer, Response};
+use parallel_downloads::{
+    download_batch_requests, BatchConfig, DownloadError, DownloadEvent, DownloadRequest,
+    DownloadResult, UrlRefresher,
+};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// The error document S3 answers an expired signature with.
+const EXPIRED_BODY: &str = "<Error><Code>AccessDenied</Code>\
+    <Message>Request has expired</Message></Error>";
+
+/// Downloads `url` in a batch with `config`, returning its result and the events it reported.
+fn download(url: String, name: &str, config: BatchConfig) -> (DownloadResult, Vec<DownloadEvent>) {
+    let path = scratch_dir(name).join("file.bin");
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&events);
+    let results = download_batch_requests(
+        vec![DownloadRequest::new(url, path)],
+        config,
+        move |event| seen.lock().unwrap().push(event.clone()),
+    )
+    .unwrap();
+    let events = events.lock().unwrap().clone();
+    (results.into_iter().next().unwrap(), events)
+}
+
+#[test]
+fn an_expired_signature_is_reported_as_such_and_not_retried() {
+    let server = MockServer::start(|_| Response::status(403).body(EXPIRED_BODY));
+    let url = server.url("/file.bin?X-Amz-Signature=abc");
+    let (result, _) = download(url.clone(), "presigned_body", BatchConfig::default());
+
+    assert!(
+        matches!(result.error(), Some(DownloadError::UrlExpired(expired)) if expired.url == url),
+        "{:?}",
+        result.outcome
+    );
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn the_expiry_in_the_query_identifies_an_expired_url_without_a_body() {
+    let server = MockServer::start(|_| Response::status(403));
+    let url = server.url("/file.bin?X-Amz-Date=20200101T000000Z&X-Amz-Expires=60");
+    let (result, _) = download(url, "presigned_query", BatchConfig::default());
+
+    // 2020-01-01 00:01:00 UTC.
+    let expired_at = UNIX_EPOCH + Duration::from_secs(1_577_836_860);
+    assert!(
+        matches!(
+            result.error(),
+            Some(DownloadError::UrlExpired(expired)) if expired.expired_at == Some(expired_at)
+        ),
+        "{:?}",
+        result.outcome
+    );
+}
+
+#[test]
+fn refusals_of_other_urls_stay_status_errors() {
+    let server = MockServer::start(|_| Response::status(403).body(EXPIRED_BODY));
+    let (result, _) = download(
+        server.url("/file.bin"),
+        "presigned_unsigned",
+        BatchConfig::default(),
+    );
+
+    assert!(
+        matches!(
+            result.error(),
+            Some(DownloadError::Status { status, .. }) if status.as_u16() == 403
+        ),
+        "{:?}",
+        result.outcome
+    );
+}
+
+#[test]
+fn a_refresher_retries_with_the_fresh_url_it_mints() {
+    let server = MockServer::start(|request| {
+        if request.path.contains("Signature=old") {
+            Response::status(403).body(EXPIRED_BODY)
+        } else {
+            Response::ok("content")
+        }
+    });
+    let expired = server.url("/file.bin?X-Amz-Signature=old");
+    let fresh = server.url("/file.bin?X-Amz-Signature=new");
+    let minted = fresh.clone();
+    let config = BatchConfig {
+        refresh_url: Some(UrlRefresher::new(move |_| Some(minted.clone()))),
+        ..BatchConfig::default()
+    };
+    let (result, events) = download(expired.clone(), "presigned_refresh", config);
+
+    assert!(result.is_success(), "{:?}", result.outcome);
+    assert_eq!(std::fs::read(result.path().unwrap()).unwrap(), b"content");
+    assert!(events.iter().any(|event| matches!(
+        event,
+        DownloadEvent::UrlRefreshed { from, to } if *from == expired && *to == fresh
+    )));
+}
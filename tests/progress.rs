@@ -0,0 +1,109 @@
+//! Progress reported while a body streams to disk, and what a progress snapshot tells.
+
+mod common;
+
+use common::{pattern, scratch_dir, MockServer, Response};
+use parallel_downloads::{
+    download_file_with_config, DownloadCallbackProgress, DownloadConfig, DownloadEvent,
+    ProgressThrottle,
+};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Downloads `url` to `name` in a scratch directory with every chunk reported, returning the
+/// progress events it sent.
+fn progress_of(url: &str, name: &str) -> Vec<DownloadCallbackProgress> {
+    let config = DownloadConfig {
+        progress_throttle: ProgressThrottle::NONE,
+        ..DownloadConfig::default()
+    };
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&events);
+    let path = scratch_dir(name).join("file.bin");
+    let client = reqwest::blocking::Client::new();
+    download_file_with_config(&client, url, &path, &config, move |event| {
+        if let DownloadEvent::Progress(progress) = event {
+            seen.lock().unwrap().push(progress.clone());
+        }
+    })
+    .unwrap();
+    let events = events.lock().unwrap().clone();
+    events
+}
+
+#[test]
+fn large_bodies_report_progress_per_chunk() {
+    let content = pattern(4 * 1024 * 1024);
+    let length = content.len() as u64;
+    // Paced writes make the body arrive in many reads rather than one.
+    let server = MockServer::start(move |_| {
+        Response::ok(content.clone()).paced(256 * 1024, Duration::from_millis(5))
+    });
+    let events = progress_of(&server.url("/large.bin"), "progress_large");
+
+    assert!(events.len() >= 16, "only {} progress events", events.len());
+    assert!(events
+        .windows(2)
+        .all(|pair| pair[0].bytes_downloaded() <= pair[1].bytes_downloaded()));
+    assert!(events
+        .iter()
+        .all(|event| event.total_bytes() == Some(length)));
+    let last = events.last().unwrap();
+    assert_eq!(last.bytes_downloaded(), length);
+    assert!(last.is_complete());
+}
+
+#[test]
+fn empty_bodies_report_progress_once() {
+    let server = MockServer::start(|_| Response::ok(Vec::new()));
+    let events = progress_of(&server.url("/empty.bin"), "progress_empty");
+    assert!(!events.is_empty());
+    assert_eq!(events.last().unwrap().bytes_downloaded(), 0);
+    assert_eq!(events.last().unwrap().total_bytes(), Some(0));
+}
+
+#[test]
+fn an_unknown_total_tells_nothing() {
+    let progress = DownloadCallbackProgress::new(1024, None);
+    assert_eq!(progress.fraction(), None);
+    assert_eq!(progress.percent(), None);
+    assert_eq!(progress.remaining_bytes(), None);
+    assert!(!progress.is_complete());
+    assert!(!progress.is_total_known());
+}
+
+#[test]
+fn a_partly_downloaded_file() {
+    let progress = DownloadCallbackProgress::new(250, Some(1000));
+    assert_eq!(progress.fraction(), Some(0.25));
+    assert_eq!(progress.percent(), Some(25.0));
+    assert_eq!(progress.remaining_bytes(), Some(750));
+    assert!(!progress.is_complete());
+}
+
+#[test]
+fn an_empty_file_is_complete() {
+    let progress = DownloadCallbackProgress::new(0, Some(0));
+    assert_eq!(progress.fraction(), Some(1.0));
+    assert_eq!(progress.percent(), Some(100.0));
+    assert_eq!(progress.remaining_bytes(), Some(0));
+    assert!(progress.is_complete());
+}
+
+#[test]
+fn more_bytes_than_announced_stop_at_complete() {
+    let progress = DownloadCallbackProgress::new(1500, Some(1000));
+    assert_eq!(progress.fraction(), Some(1.0));
+    assert_eq!(progress.percent(), Some(100.0));
+    assert_eq!(progress.remaining_bytes(), Some(0));
+    assert!(progress.is_complete());
+}
+
+#[test]
+fn nothing_downloaded_yet() {
+    let progress = DownloadCallbackProgress::new(0, Some(1000));
+    assert_eq!(progress.fraction(), Some(0.0));
+    assert_eq!(progress.percent(), Some(0.0));
+    assert_eq!(progress.remaining_bytes(), Some(1000));
+    assert!(!progress.is_complete());
+}
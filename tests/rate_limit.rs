@@ -0,0 +1,53 @@
+//! The bandwidth cap shared by every download of a batch.
+
+mod common;
+
+use common::{pattern, scratch_dir, MockServer};
+use parallel_downloads::{download_batch_requests, BatchConfig, DownloadConfig, DownloadRequest};
+use std::time::{Duration, Instant};
+
+#[test]
+fn concurrent_downloads_share_the_batch_rate() {
+    const FILES: usize = 4;
+    const FILE_SIZE: usize = 256 * 1024;
+    const RATE: u64 = 512 * 1024;
+
+    let server = MockServer::serving(pattern(FILE_SIZE));
+    let directory = scratch_dir("rate_limit_batch");
+    let requests = (0..FILES)
+        .map(|index| {
+            let name = format!("file-{}", index);
+            DownloadRequest::new(server.url(&format!("/{}", name)), directory.join(name))
+        })
+        .collect();
+    let config = BatchConfig {
+        concurrency: FILES,
+        max_bytes_per_sec: Some(RATE),
+        download: DownloadConfig {
+            // Small reads keep the debt the last reads leave unpaid out of the measurement.
+            chunk_size: Some(8 * 1024),
+            ..DownloadConfig::default()
+        },
+        ..BatchConfig::default()
+    };
+
+    let started = Instant::now();
+    let results = download_batch_requests(requests, config, |_| {}).unwrap();
+    let elapsed = started.elapsed();
+    assert!(results.iter().all(|result| result.is_success()));
+
+    // Every file on its own rate would take a quarter of this.
+    let expected = Duration::from_secs_f64((FILES * FILE_SIZE) as f64 / RATE as f64);
+    assert!(
+        elapsed >= expected.mul_f64(0.85),
+        "{:?} is faster than the cap allows ({:?})",
+        elapsed,
+        expected
+    );
+    assert!(
+        elapsed <= expected.mul_f64(1.75),
+        "{:?} is much slower than the cap ({:?})",
+        elapsed,
+        expected
+    );
+}
@@ -0,0 +1,104 @@
+//! How a partial file is resumed depending on how the server answers its `Range` request.
+
+mod common;
+
+use common::{pattern, scratch_dir, MockServer, Response};
+use parallel_downloads::{
+    download_file_with_config, DownloadConfig, DownloadError, DownloadEvent, RetryConfig,
+};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Leaves `partial` as the partial file of `name` in a scratch directory of its own and
+/// returns the destination.
+fn with_partial(name: &str, partial: &[u8]) -> PathBuf {
+    let path = scratch_dir(name).join("file.bin");
+    std::fs::write(path.with_file_name("file.bin.part"), partial).unwrap();
+    path
+}
+
+/// Resumes `url` into `path`, returning how the download ended and the bytes every
+/// [`DownloadEvent::ResumeRestarted`] discarded.
+fn resume(url: &str, path: &Path) -> (Result<(), DownloadError>, Vec<u64>) {
+    let config = DownloadConfig {
+        resume: true,
+        retry: RetryConfig::none(),
+        ..DownloadConfig::default()
+    };
+    let restarts = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&restarts);
+    let client = reqwest::blocking::Client::new();
+    let result = download_file_with_config(&client, url, path, &config, move |event| {
+        if let DownloadEvent::ResumeRestarted {
+            discarded_bytes, ..
+        } = event
+        {
+            seen.lock().unwrap().push(*discarded_bytes);
+        }
+    });
+    let restarts = restarts.lock().unwrap().clone();
+    (result, restarts)
+}
+
+/// The `Range` header of the `GET` requests `server` received.
+fn ranges(server: &MockServer) -> Vec<Option<String>> {
+    server
+        .requests()
+        .into_iter()
+        .filter(|request| request.method == "GET")
+        .map(|request| request.header("range").map(str::to_string))
+        .collect()
+}
+
+#[test]
+fn partial_content_is_appended() {
+    let content = pattern(64 * 1024);
+    let server = MockServer::serving(content.clone());
+    let path = with_partial("resume_206", &content[..10_000]);
+    let (result, restarts) = resume(&server.url("/file.bin"), &path);
+    result.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), content);
+    assert!(restarts.is_empty());
+    assert_eq!(ranges(&server), vec![Some("bytes=10000-".to_string())]);
+}
+
+#[test]
+fn a_whole_file_answer_starts_over() {
+    let content = pattern(64 * 1024);
+    let served = content.clone();
+    // The server ignores `Range` and sends the whole file with `200 OK`.
+    let server = MockServer::start(move |_| Response::ok(served.clone()));
+    let path = with_partial("resume_200", &[0xff; 10_000]);
+    let (result, restarts) = resume(&server.url("/file.bin"), &path);
+    result.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), content);
+    assert_eq!(restarts, vec![10_000]);
+}
+
+#[test]
+fn an_unsatisfiable_range_over_a_complete_file_succeeds() {
+    let content = pattern(64 * 1024);
+    let server = MockServer::serving(content.clone());
+    let path = with_partial("resume_416_complete", &content);
+    let (result, restarts) = resume(&server.url("/file.bin"), &path);
+    result.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), content);
+    assert!(restarts.is_empty());
+    assert_eq!(ranges(&server), vec![Some("bytes=65536-".to_string())]);
+}
+
+#[test]
+fn an_unsatisfiable_range_over_a_longer_file_discards_it() {
+    let content = pattern(64 * 1024);
+    let server = MockServer::serving(content.clone());
+    let path = with_partial("resume_416_longer", &pattern(80 * 1024));
+    let (result, _) = resume(&server.url("/file.bin"), &path);
+    assert!(result.is_err());
+    assert!(!path.with_file_name("file.bin.part").exists());
+    assert!(!path.exists());
+
+    // Nothing is left to resume, so the next run downloads the whole file.
+    let (result, _) = resume(&server.url("/file.bin"), &path);
+    result.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), content);
+}
@@ -0,0 +1,161 @@
+//! The downloader's own logic, driven by scripted responses instead of a server.
+
+mod common;
+
+use common::{pattern, scratch_dir};
+use parallel_downloads::{
+    download_file_with_config, Answer, BodyPart, DownloadConfig, DownloadError, DownloadEvent,
+    ProgressThrottle, RetryConfig, ScriptedBackend, ScriptedResponse,
+};
+use reqwest::StatusCode;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const URL: &str = "http://example.com/file.bin";
+
+/// Settings reporting every chunk and retrying `attempts` times without waiting long.
+fn config(attempts: u32) -> DownloadConfig {
+    DownloadConfig {
+        progress_throttle: ProgressThrottle::NONE,
+        retry: RetryConfig {
+            max_attempts: attempts,
+            base_delay: Duration::from_millis(1),
+            ..RetryConfig::default()
+        },
+        ..DownloadConfig::default()
+    }
+}
+
+/// Downloads [`URL`] from `backend` into a scratch directory of its own, returning how it
+/// ended, where it saved the file, and every event it sent.
+fn download(
+    backend: &ScriptedBackend,
+    name: &str,
+    config: &DownloadConfig,
+) -> (Result<(), DownloadError>, PathBuf, Vec<DownloadEvent>) {
+    let path = scratch_dir(name).join("file.bin");
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&events);
+    let result = download_file_with_config(backend, URL, &path, config, move |event| {
+        seen.lock().unwrap().push(event.clone());
+    });
+    let events = events.lock().unwrap().clone();
+    (result, path, events)
+}
+
+/// The bytes downloaded of every progress event.
+fn progress(events: &[DownloadEvent]) -> Vec<(u64, Option<u64>)> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            DownloadEvent::Progress(progress) => {
+                Some((progress.bytes_downloaded(), progress.total_bytes()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn progress_follows_every_part_of_the_body() {
+    let backend = ScriptedBackend::new();
+    backend.push(
+        URL,
+        ScriptedResponse::chunked(vec![
+            BodyPart::Data(vec![1; 100]),
+            BodyPart::Data(vec![2; 300]),
+            BodyPart::Data(vec![3; 600]),
+        ]),
+    );
+    let (result, path, events) = download(&backend, "scripted_progress", &config(1));
+    result.unwrap();
+    assert_eq!(
+        progress(&events),
+        vec![(100, Some(1000)), (400, Some(1000)), (1000, Some(1000))]
+    );
+    assert_eq!(std::fs::metadata(path).unwrap().len(), 1000);
+}
+
+#[test]
+fn connection_errors_are_retried() {
+    let content = pattern(4096);
+    let backend = ScriptedBackend::new();
+    backend.push(URL, Answer::Error(io::ErrorKind::ConnectionReset));
+    backend.push(
+        URL,
+        ScriptedResponse::status(StatusCode::SERVICE_UNAVAILABLE),
+    );
+    backend.push(URL, ScriptedResponse::ok(content.clone()));
+    let (result, path, events) = download(&backend, "scripted_retries", &config(3));
+    result.unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), content);
+    let retries = events
+        .iter()
+        .filter(|event| matches!(event, DownloadEvent::Retrying { .. }))
+        .count();
+    assert_eq!(retries, 2);
+    assert_eq!(backend.requests().len(), 3);
+}
+
+#[test]
+fn a_body_failing_midway_is_downloaded_again() {
+    let content = pattern(4096);
+    let backend = ScriptedBackend::new();
+    backend.push(
+        URL,
+        ScriptedResponse::chunked(vec![
+            BodyPart::Data(content[..1000].to_vec()),
+            BodyPart::Error(io::ErrorKind::ConnectionReset),
+            BodyPart::Data(content[1000..].to_vec()),
+        ]),
+    );
+    backend.push(URL, ScriptedResponse::ok(content.clone()));
+    let (result, path, _) = download(&backend, "scripted_mid_body", &config(2));
+    result.unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), content);
+}
+
+#[test]
+fn client_errors_are_not_retried() {
+    let backend = ScriptedBackend::new();
+    backend.push(URL, ScriptedResponse::status(StatusCode::NOT_FOUND));
+    let (result, path, _) = download(&backend, "scripted_not_found", &config(3));
+    let error = result.unwrap_err();
+    assert!(!error.is_retriable(), "{:?}", error);
+    assert_eq!(backend.requests().len(), 1);
+    assert!(!path.exists());
+}
+
+#[test]
+fn retries_give_up_after_the_last_attempt() {
+    let backend = ScriptedBackend::new();
+    backend.push(URL, Answer::Error(io::ErrorKind::ConnectionRefused));
+    let (result, _, _) = download(&backend, "scripted_exhausted", &config(3));
+    assert!(matches!(
+        result.unwrap_err(),
+        DownloadError::RetriesExhausted(exhausted) if exhausted.attempts == 3
+    ));
+    assert_eq!(backend.requests().len(), 3);
+}
+
+#[test]
+fn a_stalled_body_times_out() {
+    let backend = ScriptedBackend::new();
+    backend.push(
+        URL,
+        ScriptedResponse::chunked(vec![
+            BodyPart::Data(vec![0; 100]),
+            BodyPart::Delay(Duration::from_millis(300)),
+            BodyPart::Data(vec![0; 100]),
+        ]),
+    );
+    let mut config = config(1);
+    config.timeouts.total = Some(Duration::from_millis(100));
+    let started = Instant::now();
+    let (result, path, _) = download(&backend, "scripted_stall", &config);
+    assert!(result.is_err());
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert!(!path.exists());
+}
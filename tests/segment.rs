@@ -0,0 +1,76 @@
+//! Files downloaded over several ranged connections at once.
+
+mod common;
+
+use common::{pattern, scratch_dir, MockServer};
+use parallel_downloads::{
+    download_file_with_config, DownloadConfig, DownloadError, DownloadEvent, ProgressThrottle,
+    RetryConfig, SegmentConfig,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Settings splitting a file into four segments of at least 16 KiB, reading 4 KiB at a time.
+fn segmented() -> DownloadConfig {
+    DownloadConfig {
+        segments: SegmentConfig {
+            count: 4,
+            min_segment_size: 16 * 1024,
+        },
+        chunk_size: Some(4096),
+        progress_throttle: ProgressThrottle::NONE,
+        retry: RetryConfig::none(),
+        ..DownloadConfig::default()
+    }
+}
+
+#[test]
+fn a_panicking_segment_fails_the_download_instead_of_the_caller() {
+    let server = MockServer::serving(pattern(256 * 1024));
+    let path = scratch_dir("segment_panic").join("file.bin");
+    let panicked = AtomicBool::new(false);
+    let client = reqwest::blocking::Client::new();
+    let result = download_file_with_config(
+        &client,
+        server.url("/file.bin"),
+        &path,
+        &segmented(),
+        move |event| {
+            if matches!(event, DownloadEvent::Progress(_)) && !panicked.swap(true, Ordering::SeqCst)
+            {
+                panic!("callback failed");
+            }
+        },
+    );
+
+    match result {
+        Err(DownloadError::SegmentPanicked(error)) => {
+            assert_eq!(error.message.as_deref(), Some("callback failed"));
+        }
+        result => panic!("expected a panicked segment, got {:?}", result),
+    }
+    assert!(!path.exists());
+    assert!(!path.with_file_name("file.bin.part").exists());
+}
+
+#[test]
+fn segments_share_the_rate_limit_of_their_download() {
+    let content = pattern(128 * 1024);
+    let server = MockServer::serving(content.clone());
+    let path = scratch_dir("segment_rate_limit").join("file.bin");
+    let config = DownloadConfig {
+        max_bytes_per_sec: Some(256 * 1024),
+        ..segmented()
+    };
+    let client = reqwest::blocking::Client::new();
+    let started = Instant::now();
+    download_file_with_config(&client, server.url("/file.bin"), &path, &config, |_| {}).unwrap();
+
+    // Each segment reads its first chunk before the limit holds, leaving 112 KiB at 256 KiB/s.
+    assert!(
+        started.elapsed() >= Duration::from_millis(350),
+        "took only {:?}",
+        started.elapsed()
+    );
+    assert_eq!(std::fs::read(&path).unwrap(), content);
+}
@@ -0,0 +1,77 @@
+//! The SHA-256 reported for downloads whose bytes don't all stream past in order.
+
+mod common;
+
+use common::{pattern, scratch_dir, MockServer};
+use parallel_downloads::{download_file_with_config, DownloadConfig, DownloadEvent, SegmentConfig};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+fn hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Downloads `url` to `path` with `config`, returning the SHA-256 it reported.
+fn reported_sha256(url: &str, path: &Path, config: &DownloadConfig) -> Option<String> {
+    let reported = Arc::new(Mutex::new(None));
+    let seen = Arc::clone(&reported);
+    let client = reqwest::blocking::Client::new();
+    download_file_with_config(&client, url, path, config, move |event| {
+        if let DownloadEvent::Completed { sha256, .. } = event {
+            *seen.lock().unwrap() = sha256.clone();
+        }
+    })
+    .unwrap();
+    let sha256 = reported.lock().unwrap().clone();
+    sha256
+}
+
+#[test]
+fn segmented_downloads_hash_the_assembled_file() {
+    let content = pattern(256 * 1024);
+    let server = MockServer::serving(content.clone());
+    let config = DownloadConfig {
+        compute_sha256: true,
+        segments: SegmentConfig {
+            count: 4,
+            min_segment_size: 16 * 1024,
+        },
+        ..DownloadConfig::default()
+    };
+    let path = scratch_dir("sha256_segments").join("file.bin");
+    let sha256 = reported_sha256(&server.url("/file.bin"), &path, &config);
+    assert_eq!(std::fs::read(&path).unwrap(), content);
+    assert_eq!(sha256, Some(hex(&content)));
+    let ranged = server
+        .requests()
+        .iter()
+        .filter(|request| request.header("range").is_some())
+        .count();
+    assert_eq!(ranged, 4, "the file wasn't downloaded in segments");
+}
+
+#[test]
+fn resumed_downloads_hash_the_whole_file() {
+    let content = pattern(64 * 1024);
+    let server = MockServer::serving(content.clone());
+    let config = DownloadConfig {
+        compute_sha256: true,
+        resume: true,
+        ..DownloadConfig::default()
+    };
+    let path = scratch_dir("sha256_resumed").join("file.bin");
+    std::fs::write(path.with_file_name("file.bin.part"), &content[..10_000]).unwrap();
+    let sha256 = reported_sha256(&server.url("/file.bin"), &path, &config);
+    assert_eq!(std::fs::read(&path).unwrap(), content);
+    assert_eq!(sha256, Some(hex(&content)));
+    let get = server
+        .requests()
+        .into_iter()
+        .find(|request| request.method == "GET")
+        .unwrap();
+    assert_eq!(get.header("range"), Some("bytes=10000-"));
+}
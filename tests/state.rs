@@ -0,0 +1,69 @@
+//! What a batch state file keeps of its requests.
+
+mod common;
+
+use common::{pattern, scratch_dir, MockServer, Response};
+use parallel_downloads::{
+    download_batch_requests, resume_batch, BatchConfig, DownloadConfig, DownloadRequest,
+    RetryConfig,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[test]
+fn credentials_are_left_out_of_the_state_file_and_supplied_again_on_resume() {
+    let content = pattern(8 * 1024);
+    let served = content.clone();
+    let calls = AtomicUsize::new(0);
+    // The first run fails, so resuming downloads the file again.
+    let server = MockServer::start(move |_| match calls.fetch_add(1, Ordering::SeqCst) {
+        0 => Response::status(500),
+        _ => Response::ok(served.clone()),
+    });
+    let directory = scratch_dir("state_credentials");
+    let state_file = directory.join("batch.json");
+    let mut request = DownloadRequest::new(server.url("/file.bin"), directory.join("file.bin"));
+    request.headers = vec![
+        (
+            "Authorization".to_string(),
+            "Bearer secret-token".to_string(),
+        ),
+        ("X-Api-Key".to_string(), "secret-key".to_string()),
+        ("Accept".to_string(), "application/octet-stream".to_string()),
+    ];
+    let download = DownloadConfig {
+        retry: RetryConfig::none(),
+        ..DownloadConfig::default()
+    };
+    let config = BatchConfig {
+        download: download.clone(),
+        state_file: Some(state_file.clone()),
+        ..BatchConfig::default()
+    };
+    let _ = download_batch_requests(vec![request], config, |_| {});
+    assert_eq!(
+        server.requests()[0].header("authorization"),
+        Some("Bearer secret-token")
+    );
+
+    let saved = std::fs::read_to_string(&state_file).unwrap();
+    assert!(
+        !saved.contains("secret"),
+        "credentials were saved: {}",
+        saved
+    );
+    assert!(saved.contains("application/octet-stream"));
+
+    let config = BatchConfig {
+        download: DownloadConfig {
+            headers: vec![("Authorization".to_string(), "Bearer again".to_string())],
+            ..download
+        },
+        ..BatchConfig::default()
+    };
+    resume_batch(&state_file, config, |_| {}).unwrap();
+    assert_eq!(std::fs::read(directory.join("file.bin")).unwrap(), content);
+    let resumed = server.requests().last().cloned().unwrap();
+    assert_eq!(resumed.header("authorization"), Some("Bearer again"));
+    assert_eq!(resumed.header("accept"), Some("application/octet-stream"));
+    assert_eq!(resumed.header("x-api-key"), None);
+}
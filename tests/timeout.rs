@@ -0,0 +1,75 @@
+//! The overall time limit of an attempt, which only counts the time spent transferring.
+
+mod common;
+
+use common::scratch_dir;
+use parallel_downloads::{
+    download_file_with_config, BodyPart, DownloadConfig, DownloadError, RetryConfig,
+    ScriptedBackend, ScriptedResponse, TimeoutConfig, TimeoutPhase,
+};
+use std::time::Duration;
+
+const URL: &str = "http://example.test/file.bin";
+
+/// A config allowing one attempt of at most `total` transferring.
+fn limited(total: Duration) -> DownloadConfig {
+    DownloadConfig {
+        timeouts: TimeoutConfig {
+            total: Some(total),
+            ..TimeoutConfig::default()
+        },
+        retry: RetryConfig {
+            max_attempts: 1,
+            ..RetryConfig::default()
+        },
+        ..DownloadConfig::default()
+    }
+}
+
+#[test]
+fn the_total_limit_is_finite_by_default() {
+    assert_eq!(
+        TimeoutConfig::default().total,
+        Some(Duration::from_secs(60 * 60))
+    );
+}
+
+#[test]
+fn waiting_for_the_rate_limit_does_not_count() {
+    let backend = ScriptedBackend::new();
+    backend.push(
+        URL,
+        ScriptedResponse::chunked(vec![BodyPart::Data(vec![7; 1000]); 4]),
+    );
+    let config = DownloadConfig {
+        // Four chunks at 4000 bytes a second wait about 750 ms in all.
+        max_bytes_per_sec: Some(4000),
+        chunk_size: Some(1000),
+        ..limited(Duration::from_millis(300))
+    };
+    let path = scratch_dir("timeout_rate_limit").join("file.bin");
+    download_file_with_config(&backend, URL, &path, &config, |_| ()).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), vec![7; 4000]);
+}
+
+#[test]
+fn a_slow_server_still_times_out() {
+    let backend = ScriptedBackend::new();
+    backend.push(
+        URL,
+        ScriptedResponse::chunked(vec![
+            BodyPart::Data(vec![1; 10]),
+            BodyPart::Delay(Duration::from_millis(400)),
+            BodyPart::Data(vec![2; 10]),
+            BodyPart::Data(vec![3; 10]),
+        ]),
+    );
+    let config = limited(Duration::from_millis(300));
+    let path = scratch_dir("timeout_slow_server").join("file.bin");
+    let error = download_file_with_config(&backend, URL, &path, &config, |_| ()).unwrap_err();
+    match error {
+        DownloadError::Timeout(timeout) => assert_eq!(timeout.phase, TimeoutPhase::Total),
+        error => panic!("expected a total timeout, got {:?}", error),
+    }
+    assert!(!path.exists());
+}
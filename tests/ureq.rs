@@ -0,0 +1,78 @@
+//! The batch settings a `UreqBackend` maps onto its ureq agent.
+
+mod common;
+
+use common::{pattern, scratch_dir, MockServer};
+use parallel_downloads::{
+    download_file_with_config, BatchConfig, DnsConfig, DownloadConfig, DownloadError, IpFamily,
+    ProxyConfig, RetryConfig, UreqBackend,
+};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// Downloads `url` into the scratch directory `name` through a backend built from `config`.
+fn download(url: &str, name: &str, config: &BatchConfig) -> Result<Vec<u8>, DownloadError> {
+    let path = scratch_dir(name).join("file.bin");
+    let download = DownloadConfig {
+        retry: RetryConfig::none(),
+        ..DownloadConfig::default()
+    };
+    download_file_with_config(&UreqBackend::new(config), url, &path, &download, |_| {})?;
+    Ok(std::fs::read(&path).unwrap())
+}
+
+#[test]
+fn overridden_hosts_connect_to_their_address_and_keep_their_name() {
+    let content = pattern(16 * 1024);
+    let server = MockServer::serving(content.clone());
+    let port = server.addr().port();
+    let config = BatchConfig {
+        dns: DnsConfig {
+            overrides: HashMap::from([(
+                "Files.Test".to_string(),
+                vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            )]),
+            ..DnsConfig::default()
+        },
+        ..BatchConfig::default()
+    };
+    let url = format!("http://files.test:{}/file.bin", port);
+
+    assert_eq!(download(&url, "ureq_dns", &config).unwrap(), content);
+    let host = format!("files.test:{}", port);
+    assert_eq!(server.requests()[0].header("host"), Some(host.as_str()));
+}
+
+#[test]
+fn hosts_without_an_address_of_the_family_fail_to_connect() {
+    let server = MockServer::serving(pattern(1024));
+    let config = BatchConfig {
+        dns: DnsConfig {
+            family: IpFamily::Ipv6Only,
+            ..DnsConfig::default()
+        },
+        ..BatchConfig::default()
+    };
+    let result = download(&server.url("/file.bin"), "ureq_family", &config);
+
+    assert!(result.is_err(), "{:?}", result);
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn a_proxy_for_both_schemes_receives_every_request() {
+    let content = pattern(1024);
+    let proxy = MockServer::serving(content.clone());
+    let config = BatchConfig {
+        proxy: ProxyConfig {
+            http: Some(proxy.url("")),
+            https: Some(proxy.url("")),
+            ..ProxyConfig::default()
+        },
+        ..BatchConfig::default()
+    };
+    let url = "http://origin.invalid/file.bin";
+
+    assert_eq!(download(url, "ureq_proxy", &config).unwrap(), content);
+    assert_eq!(proxy.requests()[0].path, url);
+}
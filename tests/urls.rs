@@ -0,0 +1,164 @@
+//! URLs checked and normalized before a batch starts its workers, and the `file://` and
+//! `data:` URLs answered without the HTTP client.
+
+mod common;
+
+use common::{pattern, scratch_dir, MockServer, Response};
+use parallel_downloads::{
+    download_batch_requests, download_file, download_file_with_config, BatchConfig, DownloadConfig,
+    DownloadError, DownloadEvent, DownloadRequest,
+};
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn batches_normalize_their_urls_before_sending_them() {
+    let server = MockServer::start(|request| Response::ok(request.path.clone()));
+    let directory = scratch_dir("urls_normalized");
+    let url = server
+        .url("/file.txt#section")
+        .replace("http://", "HTTP://");
+    let results = download_batch_requests(
+        vec![DownloadRequest::new(url, directory.join("file.txt"))],
+        BatchConfig::default(),
+        |_| {},
+    )
+    .unwrap();
+
+    assert_eq!(results[0].url, server.url("/file.txt"));
+    assert!(results[0].is_success(), "{:?}", results[0].outcome);
+    assert_eq!(server.requests()[0].path, "/file.txt");
+}
+
+/// Two requests on `server`, the second with a URL of an unsupported scheme.
+fn with_invalid_url(server: &MockServer, name: &str) -> Vec<DownloadRequest> {
+    let directory = scratch_dir(name);
+    vec![
+        DownloadRequest::new(server.url("/file.txt"), directory.join("file.txt")),
+        DownloadRequest::new("gopher://example.com/file.txt", directory.join("other.txt")),
+    ]
+}
+
+#[test]
+fn an_invalid_url_fails_only_its_own_request() {
+    let server = MockServer::start(|_| Response::ok("content"));
+    let requests = with_invalid_url(&server, "urls_lenient");
+    let results = download_batch_requests(requests, BatchConfig::default(), |_| {}).unwrap();
+
+    assert!(results[0].is_success(), "{:?}", results[0].outcome);
+    assert!(
+        matches!(
+            results[1].error(),
+            Some(DownloadError::InvalidUrl(error)) if error.url == "gopher://example.com/file.txt"
+        ),
+        "{:?}",
+        results[1].outcome
+    );
+}
+
+#[test]
+fn strict_batches_fail_at_an_invalid_url_before_sending_anything() {
+    let server = MockServer::start(|_| Response::ok("content"));
+    let requests = with_invalid_url(&server, "urls_strict");
+    let config = BatchConfig {
+        strict_urls: true,
+        ..BatchConfig::default()
+    };
+    let result = download_batch_requests(requests, config, |_| {});
+
+    assert!(
+        matches!(&result, Err(DownloadError::InvalidUrl(error)) if error.index == Some(1)),
+        "{:?}",
+        result
+    );
+    assert!(server.requests().is_empty());
+}
+
+/// Returns the `file://` URL of `path`.
+fn file_url(path: &std::path::Path) -> String {
+    format!("file://{}", path.display())
+}
+
+#[test]
+fn file_urls_are_copied_from_the_disk() {
+    let directory = scratch_dir("urls_file");
+    let source = directory.join("source.bin");
+    let content = pattern(64 * 1024);
+    std::fs::write(&source, &content).unwrap();
+    let destination = directory.join("copy.bin");
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&events);
+    download_file(file_url(&source), &destination, move |event| {
+        seen.lock().unwrap().push(event.clone())
+    })
+    .unwrap();
+
+    assert_eq!(std::fs::read(&destination).unwrap(), content);
+    assert!(events.lock().unwrap().iter().any(|event| matches!(
+        event,
+        DownloadEvent::Progress(progress) if progress.total_bytes() == Some(64 * 1024)
+    )));
+}
+
+#[test]
+fn a_missing_local_file_fails_naming_it() {
+    let directory = scratch_dir("urls_file_missing");
+    let source = directory.join("missing.bin");
+    let result = download_file(file_url(&source), directory.join("copy.bin"), |_| {});
+
+    assert!(
+        matches!(&result, Err(DownloadError::Io { path, .. }) if *path == source),
+        "{:?}",
+        result
+    );
+}
+
+#[test]
+fn data_urls_are_decoded_into_the_file() {
+    let directory = scratch_dir("urls_data");
+    let plain = directory.join("plain.txt");
+    let encoded = directory.join("encoded.txt");
+    download_file("data:text/plain,hello%20world", &plain, |_| {}).unwrap();
+    // Unpadded, with whitespace inside the payload.
+    download_file("data:;base64,aGVs bG8gd29y bGQ", &encoded, |_| {}).unwrap();
+
+    assert_eq!(std::fs::read(plain).unwrap(), b"hello world");
+    assert_eq!(std::fs::read(encoded).unwrap(), b"hello world");
+}
+
+#[test]
+fn malformed_data_urls_fail_without_writing_anything() {
+    let directory = scratch_dir("urls_data_malformed");
+    for (index, url) in ["data:text/plain", "data:;base64,a*b="]
+        .into_iter()
+        .enumerate()
+    {
+        let path = directory.join(format!("{}.txt", index));
+        let result = download_file(url, &path, |_| {});
+
+        assert!(
+            matches!(&result, Err(DownloadError::InvalidUrl(_))),
+            "{}: {:?}",
+            url,
+            result
+        );
+        assert!(!path.exists());
+    }
+}
+
+#[test]
+fn data_urls_over_the_size_limit_are_refused() {
+    let path = scratch_dir("urls_data_max_size").join("file.txt");
+    let config = DownloadConfig {
+        max_size: Some(4),
+        ..DownloadConfig::default()
+    };
+    let client = reqwest::blocking::Client::new();
+    let result = download_file_with_config(&client, "data:,hello", &path, &config, |_| {});
+
+    assert!(
+        matches!(&result, Err(DownloadError::SizeLimitExceeded(_))),
+        "{:?}",
+        result
+    );
+    assert!(!path.exists());
+}
@@ -0,0 +1,93 @@
+//! Downloads streamed into a writer, which share the chunk loop of downloads to a file.
+
+mod common;
+
+use common::{pattern, MockServer};
+use parallel_downloads::{
+    download_to_writer_with_config, BodyPart, DownloadConfig, DownloadError, RetryConfig,
+    ScriptedBackend, ScriptedResponse,
+};
+use reqwest::header::CONTENT_LENGTH;
+use std::io::{self, Write};
+
+/// A writer that fails once it has been given `capacity` bytes.
+struct Full {
+    capacity: usize,
+}
+
+impl Write for Full {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > self.capacity {
+            return Err(io::Error::new(io::ErrorKind::StorageFull, "full"));
+        }
+        self.capacity -= buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn the_body_is_streamed_into_the_writer() {
+    let content = pattern(512 * 1024);
+    let server = MockServer::serving(content.clone());
+    let client = reqwest::blocking::Client::new();
+    let mut received = Vec::new();
+    let body = download_to_writer_with_config(
+        &client,
+        server.url("/file.bin"),
+        &mut received,
+        &DownloadConfig::default(),
+        |_| {},
+    )
+    .unwrap();
+    assert_eq!(body.bytes, content.len() as u64);
+    assert_eq!(received, content);
+}
+
+#[test]
+fn writer_failures_are_told_apart_from_network_ones() {
+    let server = MockServer::serving(pattern(512 * 1024));
+    let client = reqwest::blocking::Client::new();
+    let config = DownloadConfig {
+        retry: RetryConfig::none(),
+        ..DownloadConfig::default()
+    };
+    let writer = Full { capacity: 100_000 };
+    let error =
+        download_to_writer_with_config(&client, server.url("/file.bin"), writer, &config, |_| {})
+            .unwrap_err();
+    match error {
+        DownloadError::Writer(source) => assert_eq!(source.kind(), io::ErrorKind::StorageFull),
+        error => panic!("expected a writer error, got {:?}", error),
+    }
+}
+
+#[test]
+fn the_size_limit_stops_a_body_of_unknown_length() {
+    let url = "http://example.com/file.bin";
+    let backend = ScriptedBackend::new();
+    let mut response =
+        ScriptedResponse::chunked((0..8).map(|_| BodyPart::Data(pattern(64 * 1024))).collect());
+    response.headers.remove(CONTENT_LENGTH);
+    backend.push(url, response);
+    let config = DownloadConfig {
+        max_size: Some(100_000),
+        retry: RetryConfig::none(),
+        ..DownloadConfig::default()
+    };
+    let mut received = Vec::new();
+    let error =
+        download_to_writer_with_config(&backend, url, &mut received, &config, |_| {}).unwrap_err();
+    match error {
+        DownloadError::SizeLimitExceeded(exceeded) => {
+            assert_eq!(exceeded.advertised, None);
+            assert!(exceeded.received > 100_000);
+        }
+        error => panic!("expected the size limit, got {:?}", error),
+    }
+    // The chunk that would go over the limit is never written.
+    assert_eq!(received.len(), 64 * 1024);
+}